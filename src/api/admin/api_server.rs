@@ -299,6 +299,13 @@ impl ApiHandler for AdminApiServer {
 			Endpoint::CreateBucket => handle_create_bucket(&self.garage, req).await,
 			Endpoint::DeleteBucket { id } => handle_delete_bucket(&self.garage, id).await,
 			Endpoint::UpdateBucket { id } => handle_update_bucket(&self.garage, id, req).await,
+			Endpoint::GetUploadProgress { id, key } => {
+				handle_get_upload_progress(&self.garage, id, key).await
+			}
+			Endpoint::InspectObject { id, key } => {
+				handle_inspect_object(&self.garage, id, key).await
+			}
+			Endpoint::CleanupStaleUploads => handle_cleanup_stale_uploads(&self.garage, req).await,
 			// Bucket-key permissions
 			Endpoint::BucketAllowKey => {
 				handle_bucket_change_key_perm(&self.garage, req, true).await
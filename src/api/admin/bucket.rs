@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use hyper::{body::Incoming as IncomingBody, Request, Response, StatusCode};
 use serde::{Deserialize, Serialize};
@@ -574,6 +575,247 @@ pub async fn handle_local_unalias_bucket(
 	bucket_info_results(garage, bucket_id).await
 }
 
+// ---- UPLOAD PROGRESS ----
+
+pub async fn handle_get_upload_progress(
+	garage: &Arc<Garage>,
+	id: String,
+	key: String,
+) -> Result<Response<ResBody>, Error> {
+	let bucket_id = parse_bucket_id(&id)?;
+	garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let object = garage.object_table.get(&bucket_id, &key).await?;
+	let uploading_versions = object.iter().flat_map(|o| o.versions()).filter(|v| {
+		matches!(
+			v.state,
+			ObjectVersionState::Uploading {
+				multipart: false,
+				..
+			}
+		)
+	});
+
+	let mut uploads = vec![];
+	for v in uploading_versions {
+		// The version is written with an empty block list when the upload
+		// starts, and the size of each block is added once it is stored
+		let bytes_received = garage
+			.version_table
+			.get(&v.uuid, &EmptyKey)
+			.await?
+			.map(|version| version.blocks.items().iter().map(|(_, b)| b.size).sum())
+			.unwrap_or(0);
+		uploads.push(UploadProgress {
+			version_id: hex::encode(v.uuid),
+			started: msec_to_rfc3339(v.timestamp),
+			bytes_received,
+		});
+	}
+
+	let res = GetUploadProgressResult {
+		bucket_id: hex::encode(bucket_id),
+		key,
+		uploads,
+	};
+
+	Ok(json_ok_response(&res)?)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GetUploadProgressResult {
+	bucket_id: String,
+	key: String,
+	uploads: Vec<UploadProgress>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadProgress {
+	version_id: String,
+	started: String,
+	bytes_received: u64,
+}
+
+// ---- INSPECT OBJECT ----
+
+pub async fn handle_inspect_object(
+	garage: &Arc<Garage>,
+	id: String,
+	key: String,
+) -> Result<Response<ResBody>, Error> {
+	let bucket_id = parse_bucket_id(&id)?;
+	garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let object = garage.object_table.get(&bucket_id, &key).await?;
+
+	let mut versions = vec![];
+	for v in object.iter().flat_map(|o| o.versions()) {
+		let mut res = InspectObjectVersion {
+			version_id: hex::encode(v.uuid),
+			timestamp: msec_to_rfc3339(v.timestamp),
+			state: "complete",
+			multipart: None,
+			encrypted: false,
+			delete_marker: false,
+			inline: false,
+			size: None,
+			etag: None,
+			first_block: None,
+			blocks: vec![],
+		};
+		let meta = match &v.state {
+			ObjectVersionState::Uploading {
+				multipart,
+				encryption,
+				..
+			} => {
+				res.state = "uploading";
+				res.multipart = Some(*multipart);
+				res.encrypted = matches!(encryption, ObjectVersionEncryption::SseC { .. });
+				None
+			}
+			ObjectVersionState::Aborted => {
+				res.state = "aborted";
+				None
+			}
+			ObjectVersionState::Complete(ObjectVersionData::DeleteMarker) => {
+				res.delete_marker = true;
+				None
+			}
+			// The inline data itself is never returned
+			ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+			| ObjectVersionState::Complete(ObjectVersionData::InlineRef(meta, _)) => {
+				res.inline = true;
+				Some(meta)
+			}
+			ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, first_block)) => {
+				res.first_block = Some(hex::encode(first_block));
+				Some(meta)
+			}
+		};
+		if let Some(meta) = meta {
+			res.encrypted = matches!(meta.encryption, ObjectVersionEncryption::SseC { .. });
+			res.size = Some(meta.size);
+			res.etag = Some(meta.etag.clone());
+		}
+
+		// Inline versions and delete markers have no entry in the version table
+		if !res.inline && !res.delete_marker {
+			if let Some(version) = garage.version_table.get(&v.uuid, &EmptyKey).await? {
+				res.blocks = version
+					.blocks
+					.items()
+					.iter()
+					.map(|(k, b)| InspectObjectBlock {
+						part_number: k.part_number,
+						offset: k.offset,
+						hash: hex::encode(b.hash),
+						size: b.size,
+					})
+					.collect();
+			}
+		}
+		versions.push(res);
+	}
+
+	let res = InspectObjectResult {
+		bucket_id: hex::encode(bucket_id),
+		key,
+		versions,
+	};
+
+	Ok(json_ok_response(&res)?)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InspectObjectResult {
+	bucket_id: String,
+	key: String,
+	versions: Vec<InspectObjectVersion>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InspectObjectVersion {
+	version_id: String,
+	timestamp: String,
+	state: &'static str,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	multipart: Option<bool>,
+	encrypted: bool,
+	delete_marker: bool,
+	inline: bool,
+	size: Option<u64>,
+	etag: Option<String>,
+	first_block: Option<String>,
+	blocks: Vec<InspectObjectBlock>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InspectObjectBlock {
+	part_number: u64,
+	offset: u64,
+	hash: String,
+	size: u64,
+}
+
+// ---- CLEANUP STALE UPLOADS ----
+
+pub async fn handle_cleanup_stale_uploads(
+	garage: &Arc<Garage>,
+	req: Request<IncomingBody>,
+) -> Result<Response<ResBody>, Error> {
+	let req = parse_json_body::<CleanupStaleUploadsRequest, _, Error>(req).await?;
+	let older_than = Duration::from_secs(req.older_than_secs);
+
+	let uploads_aborted = match req.bucket_id {
+		Some(id) => {
+			let bucket_id = parse_bucket_id(&id)?;
+			garage
+				.bucket_helper()
+				.get_existing_bucket(bucket_id)
+				.await?;
+			garage
+				.bucket_helper()
+				.cleanup_incomplete_uploads(&bucket_id, older_than)
+				.await?
+		}
+		None => {
+			garage
+				.bucket_helper()
+				.cleanup_stale_uploads(older_than)
+				.await?
+		}
+	};
+
+	Ok(json_ok_response(&CleanupStaleUploadsResponse {
+		uploads_aborted,
+	})?)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CleanupStaleUploadsRequest {
+	older_than_secs: u64,
+	bucket_id: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CleanupStaleUploadsResponse {
+	uploads_aborted: usize,
+}
+
 // ---- HELPER ----
 
 fn parse_bucket_id(id: &str) -> Result<Uuid, Error> {
@@ -57,6 +57,15 @@ pub enum Endpoint {
 	UpdateBucket {
 		id: String,
 	},
+	GetUploadProgress {
+		id: String,
+		key: String,
+	},
+	InspectObject {
+		id: String,
+		key: String,
+	},
+	CleanupStaleUploads,
 	// Bucket-Key Permissions
 	BucketAllowKey,
 	BucketDenyKey,
@@ -120,6 +129,9 @@ impl Endpoint {
 			POST "/v1/bucket" => CreateBucket,
 			DELETE "/v1/bucket" if id => DeleteBucket (query::id),
 			PUT "/v1/bucket" if id => UpdateBucket (query::id),
+			GET "/v1/bucket/upload-progress" => GetUploadProgress (query::id, query::key),
+			GET "/v1/bucket/inspect-object" => InspectObject (query::id, query::key),
+			POST "/v1/bucket/cleanup-stale-uploads" => CleanupStaleUploads,
 			// Bucket-key permissions
 			POST "/v1/bucket/allow" => BucketAllowKey,
 			POST "/v1/bucket/deny" => BucketDenyKey,
@@ -230,6 +242,7 @@ generateQueryParameters! {
 		"globalAlias" => global_alias,
 		"alias" => alias,
 		"accessKeyId" => access_key_id,
+		"key" => key,
 		"showSecretKey" => show_secret_key
 	]
 }
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
 use async_trait::async_trait;
 
@@ -6,7 +6,12 @@ use hyper::header;
 use hyper::{body::Incoming as IncomingBody, Request, Response};
 use tokio::sync::watch;
 
-use opentelemetry::{trace::SpanRef, KeyValue};
+use opentelemetry::{
+	global,
+	metrics::{Counter, ValueRecorder},
+	trace::SpanRef,
+	KeyValue,
+};
 
 use garage_util::error::Error as GarageError;
 use garage_util::socket_address::UnixOrTCPSocketAddress;
@@ -30,7 +35,9 @@ use crate::s3::list::*;
 use crate::s3::multipart::*;
 use crate::s3::post_object::handle_post_object;
 use crate::s3::put::*;
+use crate::s3::restore::*;
 use crate::s3::router::Endpoint;
+use crate::s3::tagging::*;
 use crate::s3::website::*;
 
 pub use crate::signature::streaming::ReqBody;
@@ -40,6 +47,39 @@ pub struct S3ApiServer {
 	garage: Arc<Garage>,
 }
 
+/// Metrics recorded while handling S3 API requests, in addition to
+/// the per-endpoint metrics of the generic API server
+pub(crate) struct S3ApiMetrics {
+	pub(crate) put_block_duration: ValueRecorder<f64>,
+	pub(crate) put_pipeline_backpressure: Counter<u64>,
+}
+
+impl S3ApiMetrics {
+	fn new() -> Self {
+		let meter = global::meter("garage/api");
+		Self {
+			put_block_duration: meter
+				.f64_value_recorder("garage.s3.put_block_duration_ms")
+				.with_description(
+					"Duration of the storage of individual data blocks in uploads, in milliseconds",
+				)
+				.init(),
+			put_pipeline_backpressure: meter
+				.u64_counter("api.s3.put_pipeline_backpressure")
+				.with_description(
+					"Number of times a stage of the upload pipeline waited for the next stage",
+				)
+				.init(),
+		}
+	}
+
+	/// Get the S3 API metrics, registering them on first use
+	pub(crate) fn get() -> &'static Self {
+		static METRICS: OnceLock<S3ApiMetrics> = OnceLock::new();
+		METRICS.get_or_init(Self::new)
+	}
+}
+
 pub(crate) struct S3ApiEndpoint {
 	bucket_name: Option<String>,
 	endpoint: Endpoint,
@@ -111,7 +151,7 @@ impl ApiHandler for S3ApiServer {
 	) -> Result<Response<ResBody>, Error> {
 		let S3ApiEndpoint {
 			bucket_name,
-			endpoint,
+			mut endpoint,
 		} = endpoint;
 		let garage = self.garage.clone();
 
@@ -170,6 +210,14 @@ impl ApiHandler for S3ApiServer {
 
 		let matching_cors_rule = find_matching_cors_rule(&bucket_params, &req)?.cloned();
 
+		// Keys are normalized before being used in any way, so that
+		// all spellings of a key given by clients reach the same object
+		let key_normalization = bucket_params.options.get().key_normalization;
+		if let Some(key) = endpoint.get_key_mut() {
+			*key = key_normalization.normalize(key);
+		}
+		let normalize = |key: Option<String>| key.map(|k| key_normalization.normalize(&k));
+
 		let ctx = ReqCtx {
 			garage,
 			bucket_id,
@@ -180,8 +228,10 @@ impl ApiHandler for S3ApiServer {
 
 		let resp = match endpoint {
 			Endpoint::HeadObject {
-				key, part_number, ..
-			} => handle_head(ctx, &req, &key, part_number).await,
+				key,
+				part_number,
+				version_id,
+			} => handle_head(ctx, &req, &key, part_number, version_id.as_deref()).await,
 			Endpoint::GetObject {
 				key,
 				part_number,
@@ -191,7 +241,7 @@ impl ApiHandler for S3ApiServer {
 				response_content_language,
 				response_content_type,
 				response_expires,
-				..
+				version_id,
 			} => {
 				let overrides = GetObjectOverrides {
 					response_cache_control,
@@ -201,7 +251,15 @@ impl ApiHandler for S3ApiServer {
 					response_content_type,
 					response_expires,
 				};
-				handle_get(ctx, &req, &key, part_number, overrides).await
+				handle_get(
+					ctx,
+					&req,
+					&key,
+					part_number,
+					version_id.as_deref(),
+					overrides,
+				)
+				.await
 			}
 			Endpoint::UploadPart {
 				key,
@@ -218,7 +276,20 @@ impl ApiHandler for S3ApiServer {
 			Endpoint::AbortMultipartUpload { key, upload_id } => {
 				handle_abort_multipart_upload(ctx, &key, &upload_id).await
 			}
-			Endpoint::DeleteObject { key, .. } => handle_delete(ctx, &key).await,
+			Endpoint::DeleteObject { key, version_id } => {
+				handle_delete(ctx, &req, &key, version_id.as_deref()).await
+			}
+			Endpoint::GetObjectTagging { key, .. } => {
+				handle_get_object_tagging(ctx, &req, &key).await
+			}
+			Endpoint::PutObjectTagging { key, version_id } => {
+				handle_put_object_tagging(ctx, req, &key, version_id.as_deref(), content_sha256)
+					.await
+			}
+			Endpoint::DeleteObjectTagging { key, version_id } => {
+				handle_delete_object_tagging(ctx, &req, &key, version_id.as_deref()).await
+			}
+			Endpoint::RestoreObject { key, .. } => handle_restore(ctx, &key).await,
 			Endpoint::CreateMultipartUpload { key } => {
 				handle_create_multipart_upload(ctx, &req, &key).await
 			}
@@ -232,7 +303,10 @@ impl ApiHandler for S3ApiServer {
 			}
 			Endpoint::DeleteBucket {} => handle_delete_bucket(ctx).await,
 			Endpoint::GetBucketLocation {} => handle_get_bucket_location(ctx),
-			Endpoint::GetBucketVersioning {} => handle_get_bucket_versioning(),
+			Endpoint::GetBucketVersioning {} => handle_get_bucket_versioning(ctx),
+			Endpoint::PutBucketVersioning {} => {
+				handle_put_bucket_versioning(ctx, req, content_sha256).await
+			}
 			Endpoint::ListObjects {
 				delimiter,
 				encoding_type,
@@ -246,11 +320,11 @@ impl ApiHandler for S3ApiServer {
 						bucket_id,
 						delimiter,
 						page_size: max_keys.unwrap_or(1000).clamp(1, 1000),
-						prefix: prefix.unwrap_or_default(),
+						prefix: normalize(prefix).unwrap_or_default(),
 						urlencode_resp: encoding_type.map(|e| e == "url").unwrap_or(false),
 					},
 					is_v2: false,
-					marker,
+					marker: normalize(marker),
 					continuation_token: None,
 					start_after: None,
 				};
@@ -274,12 +348,12 @@ impl ApiHandler for S3ApiServer {
 							delimiter,
 							page_size: max_keys.unwrap_or(1000).clamp(1, 1000),
 							urlencode_resp: encoding_type.map(|e| e == "url").unwrap_or(false),
-							prefix: prefix.unwrap_or_default(),
+							prefix: normalize(prefix).unwrap_or_default(),
 						},
 						is_v2: true,
 						marker: None,
 						continuation_token,
-						start_after,
+						start_after: normalize(start_after),
 					};
 					handle_list(ctx, &query).await
 				} else {
@@ -303,14 +377,36 @@ impl ApiHandler for S3ApiServer {
 						bucket_id,
 						delimiter,
 						page_size: max_uploads.unwrap_or(1000).clamp(1, 1000),
-						prefix: prefix.unwrap_or_default(),
+						prefix: normalize(prefix).unwrap_or_default(),
 						urlencode_resp: encoding_type.map(|e| e == "url").unwrap_or(false),
 					},
-					key_marker,
+					key_marker: normalize(key_marker),
 					upload_id_marker,
 				};
 				handle_list_multipart_upload(ctx, &query).await
 			}
+			Endpoint::ListObjectVersions {
+				delimiter,
+				encoding_type,
+				key_marker,
+				max_keys,
+				prefix,
+				version_id_marker,
+			} => {
+				let query = ListObjectVersionsQuery {
+					common: ListQueryCommon {
+						bucket_name: ctx.bucket_name.clone(),
+						bucket_id,
+						delimiter,
+						page_size: max_keys.unwrap_or(1000).clamp(1, 1000) as usize,
+						prefix: normalize(prefix).unwrap_or_default(),
+						urlencode_resp: encoding_type.map(|e| e == "url").unwrap_or(false),
+					},
+					key_marker: normalize(key_marker),
+					version_id_marker,
+				};
+				handle_list_object_versions(ctx, &query).await
+			}
 			Endpoint::ListParts {
 				key,
 				max_parts,
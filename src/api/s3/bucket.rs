@@ -1,10 +1,12 @@
+use quick_xml::de::from_reader;
+
 use std::collections::HashMap;
 
 use http_body_util::BodyExt;
 use hyper::{Request, Response, StatusCode};
 
 use garage_model::bucket_alias_table::*;
-use garage_model::bucket_table::Bucket;
+use garage_model::bucket_table::{Bucket, VersioningStatus};
 use garage_model::garage::Garage;
 use garage_model::key_table::Key;
 use garage_model::permission::BucketKeyPerm;
@@ -33,10 +35,18 @@ pub fn handle_get_bucket_location(ctx: ReqCtx) -> Result<Response<ResBody>, Erro
 		.body(string_body(xml))?)
 }
 
-pub fn handle_get_bucket_versioning() -> Result<Response<ResBody>, Error> {
+pub fn handle_get_bucket_versioning(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
+	let status = ctx
+		.bucket_params
+		.versioning
+		.get()
+		.map(|status| match status {
+			VersioningStatus::Enabled => "Enabled",
+			VersioningStatus::Suspended => "Suspended",
+		});
 	let versioning = s3_xml::VersioningConfiguration {
 		xmlns: (),
-		status: None,
+		status: status.map(|s| s3_xml::Value(s.to_string())),
 	};
 
 	let xml = s3_xml::to_xml_with_header(&versioning)?;
@@ -46,6 +56,46 @@ pub fn handle_get_bucket_versioning() -> Result<Response<ResBody>, Error> {
 		.body(string_body(xml))?)
 }
 
+pub async fn handle_put_bucket_versioning(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<ResBody>, Error> {
+	let ReqCtx {
+		garage,
+		bucket_id,
+		mut bucket_params,
+		..
+	} = ctx;
+
+	let body = BodyExt::collect(req.into_body()).await?.to_bytes();
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let conf: s3_xml::VersioningConfiguration = from_reader(&body as &[u8])?;
+	let status = match conf.status.as_ref().map(|s| s.0.as_str()) {
+		Some("Enabled") => VersioningStatus::Enabled,
+		Some("Suspended") => VersioningStatus::Suspended,
+		_ => {
+			return Err(Error::bad_request(
+				"Versioning status must be Enabled or Suspended",
+			))
+		}
+	};
+
+	bucket_params.versioning.update(Some(status));
+	garage
+		.bucket_table
+		.insert(&Bucket::present(bucket_id, bucket_params))
+		.await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(empty_body())?)
+}
+
 pub async fn handle_list_buckets(
 	garage: &Garage,
 	api_key: &Key,
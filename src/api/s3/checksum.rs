@@ -2,6 +2,7 @@ use std::convert::{TryFrom, TryInto};
 use std::hash::Hasher;
 
 use base64::prelude::*;
+use blake2::Blake2b512;
 use crc32c::Crc32cHasher as Crc32c;
 use crc32fast::Hasher as Crc32;
 use md5::{Digest, Md5};
@@ -17,19 +18,26 @@ use garage_model::s3::object_table::*;
 
 use crate::s3::error::*;
 
+pub const CONTENT_MD5: HeaderName = HeaderName::from_static("content-md5");
+
 pub const X_AMZ_CHECKSUM_ALGORITHM: HeaderName =
 	HeaderName::from_static("x-amz-checksum-algorithm");
+pub const X_AMZ_SDK_CHECKSUM_ALGORITHM: &str = "x-amz-sdk-checksum-algorithm";
 pub const X_AMZ_CHECKSUM_MODE: HeaderName = HeaderName::from_static("x-amz-checksum-mode");
+pub const X_AMZ_CHECKSUM_TYPE: HeaderName = HeaderName::from_static("x-amz-checksum-type");
 pub const X_AMZ_CHECKSUM_CRC32: HeaderName = HeaderName::from_static("x-amz-checksum-crc32");
 pub const X_AMZ_CHECKSUM_CRC32C: HeaderName = HeaderName::from_static("x-amz-checksum-crc32c");
 pub const X_AMZ_CHECKSUM_SHA1: HeaderName = HeaderName::from_static("x-amz-checksum-sha1");
 pub const X_AMZ_CHECKSUM_SHA256: HeaderName = HeaderName::from_static("x-amz-checksum-sha256");
+pub const X_AMZ_CHECKSUM_CRC64NVME: HeaderName =
+	HeaderName::from_static("x-amz-checksum-crc64nvme");
 
 pub type Crc32Checksum = [u8; 4];
 pub type Crc32cChecksum = [u8; 4];
 pub type Md5Checksum = [u8; 16];
 pub type Sha1Checksum = [u8; 20];
 pub type Sha256Checksum = [u8; 32];
+pub type Crc64NvmeChecksum = [u8; 8];
 
 #[derive(Debug, Default)]
 pub(crate) struct ExpectedChecksums {
@@ -39,6 +47,11 @@ pub(crate) struct ExpectedChecksums {
 	pub sha256: Option<Hash>,
 	// extra x-amz-checksum-* header
 	pub extra: Option<ChecksumValue>,
+	// algorithm announced by the x-amz-sdk-checksum-algorithm header,
+	// whose checksum is computed and stored even if no value is given
+	pub algorithm: Option<ChecksumAlgorithm>,
+	// blake2 hash of each data block (x-garage-block-hashes header)
+	pub block_hashes: Option<Vec<Hash>>,
 }
 
 pub(crate) struct Checksummer {
@@ -47,6 +60,8 @@ pub(crate) struct Checksummer {
 	pub md5: Option<Md5>,
 	pub sha1: Option<Sha1>,
 	pub sha256: Option<Sha256>,
+	pub crc64nvme: Option<Crc64Nvme>,
+	pub blake2: Option<Blake2b512>,
 }
 
 #[derive(Default)]
@@ -56,6 +71,8 @@ pub(crate) struct Checksums {
 	pub md5: Option<Md5Checksum>,
 	pub sha1: Option<Sha1Checksum>,
 	pub sha256: Option<Sha256Checksum>,
+	pub crc64nvme: Option<Crc64NvmeChecksum>,
+	pub blake2: Option<Hash>,
 }
 
 impl Checksummer {
@@ -66,6 +83,8 @@ impl Checksummer {
 			md5: None,
 			sha1: None,
 			sha256: None,
+			crc64nvme: None,
+			blake2: None,
 		};
 
 		if expected.md5.is_some() || require_md5 {
@@ -83,7 +102,10 @@ impl Checksummer {
 		if matches!(&expected.extra, Some(ChecksumValue::Sha1(_))) {
 			ret.sha1 = Some(Sha1::new());
 		}
-		ret
+		if matches!(&expected.extra, Some(ChecksumValue::Crc64Nvme(_))) {
+			ret.crc64nvme = Some(Crc64Nvme::new());
+		}
+		ret.add(expected.algorithm)
 	}
 
 	pub(crate) fn add(mut self, algo: Option<ChecksumAlgorithm>) -> Self {
@@ -100,11 +122,19 @@ impl Checksummer {
 			Some(ChecksumAlgorithm::Sha256) => {
 				self.sha256 = Some(Sha256::new());
 			}
+			Some(ChecksumAlgorithm::Crc64Nvme) => {
+				self.crc64nvme = Some(Crc64Nvme::new());
+			}
 			None => (),
 		}
 		self
 	}
 
+	pub(crate) fn add_blake2(mut self) -> Self {
+		self.blake2 = Some(Blake2b512::new());
+		self
+	}
+
 	pub(crate) fn update(&mut self, bytes: &[u8]) {
 		if let Some(crc32) = &mut self.crc32 {
 			crc32.update(bytes);
@@ -121,6 +151,12 @@ impl Checksummer {
 		if let Some(sha256) = &mut self.sha256 {
 			sha256.update(bytes);
 		}
+		if let Some(crc64nvme) = &mut self.crc64nvme {
+			crc64nvme.update(bytes);
+		}
+		if let Some(blake2) = &mut self.blake2 {
+			blake2.update(bytes);
+		}
 	}
 
 	pub(crate) fn finalize(self) -> Checksums {
@@ -132,6 +168,10 @@ impl Checksummer {
 			md5: self.md5.map(|x| x.finalize()[..].try_into().unwrap()),
 			sha1: self.sha1.map(|x| x.finalize()[..].try_into().unwrap()),
 			sha256: self.sha256.map(|x| x.finalize()[..].try_into().unwrap()),
+			crc64nvme: self.crc64nvme.map(|x| u64::to_be_bytes(x.finalize())),
+			blake2: self
+				.blake2
+				.map(|x| Hash::try_from(&x.finalize()[..32]).unwrap()),
 		}
 	}
 }
@@ -139,23 +179,21 @@ impl Checksummer {
 impl Checksums {
 	pub fn verify(&self, expected: &ExpectedChecksums) -> Result<(), Error> {
 		if let Some(expected_md5) = &expected.md5 {
-			match self.md5 {
-				Some(md5) if BASE64_STANDARD.encode(&md5) == expected_md5.trim_matches('"') => (),
-				_ => {
-					return Err(Error::InvalidDigest(
-						"MD5 checksum verification failed (from content-md5)".into(),
-					))
-				}
+			let expected_md5 = expected_md5.trim_matches('"');
+			let computed_md5 = self.md5.map(|md5| BASE64_STANDARD.encode(md5));
+			if computed_md5.as_deref() != Some(expected_md5) {
+				return Err(Error::BadDigest(
+					expected_md5.to_string(),
+					computed_md5.unwrap_or_default(),
+				));
 			}
 		}
 		if let Some(expected_sha256) = &expected.sha256 {
-			match self.sha256 {
-				Some(sha256) if &sha256[..] == expected_sha256.as_slice() => (),
-				_ => {
-					return Err(Error::InvalidDigest(
-						"SHA256 checksum verification failed (from x-amz-content-sha256)".into(),
-					))
-				}
+			if self.sha256.as_ref().map(|x| &x[..]) != Some(expected_sha256.as_slice()) {
+				return Err(Error::Sha256Mismatch(
+					hex::encode(expected_sha256.as_slice()),
+					self.sha256.map(hex::encode).unwrap_or_default(),
+				));
 			}
 		}
 		if let Some(extra) = expected.extra {
@@ -177,7 +215,59 @@ impl Checksums {
 			Some(ChecksumAlgorithm::Crc32c) => Some(ChecksumValue::Crc32c(self.crc32c.unwrap())),
 			Some(ChecksumAlgorithm::Sha1) => Some(ChecksumValue::Sha1(self.sha1.unwrap())),
 			Some(ChecksumAlgorithm::Sha256) => Some(ChecksumValue::Sha256(self.sha256.unwrap())),
+			Some(ChecksumAlgorithm::Crc64Nvme) => {
+				Some(ChecksumValue::Crc64Nvme(self.crc64nvme.unwrap()))
+			}
+		}
+	}
+}
+
+// ----
+
+/// CRC-64/NVME (reflected polynomial 0x9a6c9329ac4bc9b5, as used by the
+/// x-amz-checksum-crc64nvme header). No crate in our dependency tree
+/// implements it, so it is computed here with a simple lookup table.
+#[derive(Clone)]
+pub(crate) struct Crc64Nvme(u64);
+
+const CRC64_NVME_POLY: u64 = 0x9a6c9329ac4bc9b5;
+const CRC64_NVME_TABLE: [u64; 256] = crc64_nvme_table();
+
+const fn crc64_nvme_table() -> [u64; 256] {
+	let mut table = [0u64; 256];
+	let mut i = 0;
+	while i < 256 {
+		let mut crc = i as u64;
+		let mut j = 0;
+		while j < 8 {
+			crc = if crc & 1 == 1 {
+				(crc >> 1) ^ CRC64_NVME_POLY
+			} else {
+				crc >> 1
+			};
+			j += 1;
+		}
+		table[i] = crc;
+		i += 1;
+	}
+	table
+}
+
+impl Crc64Nvme {
+	pub(crate) fn new() -> Self {
+		Self(!0)
+	}
+
+	pub(crate) fn update(&mut self, bytes: &[u8]) {
+		let mut crc = self.0;
+		for b in bytes {
+			crc = CRC64_NVME_TABLE[((crc ^ *b as u64) & 0xff) as usize] ^ (crc >> 8);
 		}
+		self.0 = crc;
+	}
+
+	pub(crate) fn finalize(self) -> u64 {
+		!self.0
 	}
 }
 
@@ -194,6 +284,7 @@ pub(crate) enum MultipartExtraChecksummer {
 	Crc32c(Crc32c),
 	Sha1(Sha1),
 	Sha256(Sha256),
+	Crc64Nvme(Crc64Nvme),
 }
 
 impl MultipartChecksummer {
@@ -212,6 +303,9 @@ impl MultipartChecksummer {
 				Some(ChecksumAlgorithm::Sha256) => {
 					Some(MultipartExtraChecksummer::Sha256(Sha256::new()))
 				}
+				Some(ChecksumAlgorithm::Crc64Nvme) => {
+					Some(MultipartExtraChecksummer::Crc64Nvme(Crc64Nvme::new()))
+				}
 			},
 		}
 	}
@@ -246,6 +340,12 @@ impl MultipartChecksummer {
 			) => {
 				sha256.update(&x);
 			}
+			(
+				Some(MultipartExtraChecksummer::Crc64Nvme(ref mut crc64nvme)),
+				Some(ChecksumValue::Crc64Nvme(x)),
+			) => {
+				crc64nvme.update(&x);
+			}
 			(Some(_), b) => {
 				return Err(Error::internal_error(format!(
 					"part checksum was not computed correctly, got: {:?}",
@@ -272,6 +372,9 @@ impl MultipartChecksummer {
 			Some(MultipartExtraChecksummer::Sha256(sha256)) => Some(ChecksumValue::Sha256(
 				sha256.finalize()[..].try_into().unwrap(),
 			)),
+			Some(MultipartExtraChecksummer::Crc64Nvme(crc64nvme)) => Some(
+				ChecksumValue::Crc64Nvme(u64::to_be_bytes(crc64nvme.finalize())),
+			),
 		};
 		(md5, extra)
 	}
@@ -285,11 +388,63 @@ pub(crate) fn request_checksum_algorithm(
 ) -> Result<Option<ChecksumAlgorithm>, Error> {
 	match headers.get(X_AMZ_CHECKSUM_ALGORITHM) {
 		None => Ok(None),
-		Some(x) if x == "CRC32" => Ok(Some(ChecksumAlgorithm::Crc32)),
-		Some(x) if x == "CRC32C" => Ok(Some(ChecksumAlgorithm::Crc32c)),
-		Some(x) if x == "SHA1" => Ok(Some(ChecksumAlgorithm::Sha1)),
-		Some(x) if x == "SHA256" => Ok(Some(ChecksumAlgorithm::Sha256)),
-		_ => Err(Error::bad_request("invalid checksum algorithm")),
+		Some(x) => parse_checksum_algorithm(x)
+			.map(Some)
+			.ok_or_else(|| Error::bad_request("invalid checksum algorithm")),
+	}
+}
+
+/// Extract the value of the x-amz-sdk-checksum-algorithm header, which
+/// SDKs send to announce the checksum algorithm used for the request body
+pub(crate) fn request_sdk_checksum_algorithm(
+	headers: &HeaderMap<HeaderValue>,
+) -> Result<Option<ChecksumAlgorithm>, Error> {
+	match headers.get(X_AMZ_SDK_CHECKSUM_ALGORITHM) {
+		None => Ok(None),
+		Some(x) => parse_checksum_algorithm(x).map(Some).ok_or_else(|| {
+			Error::bad_request(format!(
+				"unsupported checksum algorithm: {}",
+				String::from_utf8_lossy(x.as_bytes())
+			))
+		}),
+	}
+}
+
+fn parse_checksum_algorithm(value: &HeaderValue) -> Option<ChecksumAlgorithm> {
+	match value.as_bytes() {
+		b"CRC32" => Some(ChecksumAlgorithm::Crc32),
+		b"CRC32C" => Some(ChecksumAlgorithm::Crc32c),
+		b"SHA1" => Some(ChecksumAlgorithm::Sha1),
+		b"SHA256" => Some(ChecksumAlgorithm::Sha256),
+		b"CRC64NVME" => Some(ChecksumAlgorithm::Crc64Nvme),
+		_ => None,
+	}
+}
+
+/// Extract the value of the content-md5 header, checking that it is
+/// the base64 encoding of exactly 16 bytes (surrounding quotes are allowed).
+/// This allows rejecting invalid requests before any of the body is read.
+pub(crate) fn request_content_md5(
+	headers: &HeaderMap<HeaderValue>,
+) -> Result<Option<String>, Error> {
+	match headers.get(CONTENT_MD5) {
+		None => Ok(None),
+		Some(x) => {
+			let md5_str = x
+				.to_str()
+				.map_err(|_| Error::InvalidDigest("invalid content-md5 header".into()))?;
+			let valid = BASE64_STANDARD
+				.decode(md5_str.trim_matches('"'))
+				.map(|md5| md5.len() == 16)
+				.unwrap_or(false);
+			if !valid {
+				return Err(Error::InvalidDigest(format!(
+					"content-md5 header is not a valid base64-encoded md5 digest: {}",
+					md5_str
+				)));
+			}
+			Ok(Some(md5_str.to_string()))
+		}
 	}
 }
 
@@ -331,6 +486,14 @@ pub(crate) fn request_checksum_value(
 			.ok_or_bad_request("invalid x-amz-checksum-sha256 header")?;
 		ret.push(ChecksumValue::Sha256(sha256))
 	}
+	if let Some(crc64nvme_str) = headers.get(X_AMZ_CHECKSUM_CRC64NVME) {
+		let crc64nvme = BASE64_STANDARD
+			.decode(&crc64nvme_str)
+			.ok()
+			.and_then(|x| x.try_into().ok())
+			.ok_or_bad_request("invalid x-amz-checksum-crc64nvme header")?;
+		ret.push(ChecksumValue::Crc64Nvme(crc64nvme))
+	}
 
 	if ret.len() > 1 {
 		return Err(Error::bad_request(
@@ -378,6 +541,14 @@ pub(crate) fn request_checksum_algorithm_value(
 				.ok_or_bad_request("invalid x-amz-checksum-sha256 header")?;
 			Ok(Some(ChecksumValue::Sha256(sha256)))
 		}
+		Some(x) if x == "CRC64NVME" => {
+			let crc64nvme = headers
+				.get(X_AMZ_CHECKSUM_CRC64NVME)
+				.and_then(|x| BASE64_STANDARD.decode(&x).ok())
+				.and_then(|x| x.try_into().ok())
+				.ok_or_bad_request("invalid x-amz-checksum-crc64nvme header")?;
+			Ok(Some(ChecksumValue::Crc64Nvme(crc64nvme)))
+		}
 		Some(_) => Err(Error::bad_request("invalid x-amz-checksum-algorithm")),
 		None => Ok(None),
 	}
@@ -400,7 +571,144 @@ pub(crate) fn add_checksum_response_headers(
 		Some(ChecksumValue::Sha256(sha256)) => {
 			resp = resp.header(X_AMZ_CHECKSUM_SHA256, BASE64_STANDARD.encode(&sha256));
 		}
+		Some(ChecksumValue::Crc64Nvme(crc64nvme)) => {
+			resp = resp.header(X_AMZ_CHECKSUM_CRC64NVME, BASE64_STANDARD.encode(&crc64nvme));
+		}
 		None => (),
 	}
 	resp
 }
+
+/// Add the x-amz-checksum-type header, that tells whether the checksum of an
+/// object is computed on its whole data or on the checksums of its parts
+pub(crate) fn add_checksum_type_header(
+	checksum_type: ChecksumType,
+	resp: http::response::Builder,
+) -> http::response::Builder {
+	let value = match checksum_type {
+		ChecksumType::FullObject => "FULL_OBJECT",
+		ChecksumType::Composite => "COMPOSITE",
+	};
+	resp.header(X_AMZ_CHECKSUM_TYPE, value)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_verify_mismatch_errors() {
+		let md5 = BASE64_STANDARD.encode(Md5::digest(b"hello"));
+		let expected = ExpectedChecksums {
+			md5: Some(format!("\"{}\"", md5)),
+			sha256: Some(sha256sum(b"hello")),
+			..Default::default()
+		};
+		let mut checksummer = Checksummer::init(&expected, true);
+		checksummer.update(b"hello");
+		let checksums = checksummer.finalize();
+		assert!(checksums.verify(&expected).is_ok());
+
+		let expected = ExpectedChecksums {
+			md5: Some("1B2M2Y8AsgTpgAmY7PhCfg==".into()),
+			..Default::default()
+		};
+		match checksums.verify(&expected) {
+			Err(e @ Error::BadDigest(..)) => {
+				assert_eq!(e.aws_code(), "BadDigest");
+				assert!(e.to_string().contains(&format!(
+					"expected 1B2M2Y8AsgTpgAmY7PhCfg==, computed {}",
+					md5
+				)));
+			}
+			r => panic!("unexpected result: {:?}", r),
+		}
+
+		let expected = ExpectedChecksums {
+			sha256: Some(sha256sum(b"world")),
+			..Default::default()
+		};
+		match checksums.verify(&expected) {
+			Err(e @ Error::Sha256Mismatch(..)) => {
+				assert_eq!(e.aws_code(), "XAmzContentSHA256Mismatch");
+				assert!(e.to_string().contains(&hex::encode(sha256sum(b"hello"))));
+			}
+			r => panic!("unexpected result: {:?}", r),
+		}
+	}
+
+	#[test]
+	fn test_sdk_checksum_algorithm() {
+		let mut headers = HeaderMap::new();
+		assert_eq!(request_sdk_checksum_algorithm(&headers).unwrap(), None);
+
+		headers.insert(
+			X_AMZ_SDK_CHECKSUM_ALGORITHM,
+			HeaderValue::from_static("CRC32C"),
+		);
+		let algorithm = request_sdk_checksum_algorithm(&headers).unwrap();
+		assert_eq!(algorithm, Some(ChecksumAlgorithm::Crc32c));
+
+		// the announced checksum is computed without an expected value
+		let expected = ExpectedChecksums {
+			algorithm,
+			..Default::default()
+		};
+		let mut checksummer = Checksummer::init(&expected, false);
+		checksummer.update(b"123456789");
+		let checksums = checksummer.finalize();
+		assert!(checksums.verify(&expected).is_ok());
+		assert_eq!(
+			checksums.extract(algorithm),
+			Some(ChecksumValue::Crc32c(0xe3069283u32.to_be_bytes()))
+		);
+
+		headers.insert(
+			X_AMZ_SDK_CHECKSUM_ALGORITHM,
+			HeaderValue::from_static("MD5"),
+		);
+		match request_sdk_checksum_algorithm(&headers) {
+			Err(e) => {
+				assert_eq!(e.aws_code(), "InvalidRequest");
+				assert!(e
+					.to_string()
+					.contains("unsupported checksum algorithm: MD5"));
+			}
+			r => panic!("unexpected result: {:?}", r),
+		}
+	}
+
+	#[test]
+	fn test_crc64nvme_check_value() {
+		let mut crc = Crc64Nvme::new();
+		crc.update(b"123456789");
+		assert_eq!(crc.finalize(), 0xae8b14860a799888);
+
+		// same result when the input is fed in several pieces
+		let mut checksummer =
+			Checksummer::init(&Default::default(), false).add(Some(ChecksumAlgorithm::Crc64Nvme));
+		checksummer.update(b"1234");
+		checksummer.update(b"");
+		checksummer.update(b"56789");
+		let checksums = checksummer.finalize();
+		assert_eq!(
+			checksums.extract(Some(ChecksumAlgorithm::Crc64Nvme)),
+			Some(ChecksumValue::Crc64Nvme(
+				0xae8b14860a799888u64.to_be_bytes()
+			))
+		);
+		assert!(checksums.md5.is_none());
+		assert!(checksums.sha256.is_none());
+
+		let mut headers = HeaderMap::new();
+		headers.insert(
+			X_AMZ_CHECKSUM_CRC64NVME,
+			HeaderValue::from_static("rosUhgp5mIg="),
+		);
+		let expected = ExpectedChecksums {
+			extra: request_checksum_value(&headers).unwrap(),
+			..Default::default()
+		};
+		assert!(checksums.verify(&expected).is_ok());
+	}
+}
@@ -25,9 +25,13 @@ use crate::s3::api_server::{ReqBody, ResBody};
 use crate::s3::checksum::*;
 use crate::s3::encryption::EncryptionParams;
 use crate::s3::error::*;
-use crate::s3::get::full_object_byte_stream;
+use crate::s3::get::{etag_matches, full_object_byte_stream, is_md5_etag};
 use crate::s3::multipart;
-use crate::s3::put::{get_headers, save_stream, ChecksumMode, SaveStreamResult};
+use crate::s3::put::{
+	check_object_key, check_worm_overwrite, get_headers, next_timestamp, save_stream, ChecksumMode,
+	Durability, SaveStreamResult,
+};
+use crate::s3::tagging::request_object_tags;
 use crate::s3::xml::{self as s3_xml, xmlns_tag};
 
 // -------- CopyObject ---------
@@ -37,6 +41,16 @@ pub async fn handle_copy(
 	req: &Request<ReqBody>,
 	dest_key: &str,
 ) -> Result<Response<ResBody>, Error> {
+	check_object_key(&ctx.garage, dest_key)?;
+	if ctx.bucket_params.options.get().worm {
+		let dest_object = ctx
+			.garage
+			.object_table
+			.get(&ctx.bucket_id, &dest_key.to_string())
+			.await?;
+		check_worm_overwrite(&ctx, dest_object.as_ref())?;
+	}
+
 	let copy_precondition = CopyPreconditionHeaders::parse(req)?;
 
 	let checksum_algorithm = request_checksum_algorithm(req.headers())?;
@@ -69,14 +83,22 @@ pub async fn handle_copy(
 
 	// Determine metadata of destination object
 	let was_multipart = source_version_meta.etag.contains('-');
+	let source_object_meta_inner = source_object_meta_inner.into_owned();
 	let dest_object_meta = ObjectVersionMetaInner {
 		headers: match req.headers().get("x-amz-metadata-directive") {
 			Some(v) if v == hyper::header::HeaderValue::from_static("REPLACE") => {
-				get_headers(req.headers())?
+				get_headers(req.headers(), ctx.garage.config.s3_api.max_metadata_size)?
 			}
-			_ => source_object_meta_inner.into_owned().headers,
+			_ => source_object_meta_inner.headers,
 		},
 		checksum: source_checksum,
+		tags: match req.headers().get("x-amz-tagging-directive") {
+			Some(v) if v == hyper::header::HeaderValue::from_static("REPLACE") => {
+				request_object_tags(req.headers())?
+			}
+			_ => source_object_meta_inner.tags,
+		},
+		checksum_type: source_object_meta_inner.checksum_type,
 	};
 
 	// Do actual object copying
@@ -102,6 +124,7 @@ pub async fn handle_copy(
 	let res = if !must_recopy {
 		// In most cases, we can just copy the metadata and link blocks of the
 		// old object from the new object.
+		let versioned = ctx.bucket_params.versioning_enabled();
 		handle_copy_metaonly(
 			ctx,
 			dest_key,
@@ -110,6 +133,7 @@ pub async fn handle_copy(
 			source_version,
 			source_version_data,
 			source_version_meta,
+			versioned,
 		)
 		.await?
 	} else {
@@ -117,6 +141,8 @@ pub async fn handle_copy(
 			md5: None,
 			sha256: None,
 			extra: source_checksum,
+			algorithm: None,
+			block_hashes: None,
 		};
 		let checksum_mode = if was_multipart || source_checksum_algorithm != checksum_algorithm {
 			ChecksumMode::Calculate(checksum_algorithm)
@@ -156,7 +182,8 @@ pub async fn handle_copy(
 	Ok(resp.body(string_body(xml))?)
 }
 
-async fn handle_copy_metaonly(
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn handle_copy_metaonly(
 	ctx: ReqCtx,
 	dest_key: &str,
 	dest_object_meta: ObjectVersionMetaInner,
@@ -164,134 +191,53 @@ async fn handle_copy_metaonly(
 	source_version: &ObjectVersion,
 	source_version_data: &ObjectVersionData,
 	source_version_meta: &ObjectVersionMeta,
+	versioned: bool,
 ) -> Result<SaveStreamResult, Error> {
 	let ReqCtx {
 		garage,
 		bucket_id: dest_bucket_id,
+		bucket_params: dest_bucket_params,
 		..
 	} = ctx;
 
-	// Generate parameters for copied object
-	let new_uuid = gen_uuid();
-	let new_timestamp = now_msec();
-
 	let new_meta = ObjectVersionMeta {
 		encryption: dest_encryption.encrypt_meta(dest_object_meta)?,
 		size: source_version_meta.size,
 		etag: source_version_meta.etag.clone(),
 	};
+	let res_etag = new_meta.etag.clone();
+	let res_size = new_meta.size;
 
-	let res = SaveStreamResult {
-		version_uuid: new_uuid,
-		version_timestamp: new_timestamp,
-		etag: new_meta.etag.clone(),
-	};
+	// The new version must be more recent than all versions of the
+	// destination object, even if their timestamps are in the future
+	let dest_object = garage
+		.object_table
+		.get(&dest_bucket_id, &dest_key.to_string())
+		.await?;
+	let new_timestamp = next_timestamp(dest_object.as_ref(), &garage.version_clock).await?;
 
-	// Save object copy
-	match source_version_data {
-		ObjectVersionData::DeleteMarker => unreachable!(),
-		ObjectVersionData::Inline(_meta, bytes) => {
-			// bytes is either plaintext before&after or encrypted with the
-			// same keys, so it's ok to just copy it as is
-			let dest_object_version = ObjectVersion {
-				uuid: new_uuid,
-				timestamp: new_timestamp,
-				state: ObjectVersionState::Complete(ObjectVersionData::Inline(
-					new_meta,
-					bytes.clone(),
-				)),
-			};
-			let dest_object = Object::new(
-				dest_bucket_id,
-				dest_key.to_string(),
-				vec![dest_object_version],
-			);
-			garage.object_table.insert(&dest_object).await?;
-		}
-		ObjectVersionData::FirstBlock(_meta, first_block_hash) => {
-			// Get block list from source version
-			let source_version = garage
-				.version_table
-				.get(&source_version.uuid, &EmptyKey)
-				.await?;
-			let source_version = source_version.ok_or(Error::NoSuchKey)?;
-
-			// Write an "uploading" marker in Object table
-			// This holds a reference to the object in the Version table
-			// so that it won't be deleted, e.g. by repair_versions.
-			let tmp_dest_object_version = ObjectVersion {
-				uuid: new_uuid,
-				timestamp: new_timestamp,
-				state: ObjectVersionState::Uploading {
-					encryption: new_meta.encryption.clone(),
-					checksum_algorithm: None,
-					multipart: false,
-				},
-			};
-			let tmp_dest_object = Object::new(
-				dest_bucket_id,
-				dest_key.to_string(),
-				vec![tmp_dest_object_version],
-			);
-			garage.object_table.insert(&tmp_dest_object).await?;
-
-			// Write version in the version table. Even with empty block list,
-			// this means that the BlockRef entries linked to this version cannot be
-			// marked as deleted (they are marked as deleted only if the Version
-			// doesn't exist or is marked as deleted).
-			let mut dest_version = Version::new(
-				new_uuid,
-				VersionBacklink::Object {
-					bucket_id: dest_bucket_id,
-					key: dest_key.to_string(),
-				},
-				false,
-			);
-			garage.version_table.insert(&dest_version).await?;
-
-			// Fill in block list for version and insert block refs
-			for (bk, bv) in source_version.blocks.items().iter() {
-				dest_version.blocks.put(*bk, *bv);
-			}
-			let dest_block_refs = dest_version
-				.blocks
-				.items()
-				.iter()
-				.map(|b| BlockRef {
-					block: b.1.hash,
-					version: new_uuid,
-					deleted: false.into(),
-				})
-				.collect::<Vec<_>>();
-			futures::try_join!(
-				garage.version_table.insert(&dest_version),
-				garage.block_ref_table.insert_many(&dest_block_refs[..]),
-			)?;
-
-			// Insert final object
-			// We do this last because otherwise there is a race condition in the case where
-			// the copy call has the same source and destination (this happens, rclone does
-			// it to update the modification timestamp for instance). If we did this concurrently
-			// with the stuff before, the block's reference counts could be decremented before
-			// they are incremented again for the new version, leading to data being deleted.
-			let dest_object_version = ObjectVersion {
-				uuid: new_uuid,
-				timestamp: new_timestamp,
-				state: ObjectVersionState::Complete(ObjectVersionData::FirstBlock(
-					new_meta,
-					*first_block_hash,
-				)),
-			};
-			let dest_object = Object::new(
-				dest_bucket_id,
-				dest_key.to_string(),
-				vec![dest_object_version],
-			);
-			garage.object_table.insert(&dest_object).await?;
-		}
-	}
+	let dest_object_version = garage
+		.object_helper()
+		.copy_version_data(
+			source_version.uuid,
+			source_version_data,
+			dest_bucket_id,
+			&dest_bucket_params,
+			dest_key,
+			new_timestamp,
+			versioned,
+			new_meta,
+		)
+		.await?;
 
-	Ok(res)
+	Ok(SaveStreamResult {
+		version_uuid: dest_object_version.uuid,
+		version_timestamp: dest_object_version.timestamp,
+		weak_etag: !is_md5_etag(&res_etag),
+		etag: res_etag,
+		size: res_size,
+		stored_size: None,
+	})
 }
 
 async fn handle_copy_reencrypt(
@@ -312,6 +258,7 @@ async fn handle_copy_reencrypt(
 		source_version,
 		source_version_data,
 		source_encryption,
+		false,
 	);
 
 	save_stream(
@@ -319,8 +266,11 @@ async fn handle_copy_reencrypt(
 		dest_object_meta,
 		dest_encryption,
 		source_stream.map_err(|e| Error::from(GarageError::from(e))),
+		ctx.garage.config.block_size,
 		&dest_key.to_string(),
 		checksum_mode,
+		None,
+		Durability::default(),
 	)
 	.await
 }
@@ -344,7 +294,13 @@ pub async fn handle_upload_part_copy(
 		multipart::get_upload(&ctx, &dest_key, &dest_upload_id)
 	)?;
 
-	let ReqCtx { garage, .. } = ctx;
+	let ReqCtx {
+		garage,
+		bucket_id: dest_bucket_id,
+		bucket_params: dest_bucket_params,
+		..
+	} = ctx;
+	let allow_degraded_writes = dest_bucket_params.options.get().allow_degraded_writes;
 
 	let (source_object_version, source_version_data, source_version_meta) =
 		extract_source_info(&source_object)?;
@@ -391,7 +347,10 @@ pub async fn handle_upload_part_copy(
 	};
 
 	// Check source version is not inlined
-	if matches!(source_version_data, ObjectVersionData::Inline(_, _)) {
+	if matches!(
+		source_version_data,
+		ObjectVersionData::Inline(_, _) | ObjectVersionData::InlineRef(_, _)
+	) {
 		// This is only for small files, we don't bother handling this.
 		// (in AWS UploadPartCopy works for parts at least 5MB which
 		// is never the case of an inline object)
@@ -498,7 +457,12 @@ pub async fn handle_upload_part_copy(
 			let garage3 = garage2.clone();
 			async move {
 				let stream = source_encryption
-					.get_block(&garage3, &block_hash, Some(order_stream.order(i as u64)))
+					.get_block(
+						&garage3,
+						&block_hash,
+						Some(order_stream.order(i as u64)),
+						false,
+					)
 					.await?;
 				let data = read_stream_to_end(stream).await?.into_bytes();
 				// For each item, we return a tuple of:
@@ -583,6 +547,7 @@ pub async fn handle_upload_part_copy(
 			block: final_hash,
 			version: dest_version_id,
 			deleted: false.into(),
+			bucket_id: Some(dest_bucket_id),
 		};
 
 		let (_, _, _, next) = futures::try_join!(
@@ -592,8 +557,16 @@ pub async fn handle_upload_part_copy(
 				if let Some(final_data) = data_to_upload {
 					garage
 						.block_manager
-						.rpc_put_block(final_hash, final_data, dest_encryption.is_encrypted(), None)
+						.rpc_put_block(
+							final_hash,
+							final_data,
+							dest_encryption.is_encrypted(),
+							None,
+							allow_degraded_writes,
+							false,
+						)
 						.await
+						.map(|_| ())
 				} else {
 					Ok(())
 				}
@@ -630,7 +603,7 @@ pub async fn handle_upload_part_copy(
 	let resp_xml = s3_xml::to_xml_with_header(&CopyPartResult {
 		xmlns: (),
 		etag: s3_xml::Value(format!("\"{}\"", etag)),
-		last_modified: s3_xml::Value(msec_to_rfc3339(source_object_version.timestamp)),
+		last_modified: s3_xml::Value(msec_to_rfc3339(source_object_version.last_modified())),
 	})?;
 
 	let mut resp = Response::builder()
@@ -665,10 +638,22 @@ async fn get_copy_source(ctx: &ReqCtx, req: &Request<ReqBody>) -> Result<Object,
 	}
 
 	let source_key = source_key.ok_or_bad_request("No source key specified")?;
+	let source_bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(source_bucket_id)
+		.await?;
+	let source_key = source_bucket
+		.state
+		.as_option()
+		.unwrap()
+		.options
+		.get()
+		.key_normalization
+		.normalize(source_key);
 
 	let source_object = garage
 		.object_table
-		.get(&source_bucket_id, &source_key.to_string())
+		.get(&source_bucket_id, &source_key)
 		.await?
 		.ok_or(Error::NoSuchKey)?;
 
@@ -695,6 +680,7 @@ fn extract_source_info(
 			return Err(Error::NoSuchKey);
 		}
 		ObjectVersionData::Inline(meta, _bytes) => meta,
+		ObjectVersionData::InlineRef(meta, _uuid) => meta,
 		ObjectVersionData::FirstBlock(meta, _fbh) => meta,
 	};
 
@@ -716,11 +702,7 @@ impl CopyPreconditionHeaders {
 				.get("x-amz-copy-source-if-match")
 				.map(|x| x.to_str())
 				.transpose()?
-				.map(|x| {
-					x.split(',')
-						.map(|m| m.trim().trim_matches('"').to_string())
-						.collect::<Vec<_>>()
-				}),
+				.map(|x| x.split(',').map(str::to_string).collect::<Vec<_>>()),
 			copy_source_if_modified_since: req
 				.headers()
 				.get("x-amz-copy-source-if-modified-since")
@@ -734,11 +716,7 @@ impl CopyPreconditionHeaders {
 				.get("x-amz-copy-source-if-none-match")
 				.map(|x| x.to_str())
 				.transpose()?
-				.map(|x| {
-					x.split(',')
-						.map(|m| m.trim().trim_matches('"').to_string())
-						.collect::<Vec<_>>()
-				}),
+				.map(|x| x.split(',').map(str::to_string).collect::<Vec<_>>()),
 			copy_source_if_unmodified_since: req
 				.headers()
 				.get("x-amz-copy-source-if-unmodified-since")
@@ -751,7 +729,7 @@ impl CopyPreconditionHeaders {
 	}
 
 	fn check(&self, v: &ObjectVersion, etag: &str) -> Result<(), Error> {
-		let v_date = UNIX_EPOCH + Duration::from_millis(v.timestamp);
+		let v_date = UNIX_EPOCH + Duration::from_millis(v.last_modified());
 
 		let ok = match (
 			&self.copy_source_if_match,
@@ -766,15 +744,13 @@ impl CopyPreconditionHeaders {
 			// because in the spec it says that if if-match evaluates to
 			// true but if-unmodified-since evaluates to false,
 			// the copy is still done.
-			(Some(im), _, None, None) => im.iter().any(|x| x == etag || x == "*"),
+			(Some(im), _, None, None) => etag_in_list(im, etag),
 			(None, Some(ius), None, None) => v_date <= *ius,
 
 			// If we have both if-none-match and if-modified-since,
 			// then both of the two conditions must evaluate to true
-			(None, None, Some(inm), Some(ims)) => {
-				!inm.iter().any(|x| x == etag || x == "*") && v_date > *ims
-			}
-			(None, None, Some(inm), None) => !inm.iter().any(|x| x == etag || x == "*"),
+			(None, None, Some(inm), Some(ims)) => !etag_in_list(inm, etag) && v_date > *ims,
+			(None, None, Some(inm), None) => !etag_in_list(inm, etag),
 			(None, None, None, Some(ims)) => v_date > *ims,
 			(None, None, None, None) => true,
 			_ => {
@@ -792,6 +768,11 @@ impl CopyPreconditionHeaders {
 	}
 }
 
+fn etag_in_list(list: &[String], etag: &str) -> bool {
+	list.iter()
+		.any(|x| x.trim().trim_matches('"') == "*" || etag_matches(etag, x))
+}
+
 type BlockStreamItemOk = (Bytes, Option<Hash>);
 type BlockStreamItem = Result<BlockStreamItemOk, garage_util::error::Error>;
 
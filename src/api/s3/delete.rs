@@ -1,28 +1,53 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
 use http_body_util::BodyExt;
-use hyper::{Request, Response, StatusCode};
+use hyper::header::HeaderName;
+use hyper::{HeaderMap, Request, Response, StatusCode};
 
 use garage_util::data::*;
+use garage_util::time::*;
 
+use garage_model::bucket_table::KeyNormalization;
 use garage_model::s3::object_table::*;
 
 use crate::helpers::*;
 use crate::s3::api_server::{ReqBody, ResBody};
 use crate::s3::error::*;
-use crate::s3::put::next_timestamp;
+use crate::s3::get::etag_matches;
+use crate::s3::put::{check_object_key, next_timestamp};
 use crate::s3::xml as s3_xml;
 use crate::signature::verify_signed_content;
 
-async fn handle_delete_internal(ctx: &ReqCtx, key: &str) -> Result<(Uuid, Uuid), Error> {
+async fn handle_delete_internal(
+	ctx: &ReqCtx,
+	key: &str,
+	condition: &DeleteCondition,
+) -> Result<(Uuid, Uuid), Error> {
+	check_object_key(&ctx.garage, key)?;
+
 	let ReqCtx {
-		garage, bucket_id, ..
+		garage,
+		bucket_id,
+		bucket_params,
+		..
 	} = ctx;
-	let object = garage
-		.object_table
-		.get(bucket_id, &key.to_string())
-		.await?
-		.ok_or(Error::NoSuchKey)?; // No need to delete
+	let object = match garage.object_table.get(bucket_id, &key.to_string()).await? {
+		Some(object) => object,
+		// A missing object can't match the expected version
+		None if !condition.is_empty() => return Err(Error::PreconditionFailed),
+		None => return Err(Error::NoSuchKey), // No need to delete
+	};
+
+	// In strict mode, an object whose versions are all delete markers
+	// (or aborted uploads) does not exist either
+	if bucket_params.options.get().strict_delete_missing
+		&& !object.versions().iter().any(|v| v.is_data())
+	{
+		return Err(Error::NoSuchKey);
+	}
 
-	let del_timestamp = next_timestamp(Some(&object));
+	let del_timestamp = next_timestamp(Some(&object), &garage.version_clock).await?;
 	let del_uuid = gen_uuid();
 
 	let deleted_version = object
@@ -31,6 +56,9 @@ async fn handle_delete_internal(ctx: &ReqCtx, key: &str) -> Result<(Uuid, Uuid),
 		.rev()
 		.find(|v| !matches!(&v.state, ObjectVersionState::Aborted))
 		.or_else(|| object.versions().iter().rev().next());
+	if !condition.is_empty() && !deleted_version.is_some_and(|v| condition.matches(v)) {
+		return Err(Error::PreconditionFailed);
+	}
 	let deleted_version = match deleted_version {
 		Some(dv) => dv.uuid,
 		None => {
@@ -45,6 +73,8 @@ async fn handle_delete_internal(ctx: &ReqCtx, key: &str) -> Result<(Uuid, Uuid),
 		vec![ObjectVersion {
 			uuid: del_uuid,
 			timestamp: del_timestamp,
+			created_at: Some(now_msec()),
+			versioned: bucket_params.versioning_enabled(),
 			state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
 		}],
 	);
@@ -54,8 +84,27 @@ async fn handle_delete_internal(ctx: &ReqCtx, key: &str) -> Result<(Uuid, Uuid),
 	Ok((deleted_version, del_uuid))
 }
 
-pub async fn handle_delete(ctx: ReqCtx, key: &str) -> Result<Response<ResBody>, Error> {
-	match handle_delete_internal(&ctx, key).await {
+const X_AMZ_MFA: &str = "x-amz-mfa";
+const X_GARAGE_ABORT_ON_ERROR_THRESHOLD: HeaderName =
+	HeaderName::from_static("x-garage-abort-on-error-threshold");
+const X_GARAGE_UNPROCESSED_KEYS: HeaderName = HeaderName::from_static("x-garage-unprocessed-keys");
+
+pub async fn handle_delete(
+	ctx: ReqCtx,
+	req: &Request<ReqBody>,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<ResBody>, Error> {
+	check_mfa_delete(&ctx, req.headers())?;
+	check_worm_delete(&ctx)?;
+	if version_id.is_some() {
+		return Err(version_deletion_not_implemented());
+	}
+
+	match handle_delete_internal(&ctx, key, &DeleteCondition::default()).await {
+		Err(Error::NoSuchKey) if ctx.bucket_params.options.get().strict_delete_missing => {
+			Err(Error::NoSuchKey)
+		}
 		Ok(_) | Err(Error::NoSuchKey) => Ok(Response::builder()
 			.status(StatusCode::NO_CONTENT)
 			.body(empty_body())
@@ -69,28 +118,70 @@ pub async fn handle_delete_objects(
 	req: Request<ReqBody>,
 	content_sha256: Option<Hash>,
 ) -> Result<Response<ResBody>, Error> {
+	check_mfa_delete(&ctx, req.headers())?;
+	check_worm_delete(&ctx)?;
+	let error_threshold = request_error_threshold(req.headers())?;
+
 	let body = BodyExt::collect(req.into_body()).await?.to_bytes();
 
 	if let Some(content_sha256) = content_sha256 {
 		verify_signed_content(content_sha256, &body[..])?;
 	}
 
+	let bucket_options = ctx.bucket_params.options.get();
+	let key_normalization = bucket_options.key_normalization;
+
 	let cmd_xml = roxmltree::Document::parse(std::str::from_utf8(&body)?)?;
-	let cmd = parse_delete_objects_xml(&cmd_xml).ok_or_bad_request("Invalid delete XML query")?;
+	let cmd = parse_delete_objects_xml(&cmd_xml, key_normalization)
+		.ok_or_else(|| Error::InvalidXml("Invalid delete XML query".into()))?;
 
 	let mut ret_deleted = Vec::new();
 	let mut ret_errors = Vec::new();
 
-	for obj in cmd.objects.iter() {
-		match handle_delete_internal(&ctx, &obj.key).await {
+	let strict_delete_missing = bucket_options.strict_delete_missing;
+	let mut unprocessed = 0;
+	for (i, obj) in cmd.objects.iter().enumerate() {
+		// Stop once the client's error threshold is reached, leaving
+		// the remaining keys as they are
+		if error_threshold.is_some_and(|t| ret_errors.len() >= t) {
+			unprocessed = cmd.objects.len() - i;
+			break;
+		}
+
+		if obj.version_id.is_some() {
+			let e = version_deletion_not_implemented();
+			ret_errors.push(s3_xml::DeleteError {
+				code: s3_xml::Value(e.aws_code().to_string()),
+				key: Some(s3_xml::Value(obj.key.clone())),
+				message: s3_xml::Value(format!("{}", e)),
+				version_id: obj.version_id.clone().map(s3_xml::Value),
+			});
+			continue;
+		}
+
+		let key = key_normalization.normalize(&obj.key);
+		match handle_delete_internal(&ctx, &key, &obj.condition).await {
 			Ok((deleted_version, delete_marker_version)) => {
 				if cmd.quiet {
 					continue;
 				}
 				ret_deleted.push(s3_xml::Deleted {
 					key: s3_xml::Value(obj.key.clone()),
-					version_id: s3_xml::Value(hex::encode(deleted_version)),
-					delete_marker_version_id: s3_xml::Value(hex::encode(delete_marker_version)),
+					version_id: Some(s3_xml::Value(hex::encode(deleted_version))),
+					delete_marker_version_id: Some(s3_xml::Value(hex::encode(
+						delete_marker_version,
+					))),
+				});
+			}
+			Err(Error::NoSuchKey) if !strict_delete_missing => {
+				// Deleting a key that doesn't exist is a success, as for DeleteObject
+				if cmd.quiet {
+					continue;
+				}
+				ret_deleted.push(s3_xml::Deleted {
+					key: s3_xml::Value(obj.key.clone()),
+					version_id: None,
+					delete_marker_version_id: None,
 				});
 			}
 			Err(e) => {
@@ -110,9 +201,55 @@ pub async fn handle_delete_objects(
 		errors: ret_errors,
 	})?;
 
-	Ok(Response::builder()
-		.header("Content-Type", "application/xml")
-		.body(string_body(xml))?)
+	let mut resp = Response::builder().header("Content-Type", "application/xml");
+	if unprocessed > 0 {
+		resp = resp.header(X_GARAGE_UNPROCESSED_KEYS, unprocessed.to_string());
+	}
+	Ok(resp.body(string_body(xml))?)
+}
+
+/// Get the number of errors after which a DeleteObjects request stops
+/// processing keys, as given in the x-garage-abort-on-error-threshold header
+fn request_error_threshold(headers: &HeaderMap) -> Result<Option<usize>, Error> {
+	headers
+		.get(X_GARAGE_ABORT_ON_ERROR_THRESHOLD)
+		.map(|v| {
+			v.to_str()
+				.ok()
+				.and_then(|x| x.parse::<usize>().ok())
+				.filter(|x| *x > 0)
+				.ok_or_bad_request("Invalid x-garage-abort-on-error-threshold header")
+		})
+		.transpose()
+		.map_err(Error::from)
+}
+
+/// Deleting a specific version of an object is not supported: versions
+/// are only removed when they are replaced by a newer version
+fn version_deletion_not_implemented() -> Error {
+	Error::NotImplemented("Deleting a specific version of an object is not supported".into())
+}
+
+/// Check that a delete request includes the x-amz-mfa header, if the bucket
+/// requires one. The MFA code itself is not verified: only its presence is.
+fn check_mfa_delete(ctx: &ReqCtx, headers: &HeaderMap) -> Result<(), Error> {
+	if ctx.bucket_params.options.get().mfa_delete_required && !headers.contains_key(X_AMZ_MFA) {
+		return Err(Error::forbidden(
+			"MFA delete is enabled for this bucket: the x-amz-mfa header is required",
+		));
+	}
+	Ok(())
+}
+
+/// Check that objects can be deleted from the bucket, i.e. that it is not
+/// a write-once bucket
+fn check_worm_delete(ctx: &ReqCtx) -> Result<(), Error> {
+	if ctx.bucket_params.options.get().worm {
+		return Err(Error::forbidden(
+			"This bucket is write-once: objects cannot be deleted",
+		));
+	}
+	Ok(())
 }
 
 struct DeleteRequest {
@@ -122,28 +259,93 @@ struct DeleteRequest {
 
 struct DeleteObject {
 	key: String,
+	version_id: Option<String>,
+	condition: DeleteCondition,
 }
 
-fn parse_delete_objects_xml(xml: &roxmltree::Document) -> Option<DeleteRequest> {
+/// Version that an object must have for a DeleteObjects request to delete it,
+/// as given by the optional ETag and LastModifiedTime elements of the object
+#[derive(Default)]
+struct DeleteCondition {
+	etag: Option<String>,
+	/// Timestamp in milliseconds, only compared to the second
+	last_modified: Option<u64>,
+}
+
+impl DeleteCondition {
+	fn is_empty(&self) -> bool {
+		self.etag.is_none() && self.last_modified.is_none()
+	}
+
+	/// Check whether the current version of an object is the expected one.
+	/// A delete marker never matches.
+	fn matches(&self, version: &ObjectVersion) -> bool {
+		let meta = match &version.state {
+			ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+			| ObjectVersionState::Complete(ObjectVersionData::InlineRef(meta, _))
+			| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => meta,
+			_ => return false,
+		};
+		let etag_ok = self
+			.etag
+			.as_ref()
+			.map_or(true, |etag| etag_matches(&meta.etag, etag));
+		let last_modified_ok = self
+			.last_modified
+			.map_or(true, |t| t / 1000 == version.last_modified() / 1000);
+		etag_ok && last_modified_ok
+	}
+}
+
+fn parse_delete_objects_xml(
+	xml: &roxmltree::Document,
+	key_normalization: KeyNormalization,
+) -> Option<DeleteRequest> {
 	let mut ret = DeleteRequest {
 		quiet: false,
 		objects: vec![],
 	};
 
-	let root = xml.root();
-	let delete = root.first_child()?;
+	let delete = xml.root_element();
 
 	if !delete.has_tag_name("Delete") {
 		return None;
 	}
 
-	for item in delete.children() {
+	// The same key may be listed several times, possibly with spellings
+	// that are normalized to the same key in the bucket, in which case
+	// it is deleted (and reported) only once
+	let mut seen_keys = HashSet::new();
+
+	for item in delete.children().filter(|n| n.is_element()) {
 		if item.has_tag_name("Object") {
 			let key = item.children().find(|e| e.has_tag_name("Key"))?;
 			let key_str = key.text()?;
-			ret.objects.push(DeleteObject {
-				key: key_str.to_string(),
-			});
+			let version_id = match item.children().find(|e| e.has_tag_name("VersionId")) {
+				Some(v) => Some(v.text()?.to_string()),
+				None => None,
+			};
+			let etag = match item.children().find(|e| e.has_tag_name("ETag")) {
+				Some(etag) => Some(etag.text()?.to_string()),
+				None => None,
+			};
+			let last_modified = match item.children().find(|e| e.has_tag_name("LastModifiedTime")) {
+				Some(t) => {
+					let t = chrono::DateTime::parse_from_rfc3339(t.text()?.trim()).ok()?;
+					Some(u64::try_from(t.timestamp_millis()).ok()?)
+				}
+				None => None,
+			};
+			if seen_keys.insert((key_normalization.normalize(key_str), version_id.clone())) {
+				ret.objects.push(DeleteObject {
+					key: key_str.to_string(),
+					version_id,
+					condition: DeleteCondition {
+						etag,
+						last_modified,
+					},
+				});
+			}
 		} else if item.has_tag_name("Quiet") {
 			if item.text()? == "true" {
 				ret.quiet = true;
@@ -157,3 +359,136 @@ fn parse_delete_objects_xml(xml: &roxmltree::Document) -> Option<DeleteRequest>
 
 	Some(ret)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_delete_objects_duplicate_keys() {
+		let message = concat!(
+			r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+			r#"<Delete xmlns="http://s3.amazonaws.com/doc/2006-03-01/">"#,
+			"<Object><Key>a</Key></Object>",
+			"<Object><Key>b</Key></Object>",
+			"<Object><Key>a</Key></Object>",
+			"<Object><Key>a</Key><VersionId>0123</VersionId></Object>",
+			"<Quiet>false</Quiet>",
+			"</Delete>"
+		);
+		let xml = roxmltree::Document::parse(message).unwrap();
+		let cmd = parse_delete_objects_xml(&xml, KeyNormalization::None).unwrap();
+		let keys = cmd
+			.objects
+			.iter()
+			.map(|o| (o.key.as_str(), o.version_id.as_deref()))
+			.collect::<Vec<_>>();
+		assert_eq!(keys, vec![("a", None), ("b", None), ("a", Some("0123"))]);
+		assert!(!cmd.quiet);
+
+		// Keys are compared once normalized as in the bucket
+		let message = concat!(
+			"<Delete>",
+			"<Object><Key>Foo</Key></Object>",
+			"<Object><Key>foo</Key></Object>",
+			"<Object><Key>bar</Key></Object>",
+			"</Delete>"
+		);
+		let xml = roxmltree::Document::parse(message).unwrap();
+		for (normalization, expected) in [
+			(KeyNormalization::None, vec!["Foo", "foo", "bar"]),
+			(KeyNormalization::Lowercase, vec!["Foo", "bar"]),
+		] {
+			let cmd = parse_delete_objects_xml(&xml, normalization).unwrap();
+			let keys = cmd
+				.objects
+				.iter()
+				.map(|o| o.key.as_str())
+				.collect::<Vec<_>>();
+			assert_eq!(keys, expected);
+		}
+	}
+
+	#[test]
+	fn test_parse_delete_objects_conditions() {
+		let message = concat!(
+			"<Delete>",
+			"<Object><Key>a</Key><ETag>\"0123\"</ETag></Object>",
+			"<Object><Key>b</Key><LastModifiedTime>2024-05-06T07:08:09.500Z</LastModifiedTime></Object>",
+			"<Object><Key>c</Key></Object>",
+			"</Delete>"
+		);
+		let xml = roxmltree::Document::parse(message).unwrap();
+		let cmd = parse_delete_objects_xml(&xml, KeyNormalization::None).unwrap();
+		assert_eq!(cmd.objects[0].condition.etag.as_deref(), Some("\"0123\""));
+		assert_eq!(cmd.objects[0].condition.last_modified, None);
+		assert_eq!(cmd.objects[1].condition.etag, None);
+		assert_eq!(cmd.objects[1].condition.last_modified, Some(1714979289500));
+		assert!(cmd.objects[2].condition.is_empty());
+
+		let version = ObjectVersion {
+			uuid: gen_uuid(),
+			timestamp: 1,
+			created_at: Some(1714979289000),
+			versioned: false,
+			state: ObjectVersionState::Complete(ObjectVersionData::FirstBlock(
+				ObjectVersionMeta {
+					encryption: ObjectVersionEncryption::Plaintext {
+						inner: ObjectVersionMetaInner {
+							headers: vec![],
+							checksum: None,
+							tags: Default::default(),
+							checksum_type: Default::default(),
+						},
+					},
+					size: 100,
+					etag: "0123".into(),
+				},
+				Hash::from([0u8; 32]),
+			)),
+		};
+		assert!(cmd.objects[0].condition.matches(&version));
+		assert!(cmd.objects[1].condition.matches(&version));
+		let other = DeleteCondition {
+			etag: Some("4567".into()),
+			last_modified: None,
+		};
+		assert!(!other.matches(&version));
+
+		let message = "<Delete><Object><Key>a</Key><LastModifiedTime>yesterday</LastModifiedTime></Object></Delete>";
+		let xml = roxmltree::Document::parse(message).unwrap();
+		assert!(parse_delete_objects_xml(&xml, KeyNormalization::None).is_none());
+	}
+
+	#[test]
+	fn test_parse_delete_objects_empty() {
+		for message in [
+			"<Delete></Delete>",
+			"<Delete/>",
+			"<?xml version=\"1.0\"?>\n<Delete>\n  <Quiet>true</Quiet>\n</Delete>\n",
+		] {
+			let xml = roxmltree::Document::parse(message).unwrap();
+			let cmd = parse_delete_objects_xml(&xml, KeyNormalization::None).unwrap();
+			assert!(cmd.objects.is_empty());
+		}
+	}
+
+	#[test]
+	fn test_parse_delete_objects_malformed() {
+		let err = Error::from(roxmltree::Document::parse("<Delete><Object></Delete>").unwrap_err());
+		assert_eq!(err.aws_code(), "MalformedXML");
+		assert!(matches!(err, Error::InvalidXml(msg) if msg.ends_with("at 1:17")));
+
+		let err = Error::from(roxmltree::Document::parse("<Delete>").unwrap_err());
+		assert!(matches!(err, Error::InvalidXml(msg) if msg.ends_with("never closed at 1:1")));
+
+		for message in [
+			"<Remove></Remove>",
+			"<Delete><Object></Object></Delete>",
+			"<Delete><Thing/></Delete>",
+		] {
+			let xml = roxmltree::Document::parse(message).unwrap();
+			assert!(parse_delete_objects_xml(&xml, KeyNormalization::None).is_none());
+		}
+	}
+}
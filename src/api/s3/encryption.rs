@@ -4,7 +4,7 @@ use std::pin::Pin;
 
 use aes_gcm::{
 	aead::stream::{DecryptorLE31, EncryptorLE31, StreamLE31},
-	aead::{Aead, AeadCore, KeyInit, OsRng},
+	aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
 	aes::cipher::crypto_common::rand_core::RngCore,
 	aes::cipher::typenum::Unsigned,
 	Aes256Gcm, Key, Nonce,
@@ -32,6 +32,10 @@ use crate::common_error::*;
 use crate::s3::checksum::Md5Checksum;
 use crate::s3::error::Error;
 
+/// Prefix of etags derived from the blake2 hash of the object's data,
+/// to distinguish them from the usual md5-based etags
+pub const BLAKE2_ETAG_PREFIX: &str = "b2-";
+
 const X_AMZ_SERVER_SIDE_ENCRYPTION_CUSTOMER_ALGORITHM: HeaderName =
 	HeaderName::from_static("x-amz-server-side-encryption-customer-algorithm");
 const X_AMZ_SERVER_SIDE_ENCRYPTION_CUSTOMER_KEY: HeaderName =
@@ -59,6 +63,13 @@ type StreamNonceSize = aes_gcm::aead::stream::NonceSize<Aes256Gcm, StreamLE31<Ae
 const STREAM_ENC_PLAIN_CHUNK_SIZE: usize = 0x1000; // 4096 bytes
 const STREAM_ENC_CYPER_CHUNK_SIZE: usize = STREAM_ENC_PLAIN_CHUNK_SIZE + 16;
 
+// Encrypted blobs (object metadata and inline data) are prefixed with the
+// version of their format, which is also authenticated as associated data.
+// Blobs written before versions were introduced are made of the nonce followed
+// by the ciphertext, and can only be told apart from newer blobs by failing to
+// decrypt them as such. DO NOT REUSE VERSION NUMBERS.
+const ENCRYPTED_BLOB_VERSION: u8 = 1;
+
 #[derive(Clone, Copy)]
 pub enum EncryptionParams {
 	Plaintext,
@@ -106,6 +117,10 @@ impl EncryptionParams {
 		}
 	}
 
+	/// Add the headers describing the encryption of an object to a response.
+	/// As in AWS S3, SSE-C objects get the algorithm and key md5 echoed back.
+	/// Garage has no server-managed keys, so `x-amz-server-side-encryption`
+	/// is never returned: plaintext objects get no encryption header at all.
 	pub fn add_response_headers(&self, resp: &mut http::response::Builder) {
 		if let Self::SseC { client_key_md5, .. } = self {
 			let md5 = BASE64_STANDARD.encode(&client_key_md5);
@@ -211,19 +226,31 @@ impl EncryptionParams {
 			Self::Plaintext => md5sum
 				.map(|x| hex::encode(&x[..]))
 				.expect("md5 digest should have been computed"),
-			Self::SseC { .. } => {
-				// AWS specifies that for encrypted objects, the Etag is not
-				// the md5sum of the data, but doesn't say what it is.
-				// So we just put some random bytes.
-				let mut random = [0u8; 16];
-				OsRng.fill_bytes(&mut random);
-				hex::encode(&random)
-			}
+			Self::SseC { .. } => Self::random_etag(),
+		}
+	}
+
+	/// Alternative etag derivation, for buckets where the blake2_etag option
+	/// is set. Etags are opaque strings in S3 so this is allowed, but clients
+	/// that check that the etag of an object is its md5 will reject them.
+	pub fn etag_from_blake2(&self, blake2sum: &Hash) -> String {
+		match self {
+			Self::Plaintext => format!("{}{}", BLAKE2_ETAG_PREFIX, hex::encode(blake2sum)),
+			Self::SseC { .. } => Self::random_etag(),
 		}
 	}
 
+	fn random_etag() -> String {
+		// AWS specifies that for encrypted objects, the Etag is not
+		// the md5sum of the data, but doesn't say what it is.
+		// So we just put some random bytes.
+		let mut random = [0u8; 16];
+		OsRng.fill_bytes(&mut random);
+		hex::encode(random)
+	}
+
 	// ---- generic function for encrypting / decrypting blobs ----
-	// Prepends a randomly-generated nonce to the encrypted value.
+	// Prepends the format version and a randomly-generated nonce to the encrypted value.
 	// This is used for encrypting object metadata and inlined data for small objects.
 	// This does not compress anything.
 
@@ -232,29 +259,37 @@ impl EncryptionParams {
 			Self::SseC { client_key, .. } => {
 				let cipher = Aes256Gcm::new(&client_key);
 				let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+				let payload = Payload {
+					msg: blob,
+					aad: &[ENCRYPTED_BLOB_VERSION],
+				};
 				let ciphertext = cipher
-					.encrypt(&nonce, blob)
+					.encrypt(&nonce, payload)
 					.ok_or_internal_error("Encryption failed")?;
-				Ok(Cow::Owned([nonce.to_vec(), ciphertext].concat()))
+				Ok(Cow::Owned(
+					[&[ENCRYPTED_BLOB_VERSION][..], &nonce, &ciphertext].concat(),
+				))
 			}
 			Self::Plaintext => Ok(Cow::Borrowed(blob)),
 		}
 	}
 
+	/// Decrypt a blob written by `encrypt_blob`, in the current format
+	/// or in the format of older versions of Garage
 	pub fn decrypt_blob<'a>(&self, blob: &'a [u8]) -> Result<Cow<'a, [u8]>, Error> {
 		match self {
 			Self::SseC { client_key, .. } => {
 				let cipher = Aes256Gcm::new(&client_key);
-				let nonce_size = <Aes256Gcm as AeadCore>::NonceSize::to_usize();
-				let nonce = Nonce::from_slice(
-					blob.get(..nonce_size)
-						.ok_or_internal_error("invalid encrypted data")?,
-				);
-				let plaintext = cipher
-					.decrypt(nonce, &blob[nonce_size..])
-					.ok_or_bad_request(
-						"Invalid encryption key, could not decrypt object metadata.",
-					)?;
+				let plaintext = match blob.split_first() {
+					Some((&ENCRYPTED_BLOB_VERSION, rest)) => {
+						decrypt_nonce_blob(&cipher, rest, &[ENCRYPTED_BLOB_VERSION]).ok()
+					}
+					_ => None,
+				};
+				let plaintext = match plaintext {
+					Some(plaintext) => plaintext,
+					None => decrypt_nonce_blob(&cipher, blob, &[])?,
+				};
 				Ok(Cow::Owned(plaintext))
 			}
 			Self::Plaintext => Ok(Cow::Borrowed(blob)),
@@ -265,16 +300,26 @@ impl EncryptionParams {
 
 	/// Get a data block from the storage node, and decrypt+decompress it
 	/// if necessary. If object is plaintext, just get it without any processing.
+	/// If `verify` is set, the raw block data is checked against its hash
+	/// as it is being streamed.
 	pub async fn get_block(
 		&self,
 		garage: &Garage,
 		hash: &Hash,
 		order: Option<OrderTag>,
+		verify: bool,
 	) -> Result<ByteStream, GarageError> {
-		let raw_block = garage
-			.block_manager
-			.rpc_get_block_streaming(hash, order)
-			.await?;
+		let raw_block = if verify {
+			garage
+				.block_manager
+				.rpc_get_block_streaming_verified(hash, order)
+				.await?
+		} else {
+			garage
+				.block_manager
+				.rpc_get_block_streaming(hash, order)
+				.await?
+		};
 		Ok(self.decrypt_block_stream(raw_block))
 	}
 
@@ -419,6 +464,24 @@ fn parse_request_headers(
 	}
 }
 
+/// Decrypt a blob made of a nonce followed by the ciphertext,
+/// which was encrypted with the given associated data
+fn decrypt_nonce_blob(cipher: &Aes256Gcm, blob: &[u8], aad: &[u8]) -> Result<Vec<u8>, Error> {
+	let nonce_size = <Aes256Gcm as AeadCore>::NonceSize::to_usize();
+	let nonce = Nonce::from_slice(
+		blob.get(..nonce_size)
+			.ok_or_internal_error("invalid encrypted data")?,
+	);
+	let payload = Payload {
+		msg: &blob[nonce_size..],
+		aad,
+	};
+	cipher
+		.decrypt(nonce, payload)
+		.ok_or_bad_request("Invalid encryption key, could not decrypt object metadata.")
+		.map_err(Error::from)
+}
+
 // ---- encrypt & decrypt streams ----
 
 #[pin_project::pin_project]
@@ -592,4 +655,51 @@ mod tests {
 	async fn test_encrypt_block_compressed() {
 		test_block_enc(Some(1)).await
 	}
+
+	#[test]
+	fn test_decrypt_legacy_blob() {
+		let client_key = Aes256Gcm::generate_key(&mut OsRng);
+		let enc = EncryptionParams::SseC {
+			client_key,
+			client_key_md5: Default::default(), // not needed
+			compression_level: None,
+		};
+		let meta = ObjectVersionMetaInner {
+			headers: vec![("content-type".into(), "text/plain".into())],
+			checksum: None,
+			tags: Default::default(),
+			checksum_type: Default::default(),
+		};
+		let plaintext = meta.encode().unwrap();
+
+		// Blobs are written in the current format
+		let blob = enc.encrypt_blob(&plaintext).unwrap();
+		assert_eq!(blob[0], ENCRYPTED_BLOB_VERSION);
+		assert_eq!(enc.decrypt_blob(&blob).unwrap(), &plaintext[..]);
+
+		// Blobs written by older versions have no version byte, and can
+		// still be read whatever the first byte of their nonce is
+		for first_byte in [0, ENCRYPTED_BLOB_VERSION, 0xff] {
+			let cipher = Aes256Gcm::new(&client_key);
+			let mut nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+			nonce[0] = first_byte;
+			let ciphertext = cipher.encrypt(&nonce, &plaintext[..]).unwrap();
+			let legacy_blob = [nonce.to_vec(), ciphertext].concat();
+
+			let decrypted = enc.decrypt_blob(&legacy_blob).unwrap();
+			assert_eq!(
+				ObjectVersionMetaInner::decode(&decrypted),
+				Some(meta.clone())
+			);
+		}
+
+		// Blobs can't be decrypted with another key, in either format
+		let other = EncryptionParams::SseC {
+			client_key: Aes256Gcm::generate_key(&mut OsRng),
+			client_key_md5: Default::default(),
+			compression_level: None,
+		};
+		assert!(other.decrypt_blob(&blob).is_err());
+		assert!(other.decrypt_blob(&blob[1..]).is_err());
+	}
 }
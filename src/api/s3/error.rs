@@ -4,6 +4,8 @@ use err_derive::Error;
 use hyper::header::HeaderValue;
 use hyper::{HeaderMap, StatusCode};
 
+use garage_util::data::Uuid;
+
 use crate::common_error::CommonError;
 pub use crate::common_error::{CommonErrorDerivative, OkOrBadRequest, OkOrInternalError};
 use crate::generic_server::ApiError;
@@ -27,6 +29,14 @@ pub enum Error {
 	#[error(display = "Key not found")]
 	NoSuchKey,
 
+	/// The version of the object requested doesn't exist
+	#[error(display = "Version not found")]
+	NoSuchVersion,
+
+	/// The latest version of the object requested is a delete marker
+	#[error(display = "Key not found (deleted)")]
+	DeleteMarker(Uuid),
+
 	/// The multipart upload requested don't exists
 	#[error(display = "Upload not found")]
 	NoSuchUpload,
@@ -35,6 +45,20 @@ pub enum Error {
 	#[error(display = "At least one of the preconditions you specified did not hold")]
 	PreconditionFailed,
 
+	/// The idempotency key of a request was already used by a different request
+	#[error(display = "Idempotency key was already used for a different request")]
+	IdempotencyKeyMismatch,
+
+	/// The timestamp given by the client for a new object version
+	/// is not more recent than an existing version of the object
+	#[error(display = "A version of the object with a more recent timestamp already exists")]
+	ObjectTimestampConflict,
+
+	/// The object would conflict with an existing object whose key only
+	/// differs by a trailing slash (when the bucket rejects key conflicts)
+	#[error(display = "Object conflicts with existing object {}", _0)]
+	KeyConflict(String),
+
 	/// Parts specified in CMU request do not match parts actually uploaded
 	#[error(display = "Parts given to CompleteMultipartUpload do not match uploaded parts")]
 	InvalidPart,
@@ -48,6 +72,24 @@ pub enum Error {
 	#[error(display = "Proposed upload is smaller than the minimum allowed object size")]
 	EntityTooSmall,
 
+	/// The object is bigger than the maximum object size allowed by the server
+	#[error(display = "Proposed upload exceeds the maximum allowed object size")]
+	EntityTooLarge,
+
+	/// The upload would exceed a quota of the bucket
+	#[error(display = "{}", _1)]
+	QuotaExceeded(QuotaType, String),
+
+	/// The upload took longer than the maximum duration allowed by the server
+	#[error(display = "Upload did not complete within the allowed time")]
+	RequestTimeout,
+
+	/// Too many uploads are in progress in the bucket
+	#[error(
+		display = "Too many uploads in progress in this bucket, please reduce your request rate"
+	)]
+	SlowDown,
+
 	// Category: bad request
 	/// The request contained an invalid UTF-8 sequence in its path or in other parameters
 	#[error(display = "Invalid UTF-8: {}", _0)]
@@ -73,11 +115,68 @@ pub enum Error {
 	#[error(display = "Invalid digest: {}", _0)]
 	InvalidDigest(String),
 
+	/// The content-md5 header sent by the client does not match the data received
+	#[error(
+		display = "The Content-MD5 you specified did not match what we received (expected {}, computed {})",
+		_0,
+		_1
+	)]
+	BadDigest(String, String),
+
+	/// The x-amz-content-sha256 header sent by the client does not match the data received
+	#[error(
+		display = "The provided 'x-amz-content-sha256' header does not match what was computed (expected {}, computed {})",
+		_0,
+		_1
+	)]
+	Sha256Mismatch(String, String),
+
+	/// The client sent object tags that are invalid or exceed the allowed limits
+	#[error(display = "Invalid tag: {}", _0)]
+	InvalidTag(String),
+
+	/// The object key is longer than the maximum allowed by the server
+	#[error(display = "Your key is too long (maximum is {} bytes)", _0)]
+	KeyTooLong(usize),
+
+	/// The object key is not valid UTF-8 or contains characters that are not allowed
+	#[error(display = "Invalid object key: {}", _0)]
+	InvalidObjectKey(String),
+
+	/// The user metadata of the object is larger than the maximum allowed by the server
+	#[error(
+		display = "Your metadata headers exceed the maximum allowed metadata size ({} bytes)",
+		_0
+	)]
+	MetadataTooLarge(usize),
+
+	/// The storage class given by the client is not a known S3 storage class
+	#[error(display = "Invalid storage class: {}", _0)]
+	InvalidStorageClass(String),
+
 	/// The client sent a request for an action not supported by garage
 	#[error(display = "Unimplemented action: {}", _0)]
 	NotImplemented(String),
 }
 
+/// Kind of bucket quota that can be exceeded by an upload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaType {
+	/// Maximum number of objects in the bucket
+	Objects,
+	/// Maximum total size of the objects in the bucket
+	Size,
+}
+
+impl QuotaType {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			QuotaType::Objects => "objects",
+			QuotaType::Size => "size",
+		}
+	}
+}
+
 impl<T> From<T> for Error
 where
 	CommonError: From<T>,
@@ -91,7 +190,14 @@ impl CommonErrorDerivative for Error {}
 
 impl From<roxmltree::Error> for Error {
 	fn from(err: roxmltree::Error) -> Self {
-		Self::InvalidXml(format!("{}", err))
+		// Most parse errors already mention where they happened, but not all of them
+		let msg = err.to_string();
+		let pos = err.pos().to_string();
+		if msg.ends_with(&pos) {
+			Self::InvalidXml(msg)
+		} else {
+			Self::InvalidXml(format!("{} at {}", msg, pos))
+		}
 	}
 }
 
@@ -123,17 +229,32 @@ impl Error {
 	pub fn aws_code(&self) -> &'static str {
 		match self {
 			Error::Common(c) => c.aws_code(),
-			Error::NoSuchKey => "NoSuchKey",
+			Error::NoSuchKey | Error::DeleteMarker(_) => "NoSuchKey",
+			Error::NoSuchVersion => "NoSuchVersion",
 			Error::NoSuchUpload => "NoSuchUpload",
 			Error::PreconditionFailed => "PreconditionFailed",
+			Error::IdempotencyKeyMismatch => "IdempotencyParameterMismatch",
+			Error::ObjectTimestampConflict => "OperationAborted",
+			Error::KeyConflict(_) => "KeyConflict",
 			Error::InvalidPart => "InvalidPart",
 			Error::InvalidPartOrder => "InvalidPartOrder",
 			Error::EntityTooSmall => "EntityTooSmall",
+			Error::EntityTooLarge => "EntityTooLarge",
+			Error::QuotaExceeded(..) => "QuotaExceeded",
+			Error::RequestTimeout => "RequestTimeout",
+			Error::SlowDown => "SlowDown",
 			Error::AuthorizationHeaderMalformed(_) => "AuthorizationHeaderMalformed",
 			Error::NotImplemented(_) => "NotImplemented",
 			Error::InvalidXml(_) => "MalformedXML",
 			Error::InvalidRange(_) => "InvalidRange",
 			Error::InvalidDigest(_) => "InvalidDigest",
+			Error::BadDigest(..) => "BadDigest",
+			Error::Sha256Mismatch(..) => "XAmzContentSHA256Mismatch",
+			Error::InvalidTag(_) => "InvalidTag",
+			Error::KeyTooLong(_) => "KeyTooLongError",
+			Error::InvalidObjectKey(_) => "InvalidArgument",
+			Error::MetadataTooLarge(_) => "MetadataTooLarge",
+			Error::InvalidStorageClass(_) => "InvalidStorageClass",
 			Error::InvalidUtf8Str(_) | Error::InvalidUtf8String(_) => "InvalidRequest",
 			Error::InvalidEncryptionAlgorithm(_) => "InvalidEncryptionAlgorithmError",
 		}
@@ -145,15 +266,32 @@ impl ApiError for Error {
 	fn http_status_code(&self) -> StatusCode {
 		match self {
 			Error::Common(c) => c.http_status_code(),
-			Error::NoSuchKey | Error::NoSuchUpload => StatusCode::NOT_FOUND,
+			Error::NoSuchKey
+			| Error::NoSuchVersion
+			| Error::DeleteMarker(_)
+			| Error::NoSuchUpload => StatusCode::NOT_FOUND,
 			Error::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+			Error::IdempotencyKeyMismatch
+			| Error::ObjectTimestampConflict
+			| Error::KeyConflict(_) => StatusCode::CONFLICT,
 			Error::InvalidRange(_) => StatusCode::RANGE_NOT_SATISFIABLE,
 			Error::NotImplemented(_) => StatusCode::NOT_IMPLEMENTED,
+			Error::RequestTimeout => StatusCode::REQUEST_TIMEOUT,
+			Error::SlowDown => StatusCode::SERVICE_UNAVAILABLE,
+			Error::QuotaExceeded(..) => StatusCode::FORBIDDEN,
 			Error::AuthorizationHeaderMalformed(_)
 			| Error::InvalidPart
 			| Error::InvalidPartOrder
 			| Error::EntityTooSmall
+			| Error::EntityTooLarge
 			| Error::InvalidDigest(_)
+			| Error::BadDigest(..)
+			| Error::Sha256Mismatch(..)
+			| Error::InvalidTag(_)
+			| Error::KeyTooLong(_)
+			| Error::InvalidObjectKey(_)
+			| Error::MetadataTooLarge(_)
+			| Error::InvalidStorageClass(_)
 			| Error::InvalidEncryptionAlgorithm(_)
 			| Error::InvalidXml(_)
 			| Error::InvalidUtf8Str(_)
@@ -166,7 +304,6 @@ impl ApiError for Error {
 
 		header_map.append(header::CONTENT_TYPE, "application/xml".parse().unwrap());
 
-		#[allow(clippy::single_match)]
 		match self {
 			Error::InvalidRange((_, len)) => {
 				header_map.append(
@@ -176,6 +313,21 @@ impl ApiError for Error {
 						.expect("header value only contain ascii"),
 				);
 			}
+			Error::DeleteMarker(version_uuid) => {
+				header_map.append("x-amz-delete-marker", HeaderValue::from_static("true"));
+				header_map.append(
+					"x-amz-version-id",
+					hex::encode(version_uuid)
+						.try_into()
+						.expect("header value only contain ascii"),
+				);
+			}
+			Error::QuotaExceeded(quota_type, _) => {
+				header_map.append(
+					"x-garage-quota-type",
+					HeaderValue::from_static(quota_type.as_str()),
+				);
+			}
 			_ => (),
 		}
 	}
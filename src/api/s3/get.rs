@@ -1,4 +1,5 @@
 //! Function related to GET and HEAD requests
+use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::sync::Arc;
@@ -8,31 +9,46 @@ use bytes::Bytes;
 use futures::future;
 use futures::stream::{self, Stream, StreamExt};
 use http::header::{
-	ACCEPT_RANGES, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_ENCODING, CONTENT_LANGUAGE,
-	CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, EXPIRES, IF_MODIFIED_SINCE, IF_NONE_MATCH,
-	LAST_MODIFIED, RANGE,
+	ACCEPT_ENCODING, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_ENCODING,
+	CONTENT_LANGUAGE, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, EXPIRES,
+	IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RANGE, VARY,
 };
-use hyper::{body::Body, Request, Response, StatusCode};
+use hyper::{body::Body, HeaderMap, Request, Response, StatusCode};
+use tokio::io::BufReader;
 use tokio::sync::mpsc;
 
-use garage_net::stream::ByteStream;
+use garage_net::stream::{stream_asyncread, ByteStream};
 use garage_rpc::rpc_helper::OrderTag;
 use garage_table::EmptyKey;
 use garage_util::data::*;
 use garage_util::error::OkOrMessage;
 
+use garage_model::bucket_table::BucketParams;
 use garage_model::garage::Garage;
 use garage_model::s3::object_table::*;
 use garage_model::s3::version_table::*;
 
 use crate::helpers::*;
 use crate::s3::api_server::ResBody;
-use crate::s3::checksum::{add_checksum_response_headers, X_AMZ_CHECKSUM_MODE};
+use crate::s3::checksum::{
+	add_checksum_response_headers, add_checksum_type_header, X_AMZ_CHECKSUM_MODE,
+};
 use crate::s3::encryption::EncryptionParams;
 use crate::s3::error::*;
+use crate::s3::tagging::X_AMZ_TAGGING_COUNT;
 
 const X_AMZ_MP_PARTS_COUNT: &str = "x-amz-mp-parts-count";
 
+/// Header to read a version that is still being uploaded, see `handle_get_follow`
+const X_GARAGE_FOLLOW: &str = "x-garage-follow";
+
+/// Delay between two checks for new blocks of a version being followed
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Objects smaller than this are never compressed on read,
+/// as the gain would not be worth the overhead
+const COMPRESS_ON_READ_MIN_SIZE: u64 = 1024;
+
 #[derive(Default)]
 pub struct GetObjectOverrides {
 	pub(crate) response_cache_control: Option<String>,
@@ -52,7 +68,7 @@ fn object_headers(
 ) -> http::response::Builder {
 	debug!("Version meta: {:?}", version_meta);
 
-	let date = UNIX_EPOCH + Duration::from_millis(version.timestamp);
+	let date = UNIX_EPOCH + Duration::from_millis(version.last_modified());
 	let date_str = httpdate::fmt_http_date(date);
 
 	let mut resp = Response::builder()
@@ -60,9 +76,36 @@ fn object_headers(
 		.header(ACCEPT_RANGES, "bytes".to_string());
 
 	if !version_meta.etag.is_empty() {
-		resp = resp.header(ETAG, format!("\"{}\"", version_meta.etag));
+		resp = resp.header(
+			ETAG,
+			etag_header_value(&version_meta.etag, !is_md5_etag(&version_meta.etag)),
+		);
+	}
+	resp = resp.header("x-amz-version-id", hex::encode(version.uuid));
+
+	resp = add_meta_headers(resp, meta_inner);
+
+	if !meta_inner.tags.is_empty() {
+		resp = resp.header(X_AMZ_TAGGING_COUNT, meta_inner.tags.len().to_string());
+	}
+
+	if checksum_mode.enabled {
+		resp = add_checksum_response_headers(&meta_inner.checksum, resp);
+		if meta_inner.checksum.is_some() {
+			resp = add_checksum_type_header(meta_inner.checksum_type, resp);
+		}
 	}
 
+	encryption.add_response_headers(&mut resp);
+
+	resp
+}
+
+/// Add the headers stored with an object version to a response
+fn add_meta_headers(
+	mut resp: http::response::Builder,
+	meta_inner: &ObjectVersionMetaInner,
+) -> http::response::Builder {
 	// When metadata is retrieved through the REST API, Amazon S3 combines headers that
 	// have the same name (ignoring case) into a comma-delimited list.
 	// See: https://docs.aws.amazon.com/AmazonS3/latest/userguide/UsingMetadata.html
@@ -82,12 +125,6 @@ fn object_headers(
 		resp = resp.header(name, values.join(","));
 	}
 
-	if checksum_mode.enabled {
-		resp = add_checksum_response_headers(&meta_inner.checksum, resp);
-	}
-
-	encryption.add_response_headers(&mut resp);
-
 	resp
 }
 
@@ -129,16 +166,13 @@ fn try_answer_cached(
 	// for instance (in case of very fast updates)
 	let cached = if let Some(none_match) = req.headers().get(IF_NONE_MATCH) {
 		let none_match = none_match.to_str().ok()?;
-		let expected = format!("\"{}\"", version_meta.etag);
-		let found = none_match
-			.split(',')
-			.map(str::trim)
-			.any(|etag| etag == expected || etag == "\"*\"");
-		found
+		etag_list_matches(none_match, &version_meta.etag)
 	} else if let Some(modified_since) = req.headers().get(IF_MODIFIED_SINCE) {
 		let modified_since = modified_since.to_str().ok()?;
 		let client_date = httpdate::parse_http_date(modified_since).ok()?;
-		let server_date = UNIX_EPOCH + Duration::from_millis(version.timestamp);
+		// HTTP dates have a precision of one second, so the Last-Modified
+		// value that the client got from us was truncated to the second
+		let server_date = UNIX_EPOCH + Duration::from_secs(version.last_modified() / 1000);
 		client_date >= server_date
 	} else {
 		false
@@ -156,14 +190,70 @@ fn try_answer_cached(
 	}
 }
 
+/// Check whether an etag matches a list of etags as found in an If-None-Match header.
+/// Quotes and weak validator prefixes are ignored, and `*` matches any etag.
+fn etag_list_matches(etag_list: &str, etag: &str) -> bool {
+	etag_list
+		.split(',')
+		.any(|e| normalize_etag(e) == "*" || etag_matches(etag, e))
+}
+
+/// Check whether the etag of a stored object, usually without quotes, matches
+/// an etag given by a client. Quotes and weak validator prefixes are ignored,
+/// and multipart etags (ending with `-N`) only match with the same number of parts.
+pub(crate) fn etag_matches(stored: &str, client: &str) -> bool {
+	let (stored, client) = (normalize_etag(stored), normalize_etag(client));
+	match (stored.rsplit_once('-'), client.rsplit_once('-')) {
+		(Some((stored_hash, stored_parts)), Some((client_hash, client_parts))) => {
+			let stored_parts = stored_parts.parse::<u64>().ok();
+			stored_parts.is_some()
+				&& stored_parts == client_parts.parse::<u64>().ok()
+				&& stored_hash.eq_ignore_ascii_case(client_hash)
+		}
+		(None, None) => !stored.is_empty() && stored.eq_ignore_ascii_case(client),
+		_ => false,
+	}
+}
+
+/// Whether an etag, without quotes, is the hex-encoded MD5 digest of an object
+/// (or looks like one, as the random etags of encrypted objects). Other etags,
+/// such as those of multipart uploads or blake2-based etags, are returned to
+/// clients as weak validators.
+pub(crate) fn is_md5_etag(etag: &str) -> bool {
+	etag.len() == 32 && etag.bytes().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Value of the ETag header for an etag stored without quotes
+pub(crate) fn etag_header_value(etag: &str, weak: bool) -> String {
+	if weak {
+		format!("W/\"{}\"", etag)
+	} else {
+		format!("\"{}\"", etag)
+	}
+}
+
+fn normalize_etag(etag: &str) -> &str {
+	etag.trim().trim_start_matches("W/").trim_matches('"')
+}
+
 /// Handle HEAD request
 pub async fn handle_head(
 	ctx: ReqCtx,
 	req: &Request<impl Body>,
 	key: &str,
 	part_number: Option<u64>,
+	version_id: Option<&str>,
 ) -> Result<Response<ResBody>, Error> {
-	handle_head_without_ctx(ctx.garage, req, ctx.bucket_id, key, part_number).await
+	handle_head_without_ctx(
+		ctx.garage,
+		req,
+		ctx.bucket_id,
+		&ctx.bucket_params,
+		key,
+		part_number,
+		version_id,
+	)
+	.await
 }
 
 /// Handle HEAD request for website
@@ -171,8 +261,10 @@ pub async fn handle_head_without_ctx(
 	garage: Arc<Garage>,
 	req: &Request<impl Body>,
 	bucket_id: Uuid,
+	bucket_params: &BucketParams,
 	key: &str,
 	part_number: Option<u64>,
+	version_id: Option<&str>,
 ) -> Result<Response<ResBody>, Error> {
 	let object = garage
 		.object_table
@@ -180,12 +272,7 @@ pub async fn handle_head_without_ctx(
 		.await?
 		.ok_or(Error::NoSuchKey)?;
 
-	let object_version = object
-		.versions()
-		.iter()
-		.rev()
-		.find(|v| v.is_data())
-		.ok_or(Error::NoSuchKey)?;
+	let object_version = find_version(&object, version_id)?;
 
 	let version_data = match &object_version.state {
 		ObjectVersionState::Complete(c) => c,
@@ -193,23 +280,35 @@ pub async fn handle_head_without_ctx(
 	};
 
 	let version_meta = match version_data {
+		ObjectVersionData::DeleteMarker => return Err(Error::DeleteMarker(object_version.uuid)),
 		ObjectVersionData::Inline(meta, _) => meta,
+		ObjectVersionData::InlineRef(meta, _) => meta,
 		ObjectVersionData::FirstBlock(meta, _) => meta,
-		_ => unreachable!(),
 	};
 
 	if let Some(cached) = try_answer_cached(object_version, version_meta, req) {
 		return Ok(cached);
 	}
 
-	let (encryption, headers) =
+	let (encryption, mut headers) =
 		EncryptionParams::check_decrypt(&garage, req.headers(), &version_meta.encryption)?;
+	add_default_cache_control(bucket_params, &mut headers);
 
 	let checksum_mode = checksum_mode(&req);
 
 	if let Some(pn) = part_number {
+		if version_meta.size == 0 {
+			return empty_object_part(
+				pn,
+				object_version,
+				version_meta,
+				&headers,
+				encryption,
+				checksum_mode,
+			);
+		}
 		match version_data {
-			ObjectVersionData::Inline(_, _) => {
+			ObjectVersionData::Inline(_, _) | ObjectVersionData::InlineRef(_, _) => {
 				if pn != 1 {
 					return Err(Error::InvalidPart);
 				}
@@ -283,18 +382,32 @@ pub async fn handle_get(
 	req: &Request<impl Body>,
 	key: &str,
 	part_number: Option<u64>,
+	version_id: Option<&str>,
 	overrides: GetObjectOverrides,
 ) -> Result<Response<ResBody>, Error> {
-	handle_get_without_ctx(ctx.garage, req, ctx.bucket_id, key, part_number, overrides).await
+	handle_get_without_ctx(
+		ctx.garage,
+		req,
+		ctx.bucket_id,
+		&ctx.bucket_params,
+		key,
+		part_number,
+		version_id,
+		overrides,
+	)
+	.await
 }
 
 /// Handle GET request
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_get_without_ctx(
 	garage: Arc<Garage>,
 	req: &Request<impl Body>,
 	bucket_id: Uuid,
+	bucket_params: &BucketParams,
 	key: &str,
 	part_number: Option<u64>,
+	version_id: Option<&str>,
 	overrides: GetObjectOverrides,
 ) -> Result<Response<ResBody>, Error> {
 	let object = garage
@@ -303,20 +416,31 @@ pub async fn handle_get_without_ctx(
 		.await?
 		.ok_or(Error::NoSuchKey)?;
 
-	let last_v = object
-		.versions()
-		.iter()
-		.rev()
-		.find(|v| v.is_complete())
-		.ok_or(Error::NoSuchKey)?;
+	if version_id.is_none() && follow_requested(req) {
+		let uploading = object
+			.versions()
+			.last()
+			.filter(|v| v.is_uploading(Some(false)));
+		if let Some(version) = uploading {
+			if part_number.is_some() || req.headers().contains_key(RANGE) {
+				return Err(Error::bad_request(
+					"x-garage-follow cannot be used with partNumber or Range",
+				));
+			}
+			return handle_get_follow(garage, req, bucket_id, bucket_params, key, version);
+		}
+	}
+
+	let last_v = find_version(&object, version_id)?;
 
 	let last_v_data = match &last_v.state {
 		ObjectVersionState::Complete(x) => x,
 		_ => unreachable!(),
 	};
 	let last_v_meta = match last_v_data {
-		ObjectVersionData::DeleteMarker => return Err(Error::NoSuchKey),
+		ObjectVersionData::DeleteMarker => return Err(Error::DeleteMarker(last_v.uuid)),
 		ObjectVersionData::Inline(meta, _) => meta,
+		ObjectVersionData::InlineRef(meta, _) => meta,
 		ObjectVersionData::FirstBlock(meta, _) => meta,
 	};
 
@@ -324,10 +448,29 @@ pub async fn handle_get_without_ctx(
 		return Ok(cached);
 	}
 
-	let (enc, headers) =
+	let (enc, mut headers) =
 		EncryptionParams::check_decrypt(&garage, req.headers(), &last_v_meta.encryption)?;
+	add_default_cache_control(bucket_params, &mut headers);
+
+	let resolved_data;
+	let last_v_data = match last_v_data {
+		ObjectVersionData::InlineRef(meta, version_uuid) => {
+			let bytes = get_inline_data(&garage, version_uuid).await?;
+			resolved_data = ObjectVersionData::Inline(meta.clone(), bytes);
+			&resolved_data
+		}
+		data => data,
+	};
 
 	let checksum_mode = checksum_mode(&req);
+	let verify_blocks = bucket_params.options.get().verify_on_read;
+	let compression = read_compression(
+		bucket_params,
+		last_v_meta,
+		&headers,
+		&overrides,
+		req.headers(),
+	);
 
 	match (part_number, parse_range_header(req, last_v_meta.size)?) {
 		(Some(_), Some(_)) => Err(Error::bad_request(
@@ -343,6 +486,7 @@ pub async fn handle_get_without_ctx(
 				&headers,
 				pn,
 				checksum_mode,
+				verify_blocks,
 			)
 			.await
 		}
@@ -357,6 +501,7 @@ pub async fn handle_get_without_ctx(
 				range.start,
 				range.start + range.length,
 				checksum_mode,
+				verify_blocks,
 			)
 			.await
 		}
@@ -370,12 +515,281 @@ pub async fn handle_get_without_ctx(
 				&headers,
 				overrides,
 				checksum_mode,
+				verify_blocks,
+				compression,
 			)
 			.await
 		}
 	}
 }
 
+fn follow_requested(req: &Request<impl Body>) -> bool {
+	req.headers()
+		.get(X_GARAGE_FOLLOW)
+		.is_some_and(|x| x.as_bytes() == b"1")
+}
+
+/// Stream a version that is still being uploaded with PutObject: its blocks are
+/// sent as they are written, until the upload completes. The response has no
+/// Content-Length, and is interrupted with an error if the upload is aborted.
+fn handle_get_follow(
+	garage: Arc<Garage>,
+	req: &Request<impl Body>,
+	bucket_id: Uuid,
+	bucket_params: &BucketParams,
+	key: &str,
+	version: &ObjectVersion,
+) -> Result<Response<ResBody>, Error> {
+	let encryption = match &version.state {
+		ObjectVersionState::Uploading { encryption, .. } => encryption,
+		_ => unreachable!(),
+	};
+	let (encryption, meta_inner) =
+		EncryptionParams::check_decrypt(&garage, req.headers(), encryption)?;
+	let verify_blocks = bucket_params.options.get().verify_on_read;
+
+	let mut resp = Response::builder().header("x-amz-version-id", hex::encode(version.uuid));
+	resp = add_meta_headers(resp, &meta_inner);
+	encryption.add_response_headers(&mut resp);
+
+	let (tx, rx) = mpsc::channel::<ByteStream>(2);
+	let key = key.to_string();
+	let version_uuid = version.uuid;
+	tokio::spawn(async move {
+		let res = follow_version_blocks(
+			&garage,
+			bucket_id,
+			&key,
+			version_uuid,
+			encryption,
+			verify_blocks,
+			&tx,
+		)
+		.await;
+		if let Err(e) = res {
+			let _ = tx.send(error_stream_item(e)).await;
+		}
+	});
+
+	Ok(resp.body(response_body_from_block_stream(rx))?)
+}
+
+async fn follow_version_blocks(
+	garage: &Garage,
+	bucket_id: Uuid,
+	key: &str,
+	version_uuid: Uuid,
+	encryption: EncryptionParams,
+	verify_blocks: bool,
+	tx: &mpsc::Sender<ByteStream>,
+) -> Result<(), Error> {
+	let order_stream = OrderTag::stream();
+	let mut next_offset = 0;
+	let mut n_blocks = 0;
+	loop {
+		// The state of the version is read before its blocks, so that once it is
+		// complete, all of its blocks are found in the version table
+		let object = garage
+			.object_table
+			.get(&bucket_id, &key.to_string())
+			.await?;
+		let state = object
+			.as_ref()
+			.and_then(|o| o.versions().iter().find(|v| v.uuid == version_uuid))
+			.map(|v| &v.state);
+		let complete_size = match state {
+			Some(ObjectVersionState::Uploading { .. }) => None,
+			Some(ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _))) => {
+				Some(meta.size)
+			}
+			_ => {
+				return Err(Error::bad_request(
+					"The upload was aborted or replaced by another version",
+				))
+			}
+		};
+
+		// Blocks are written in parallel: only stream those that follow the
+		// data already sent without a gap, the others will be sent later
+		if let Some(version) = garage.version_table.get(&version_uuid, &EmptyKey).await? {
+			for (vk, vb) in version.blocks.items().iter() {
+				if vk.offset < next_offset {
+					continue;
+				}
+				if vk.offset > next_offset {
+					break;
+				}
+				let stream = encryption
+					.get_block(
+						garage,
+						&vb.hash,
+						Some(order_stream.order(n_blocks)),
+						verify_blocks,
+					)
+					.await?;
+				tx.send(stream).await.ok_or_message("channel closed")?;
+				next_offset += vb.size;
+				n_blocks += 1;
+			}
+		}
+
+		if let Some(size) = complete_size {
+			if next_offset != size {
+				return Err(Error::bad_request(format!(
+					"Completed upload is {} bytes, but only {} bytes were found",
+					size, next_offset
+				)));
+			}
+			return Ok(());
+		}
+		if tx.is_closed() {
+			return Ok(());
+		}
+		tokio::time::sleep(FOLLOW_POLL_INTERVAL).await;
+	}
+}
+
+/// Find the version of an object to be read: the complete version with the
+/// given id if a version id is requested, otherwise the latest complete version
+fn find_version<'a>(
+	object: &'a Object,
+	version_id: Option<&str>,
+) -> Result<&'a ObjectVersion, Error> {
+	match version_id {
+		None => object
+			.versions()
+			.iter()
+			.rev()
+			.find(|v| v.is_complete())
+			.ok_or(Error::NoSuchKey),
+		Some(version_id) => {
+			let version_uuid = hex::decode(version_id)
+				.ok()
+				.and_then(|id| Uuid::try_from(&id))
+				.ok_or_bad_request("Invalid version id")?;
+			object
+				.versions()
+				.iter()
+				.find(|v| v.uuid == version_uuid && v.is_complete())
+				.ok_or(Error::NoSuchVersion)
+		}
+	}
+}
+
+/// Add the default Cache-Control header of the bucket to the headers returned
+/// for an object that was stored without one. The stored metadata is not changed.
+fn add_default_cache_control(
+	bucket_params: &BucketParams,
+	meta_inner: &mut Cow<'_, ObjectVersionMetaInner>,
+) {
+	let options = bucket_params.options.get();
+	let default_cache_control = match &options.default_cache_control {
+		Some(v) => v,
+		None => return,
+	};
+	let has_cache_control = meta_inner
+		.headers
+		.iter()
+		.any(|(name, _)| name.eq_ignore_ascii_case(CACHE_CONTROL.as_str()));
+	if !has_cache_control {
+		meta_inner
+			.to_mut()
+			.headers
+			.push((CACHE_CONTROL.to_string(), default_cache_control.clone()));
+	}
+}
+
+/// Whether the body of a GetObject response is compressed on the fly
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ReadCompression {
+	/// The object is never compressed
+	Never,
+	/// The object could be compressed, but the client does not accept gzip
+	Identity,
+	/// The body is compressed with gzip
+	Gzip,
+}
+
+/// Determine whether an object is compressed when it is read in full,
+/// which requires the compress_on_read option of the bucket to be enabled,
+/// the object to be large enough, not already encoded and of a compressible
+/// content type, and the client to accept gzip
+fn read_compression(
+	bucket_params: &BucketParams,
+	version_meta: &ObjectVersionMeta,
+	meta_inner: &ObjectVersionMetaInner,
+	overrides: &GetObjectOverrides,
+	req_headers: &HeaderMap,
+) -> ReadCompression {
+	let header = |name: &http::header::HeaderName| {
+		meta_inner
+			.headers
+			.iter()
+			.find(|(k, _)| k.eq_ignore_ascii_case(name.as_str()))
+			.map(|(_, v)| v.as_str())
+	};
+
+	let eligible = bucket_params.options.get().compress_on_read
+		&& version_meta.size >= COMPRESS_ON_READ_MIN_SIZE
+		&& overrides.response_content_encoding.is_none()
+		&& header(&CONTENT_ENCODING).map_or(true, |ce| ce.trim().eq_ignore_ascii_case("identity"))
+		&& header(&CONTENT_TYPE).map_or(false, is_compressible_content_type);
+
+	if !eligible {
+		ReadCompression::Never
+	} else if accepts_gzip(req_headers) {
+		ReadCompression::Gzip
+	} else {
+		ReadCompression::Identity
+	}
+}
+
+fn is_compressible_content_type(content_type: &str) -> bool {
+	let mime = content_type
+		.split(';')
+		.next()
+		.unwrap_or_default()
+		.trim()
+		.to_ascii_lowercase();
+	mime.starts_with("text/")
+		|| mime.ends_with("+json")
+		|| mime.ends_with("+xml")
+		|| matches!(
+			mime.as_str(),
+			"application/json"
+				| "application/javascript"
+				| "application/xml"
+				| "application/x-javascript"
+				| "image/svg+xml"
+		)
+}
+
+/// Check whether the Accept-Encoding header of a request allows
+/// a gzip-encoded response (i.e. gzip or * with a non-zero weight)
+fn accepts_gzip(req_headers: &HeaderMap) -> bool {
+	let mut gzip = None;
+	let mut any = None;
+	for value in req_headers.get_all(ACCEPT_ENCODING) {
+		for item in value.to_str().unwrap_or_default().split(',') {
+			let mut params = item.split(';');
+			let coding = params
+				.next()
+				.unwrap_or_default()
+				.trim()
+				.to_ascii_lowercase();
+			let accepted = params
+				.filter_map(|p| p.trim().strip_prefix("q="))
+				.all(|q| q.trim().parse::<f32>().map_or(false, |q| q > 0.0));
+			match coding.as_str() {
+				"gzip" | "x-gzip" => gzip = Some(accepted),
+				"*" => any = Some(accepted),
+				_ => (),
+			}
+		}
+	}
+	gzip.or(any).unwrap_or(false)
+}
+
 async fn handle_get_full(
 	garage: Arc<Garage>,
 	version: &ObjectVersion,
@@ -385,6 +799,8 @@ async fn handle_get_full(
 	meta_inner: &ObjectVersionMetaInner,
 	overrides: GetObjectOverrides,
 	checksum_mode: ChecksumMode,
+	verify_blocks: bool,
+	compression: ReadCompression,
 ) -> Result<Response<ResBody>, Error> {
 	let mut resp_builder = object_headers(
 		version,
@@ -393,13 +809,50 @@ async fn handle_get_full(
 		encryption,
 		checksum_mode,
 	)
-	.header(CONTENT_LENGTH, format!("{}", version_meta.size))
 	.status(StatusCode::OK);
 	getobject_override_headers(overrides, &mut resp_builder)?;
+	if compression != ReadCompression::Never {
+		resp_builder = resp_builder.header(VARY, "Accept-Encoding");
+	}
 
-	let stream = full_object_byte_stream(garage, version, version_data, encryption);
+	let stream = full_object_byte_stream(garage, version, version_data, encryption, verify_blocks);
+
+	if compression == ReadCompression::Gzip {
+		// The compressed representation of the object has a different
+		// length, and its etag can only be a weak one
+		let headers = resp_builder.headers_mut().unwrap();
+		headers.insert(CONTENT_ENCODING, "gzip".try_into().unwrap());
+		if let Some(etag) = headers.get(ETAG).cloned() {
+			let etag = etag.to_str()?;
+			if !etag.starts_with("W/") {
+				let weak_etag = format!("W/{}", etag);
+				headers.insert(ETAG, weak_etag.try_into().ok_or_internal_error("etag")?);
+			}
+		}
 
-	Ok(resp_builder.body(response_body_from_stream(stream))?)
+		let reader = BufReader::new(stream_asyncread(stream));
+		let encoder = async_compression::tokio::bufread::GzipEncoder::new(reader);
+		let stream = Box::pin(tokio_util::io::ReaderStream::new(encoder));
+		Ok(resp_builder.body(response_body_from_stream(stream))?)
+	} else {
+		Ok(resp_builder
+			.header(CONTENT_LENGTH, format!("{}", version_meta.size))
+			.body(response_body_from_stream(stream))?)
+	}
+}
+
+/// Fetch the data of an object version stored in the compact inline data table
+pub(crate) async fn get_inline_data(
+	garage: &Garage,
+	version_uuid: &Uuid,
+) -> Result<Vec<u8>, Error> {
+	let inline_data = garage
+		.inline_data_table
+		.get(version_uuid, &EmptyKey)
+		.await?
+		.filter(|d| !d.deleted.get())
+		.ok_or_internal_error("inline data of object version not found")?;
+	Ok(inline_data.data)
 }
 
 pub fn full_object_byte_stream(
@@ -407,6 +860,7 @@ pub fn full_object_byte_stream(
 	version: &ObjectVersion,
 	version_data: &ObjectVersionData,
 	encryption: EncryptionParams,
+	verify_blocks: bool,
 ) -> ByteStream {
 	match &version_data {
 		ObjectVersionData::DeleteMarker => unreachable!(),
@@ -419,6 +873,26 @@ pub fn full_object_byte_stream(
 					.map_err(std_error_from_read_error)
 			}))
 		}
+		ObjectVersionData::InlineRef(_, version_uuid) => {
+			let version_uuid = *version_uuid;
+			let data_fut =
+				tokio::spawn(async move { get_inline_data(&garage, &version_uuid).await });
+			Box::pin(futures::stream::once(async move {
+				let bytes = data_fut
+					.await
+					.map_err(std_error_from_read_error)?
+					.map_err(std_error_from_read_error)?;
+				encryption
+					.decrypt_blob(&bytes)
+					.map(|x| Bytes::from(x.to_vec()))
+					.map_err(std_error_from_read_error)
+			}))
+		}
+		// Empty objects of buckets with the disable_inline option have
+		// no data block to read
+		ObjectVersionData::FirstBlock(meta, _) if meta.size == 0 => {
+			Box::pin(futures::stream::empty())
+		}
 		ObjectVersionData::FirstBlock(_, first_block_hash) => {
 			let (tx, rx) = mpsc::channel::<ByteStream>(2);
 
@@ -434,7 +908,12 @@ pub fn full_object_byte_stream(
 					});
 
 					let stream_block_0 = encryption
-						.get_block(&garage, &first_block_hash, Some(order_stream.order(0)))
+						.get_block(
+							&garage,
+							&first_block_hash,
+							Some(order_stream.order(0)),
+							verify_blocks,
+						)
 						.await?;
 
 					tx.send(stream_block_0)
@@ -444,7 +923,12 @@ pub fn full_object_byte_stream(
 					let version = version_fut.await.unwrap()?.ok_or(Error::NoSuchKey)?;
 					for (i, (_, vb)) in version.blocks.items().iter().enumerate().skip(1) {
 						let stream_block_i = encryption
-							.get_block(&garage, &vb.hash, Some(order_stream.order(i as u64)))
+							.get_block(
+								&garage,
+								&vb.hash,
+								Some(order_stream.order(i as u64)),
+								verify_blocks,
+							)
 							.await?;
 						tx.send(stream_block_i)
 							.await
@@ -477,6 +961,7 @@ async fn handle_get_range(
 	begin: u64,
 	end: u64,
 	checksum_mode: ChecksumMode,
+	verify_blocks: bool,
 ) -> Result<Response<ResBody>, Error> {
 	// Here we do not use getobject_override_headers because we don't
 	// want to add any overridden headers (those should not be added
@@ -490,7 +975,8 @@ async fn handle_get_range(
 		.status(StatusCode::PARTIAL_CONTENT);
 
 	match &version_data {
-		ObjectVersionData::DeleteMarker => unreachable!(),
+		// InlineRef data is resolved by the caller
+		ObjectVersionData::DeleteMarker | ObjectVersionData::InlineRef(..) => unreachable!(),
 		ObjectVersionData::Inline(_meta, bytes) => {
 			let bytes = encryption.decrypt_blob(&bytes)?;
 			if end as usize <= bytes.len() {
@@ -509,8 +995,14 @@ async fn handle_get_range(
 				.await?
 				.ok_or(Error::NoSuchKey)?;
 
-			let body =
-				body_from_blocks_range(garage, encryption, version.blocks.items(), begin, end);
+			let body = body_from_blocks_range(
+				garage,
+				encryption,
+				version.blocks.items(),
+				begin,
+				end,
+				verify_blocks,
+			);
 			Ok(resp_builder.body(body)?)
 		}
 	}
@@ -525,7 +1017,19 @@ async fn handle_get_part(
 	meta_inner: &ObjectVersionMetaInner,
 	part_number: u64,
 	checksum_mode: ChecksumMode,
+	verify_blocks: bool,
 ) -> Result<Response<ResBody>, Error> {
+	if version_meta.size == 0 {
+		return empty_object_part(
+			part_number,
+			object_version,
+			version_meta,
+			meta_inner,
+			encryption,
+			checksum_mode,
+		);
+	}
+
 	// Same as for get_range, no getobject_override_headers
 	let resp_builder = object_headers(
 		object_version,
@@ -562,8 +1066,14 @@ async fn handle_get_part(
 			let (begin, end) =
 				calculate_part_bounds(&version, part_number).ok_or(Error::InvalidPart)?;
 
-			let body =
-				body_from_blocks_range(garage, encryption, version.blocks.items(), begin, end);
+			let body = body_from_blocks_range(
+				garage,
+				encryption,
+				version.blocks.items(),
+				begin,
+				end,
+				verify_blocks,
+			);
 
 			Ok(resp_builder
 				.header(CONTENT_LENGTH, format!("{}", end - begin))
@@ -600,6 +1110,33 @@ fn parse_range_header(
 	Ok(range)
 }
 
+/// Response to a request for a part of an empty object. Such an object has
+/// a single part, which has no byte range, so the whole object is returned.
+/// This also covers objects stored as an empty list of blocks.
+fn empty_object_part(
+	part_number: u64,
+	object_version: &ObjectVersion,
+	version_meta: &ObjectVersionMeta,
+	meta_inner: &ObjectVersionMetaInner,
+	encryption: EncryptionParams,
+	checksum_mode: ChecksumMode,
+) -> Result<Response<ResBody>, Error> {
+	if part_number != 1 {
+		return Err(Error::InvalidPart);
+	}
+	Ok(object_headers(
+		object_version,
+		version_meta,
+		meta_inner,
+		encryption,
+		checksum_mode,
+	)
+	.header(CONTENT_LENGTH, "0")
+	.header(X_AMZ_MP_PARTS_COUNT, "1")
+	.status(StatusCode::OK)
+	.body(empty_body())?)
+}
+
 fn calculate_part_bounds(v: &Version, part_number: u64) -> Option<(u64, u64)> {
 	let mut offset = 0;
 	for (i, (bk, bv)) in v.blocks.items().iter().enumerate() {
@@ -636,6 +1173,7 @@ fn body_from_blocks_range(
 	all_blocks: &[(VersionBlockKey, VersionBlock)],
 	begin: u64,
 	end: u64,
+	verify_blocks: bool,
 ) -> ResBody {
 	// We will store here the list of blocks that have an intersection with the requested
 	// range, as well as their "true offset", which is their actual offset in the complete
@@ -664,7 +1202,12 @@ fn body_from_blocks_range(
 		match async {
 			for (i, (block, block_offset)) in blocks.iter().enumerate() {
 				let block_stream = encryption
-					.get_block(&garage, &block.hash, Some(order_stream.order(i as u64)))
+					.get_block(
+						&garage,
+						&block.hash,
+						Some(order_stream.order(i as u64)),
+						verify_blocks,
+					)
 					.await?;
 				let block_stream = block_stream
 					.scan(*block_offset, move |chunk_offset, chunk| {
@@ -753,3 +1296,138 @@ fn std_error_from_read_error<E: std::fmt::Display>(e: E) -> std::io::Error {
 		format!("Error while reading object data: {}", e),
 	)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_etag_list_matches() {
+		let etag = "a1b2c3";
+		assert!(etag_list_matches("\"a1b2c3\"", etag));
+		assert!(etag_list_matches("a1b2c3", etag));
+		assert!(etag_list_matches("W/\"a1b2c3\"", etag));
+		assert!(etag_list_matches("\"xyz\", \"a1b2c3\"", etag));
+		assert!(etag_list_matches("*", etag));
+		assert!(etag_list_matches("\"*\"", etag));
+
+		assert!(!etag_list_matches("\"xyz\"", etag));
+		assert!(!etag_list_matches("\"a1b2c\"", etag));
+		assert!(!etag_list_matches("", etag));
+	}
+
+	#[test]
+	fn test_find_version() {
+		use crate::generic_server::ApiError;
+
+		let version = |state| ObjectVersion {
+			uuid: gen_uuid(),
+			timestamp: 1,
+			created_at: None,
+			versioned: false,
+			state,
+		};
+		let complete = version(ObjectVersionState::Complete(
+			ObjectVersionData::DeleteMarker,
+		));
+		let aborted = version(ObjectVersionState::Aborted);
+		let object = Object::new(
+			gen_uuid(),
+			"key".into(),
+			vec![complete.clone(), aborted.clone()],
+		);
+
+		// The latest complete version is read by default
+		assert_eq!(find_version(&object, None).unwrap().uuid, complete.uuid);
+
+		let id = hex::encode(complete.uuid);
+		assert_eq!(
+			find_version(&object, Some(&id)).unwrap().uuid,
+			complete.uuid
+		);
+
+		// Versions that are not complete or don't exist are not found
+		for id in [hex::encode(aborted.uuid), hex::encode(gen_uuid())] {
+			assert!(matches!(
+				find_version(&object, Some(&id)),
+				Err(Error::NoSuchVersion)
+			));
+		}
+		let err = find_version(&object, Some("not-hex")).unwrap_err();
+		assert_eq!(err.http_status_code(), StatusCode::BAD_REQUEST);
+	}
+
+	#[test]
+	fn test_weak_etags() {
+		let md5_etag = "9b2cf535f27731c974343645a3985328";
+		assert!(is_md5_etag(md5_etag));
+		assert_eq!(
+			etag_header_value(md5_etag, !is_md5_etag(md5_etag)),
+			"\"9b2cf535f27731c974343645a3985328\""
+		);
+
+		let multipart_etag = "9b2cf535f27731c974343645a3985328-3";
+		assert!(!is_md5_etag(multipart_etag));
+		assert_eq!(
+			etag_header_value(multipart_etag, !is_md5_etag(multipart_etag)),
+			"W/\"9b2cf535f27731c974343645a3985328-3\""
+		);
+
+		assert!(!is_md5_etag(&format!("b2-{}", "00".repeat(32))));
+		assert!(!is_md5_etag(""));
+	}
+
+	#[test]
+	fn test_etag_matches() {
+		assert!(etag_matches("a1b2c3", "a1b2c3"));
+		assert!(etag_matches("a1b2c3", "\"a1b2c3\""));
+		assert!(etag_matches("\"a1b2c3\"", "a1b2c3"));
+		assert!(etag_matches("a1b2c3", "W/\"a1b2c3\""));
+		assert!(etag_matches("a1b2c3", " \"A1B2C3\" "));
+		assert!(!etag_matches("a1b2c3", "\"a1b2c4\""));
+		assert!(!etag_matches("a1b2c3", "W/"));
+		assert!(!etag_matches("", "\"\""));
+
+		// Multipart etags
+		assert!(etag_matches("a1b2c3-4", "a1b2c3-4"));
+		assert!(etag_matches("a1b2c3-4", "\"a1b2c3-4\""));
+		assert!(etag_matches("a1b2c3-4", "W/\"a1b2c3-4\""));
+		assert!(!etag_matches("a1b2c3-4", "a1b2c3-5"));
+		assert!(!etag_matches("a1b2c3-4", "a1b2c3"));
+		assert!(!etag_matches("a1b2c3", "a1b2c3-1"));
+		assert!(!etag_matches("a1b2c3-x", "a1b2c3-x"));
+	}
+
+	#[test]
+	fn test_accepts_gzip() {
+		let accepts = |value: &str| {
+			let mut headers = HeaderMap::new();
+			headers.insert(ACCEPT_ENCODING, value.parse().unwrap());
+			accepts_gzip(&headers)
+		};
+		assert!(!accepts_gzip(&HeaderMap::new()));
+		assert!(accepts("gzip"));
+		assert!(accepts("deflate, GZIP;q=0.5"));
+		assert!(accepts("*"));
+		assert!(accepts("gzip, *;q=0"));
+
+		assert!(!accepts("identity"));
+		assert!(!accepts("br, deflate"));
+		assert!(!accepts("gzip;q=0"));
+		assert!(!accepts("gzip;q=0.000, *"));
+		assert!(!accepts("*;q=0"));
+	}
+
+	#[test]
+	fn test_is_compressible_content_type() {
+		assert!(is_compressible_content_type("text/plain"));
+		assert!(is_compressible_content_type("text/html; charset=utf-8"));
+		assert!(is_compressible_content_type("application/json"));
+		assert!(is_compressible_content_type("application/ld+json"));
+		assert!(is_compressible_content_type("image/svg+xml"));
+
+		assert!(!is_compressible_content_type("application/octet-stream"));
+		assert!(!is_compressible_content_type("image/png"));
+		assert!(!is_compressible_content_type("application/gzip"));
+	}
+}
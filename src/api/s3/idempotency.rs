@@ -0,0 +1,160 @@
+//! Deduplication of retried PutObject requests, using the idempotency key
+//! given by the client in the x-garage-idempotency-key header
+use std::sync::{Arc, Mutex};
+
+use futures::prelude::*;
+use hyper::body::Bytes;
+use hyper::HeaderMap;
+use sha2::{Digest, Sha256};
+
+use garage_model::s3::idempotency_table::*;
+use garage_util::data::*;
+use garage_util::time::now_msec;
+
+use crate::helpers::*;
+use crate::s3::error::*;
+use crate::s3::put::{limit_stream_size, SaveStreamResult};
+
+pub const X_GARAGE_IDEMPOTENCY_KEY: &str = "x-garage-idempotency-key";
+
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 256;
+
+/// Get the idempotency key given in the headers of a request, if any
+pub(crate) fn request_idempotency_key(headers: &HeaderMap) -> Result<Option<String>, Error> {
+	let key = match headers.get(X_GARAGE_IDEMPOTENCY_KEY) {
+		Some(v) => v.to_str()?,
+		None => return Ok(None),
+	};
+	if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+		return Err(Error::bad_request(format!(
+			"Invalid {} header: must be between 1 and {} characters long",
+			X_GARAGE_IDEMPOTENCY_KEY, MAX_IDEMPOTENCY_KEY_LENGTH
+		)));
+	}
+	Ok(Some(key.to_string()))
+}
+
+/// Get the object version created by a previous request
+/// that used the same idempotency key, unless it has expired
+pub(crate) async fn get_idempotency_key(
+	ctx: &ReqCtx,
+	idempotency_key: &str,
+) -> Result<Option<IdempotencyKey>, Error> {
+	Ok(ctx
+		.garage
+		.idempotency_key_table
+		.get(&ctx.bucket_id, &idempotency_key.to_string())
+		.await?
+		.filter(|k| !k.deleted.get() && !k.is_expired()))
+}
+
+/// Check that a request reusing an idempotency key uploads the same object
+/// with the same content as the request that first used it. The content is
+/// compared using the sha256 of the payload signed by the client if there is
+/// one; otherwise, the body is read and hashed, but it is not stored again.
+pub(crate) async fn check_idempotent_put<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
+	ctx: &ReqCtx,
+	prior: &IdempotencyKey,
+	key: &str,
+	content_sha256: Option<Hash>,
+	body: S,
+) -> Result<(), Error> {
+	if prior.object_key != key {
+		return Err(Error::IdempotencyKeyMismatch);
+	}
+
+	let content_sha256 = match content_sha256 {
+		Some(sha256) => sha256,
+		None => {
+			let hasher = StreamSha256::default();
+			limit_stream_size(hasher.wrap(body), ctx.garage.config.s3_api.max_object_size)
+				.try_for_each(|_| future::ready(Ok(())))
+				.await?;
+			hasher.finalize()
+		}
+	};
+
+	if content_sha256 != prior.content_sha256 {
+		return Err(Error::IdempotencyKeyMismatch);
+	}
+	Ok(())
+}
+
+/// Remember the object version created by a request that
+/// used an idempotency key, until the key expires
+pub(crate) async fn insert_idempotency_key(
+	ctx: &ReqCtx,
+	idempotency_key: String,
+	key: &str,
+	res: &SaveStreamResult,
+	content_sha256: Hash,
+) -> Result<(), Error> {
+	let ttl = ctx.garage.config.s3_api.idempotency_key_ttl;
+	let entry = IdempotencyKey {
+		bucket_id: ctx.bucket_id,
+		key: idempotency_key,
+		object_key: key.to_string(),
+		version_uuid: res.version_uuid,
+		timestamp: res.version_timestamp,
+		etag: res.etag.clone(),
+		content_sha256,
+		expires_at: now_msec() + ttl.as_millis() as u64,
+		deleted: false.into(),
+	};
+	ctx.garage.idempotency_key_table.insert(&entry).await?;
+	Ok(())
+}
+
+/// Computes the sha256 of the data of a stream while it is being read
+#[derive(Default, Clone)]
+pub(crate) struct StreamSha256(Arc<Mutex<Sha256>>);
+
+impl StreamSha256 {
+	pub(crate) fn wrap<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
+		&self,
+		stream: S,
+	) -> impl Stream<Item = Result<Bytes, Error>> + Unpin {
+		let hasher = self.0.clone();
+		stream.inspect_ok(move |chunk| hasher.lock().unwrap().update(chunk))
+	}
+
+	pub(crate) fn finalize(self) -> Hash {
+		let hasher = std::mem::take(&mut *self.0.lock().unwrap());
+		Hash::try_from(&hasher.finalize()[..]).unwrap()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_request_idempotency_key() {
+		let headers = |value: &str| {
+			let mut headers = HeaderMap::new();
+			headers.insert(X_GARAGE_IDEMPOTENCY_KEY, value.parse().unwrap());
+			headers
+		};
+
+		assert_eq!(request_idempotency_key(&HeaderMap::new()).unwrap(), None);
+		assert_eq!(
+			request_idempotency_key(&headers("retry-1")).unwrap(),
+			Some("retry-1".to_string())
+		);
+		assert!(request_idempotency_key(&headers("")).is_err());
+		assert!(request_idempotency_key(&headers(&"k".repeat(257))).is_err());
+	}
+
+	#[tokio::test]
+	async fn test_stream_sha256() {
+		let hasher = StreamSha256::default();
+		let chunks = vec![Ok(Bytes::from("hello ")), Ok(Bytes::from("world"))];
+		let data = hasher
+			.wrap(futures::stream::iter(chunks))
+			.try_collect::<Vec<_>>()
+			.await
+			.unwrap();
+		assert_eq!(data.concat(), b"hello world");
+		assert_eq!(hasher.finalize(), sha256sum(b"hello world"));
+	}
+}
@@ -19,6 +19,7 @@ use crate::s3::api_server::{ReqBody, ResBody};
 use crate::s3::encryption::EncryptionParams;
 use crate::s3::error::*;
 use crate::s3::multipart as s3_multipart;
+use crate::s3::put::X_AMZ_STORAGE_CLASS;
 use crate::s3::xml as s3_xml;
 
 const DUMMY_NAME: &str = "Dummy Key";
@@ -50,6 +51,13 @@ pub struct ListMultipartUploadsQuery {
 	pub common: ListQueryCommon,
 }
 
+#[derive(Debug)]
+pub struct ListObjectVersionsQuery {
+	pub key_marker: Option<String>,
+	pub version_id_marker: Option<String>,
+	pub common: ListQueryCommon,
+}
+
 #[derive(Debug)]
 pub struct ListPartsQuery {
 	pub bucket_name: String,
@@ -148,7 +156,7 @@ pub async fn handle_list(
 				last_modified: s3_xml::Value(msec_to_rfc3339(info.last_modified)),
 				size: s3_xml::IntValue(info.size as i64),
 				etag: s3_xml::Value(format!("\"{}\"", info.etag)),
-				storage_class: s3_xml::Value("STANDARD".to_string()),
+				storage_class: s3_xml::Value(info.storage_class.clone()),
 			})
 			.collect(),
 		common_prefixes: acc
@@ -239,7 +247,7 @@ pub async fn handle_list_multipart_upload(
 		upload: acc
 			.keys
 			.iter()
-			.map(|(uuid, info)| s3_xml::ListMultipartItem {
+			.map(|((_, uuid), info)| s3_xml::ListMultipartItem {
 				initiated: s3_xml::Value(msec_to_rfc3339(info.timestamp)),
 				key: uriencode_maybe(&info.key, query.common.urlencode_resp),
 				upload_id: s3_xml::Value(hex::encode(uuid)),
@@ -270,6 +278,121 @@ pub async fn handle_list_multipart_upload(
 		.body(string_body(xml))?)
 }
 
+pub async fn handle_list_object_versions(
+	ctx: ReqCtx,
+	query: &ListObjectVersionsQuery,
+) -> Result<Response<ResBody>, Error> {
+	let ReqCtx { garage, .. } = &ctx;
+
+	// No filter is applied here, as we also want objects whose only
+	// complete version is a delete marker
+	let io = |bucket, key, count| {
+		let t = &garage.object_table;
+		async move {
+			t.get_range(&bucket, key, None, count, EnumerationOrder::Forward)
+				.await
+		}
+	};
+
+	debug!("ListObjectVersions {:?}", query);
+	let mut acc = query.build_accumulator();
+	let pagination = fetch_list_entries(&query.common, query.begin()?, &mut acc, &io).await?;
+
+	let owner = || s3_xml::Owner {
+		display_name: s3_xml::Value(DUMMY_NAME.to_string()),
+		id: s3_xml::Value(DUMMY_KEY.to_string()),
+	};
+
+	let mut versions = vec![];
+	let mut delete_markers = vec![];
+	for ((key, _), info) in acc.keys.iter() {
+		let key = uriencode_maybe(key, query.common.urlencode_resp);
+		let version_id = s3_xml::Value(hex::encode(info.uuid));
+		let is_latest = s3_xml::Value(format!("{}", info.is_latest));
+		let last_modified = s3_xml::Value(msec_to_rfc3339(info.last_modified));
+		match &info.data {
+			Some((size, etag, storage_class)) => versions.push(s3_xml::ListVersionsItem {
+				key,
+				version_id,
+				is_latest,
+				last_modified,
+				etag: s3_xml::Value(format!("\"{}\"", etag)),
+				size: s3_xml::IntValue(*size as i64),
+				owner: owner(),
+				storage_class: s3_xml::Value(storage_class.clone()),
+			}),
+			None => delete_markers.push(s3_xml::ListVersionsDeleteMarker {
+				key,
+				version_id,
+				is_latest,
+				last_modified,
+				owner: owner(),
+			}),
+		}
+	}
+
+	let result = s3_xml::ListVersionsResult {
+		xmlns: (),
+
+		// Sending back some information about the request
+		name: s3_xml::Value(query.common.bucket_name.to_string()),
+		prefix: uriencode_maybe(&query.common.prefix, query.common.urlencode_resp),
+		delimiter: query
+			.common
+			.delimiter
+			.as_ref()
+			.map(|d| uriencode_maybe(d, query.common.urlencode_resp)),
+		max_keys: s3_xml::IntValue(query.common.page_size as i64),
+		key_marker: query
+			.key_marker
+			.as_ref()
+			.map(|m| uriencode_maybe(m, query.common.urlencode_resp)),
+		version_id_marker: query
+			.version_id_marker
+			.as_ref()
+			.map(|m| s3_xml::Value(m.to_string())),
+		encoding_type: match query.common.urlencode_resp {
+			true => Some(s3_xml::Value("url".to_string())),
+			false => None,
+		},
+
+		// Handling pagination
+		is_truncated: s3_xml::Value(format!("{}", pagination.is_some())),
+		next_key_marker: match &pagination {
+			None => None,
+			Some(RangeBegin::AfterKey { key })
+			| Some(RangeBegin::AfterUpload { key, .. })
+			| Some(RangeBegin::IncludingKey { key, .. }) => {
+				Some(uriencode_maybe(key, query.common.urlencode_resp))
+			}
+		},
+		next_version_id_marker: match pagination {
+			Some(RangeBegin::AfterUpload { upload, .. }) => {
+				Some(s3_xml::Value(hex::encode(upload)))
+			}
+			Some(RangeBegin::IncludingKey { .. }) => Some(s3_xml::Value("include".to_string())),
+			_ => None,
+		},
+
+		// Result body
+		versions,
+		delete_markers,
+		common_prefixes: acc
+			.common_prefixes
+			.iter()
+			.map(|c| s3_xml::CommonPrefix {
+				prefix: uriencode_maybe(c, query.common.urlencode_resp),
+			})
+			.collect(),
+	};
+
+	let xml = s3_xml::to_xml_with_header(&result)?;
+
+	Ok(Response::builder()
+		.header("Content-Type", "application/xml")
+		.body(string_body(xml))?)
+}
+
 pub async fn handle_list_parts(
 	ctx: ReqCtx,
 	req: Request<ReqBody>,
@@ -338,6 +461,12 @@ pub async fn handle_list_parts(
 						}
 						_ => None,
 					},
+					checksum_crc64nvme: match &checksum {
+						Some(ChecksumValue::Crc64Nvme(x)) => {
+							Some(s3_xml::Value(BASE64_STANDARD.encode(&x)))
+						}
+						_ => None,
+					},
 				}
 			})
 			.collect(),
@@ -370,6 +499,7 @@ struct ObjectInfo {
 	last_modified: u64,
 	size: u64,
 	etag: String,
+	storage_class: String,
 }
 
 #[derive(Debug, PartialEq)]
@@ -378,6 +508,15 @@ struct UploadInfo {
 	timestamp: u64,
 }
 
+#[derive(Debug, PartialEq)]
+struct VersionInfo {
+	uuid: Uuid,
+	last_modified: u64,
+	is_latest: bool,
+	/// Size, etag and storage class of the version, or None for a delete marker
+	data: Option<(u64, String, String)>,
+}
+
 #[derive(Debug, PartialEq)]
 struct PartInfo<'a> {
 	etag: &'a str,
@@ -416,6 +555,7 @@ enum RangeBegin {
 	AfterKey {
 		key: String,
 	},
+	// Also used by ListObjectVersions, with the version uuid as upload
 	AfterUpload {
 		key: String,
 		upload: Uuid,
@@ -667,6 +807,36 @@ impl ListMultipartUploadsQuery {
 	}
 }
 
+impl ListObjectVersionsQuery {
+	fn build_accumulator(&self) -> VersionAccumulator {
+		VersionAccumulator::new(self.common.page_size)
+	}
+
+	fn begin(&self) -> Result<RangeBegin, Error> {
+		// Same logic as for ListMultipartUploads, version ids being
+		// the hex-encoded uuids of object versions
+		match (&self.version_id_marker, &self.key_marker) {
+			(Some(version_marker), Some(key_marker)) => match &version_marker[..] {
+				"include" => Ok(RangeBegin::IncludingKey {
+					key: key_marker.to_string(),
+					fallback_key: None,
+				}),
+				vid => Ok(RangeBegin::AfterUpload {
+					key: key_marker.to_string(),
+					upload: decode_version_id(vid)?,
+				}),
+			},
+			(None, Some(key_marker)) => Ok(RangeBegin::AfterKey {
+				key: key_marker.to_string(),
+			}),
+			_ => Ok(RangeBegin::IncludingKey {
+				key: self.common.prefix.to_string(),
+				fallback_key: None,
+			}),
+		}
+	}
+}
+
 /*
  * Accumulator logic
  */
@@ -687,7 +857,10 @@ struct Accumulator<K, V> {
 }
 
 type ObjectAccumulator = Accumulator<String, ObjectInfo>;
-type UploadAccumulator = Accumulator<Uuid, UploadInfo>;
+// Uploads are sorted by key, and then by upload id
+type UploadAccumulator = Accumulator<(String, Uuid), UploadInfo>;
+// Versions are sorted by key, and then from the most recent to the oldest
+type VersionAccumulator = Accumulator<(String, usize), VersionInfo>;
 
 impl<K: std::cmp::Ord, V> Accumulator<K, V> {
 	fn new(page_size: usize) -> Accumulator<K, V> {
@@ -804,13 +977,15 @@ impl ExtractAccumulator for ObjectAccumulator {
 
 		let meta = match &version.state {
 			ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _)) => meta,
+			ObjectVersionState::Complete(ObjectVersionData::InlineRef(meta, _)) => meta,
 			ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => meta,
 			_ => unreachable!(),
 		};
 		let info = ObjectInfo {
-			last_modified: version.timestamp,
+			last_modified: version.last_modified(),
 			size: meta.size,
 			etag: meta.etag.to_string(),
+			storage_class: storage_class(meta).to_string(),
 		};
 
 		match self.try_insert_entry(object.key.clone(), info) {
@@ -883,7 +1058,7 @@ impl ExtractAccumulator for UploadAccumulator {
 			key: object.key.to_string(),
 			timestamp: first_upload.timestamp,
 		};
-		if !self.try_insert_entry(first_upload.uuid, first_up_info) {
+		if !self.try_insert_entry((object.key.clone(), first_upload.uuid), first_up_info) {
 			return ExtractionResult::Filled;
 		}
 
@@ -897,7 +1072,7 @@ impl ExtractAccumulator for UploadAccumulator {
 
 			// Insert data in our accumulator
 			// If it is full, return information to paginate.
-			if !self.try_insert_entry(upload.uuid, up_info) {
+			if !self.try_insert_entry((object.key.clone(), upload.uuid), up_info) {
 				return ExtractionResult::FilledAtUpload {
 					key: object.key.clone(),
 					upload: prev_uuid,
@@ -914,10 +1089,90 @@ impl ExtractAccumulator for UploadAccumulator {
 	}
 }
 
+impl ExtractAccumulator for VersionAccumulator {
+	/// Observe the iterator, process a single key, and try to extract one or more versions
+	fn extract<'a>(
+		&mut self,
+		query: &ListQueryCommon,
+		cursor: &RangeBegin,
+		objects: &mut Peekable<impl Iterator<Item = &'a Object>>,
+	) -> ExtractionResult {
+		if let Some(e) = self.extract_common_prefix(objects, query) {
+			return e;
+		}
+
+		// Get the next object from the iterator
+		let object = objects.next().expect("This iterator can not be empty as it is checked earlier in the code. This is a logic bug, please report it.");
+
+		// Only complete versions are listed, from the most recent to the oldest.
+		// Uploads in progress and aborted uploads are skipped.
+		let versions_for_key = object
+			.versions()
+			.iter()
+			.rev()
+			.filter(|v| v.is_complete())
+			.enumerate()
+			.collect::<Vec<_>>();
+
+		// Skip results if a version marker is provided
+		let start = match cursor {
+			RangeBegin::AfterUpload { upload, .. } => versions_for_key
+				.iter()
+				.position(|(_, v)| v.uuid == *upload)
+				.map(|i| i + 1)
+				.unwrap_or(0),
+			_ => 0,
+		};
+
+		let mut prev_uuid = None;
+		for (i, version) in versions_for_key[start..].iter() {
+			let data = match &version.state {
+				ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+				| ObjectVersionState::Complete(ObjectVersionData::InlineRef(meta, _))
+				| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => Some((
+					meta.size,
+					meta.etag.to_string(),
+					storage_class(meta).to_string(),
+				)),
+				_ => None,
+			};
+			let info = VersionInfo {
+				uuid: version.uuid,
+				last_modified: version.last_modified(),
+				is_latest: *i == 0,
+				data,
+			};
+
+			// Insert data in our accumulator
+			// If it is full, return information to paginate.
+			if !self.try_insert_entry((object.key.clone(), *i), info) {
+				return match prev_uuid {
+					None => ExtractionResult::Filled,
+					Some(upload) => ExtractionResult::FilledAtUpload {
+						key: object.key.clone(),
+						upload,
+					},
+				};
+			}
+			prev_uuid = Some(version.uuid);
+		}
+
+		// We successfully collected all the versions
+		ExtractionResult::Extracted {
+			key: object.key.clone(),
+		}
+	}
+}
+
 /*
  * Utility functions
  */
 
+fn decode_version_id(id: &str) -> Result<Uuid, Error> {
+	let id_bin = hex::decode(id).ok_or_bad_request("Invalid version id")?;
+	Ok(Uuid::try_from(&id_bin[..]).ok_or_bad_request("Invalid version id")?)
+}
+
 /// Returns the common prefix of the object given the query prefix and delimiter
 fn common_prefix<'a>(object: &'a Object, query: &ListQueryCommon) -> Option<&'a str> {
 	match &query.delimiter {
@@ -928,6 +1183,20 @@ fn common_prefix<'a>(object: &'a Object, query: &ListQueryCommon) -> Option<&'a
 	}
 }
 
+/// Returns the storage class given when the object version was uploaded,
+/// which can't be read for encrypted objects
+fn storage_class(meta: &ObjectVersionMeta) -> &str {
+	match &meta.encryption {
+		ObjectVersionEncryption::Plaintext { inner } => inner
+			.headers
+			.iter()
+			.find(|(name, _)| name == X_AMZ_STORAGE_CLASS.as_str())
+			.map(|(_, value)| value.as_str())
+			.unwrap_or("STANDARD"),
+		ObjectVersionEncryption::SseC { .. } => "STANDARD",
+	}
+}
+
 /// URIencode a value if needed
 fn uriencode_maybe(s: &str, yes: bool) -> s3_xml::Value {
 	if yes {
@@ -982,12 +1251,16 @@ mod tests {
 		ObjectVersion {
 			uuid: Uuid::from(uuid),
 			timestamp: TS,
+			created_at: None,
+			versioned: false,
 			state: ObjectVersionState::Uploading {
 				multipart: true,
 				encryption: ObjectVersionEncryption::Plaintext {
 					inner: ObjectVersionMetaInner {
 						headers: vec![],
 						checksum: None,
+						tags: Default::default(),
+						checksum_type: Default::default(),
 					},
 				},
 				checksum_algorithm: None,
@@ -1061,14 +1334,18 @@ mod tests {
 
 		assert_eq!(acc.keys.len(), 2);
 		assert_eq!(
-			acc.keys.get(&Uuid::from([0x80; 32])).unwrap(),
+			acc.keys
+				.get(&("b".to_string(), Uuid::from([0x80; 32])))
+				.unwrap(),
 			&UploadInfo {
 				timestamp: TS,
 				key: "b".to_string()
 			}
 		);
 		assert_eq!(
-			acc.keys.get(&Uuid::from([0x8f; 32])).unwrap(),
+			acc.keys
+				.get(&("b".to_string(), Uuid::from([0x8f; 32])))
+				.unwrap(),
 			&UploadInfo {
 				timestamp: TS,
 				key: "b".to_string()
@@ -1166,6 +1443,114 @@ mod tests {
 		Ok(())
 	}
 
+	fn obj_version(uuid: [u8; 32], timestamp: u64, data: Option<u64>) -> ObjectVersion {
+		let meta = |size| ObjectVersionMeta {
+			size,
+			etag: format!("etag{}", size),
+			encryption: ObjectVersionEncryption::Plaintext {
+				inner: ObjectVersionMetaInner {
+					headers: vec![],
+					checksum: None,
+					tags: Default::default(),
+					checksum_type: Default::default(),
+				},
+			},
+		};
+		ObjectVersion {
+			uuid: Uuid::from(uuid),
+			timestamp,
+			created_at: None,
+			versioned: false,
+			state: ObjectVersionState::Complete(match data {
+				Some(size) => ObjectVersionData::Inline(meta(size), vec![]),
+				None => ObjectVersionData::DeleteMarker,
+			}),
+		}
+	}
+
+	#[tokio::test]
+	async fn test_fetch_object_versions() -> Result<(), Error> {
+		let mut query = ListObjectVersionsQuery {
+			common: query().common,
+			key_marker: None,
+			version_id_marker: None,
+		};
+		let versions = vec![
+			obj_version([0x01; 32], TS, Some(1)),
+			obj_version([0x02; 32], TS + 1, Some(2)),
+			obj_version([0x03; 32], TS + 2, None),
+			ObjectVersion {
+				uuid: Uuid::from([0x04; 32]),
+				timestamp: TS + 3,
+				created_at: None,
+				versioned: false,
+				state: ObjectVersionState::Aborted,
+			},
+			objup_version([0x05; 32]),
+		];
+		let mut fake_io = |_, _, _| {
+			let versions = versions.clone();
+			async move { Ok(vec![Object::new(bucket(), "k".to_string(), versions)]) }
+		};
+
+		// Complete versions are listed from the most recent,
+		// other versions are skipped
+		let mut acc = query.build_accumulator();
+		let page =
+			fetch_list_entries(&query.common, query.begin()?, &mut acc, &mut fake_io).await?;
+		assert_eq!(page, None);
+		let listed = acc
+			.keys
+			.iter()
+			.map(|((k, _), v)| (k.as_str(), v.uuid, v.is_latest, v.data.clone()))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			listed,
+			vec![
+				("k", Uuid::from([0x03; 32]), true, None),
+				(
+					"k",
+					Uuid::from([0x02; 32]),
+					false,
+					Some((2, "etag2".into(), "STANDARD".into()))
+				),
+				(
+					"k",
+					Uuid::from([0x01; 32]),
+					false,
+					Some((1, "etag1".into(), "STANDARD".into()))
+				),
+			]
+		);
+
+		// Pagination inside the versions of a key
+		query.common.page_size = 2;
+		let mut acc = query.build_accumulator();
+		let page =
+			fetch_list_entries(&query.common, query.begin()?, &mut acc, &mut fake_io).await?;
+		assert_eq!(
+			page,
+			Some(RangeBegin::AfterUpload {
+				key: "k".to_string(),
+				upload: Uuid::from([0x02; 32]),
+			})
+		);
+		assert_eq!(acc.keys.len(), 2);
+
+		query.key_marker = Some("k".to_string());
+		query.version_id_marker = Some(hex::encode([0x02; 32]));
+		let mut acc = query.build_accumulator();
+		let page =
+			fetch_list_entries(&query.common, query.begin()?, &mut acc, &mut fake_io).await?;
+		assert_eq!(page, None);
+		assert_eq!(
+			acc.keys.values().map(|v| v.uuid).collect::<Vec<_>>(),
+			vec![Uuid::from([0x01; 32])]
+		);
+
+		Ok(())
+	}
+
 	fn mpu() -> MultipartUpload {
 		let uuid = Uuid::from([0x08; 32]);
 
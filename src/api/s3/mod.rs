@@ -6,11 +6,14 @@ mod copy;
 pub mod cors;
 mod delete;
 pub mod get;
+mod idempotency;
 mod lifecycle;
 mod list;
 mod multipart;
 mod post_object;
 mod put;
+mod restore;
+mod tagging;
 mod website;
 
 mod checksum;
@@ -8,6 +8,7 @@ use hyper::{Request, Response};
 
 use garage_table::*;
 use garage_util::data::*;
+use garage_util::time::*;
 
 use garage_model::garage::Garage;
 use garage_model::s3::block_ref_table::*;
@@ -20,17 +21,23 @@ use crate::s3::api_server::{ReqBody, ResBody};
 use crate::s3::checksum::*;
 use crate::s3::encryption::EncryptionParams;
 use crate::s3::error::*;
+use crate::s3::get::etag_matches;
 use crate::s3::put::*;
+use crate::s3::tagging::request_object_tags;
 use crate::s3::xml as s3_xml;
 use crate::signature::verify_signed_content;
 
 // ----
 
+/// Minimum size of a part in a multipart upload, except for the last part
+
 pub async fn handle_create_multipart_upload(
 	ctx: ReqCtx,
 	req: &Request<ReqBody>,
 	key: &String,
 ) -> Result<Response<ResBody>, Error> {
+	check_object_key(&ctx.garage, key)?;
+
 	let ReqCtx {
 		garage,
 		bucket_id,
@@ -40,12 +47,14 @@ pub async fn handle_create_multipart_upload(
 	let existing_object = garage.object_table.get(&bucket_id, &key).await?;
 
 	let upload_id = gen_uuid();
-	let timestamp = next_timestamp(existing_object.as_ref());
+	let timestamp = next_timestamp(existing_object.as_ref(), &garage.version_clock).await?;
 
-	let headers = get_headers(req.headers())?;
+	let headers = get_headers(req.headers(), garage.config.s3_api.max_metadata_size)?;
 	let meta = ObjectVersionMetaInner {
 		headers,
 		checksum: None,
+		tags: request_object_tags(req.headers())?,
+		checksum_type: ChecksumType::Composite,
 	};
 
 	// Determine whether object should be encrypted, and if so the key
@@ -58,6 +67,8 @@ pub async fn handle_create_multipart_upload(
 	let object_version = ObjectVersion {
 		uuid: upload_id,
 		timestamp,
+		created_at: Some(now_msec()),
+		versioned: ctx.bucket_params.versioning_enabled(),
 		state: ObjectVersionState::Uploading {
 			multipart: true,
 			encryption: object_encryption,
@@ -100,13 +111,14 @@ pub async fn handle_put_part(
 	let upload_id = decode_upload_id(upload_id)?;
 
 	let expected_checksums = ExpectedChecksums {
-		md5: match req.headers().get("content-md5") {
-			Some(x) => Some(x.to_str()?.to_string()),
-			None => None,
-		},
+		md5: request_content_md5(req.headers())?,
 		sha256: content_sha256,
 		extra: request_checksum_value(req.headers())?,
+		algorithm: None,
+		block_hashes: request_block_hashes(&ctx, req.headers())?,
 	};
+	check_content_md5_required(&ctx, &expected_checksums)?;
+	let durability = request_durability(req.headers())?;
 
 	// Read first chuck, and at the same time try to get object to see if it exists
 	let key = key.to_string();
@@ -114,6 +126,9 @@ pub async fn handle_put_part(
 	let (req_head, req_body) = req.into_parts();
 	let stream = body_stream(req_body);
 	let mut chunker = StreamChunker::new(stream, garage.config.block_size);
+	if ctx.bucket_params.options.get().content_defined_chunking {
+		chunker = chunker.with_content_defined_chunking();
+	}
 
 	let ((_, object_version, mut mpu), first_block) =
 		futures::try_join!(get_upload(&ctx, &key, &upload_id), chunker.next(),)?;
@@ -127,8 +142,13 @@ pub async fn handle_put_part(
 		} => (encryption, checksum_algorithm),
 		_ => unreachable!(),
 	};
-	let (encryption, _) =
+	let (encryption, object_meta) =
 		EncryptionParams::check_decrypt(&garage, &req_head.headers, &object_encryption)?;
+	check_block_hashes_allowed(
+		&expected_checksums,
+		!encryption.is_encrypted(),
+		checksum_algorithm,
+	)?;
 
 	// Check object is valid and part can be accepted
 	let first_block = first_block.ok_or_bad_request("Empty body")?;
@@ -149,6 +169,16 @@ pub async fn handle_put_part(
 		version_uuid,
 	}));
 
+	// Previous uploads of this same part are replaced by this one,
+	// their data will be deleted once this upload has completed
+	let replaced_versions = mpu
+		.parts
+		.items()
+		.iter()
+		.filter(|(pk, _)| pk.part_number == part_number)
+		.map(|(_, p)| p.version)
+		.collect::<Vec<_>>();
+
 	// Create version and link version from MPU
 	mpu.parts.clear();
 	mpu.parts.put(
@@ -173,14 +203,19 @@ pub async fn handle_put_part(
 	// Copy data to version
 	let checksummer =
 		Checksummer::init(&expected_checksums, !encryption.is_encrypted()).add(checksum_algorithm);
-	let (total_size, checksums, _) = read_and_put_blocks(
+	let (total_size, _, checksums, _) = read_and_put_blocks(
 		&ctx,
 		&version,
 		encryption,
 		part_number,
+		0,
 		first_block,
 		&mut chunker,
 		checksummer,
+		is_content_encoded(&object_meta.headers),
+		expected_checksums.block_hashes.as_deref(),
+		durability,
+		None,
 	)
 	.await?;
 
@@ -201,6 +236,18 @@ pub async fn handle_put_part(
 	);
 	garage.mpu_table.insert(&mpu).await?;
 
+	// Delete the versions of the parts we replaced, along with their block references
+	if !replaced_versions.is_empty() {
+		let replaced_versions = replaced_versions.into_iter().map(|version_uuid| {
+			Version::new(
+				version_uuid,
+				VersionBacklink::MultipartUpload { upload_id },
+				true,
+			)
+		});
+		garage.version_table.insert_many(replaced_versions).await?;
+	}
+
 	// We were not interrupted, everything went fine.
 	// We won't have to clean up on drop.
 	interrupted_cleanup.cancel();
@@ -278,6 +325,8 @@ pub async fn handle_complete_multipart_upload(
 	// Get object and multipart upload
 	let key = key.to_string();
 	let (object, mut object_version, mpu) = get_upload(&ctx, &key, &upload_id).await?;
+	check_key_conflict(&ctx, &key).await?;
+	check_worm_overwrite(&ctx, Some(&object))?;
 
 	if mpu.parts.is_empty() {
 		return Err(Error::bad_request("No data was uploaded"));
@@ -315,7 +364,13 @@ pub async fn handle_complete_multipart_upload(
 	let mut parts = vec![];
 	for req_part in body_list_of_parts.iter() {
 		match have_parts.get(&req_part.part_number) {
-			Some(part) if part.etag.as_ref() == Some(&req_part.etag) && part.size.is_some() => {
+			Some(part)
+				if part
+					.etag
+					.as_deref()
+					.is_some_and(|etag| etag_matches(etag, &req_part.etag))
+					&& part.size.is_some() =>
+			{
 				// alternative version: if req_part.checksum.is_some() && part.checksum != req_part.checksum {
 				if part.checksum != req_part.checksum {
 					return Err(Error::InvalidDigest(format!(
@@ -329,6 +384,17 @@ pub async fn handle_complete_multipart_upload(
 		}
 	}
 
+	// Check that all parts except the last one have the minimum size
+	let min_part_size = garage.config.s3_api.multipart_min_part_size as u64;
+	if parts
+		.iter()
+		.rev()
+		.skip(1)
+		.any(|p| p.size.unwrap() < min_part_size)
+	{
+		return Err(Error::EntityTooSmall);
+	}
+
 	let grg = &garage;
 	let parts_versions = futures::future::try_join_all(parts.iter().map(|p| async move {
 		grg.version_table
@@ -367,6 +433,7 @@ pub async fn handle_complete_multipart_upload(
 		block: b.hash,
 		version: upload_id,
 		deleted: false.into(),
+		bucket_id: Some(*bucket_id),
 	});
 	garage.block_ref_table.insert_many(block_refs).await?;
 
@@ -390,6 +457,7 @@ pub async fn handle_complete_multipart_upload(
 
 	// Calculate total size of final object
 	let total_size = parts.iter().map(|x| x.size.unwrap()).sum();
+	check_object_size(garage, total_size)?;
 
 	if let Err(e) = check_quotas(&ctx, total_size, Some(&object)).await {
 		object_version.state = ObjectVersionState::Aborted;
@@ -406,8 +474,9 @@ pub async fn handle_complete_multipart_upload(
 			let (encryption, meta) =
 				EncryptionParams::check_decrypt(&garage, &req_head.headers, &object_encryption)?;
 			let new_meta = ObjectVersionMetaInner {
-				headers: meta.into_owned().headers,
 				checksum: checksum_extra,
+				checksum_type: ChecksumType::Composite,
+				..meta.into_owned()
 			};
 			encryption.encrypt_meta(new_meta)?
 		}
@@ -449,6 +518,10 @@ pub async fn handle_complete_multipart_upload(
 			Some(ChecksumValue::Sha256(x)) => Some(s3_xml::Value(BASE64_STANDARD.encode(&x))),
 			_ => None,
 		},
+		checksum_crc64nvme: match &checksum_extra {
+			Some(ChecksumValue::Crc64Nvme(x)) => Some(s3_xml::Value(BASE64_STANDARD.encode(&x))),
+			_ => None,
+		},
 	};
 	let xml = s3_xml::to_xml_with_header(&result)?;
 
@@ -577,6 +650,15 @@ fn parse_complete_multipart_upload_body(
 						.try_into()
 						.ok()?,
 				))
+			} else if let Some(crc64nvme) = item
+				.children()
+				.find(|e| e.has_tag_name("ChecksumCRC64NVME"))
+			{
+				Some(ChecksumValue::Crc64Nvme(
+					BASE64_STANDARD.decode(crc64nvme.text()?).ok()?[..]
+						.try_into()
+						.ok()?,
+				))
 			} else {
 				None
 			};
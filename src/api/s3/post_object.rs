@@ -22,7 +22,8 @@ use crate::s3::checksum::*;
 use crate::s3::cors::*;
 use crate::s3::encryption::EncryptionParams;
 use crate::s3::error::*;
-use crate::s3::put::{get_headers, save_stream, ChecksumMode};
+use crate::s3::get::etag_header_value;
+use crate::s3::put::{check_object_key, get_headers, save_stream, ChecksumMode, Durability};
 use crate::s3::xml as s3_xml;
 use crate::signature::payload::{verify_v4, Authorization};
 
@@ -222,21 +223,30 @@ pub async fn handle_post_object(
 		)));
 	}
 
-	let headers = get_headers(&params)?;
+	// The policy applies to the key given by the client,
+	// but the object is stored under the normalized key
+	let key = bucket_params
+		.options
+		.get()
+		.key_normalization
+		.normalize(&key);
+	check_object_key(&garage, &key)?;
+
+	let headers = get_headers(&params, garage.config.s3_api.max_metadata_size)?;
 
 	let expected_checksums = ExpectedChecksums {
-		md5: params
-			.get("content-md5")
-			.map(HeaderValue::to_str)
-			.transpose()?
-			.map(str::to_string),
+		md5: request_content_md5(&params)?,
 		sha256: None,
 		extra: request_checksum_algorithm_value(&params)?,
+		algorithm: None,
+		block_hashes: None,
 	};
 
 	let meta = ObjectVersionMetaInner {
 		headers,
 		checksum: expected_checksums.extra,
+		tags: Default::default(),
+		checksum_type: ChecksumType::FullObject,
 	};
 
 	let encryption = EncryptionParams::new_from_headers(&garage, &params)?;
@@ -255,12 +265,15 @@ pub async fn handle_post_object(
 		meta,
 		encryption,
 		StreamLimiter::new(stream, conditions.content_length),
+		ctx.garage.config.block_size,
 		&key,
 		ChecksumMode::Verify(&expected_checksums),
+		None,
+		Durability::default(),
 	)
 	.await?;
 
-	let etag = format!("\"{}\"", res.etag);
+	let etag = etag_header_value(&res.etag, res.weak_etag);
 
 	let mut resp = if let Some(mut target) = params
 		.get("success_action_redirect")
@@ -1,19 +1,29 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
 use futures::prelude::*;
 use futures::stream::FuturesOrdered;
 use futures::try_join;
+use md5::{Digest as Md5Digest, Md5};
 
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch, OwnedSemaphorePermit, Semaphore};
 
 use hyper::body::Bytes;
-use hyper::header::{HeaderMap, HeaderValue};
+use hyper::header::{
+	HeaderMap, HeaderName, HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE,
+	IF_NONE_MATCH,
+};
 use hyper::{Request, Response};
 
 use opentelemetry::{
-	trace::{FutureExt as OtelFutureExt, TraceContextExt, Tracer},
-	Context,
+	trace::{
+		FutureExt as OtelFutureExt, Link, SpanContext, SpanId, TraceContextExt, TraceFlags,
+		TraceId, TraceState, Tracer,
+	},
+	Context, KeyValue,
 };
 
 use garage_net::bytes_buf::BytesBuf;
@@ -21,28 +31,99 @@ use garage_rpc::rpc_helper::OrderTag;
 use garage_table::*;
 use garage_util::data::*;
 use garage_util::error::Error as GarageError;
+use garage_util::metrics::gen_trace_id;
 use garage_util::time::*;
 
 use garage_block::manager::INLINE_THRESHOLD;
+use garage_model::bucket_table::{BucketParams, BucketQuotas};
 use garage_model::garage::Garage;
 use garage_model::index_counter::CountedItem;
+use garage_model::put_hook::*;
+use garage_model::quota::*;
 use garage_model::s3::block_ref_table::*;
 use garage_model::s3::object_table::*;
 use garage_model::s3::version_table::*;
 
 use crate::helpers::*;
-use crate::s3::api_server::{ReqBody, ResBody};
+use crate::s3::api_server::{ReqBody, ResBody, S3ApiMetrics};
 use crate::s3::checksum::*;
 use crate::s3::encryption::EncryptionParams;
 use crate::s3::error::*;
+use crate::s3::get::{etag_header_value, is_md5_etag};
+use crate::s3::idempotency::*;
+use crate::s3::tagging::request_object_tags;
 
 const PUT_BLOCKS_MAX_PARALLEL: usize = 3;
 
+/// Delay before the first retry of a failed block write,
+/// doubled at each subsequent retry
+const PUT_BLOCK_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Inline data larger than this is hashed in a blocking task,
+/// so as not to stall the async runtime
+const INLINE_HASH_BLOCKING_THRESHOLD: usize = 32 * 1024;
+
+/// Storage classes that can be given for objects. Garage has a single
+/// storage tier: the storage class of objects is only stored and reported.
+const STORAGE_CLASSES: &[&str] = &[
+	"STANDARD",
+	"REDUCED_REDUNDANCY",
+	"STANDARD_IA",
+	"ONEZONE_IA",
+	"INTELLIGENT_TIERING",
+	"GLACIER",
+	"DEEP_ARCHIVE",
+	"OUTPOSTS",
+	"GLACIER_IR",
+	"SNOW",
+	"EXPRESS_ONEZONE",
+];
+
+/// Bounds of the block size that can be requested for an upload
+/// using the x-garage-block-size header
+const MIN_BLOCK_SIZE_OVERRIDE: usize = 64 * 1024;
+const MAX_BLOCK_SIZE_OVERRIDE: usize = 64 * 1024 * 1024;
+
+const X_AMZ_EXPIRATION: HeaderName = HeaderName::from_static("x-amz-expiration");
+pub(crate) const X_AMZ_STORAGE_CLASS: HeaderName = HeaderName::from_static("x-amz-storage-class");
+const X_AMZ_DECODED_CONTENT_LENGTH: HeaderName =
+	HeaderName::from_static("x-amz-decoded-content-length");
+const X_GARAGE_BLOCK_SIZE: HeaderName = HeaderName::from_static("x-garage-block-size");
+const X_GARAGE_OBJECT_TIMESTAMP: HeaderName = HeaderName::from_static("x-garage-object-timestamp");
+const X_GARAGE_BLOCK_HASHES: HeaderName = HeaderName::from_static("x-garage-block-hashes");
+const X_GARAGE_DURABLE: HeaderName = HeaderName::from_static("x-garage-durable");
+const X_GARAGE_STORED_BYTES: HeaderName = HeaderName::from_static("x-garage-stored-bytes");
+const X_GARAGE_TRACE_ID: HeaderName = HeaderName::from_static("x-garage-trace-id");
+const X_GARAGE_DECODE_CONTENT: HeaderName = HeaderName::from_static("x-garage-decode-content");
+
+/// How data blocks of an upload are persisted on storage nodes
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub(crate) enum Durability {
+	/// Blocks are synced to disk only if the nodes have data_fsync enabled
+	#[default]
+	Relaxed,
+	/// Blocks are synced to disk on all nodes of the write quorum
+	/// before the upload is acknowledged
+	Strict,
+}
+
+/// When the hashes of data blocks are supplied by the client, the first
+/// block and one in this many of the other blocks are hashed to check them
+/// even if they are already stored. Blocks that are not stored are all checked.
+const BLOCK_HASH_SPOT_CHECK_RATIO: u32 = 8;
+
 pub(crate) struct SaveStreamResult {
 	pub(crate) version_uuid: Uuid,
 	pub(crate) version_timestamp: u64,
 	/// Etag WITHOUT THE QUOTES (just the hex value)
 	pub(crate) etag: String,
+	/// Whether the etag is not the md5 of the object,
+	/// in which case it is returned as a weak etag
+	pub(crate) weak_etag: bool,
+	pub(crate) size: u64,
+	/// Number of bytes of data written to storage for the object, after
+	/// compression of its data blocks, if new data was written
+	pub(crate) stored_size: Option<u64>,
 }
 
 pub(crate) enum ChecksumMode<'a> {
@@ -56,122 +137,684 @@ pub async fn handle_put(
 	key: &String,
 	content_sha256: Option<Hash>,
 ) -> Result<Response<ResBody>, Error> {
+	check_object_key(&ctx.garage, key)?;
+
+	if let Some(range) = request_content_range(req.headers())? {
+		return handle_put_range(ctx, req, key, content_sha256, range).await;
+	}
+
 	// Retrieve interesting headers from request
-	let headers = get_headers(req.headers())?;
+	let mut headers = get_headers(req.headers(), ctx.garage.config.s3_api.max_metadata_size)?;
 	debug!("Object headers: {:?}", headers);
 
+	// A body decoded before it is stored is no longer gzip-encoded
+	let decode_gzip = request_decode_gzip(req.headers())?;
+	if decode_gzip {
+		headers.retain(|(name, value)| {
+			name != CONTENT_ENCODING.as_str() || !value.trim().eq_ignore_ascii_case("gzip")
+		});
+	}
+
 	let expected_checksums = ExpectedChecksums {
-		md5: match req.headers().get("content-md5") {
-			Some(x) => Some(x.to_str()?.to_string()),
-			None => None,
-		},
+		md5: request_content_md5(req.headers())?,
 		sha256: content_sha256,
 		extra: request_checksum_value(req.headers())?,
+		algorithm: request_sdk_checksum_algorithm(req.headers())?,
+		block_hashes: request_block_hashes(&ctx, req.headers())?,
+	};
+	check_content_md5_required(&ctx, &expected_checksums)?;
+	if let (Some(extra), Some(algo)) = (&expected_checksums.extra, expected_checksums.algorithm) {
+		if extra.algorithm() != algo {
+			return Err(Error::bad_request(
+				"checksum value does not match x-amz-sdk-checksum-algorithm",
+			));
+		}
+	}
+
+	// The checksums given by the client for a body that is decoded are those
+	// of the gzip data, and are verified before decoding it. The checksum
+	// of the stored object is calculated on the decoded data.
+	let decoded_checksums = ExpectedChecksums {
+		algorithm: expected_checksums
+			.extra
+			.map(|x| x.algorithm())
+			.or(expected_checksums.algorithm),
+		..Default::default()
 	};
+	if decode_gzip && expected_checksums.block_hashes.is_some() {
+		return Err(Error::bad_request(
+			"Block hashes cannot be supplied for bodies that are decoded",
+		));
+	}
 
 	let meta = ObjectVersionMetaInner {
 		headers,
-		checksum: expected_checksums.extra,
+		checksum: expected_checksums.extra.filter(|_| !decode_gzip),
+		tags: request_object_tags(req.headers())?,
+		checksum_type: ChecksumType::FullObject,
 	};
 
 	// Determine whether object should be encrypted, and if so the key
 	let encryption = EncryptionParams::new_from_headers(&ctx.garage, req.headers())?;
 
+	// Check the preconditions that do not depend on the body before starting
+	// to read it. Clients that send `Expect: 100-continue` wait for hyper to
+	// reply `100 Continue`, which it only does when the body is first polled,
+	// so they do not upload the body of requests rejected here.
+	check_put_preconditions(&ctx, key, req.headers()).await?;
+
+	// If the size of the object is known in advance, check size limits
+	// and quotas before starting to read the body
+	if let Some(size) = declared_content_length(req.headers())? {
+		check_object_size(&ctx.garage, size)?;
+		check_quotas_preflight(&ctx, key, size).await?;
+	}
+
+	// If the request is a retry of a previous request with the same
+	// idempotency key, return the object version it created
+	let idempotency_key = request_idempotency_key(req.headers())?;
+	if let Some(idempotency_key) = &idempotency_key {
+		if let Some(prior) = get_idempotency_key(&ctx, idempotency_key).await? {
+			let stream = body_stream(req.into_body());
+			check_idempotent_put(&ctx, &prior, key, content_sha256, stream).await?;
+			return Ok(Response::builder()
+				.header("x-amz-version-id", hex::encode(prior.version_uuid))
+				.header(
+					"ETag",
+					etag_header_value(&prior.etag, !is_md5_etag(&prior.etag)),
+				)
+				.body(empty_body())?);
+		}
+	}
+
+	let block_size = upload_block_size(&ctx.garage, req.headers());
+	let version_timestamp = request_object_timestamp(req.headers())?;
+	if let Some(timestamp) = version_timestamp {
+		let max_skew = ctx.garage.config.s3_api.max_object_timestamp_skew;
+		check_object_timestamp_skew(timestamp, now_msec(), max_skew)?;
+	}
+	let durability = request_durability(req.headers())?;
+	let trace_id = request_trace_id(req.headers())?.unwrap_or_else(gen_trace_id);
 	let stream = body_stream(req.into_body());
+	let content_hasher = idempotency_key.as_ref().map(|_| StreamSha256::default());
+	let stream = match &content_hasher {
+		Some(hasher) => future::Either::Left(hasher.wrap(stream)),
+		None => future::Either::Right(stream),
+	};
+	let (stream, checksum_mode) = if decode_gzip {
+		let gzip_checksums = ExpectedChecksums {
+			md5: expected_checksums.md5.clone(),
+			sha256: expected_checksums.sha256,
+			extra: expected_checksums.extra,
+			..Default::default()
+		};
+		(
+			future::Either::Left(decode_gzip_stream(stream, gzip_checksums)),
+			ChecksumMode::Verify(&decoded_checksums),
+		)
+	} else {
+		(
+			future::Either::Right(stream),
+			ChecksumMode::Verify(&expected_checksums),
+		)
+	};
 
 	let res = save_stream(
 		&ctx,
 		meta,
 		encryption,
 		stream,
+		block_size,
 		key,
-		ChecksumMode::Verify(&expected_checksums),
+		checksum_mode,
+		version_timestamp,
+		durability,
 	)
+	.with_context(upload_trace_context(trace_id))
 	.await?;
 
+	if let (Some(idempotency_key), Some(hasher)) = (idempotency_key, content_hasher) {
+		insert_idempotency_key(&ctx, idempotency_key, key, &res, hasher.finalize()).await?;
+	}
+
 	let mut resp = Response::builder()
 		.header("x-amz-version-id", hex::encode(res.version_uuid))
-		.header("ETag", format!("\"{}\"", res.etag));
+		.header("ETag", etag_header_value(&res.etag, res.weak_etag))
+		.header(X_GARAGE_TRACE_ID, trace_id.to_string());
+	if let Some(stored_size) = res.stored_size {
+		resp = resp.header(X_GARAGE_STORED_BYTES, stored_size.to_string());
+	}
+	if let Some(expiration) = expiration_header(&ctx, key, &res) {
+		resp = resp.header(X_AMZ_EXPIRATION, expiration);
+	}
 	encryption.add_response_headers(&mut resp);
 	let resp = add_checksum_response_headers(&expected_checksums.extra, resp);
 	Ok(resp.body(empty_body())?)
 }
 
+/// Overwrite a byte range of an existing object with the body of the request
+/// (non-standard extension, for PutObject requests with a `Content-Range` header).
+/// The range must start and end on block boundaries of the current version of
+/// the object: the blocks that it covers are replaced by the blocks of the new
+/// data, and the other blocks are referenced by the new version as they are.
+/// Objects stored inline, encrypted objects and objects uploaded in several
+/// parts cannot be patched.
+async fn handle_put_range(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+	key: &String,
+	content_sha256: Option<Hash>,
+	range: (u64, u64),
+) -> Result<Response<ResBody>, Error> {
+	let (version_uuid, etag) =
+		run_put(&ctx, key, put_range(&ctx, req, key, content_sha256, range)).await?;
+
+	Ok(Response::builder()
+		.header("x-amz-version-id", hex::encode(version_uuid))
+		.header("ETag", etag_header_value(&etag, true))
+		.body(empty_body())?)
+}
+
+/// Write the new version of an object patched by `handle_put_range`,
+/// returning its uuid and etag
+async fn put_range(
+	ctx: &ReqCtx,
+	req: Request<ReqBody>,
+	key: &String,
+	content_sha256: Option<Hash>,
+	(start, end): (u64, u64),
+) -> Result<(Uuid, String), Error> {
+	let ReqCtx {
+		garage, bucket_id, ..
+	} = ctx;
+
+	let expected_checksums = ExpectedChecksums {
+		md5: request_content_md5(req.headers())?,
+		sha256: content_sha256,
+		extra: request_checksum_value(req.headers())?,
+		algorithm: None,
+		block_hashes: None,
+	};
+	check_content_md5_required(ctx, &expected_checksums)?;
+	let durability = request_durability(req.headers())?;
+
+	// Get the current version of the object and its blocks
+	let object = garage
+		.object_table
+		.get(bucket_id, key)
+		.await?
+		.ok_or(Error::NoSuchKey)?;
+	check_worm_overwrite(ctx, Some(&object))?;
+	let current = object
+		.versions()
+		.iter()
+		.rev()
+		.find(|v| v.is_complete())
+		.ok_or(Error::NoSuchKey)?;
+	let (meta, first_block_hash) = match &current.state {
+		ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, hash)) => (meta, *hash),
+		ObjectVersionState::Complete(ObjectVersionData::Inline(..))
+		| ObjectVersionState::Complete(ObjectVersionData::InlineRef(..)) => {
+			return Err(Error::bad_request(
+				"Objects stored inline cannot be patched",
+			))
+		}
+		_ => return Err(Error::NoSuchKey),
+	};
+	let mut meta_inner = match &meta.encryption {
+		ObjectVersionEncryption::Plaintext { inner } => inner.clone(),
+		ObjectVersionEncryption::SseC { .. } => {
+			return Err(Error::bad_request("Encrypted objects cannot be patched"))
+		}
+	};
+	if end >= meta.size {
+		return Err(Error::bad_request(
+			"Content-Range exceeds the size of the object",
+		));
+	}
+
+	let current_version = garage
+		.version_table
+		.get(&current.uuid, &EmptyKey)
+		.await?
+		.ok_or(Error::NoSuchKey)?;
+	let blocks = current_version.blocks.items();
+	let part_number = blocks.first().ok_or(Error::NoSuchKey)?.0.part_number;
+	if blocks.iter().any(|(k, _)| k.part_number != part_number) {
+		return Err(Error::bad_request(
+			"Objects uploaded in several parts cannot be patched",
+		));
+	}
+	let starts_on_block = blocks.iter().any(|(k, _)| k.offset == start);
+	let ends_on_block = blocks.iter().any(|(k, b)| k.offset + b.size == end + 1);
+	if !starts_on_block || !ends_on_block {
+		return Err(Error::bad_request(
+			"Content-Range must start and end on block boundaries of the object",
+		));
+	}
+	let range_len = end - start + 1;
+	if declared_content_length(req.headers())?.is_some_and(|len| len != range_len) {
+		return Err(Error::bad_request(
+			"Content-Range does not match the length of the body",
+		));
+	}
+
+	// The checksum of the previous data no longer applies
+	meta_inner.checksum = None;
+	let content_encoded = is_content_encoded(&meta_inner.headers);
+	let current_uuid = current.uuid;
+
+	let body = limit_stream_size(body_stream(req.into_body()), Some(range_len));
+	let mut chunker = StreamChunker::new(body, garage.config.block_size);
+	let first_block = chunker.next().await?.ok_or_bad_request("Empty body")?;
+
+	let _upload_slot = UploadSlot::acquire(garage, *bucket_id)?;
+
+	let version_uuid = gen_uuid();
+	let version_timestamp = next_timestamp(Some(&object), &garage.version_clock).await?;
+	let created_at = now_msec();
+
+	let mut interrupted_cleanup = InterruptedCleanup(Some(InterruptedCleanupInner {
+		garage: garage.clone(),
+		bucket_id: *bucket_id,
+		key: key.into(),
+		version_uuid,
+		version_timestamp,
+	}));
+
+	let object_version = ObjectVersion {
+		uuid: version_uuid,
+		timestamp: version_timestamp,
+		created_at: Some(created_at),
+		versioned: ctx.bucket_params.versioning_enabled(),
+		state: ObjectVersionState::Uploading {
+			encryption: ObjectVersionEncryption::Plaintext {
+				inner: meta_inner.clone(),
+			},
+			checksum_algorithm: None,
+			multipart: false,
+		},
+	};
+	let object = Object::new(*bucket_id, key.into(), vec![object_version]);
+	garage.object_table.insert(&object).await?;
+
+	// The new version references the blocks of the current version
+	// that are outside of the range
+	let mut version = Version::new(
+		version_uuid,
+		VersionBacklink::Object {
+			bucket_id: *bucket_id,
+			key: key.into(),
+		},
+		false,
+	);
+	garage.version_table.insert(&version).await?;
+	for (bk, bv) in blocks.iter() {
+		if bk.offset < start || bk.offset > end {
+			version.blocks.put(*bk, *bv);
+		}
+	}
+	let block_refs = version
+		.blocks
+		.items()
+		.iter()
+		.map(|(_, b)| BlockRef {
+			block: b.hash,
+			version: version_uuid,
+			deleted: false.into(),
+			bucket_id: Some(*bucket_id),
+		})
+		.collect::<Vec<_>>();
+	try_join!(
+		garage.version_table.insert(&version),
+		garage.block_ref_table.insert_many(&block_refs[..]),
+	)?;
+
+	// Write the new data in the blocks of the range
+	let checksummer = Checksummer::init(&expected_checksums, false);
+	let (size, _, checksums, range_first_block_hash) = read_and_put_blocks(
+		ctx,
+		&version,
+		EncryptionParams::Plaintext,
+		part_number,
+		start,
+		first_block,
+		&mut chunker,
+		checksummer,
+		content_encoded,
+		None,
+		durability,
+		None,
+	)
+	.await?;
+	if size != range_len {
+		return Err(Error::bad_request(
+			"Content-Range does not match the length of the body",
+		));
+	}
+	checksums.verify(&expected_checksums)?;
+
+	// The md5 of the whole data is unknown, so as for multipart uploads,
+	// the etag is derived from the hashes of the blocks
+	let version = garage
+		.version_table
+		.get(&version_uuid, &EmptyKey)
+		.await?
+		.ok_or_internal_error("Version not found after writing its blocks")?;
+	let mut md5 = Md5::new();
+	for (_, b) in version.blocks.items().iter() {
+		md5.update(b.hash.as_slice());
+	}
+	let etag = format!(
+		"{}-{}",
+		hex::encode(md5.finalize()),
+		version.blocks.items().len()
+	);
+
+	let first_block_hash = if start == 0 {
+		range_first_block_hash
+	} else {
+		first_block_hash
+	};
+
+	// The new version replaces the one that was patched: if another version
+	// was stored meanwhile, e.g. by a concurrent patch, it would be lost
+	let latest = garage
+		.object_table
+		.get(bucket_id, key)
+		.await?
+		.and_then(|o| o.versions().iter().rev().find(|v| v.is_complete()).cloned());
+	if latest.map(|v| v.uuid) != Some(current_uuid) {
+		return Err(Error::PreconditionFailed);
+	}
+
+	apply_put_hook(
+		&*garage.put_hook(),
+		*bucket_id,
+		key,
+		&mut meta_inner.headers,
+	)?;
+	let object_version = ObjectVersion {
+		uuid: version_uuid,
+		timestamp: version_timestamp,
+		created_at: Some(created_at),
+		versioned: ctx.bucket_params.versioning_enabled(),
+		state: ObjectVersionState::Complete(ObjectVersionData::FirstBlock(
+			ObjectVersionMeta {
+				encryption: ObjectVersionEncryption::Plaintext { inner: meta_inner },
+				size: meta.size,
+				etag: etag.clone(),
+			},
+			first_block_hash,
+		)),
+	};
+	let object = Object::new(*bucket_id, key.into(), vec![object_version]);
+	garage.object_table.insert(&object).await?;
+
+	interrupted_cleanup.cancel();
+
+	Ok((version_uuid, etag))
+}
+
+/// Store a new version of an object with the data read from a stream.
+/// The version gets the given timestamp if there is one, which must be
+/// more recent than all existing versions of the object.
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
+	ctx: &ReqCtx,
+	meta: ObjectVersionMetaInner,
+	encryption: EncryptionParams,
+	body: S,
+	block_size: usize,
+	key: &String,
+	checksum_mode: ChecksumMode<'_>,
+	version_timestamp: Option<u64>,
+	durability: Durability,
+) -> Result<SaveStreamResult, Error> {
+	let save = save_stream_inner(
+		ctx,
+		meta,
+		encryption,
+		body,
+		block_size,
+		key,
+		checksum_mode,
+		version_timestamp,
+		durability,
+	);
+	run_put(ctx, key, save).await
+}
+
+/// Run an upload that stores a new version of an object, applying the
+/// limits and the bucket options that concern all such uploads
+async fn run_put<T, F: Future<Output = Result<T, Error>>>(
+	ctx: &ReqCtx,
+	key: &str,
+	upload: F,
+) -> Result<T, Error> {
+	// In buckets with the serialize_puts option, an upload is rejected right
+	// away if another upload of the same object is running on this node
+	let _key_lock = match ctx.bucket_params.options.get().serialize_puts {
+		true => Some(PutKeyLock::acquire(&ctx.garage, ctx.bucket_id, key)?),
+		false => None,
+	};
+
+	let res = match ctx.garage.config.s3_api.put_max_duration {
+		// When the deadline is exceeded, the upload future is dropped,
+		// and InterruptedCleanup marks the new version as aborted
+		Some(max_duration) => tokio::time::timeout(max_duration, upload)
+			.await
+			.map_err(|_| Error::RequestTimeout)??,
+		None => upload.await?,
+	};
+
+	// The new version is stored, the upload succeeds even if the
+	// oldest versions can't be pruned now
+	if let Some(max_versions) = ctx.bucket_params.options.get().max_versions_per_key {
+		if let Err(e) = prune_object_versions(&ctx.garage, ctx.bucket_id, key, max_versions).await {
+			warn!("Cannot prune old versions of {}: {}", key, e);
+		}
+	}
+
+	Ok(res)
+}
+
+/// Keep at most `max_versions` complete versions of an object, counting
+/// delete markers, by marking the oldest ones as aborted. Aborted versions
+/// are removed from the object and the deletion of their data is propagated
+/// by the object table.
+pub(crate) async fn prune_object_versions(
+	garage: &Garage,
+	bucket_id: Uuid,
+	key: &str,
+	max_versions: u64,
+) -> Result<(), Error> {
+	let object = match garage
+		.object_table
+		.get(&bucket_id, &key.to_string())
+		.await?
+	{
+		Some(o) => o,
+		None => return Ok(()),
+	};
+	let complete = object
+		.versions()
+		.iter()
+		.filter(|v| v.is_complete())
+		.collect::<Vec<_>>();
+	let excess = complete.len().saturating_sub(max_versions as usize);
+	if excess == 0 {
+		return Ok(());
+	}
+
+	let pruned = complete[..excess]
+		.iter()
+		.map(|v| ObjectVersion {
+			state: ObjectVersionState::Aborted,
+			..(*v).clone()
+		})
+		.collect::<Vec<_>>();
+	let object = Object::new(bucket_id, key.into(), pruned);
+	garage.object_table.insert(&object).await?;
+	Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn save_stream_inner<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	ctx: &ReqCtx,
 	mut meta: ObjectVersionMetaInner,
 	encryption: EncryptionParams,
 	body: S,
+	block_size: usize,
 	key: &String,
 	checksum_mode: ChecksumMode<'_>,
+	version_timestamp: Option<u64>,
+	durability: Durability,
 ) -> Result<SaveStreamResult, Error> {
 	let ReqCtx {
-		garage, bucket_id, ..
+		garage,
+		bucket_id,
+		bucket_params,
+		..
 	} = ctx;
 
-	let mut chunker = StreamChunker::new(body, garage.config.block_size);
-	let (first_block_opt, existing_object) = try_join!(
+	// The size declared by the client can't be trusted, so the
+	// maximum object size is also enforced while reading the body
+	let body = limit_stream_size(body, garage.config.s3_api.max_object_size);
+
+	let mut chunker = StreamChunker::new(body, block_size);
+	if bucket_params.options.get().content_defined_chunking {
+		chunker = chunker.with_content_defined_chunking();
+	}
+	let (first_block_opt, existing_object, ()) = try_join!(
 		chunker.next(),
 		garage.object_table.get(bucket_id, key).map_err(Error::from),
+		check_key_conflict(ctx, key),
 	)?;
+	check_worm_overwrite(ctx, existing_object.as_ref())?;
 
 	let first_block = first_block_opt.unwrap_or_default();
 
 	// Generate identity of new version
 	let version_uuid = gen_uuid();
-	let version_timestamp = next_timestamp(existing_object.as_ref());
+	// The upload start time is recorded even if the client chooses the
+	// version timestamp, as it is used to find stale uploads
+	let created_at = now_msec();
+	let version_timestamp = match version_timestamp {
+		Some(ts) => {
+			check_object_timestamp(existing_object.as_ref(), ts)?;
+			ts
+		}
+		None => next_timestamp(existing_object.as_ref(), &garage.version_clock).await?,
+	};
+
+	// The md5 of the data is needed to compute the etag, unless the object
+	// is encrypted or the bucket uses blake2-based etags
+	let blake2_etag = bucket_params.options.get().blake2_etag;
+	let require_md5 = !encryption.is_encrypted() && !blake2_etag;
+	let weak_etag = blake2_etag && !encryption.is_encrypted();
+
+	let block_hashes = match checksum_mode {
+		ChecksumMode::Verify(expected) => {
+			check_block_hashes_allowed(expected, require_md5, None)?;
+			expected.block_hashes.as_deref()
+		}
+		ChecksumMode::Calculate(_) => None,
+	};
 
 	let mut checksummer = match checksum_mode {
-		ChecksumMode::Verify(expected) => Checksummer::init(expected, !encryption.is_encrypted()),
+		ChecksumMode::Verify(expected) => Checksummer::init(expected, require_md5),
 		ChecksumMode::Calculate(algo) => {
-			Checksummer::init(&Default::default(), !encryption.is_encrypted()).add(algo)
+			Checksummer::init(&Default::default(), require_md5).add(algo)
 		}
 	};
+	if blake2_etag {
+		checksummer = checksummer.add_blake2();
+	}
 
 	// If body is small enough, store it directly in the object table
-	// as "inline data". We can then return immediately.
-	if first_block.len() < INLINE_THRESHOLD {
-		checksummer.update(&first_block);
-		let checksums = checksummer.finalize();
+	// as "inline data" (unless the bucket requires data blocks for all
+	// objects). We can then return immediately.
+	let force_block_storage = bucket_params.options.get().force_block_storage;
+	let disable_inline = bucket_params.options.get().disable_inline;
+	if first_block.len() < INLINE_THRESHOLD && !force_block_storage && !disable_inline {
+		let checksums = if first_block.len() > INLINE_HASH_BLOCKING_THRESHOLD {
+			let block = first_block.clone();
+			let tracer = opentelemetry::global::tracer("garage");
+			tokio::task::spawn_blocking(move || {
+				checksummer.update(&block);
+				checksummer.finalize()
+			})
+			.with_context(Context::current_with_span(
+				tracer.start("Hash inline data (md5, sha256)"),
+			))
+			.await
+			.unwrap()
+		} else {
+			checksummer.update(&first_block);
+			checksummer.finalize()
+		};
 
 		match checksum_mode {
 			ChecksumMode::Verify(expected) => {
 				checksums.verify(&expected)?;
+				if meta.checksum.is_none() {
+					meta.checksum = checksums.extract(expected.algorithm);
+				}
 			}
 			ChecksumMode::Calculate(algo) => {
 				meta.checksum = checksums.extract(algo);
+				meta.checksum_type = ChecksumType::FullObject;
 			}
 		};
 
 		let size = first_block.len() as u64;
 		check_quotas(ctx, size, existing_object.as_ref()).await?;
+		apply_put_hook(&*garage.put_hook(), *bucket_id, key, &mut meta.headers)?;
 
-		let etag = encryption.etag_from_md5(&checksums.md5);
+		let etag = object_etag(&encryption, &checksums, blake2_etag);
 		let inline_data = encryption.encrypt_blob(&first_block)?.to_vec();
 
+		let version_data = inline_version_data(
+			garage,
+			&ctx.bucket_params,
+			version_uuid,
+			ObjectVersionMeta {
+				encryption: encryption.encrypt_meta(meta)?,
+				size,
+				etag: etag.clone(),
+			},
+			inline_data,
+		)
+		.await?;
+
 		let object_version = ObjectVersion {
 			uuid: version_uuid,
 			timestamp: version_timestamp,
-			state: ObjectVersionState::Complete(ObjectVersionData::Inline(
-				ObjectVersionMeta {
-					encryption: encryption.encrypt_meta(meta)?,
-					size,
-					etag: etag.clone(),
-				},
-				inline_data,
-			)),
+			created_at: Some(created_at),
+			versioned: bucket_params.versioning_enabled(),
+			state: ObjectVersionState::Complete(version_data),
 		};
 
-		let object = Object::new(*bucket_id, key.into(), vec![object_version]);
-		garage.object_table.insert(&object).await?;
+		garage
+			.object_helper()
+			.insert_object_version(*bucket_id, key, object_version)
+			.await?;
 
 		return Ok(SaveStreamResult {
 			version_uuid,
 			version_timestamp,
 			etag,
+			weak_etag,
+			size,
+			stored_size: Some(size),
 		});
 	}
 
+	// Only uploads that are not stored inline count towards the limit of
+	// concurrent uploads of the bucket, the slot is released when they end
+	let _upload_slot = UploadSlot::acquire(garage, *bucket_id)?;
+
 	// The following consists in many steps that can each fail.
 	// Keep track that some cleanup will be needed if things fail
 	// before everything is finished (cleanup is done using the Drop trait).
@@ -188,6 +831,8 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	let mut object_version = ObjectVersion {
 		uuid: version_uuid,
 		timestamp: version_timestamp,
+		created_at: Some(created_at),
+		versioned: bucket_params.versioning_enabled(),
 		state: ObjectVersionState::Uploading {
 			encryption: encryption.encrypt_meta(meta.clone())?,
 			checksum_algorithm: None, // don't care; overwritten later
@@ -211,33 +856,61 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	);
 	garage.version_table.insert(&version).await?;
 
-	// Transfer data
-	let (total_size, checksums, first_block_hash) = read_and_put_blocks(
-		ctx,
-		&version,
-		encryption,
-		1,
-		first_block,
-		&mut chunker,
-		checksummer,
-	)
-	.await?;
+	// Transfer data.
+	// If the body stream fails (e.g. the client drops a chunked upload
+	// midway), mark the version as aborted before returning the error,
+	// so that it is never left as uploading once the request is done.
+	// Empty objects of buckets that never store data inline keep an empty
+	// block list: no data block is written for them.
+	let transfer = if disable_inline && first_block.is_empty() {
+		Ok((0, 0, checksummer.finalize(), blake2sum(&[])))
+	} else {
+		read_and_put_blocks(
+			ctx,
+			&version,
+			encryption,
+			1,
+			0,
+			first_block,
+			&mut chunker,
+			checksummer,
+			is_content_encoded(&meta.headers),
+			block_hashes,
+			durability,
+			None,
+		)
+		.await
+	};
+	let (total_size, stored_size, checksums, first_block_hash) = match transfer {
+		Ok(res) => res,
+		Err(e) => {
+			interrupted_cleanup.abort().await;
+			return Err(e);
+		}
+	};
 
 	// Verify checksums are ok / add calculated checksum to metadata
 	match checksum_mode {
 		ChecksumMode::Verify(expected) => {
 			checksums.verify(&expected)?;
+			if meta.checksum.is_none() {
+				meta.checksum = checksums.extract(expected.algorithm);
+			}
 		}
 		ChecksumMode::Calculate(algo) => {
 			meta.checksum = checksums.extract(algo);
+			meta.checksum_type = ChecksumType::FullObject;
 		}
 	};
 
 	// Verify quotas are respsected
 	check_quotas(ctx, total_size, existing_object.as_ref()).await?;
 
+	// Let the put hook refuse the object or change its headers
+	apply_put_hook(&*garage.put_hook(), *bucket_id, key, &mut meta.headers)?;
+
 	// Save final object state, marked as Complete
-	let etag = encryption.etag_from_md5(&checksums.md5);
+	let etag = object_etag(&encryption, &checksums, blake2_etag);
 
 	object_version.state = ObjectVersionState::Complete(ObjectVersionData::FirstBlock(
 		ObjectVersionMeta {
@@ -258,6 +931,57 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 		version_uuid,
 		version_timestamp,
 		etag,
+		weak_etag,
+		size: total_size,
+		stored_size: Some(stored_size),
+	})
+}
+
+/// Build the data of a small object version. If the bucket has the `compact_inline`
+/// option, the data is written to the inline data table and only a reference to it
+/// is kept in the object table.
+pub(crate) async fn inline_version_data(
+	garage: &Garage,
+	bucket_params: &BucketParams,
+	version_uuid: Uuid,
+	meta: ObjectVersionMeta,
+	inline_data: Vec<u8>,
+) -> Result<ObjectVersionData, Error> {
+	Ok(garage
+		.object_helper()
+		.inline_version_data(bucket_params, version_uuid, meta, inline_data)
+		.await?)
+}
+
+fn object_etag(encryption: &EncryptionParams, checksums: &Checksums, blake2_etag: bool) -> String {
+	if blake2_etag {
+		let blake2sum = checksums
+			.blake2
+			.as_ref()
+			.expect("blake2 digest should have been computed");
+		encryption.etag_from_blake2(blake2sum)
+	} else {
+		encryption.etag_from_md5(&checksums.md5)
+	}
+}
+
+/// Returns the value of the x-amz-expiration header for a newly stored object,
+/// if a lifecycle rule of the bucket will expire it
+fn expiration_header(ctx: &ReqCtx, key: &str, res: &SaveStreamResult) -> Option<String> {
+	let lifecycle_config = ctx.bucket_params.lifecycle_config.get().as_ref()?;
+	let (date, rule) = lifecycle_config
+		.iter()
+		.filter_map(|rule| {
+			rule.expiration_date(key, res.size, res.version_timestamp)
+				.map(|date| (date, rule))
+		})
+		.min_by_key(|(date, _)| *date)?;
+
+	let expiry_ts = date.and_hms_opt(0, 0, 0)?.timestamp_millis() as u64;
+	let expiry_date = httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_millis(expiry_ts));
+	Some(match &rule.id {
+		Some(id) => format!("expiry-date=\"{}\", rule-id=\"{}\"", expiry_date, id),
+		None => format!("expiry-date=\"{}\"", expiry_date),
 	})
 }
 
@@ -267,28 +991,36 @@ pub(crate) async fn check_quotas(
 	size: u64,
 	prev_object: Option<&Object>,
 ) -> Result<(), Error> {
-	let ReqCtx {
-		garage,
-		bucket_id,
-		bucket_params,
-		..
-	} = ctx;
-
-	let quotas = bucket_params.quotas.get();
-	if quotas.max_objects.is_none() && quotas.max_size.is_none() {
-		return Ok(());
-	};
-
-	let counters = garage
-		.object_counter_table
-		.table
-		.get(bucket_id, &EmptyKey)
-		.await?;
+	check_quotas_with(
+		ctx.garage.quota_provider().as_ref(),
+		ctx.bucket_id,
+		&ctx.bucket_params.quotas.get(),
+		size,
+		prev_object,
+	)
+	.await
+}
 
-	let counters = counters
-		.map(|x| x.filtered_values(&garage.system.cluster_layout()))
-		.unwrap_or_default();
+/// Run a put hook on the headers of an object about to be committed
+fn apply_put_hook(
+	hook: &dyn PutHook,
+	bucket_id: Uuid,
+	key: &str,
+	headers: &mut HeaderList,
+) -> Result<(), Error> {
+	hook.before_commit(bucket_id, key, headers)
+		.map_err(|e| Error::bad_request(format!("Object rejected: {}", e)))
+}
 
+/// Ask a quota provider whether an object of the given size can replace
+/// the previous version of the object (if any) in a bucket
+async fn check_quotas_with(
+	provider: &dyn QuotaProvider,
+	bucket_id: Uuid,
+	quotas: &BucketQuotas,
+	size: u64,
+	prev_object: Option<&Object>,
+) -> Result<(), Error> {
 	let (prev_cnt_obj, prev_cnt_size) = match prev_object {
 		Some(o) => {
 			let prev_cnt = o.counts().into_iter().collect::<HashMap<_, _>>();
@@ -302,44 +1034,489 @@ pub(crate) async fn check_quotas(
 	let cnt_obj_diff = 1 - prev_cnt_obj;
 	let cnt_size_diff = size as i64 - prev_cnt_size;
 
-	if let Some(mo) = quotas.max_objects {
-		let current_objects = counters.get(OBJECTS).cloned().unwrap_or_default();
-		if cnt_obj_diff > 0 && current_objects + cnt_obj_diff > mo as i64 {
-			return Err(Error::forbidden(format!(
-				"Object quota is reached, maximum objects for this bucket: {}",
-				mo
-			)));
+	provider
+		.check(bucket_id, quotas, cnt_size_diff, cnt_obj_diff)
+		.await
+		.map_err(|e| match e {
+			QuotaError::Objects(msg) => Error::QuotaExceeded(QuotaType::Objects, msg),
+			QuotaError::Size(msg) => Error::QuotaExceeded(QuotaType::Size, msg),
+			QuotaError::Internal(e) => e.into(),
+		})
+}
+
+/// Block size to use for an upload: the one requested by the client in the
+/// x-garage-block-size header if it is within bounds, or the one from the
+/// configuration otherwise
+fn upload_block_size(garage: &Garage, headers: &HeaderMap) -> usize {
+	headers
+		.get(X_GARAGE_BLOCK_SIZE)
+		.and_then(|x| x.to_str().ok())
+		.and_then(|x| x.parse::<usize>().ok())
+		.filter(|x| (MIN_BLOCK_SIZE_OVERRIDE..=MAX_BLOCK_SIZE_OVERRIDE).contains(x))
+		.unwrap_or(garage.config.block_size)
+}
+
+/// Get the timestamp chosen by the client for the new version of an object,
+/// given in the x-garage-object-timestamp header as milliseconds since epoch
+fn request_object_timestamp(headers: &HeaderMap) -> Result<Option<u64>, Error> {
+	headers
+		.get(X_GARAGE_OBJECT_TIMESTAMP)
+		.map(|x| {
+			x.to_str()
+				.ok()
+				.and_then(|x| x.parse::<u64>().ok())
+				.ok_or_bad_request("Invalid x-garage-object-timestamp header")
+		})
+		.transpose()
+		.map_err(Error::from)
+}
+
+/// Get the range of an existing object to overwrite, given in a non-standard
+/// `Content-Range: bytes X-Y/*` header
+fn request_content_range(headers: &HeaderMap) -> Result<Option<(u64, u64)>, Error> {
+	let value = match headers.get(CONTENT_RANGE) {
+		Some(v) => v,
+		None => return Ok(None),
+	};
+	let range = value
+		.to_str()
+		.ok()
+		.and_then(|v| v.strip_prefix("bytes "))
+		.and_then(|v| v.strip_suffix("/*"))
+		.and_then(|v| v.split_once('-'))
+		.and_then(|(start, end)| Some((start.parse::<u64>().ok()?, end.parse::<u64>().ok()?)))
+		.filter(|(start, end)| start <= end)
+		.ok_or_bad_request("Invalid Content-Range header")?;
+	Ok(Some(range))
+}
+
+/// In write-once buckets, check that the object has no complete version
+/// that would be replaced by a new version
+pub(crate) fn check_worm_overwrite(
+	ctx: &ReqCtx,
+	existing_object: Option<&Object>,
+) -> Result<(), Error> {
+	let has_data = existing_object
+		.map(|obj| obj.versions().iter().any(|v| v.is_data()))
+		.unwrap_or(false);
+	if ctx.bucket_params.options.get().worm && has_data {
+		return Err(Error::forbidden(
+			"This bucket is write-once: existing objects cannot be overwritten",
+		));
+	}
+	Ok(())
+}
+
+/// Exclusive right to upload an object through this node, which is
+/// released when the upload completes or is aborted
+struct PutKeyLock<'a> {
+	garage: &'a Garage,
+	key: (Uuid, String),
+}
+
+impl<'a> PutKeyLock<'a> {
+	fn acquire(garage: &'a Garage, bucket_id: Uuid, key: &str) -> Result<Self, Error> {
+		let key = (bucket_id, key.to_string());
+		if !garage.put_key_locks.lock().unwrap().insert(key.clone()) {
+			return Err(Error::SlowDown);
 		}
+		Ok(Self { garage, key })
+	}
+}
+
+impl<'a> Drop for PutKeyLock<'a> {
+	fn drop(&mut self) {
+		self.garage.put_key_locks.lock().unwrap().remove(&self.key);
 	}
+}
 
-	if let Some(ms) = quotas.max_size {
-		let current_size = counters.get(BYTES).cloned().unwrap_or_default();
-		if cnt_size_diff > 0 && current_size + cnt_size_diff > ms as i64 {
-			return Err(Error::forbidden(format!(
-				"Bucket size quota is reached, maximum total size of objects for this bucket: {}. The bucket is already {} bytes, and this object would add {} bytes.",
-				ms, current_size, cnt_size_diff
-			)));
+/// Check the conditions on the existing object that a PutObject request
+/// sets: an `If-None-Match: *` header requires that the object does not
+/// exist, and objects of write-once buckets cannot be overwritten.
+/// Only the latter is checked again once the body has been received.
+async fn check_put_preconditions(
+	ctx: &ReqCtx,
+	key: &str,
+	headers: &HeaderMap,
+) -> Result<(), Error> {
+	let if_none_match = match headers.get(IF_NONE_MATCH) {
+		None => false,
+		Some(x) if x == "*" => true,
+		Some(_) => {
+			return Err(Error::NotImplemented(
+				"If-None-Match is only supported with value *".into(),
+			))
 		}
+	};
+	if !if_none_match && !ctx.bucket_params.options.get().worm {
+		return Ok(());
 	}
 
+	let existing_object = ctx
+		.garage
+		.object_table
+		.get(&ctx.bucket_id, &key.to_string())
+		.await?;
+	check_worm_overwrite(ctx, existing_object.as_ref())?;
+
+	let has_data = existing_object
+		.map(|obj| obj.versions().iter().any(|v| v.is_data()))
+		.unwrap_or(false);
+	if if_none_match && has_data {
+		return Err(Error::PreconditionFailed);
+	}
 	Ok(())
 }
 
+/// Get the durability requested by the client for the blocks of an upload,
+/// given in the x-garage-durable header as either `strict` or `relaxed`
+pub(crate) fn request_durability(headers: &HeaderMap) -> Result<Durability, Error> {
+	match headers.get(X_GARAGE_DURABLE).map(|x| x.to_str()) {
+		None => Ok(Durability::Relaxed),
+		Some(Ok("relaxed")) => Ok(Durability::Relaxed),
+		Some(Ok("strict")) => Ok(Durability::Strict),
+		Some(_) => Err(Error::bad_request("Invalid x-garage-durable header")),
+	}
+}
+
+/// Get whether the client asked for the body of a PutObject request to be
+/// decompressed before it is stored, with `x-garage-decode-content: gzip`
+fn request_decode_gzip(headers: &HeaderMap) -> Result<bool, Error> {
+	match headers.get(X_GARAGE_DECODE_CONTENT).map(|x| x.to_str()) {
+		None => Ok(false),
+		Some(Ok("gzip")) => Ok(true),
+		Some(_) => Err(Error::bad_request("Invalid x-garage-decode-content header")),
+	}
+}
+
+/// Decompress a gzip-encoded request body. The checksums given by the client
+/// are verified on the gzip data, once all of it has been read, so that the
+/// decoded stream fails if they don't match. Errors of the body itself are
+/// returned as they are, and invalid or truncated gzip data is a bad request.
+fn decode_gzip_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
+	stream: S,
+	expected: ExpectedChecksums,
+) -> impl Stream<Item = Result<Bytes, Error>> + Unpin {
+	let body_error = Arc::new(Mutex::new(None));
+
+	let checksummer = Checksummer::init(&expected, false);
+	let body = futures::stream::try_unfold(
+		(stream, checksummer, expected),
+		|(mut stream, mut checksummer, expected)| async move {
+			match stream.next().await {
+				Some(chunk) => {
+					let chunk = chunk?;
+					checksummer.update(&chunk);
+					Ok(Some((chunk, (stream, checksummer, expected))))
+				}
+				None => {
+					checksummer.finalize().verify(&expected)?;
+					Ok(None)
+				}
+			}
+		},
+	);
+	let body_error2 = body_error.clone();
+	let body = Box::pin(body).map_err(move |e: Error| {
+		let msg = e.to_string();
+		*body_error2.lock().unwrap() = Some(e);
+		std::io::Error::new(std::io::ErrorKind::Other, msg)
+	});
+
+	let mut decoder = async_compression::tokio::bufread::GzipDecoder::new(
+		tokio_util::io::StreamReader::new(body),
+	);
+	// Read the body up to its end, so that its checksums are verified
+	// and that trailing data that isn't gzip is rejected
+	decoder.multiple_members(true);
+
+	tokio_util::io::ReaderStream::new(decoder).map_err(move |e| {
+		match body_error.lock().unwrap().take() {
+			Some(e) => e,
+			None => Error::bad_request(format!("Invalid gzip body: {}", e)),
+		}
+	})
+}
+
+/// Get the trace id given by the client in the x-garage-trace-id header,
+/// as 32 hexadecimal characters
+fn request_trace_id(headers: &HeaderMap) -> Result<Option<TraceId>, Error> {
+	headers
+		.get(X_GARAGE_TRACE_ID)
+		.map(|v| {
+			v.to_str()
+				.ok()
+				.filter(|x| x.len() == 32)
+				.and_then(|x| TraceId::from_hex(x).ok())
+				.filter(|id| *id != TraceId::INVALID)
+				.ok_or_bad_request("Invalid x-garage-trace-id header")
+		})
+		.transpose()
+		.map_err(Error::from)
+}
+
+/// Build the context in which an upload is processed, so that the spans of
+/// the upload belong to the given trace, which can be chosen by the client to
+/// correlate them with its own logs. The span of the upload is linked to the
+/// span of the API call, which belongs to another trace.
+fn upload_trace_context(trace_id: TraceId) -> Context {
+	let tracer = opentelemetry::global::tracer("garage");
+	let parent = SpanContext::new(
+		trace_id,
+		SpanId::from_bytes(rand::random()),
+		TraceFlags::SAMPLED,
+		true,
+		TraceState::default(),
+	);
+	let span = tracer
+		.span_builder("Upload object data")
+		.with_links(vec![Link::new(
+			Context::current().span().span_context().clone(),
+			vec![],
+		)])
+		.start_with_context(&tracer, &Context::new().with_remote_span_context(parent));
+	Context::current_with_span(span)
+}
+
+/// Check that a timestamp chosen by the client is more recent than all
+/// existing versions of the object, so that the new version becomes the
+/// current one regardless of the order in which requests are received
+fn check_object_timestamp(existing_object: Option<&Object>, timestamp: u64) -> Result<(), Error> {
+	let newer_exists = existing_object
+		.map(|obj| obj.versions().iter().any(|v| v.timestamp >= timestamp))
+		.unwrap_or(false);
+	if newer_exists {
+		return Err(Error::ObjectTimestampConflict);
+	}
+	Ok(())
+}
+
+/// Check that a timestamp chosen by the client is not further in the future than
+/// the allowed clock skew. The versions of the object created afterwards without
+/// a timestamp given by the client are timestamped after it, so they would all be
+/// moved to the future too.
+fn check_object_timestamp_skew(timestamp: u64, now: u64, max_skew: Duration) -> Result<(), Error> {
+	let max_skew_msec = u64::try_from(max_skew.as_millis()).unwrap_or(u64::MAX);
+	if timestamp > now.saturating_add(max_skew_msec) {
+		return Err(Error::bad_request(
+			"x-garage-object-timestamp is too far in the future",
+		));
+	}
+	Ok(())
+}
+
+/// Check that an object key can be used to create or delete an object:
+/// its UTF-8 encoding must not be longer than the configured maximum,
+/// and it must not contain control characters (including NUL)
+pub(crate) fn check_object_key(garage: &Garage, key: &str) -> Result<(), Error> {
+	validate_object_key(key, garage.config.s3_api.max_key_length)
+}
+
+fn validate_object_key(key: &str, max_key_length: usize) -> Result<(), Error> {
+	if key.len() > max_key_length {
+		return Err(Error::KeyTooLong(max_key_length));
+	}
+	if key.chars().any(char::is_control) {
+		return Err(Error::InvalidObjectKey(
+			"it must not contain control characters".into(),
+		));
+	}
+	Ok(())
+}
+
+/// If the bucket rejects key conflicts, check that no object exists whose key
+/// only differs from the given key by a trailing slash (`a/b` vs. `a/b/`)
+pub(crate) async fn check_key_conflict(ctx: &ReqCtx, key: &str) -> Result<(), Error> {
+	if !ctx.bucket_params.options.get().reject_key_conflicts {
+		return Ok(());
+	}
+
+	let other_key = match key.strip_suffix('/') {
+		Some(k) => k.to_string(),
+		None => format!("{}/", key),
+	};
+	if other_key.is_empty() {
+		return Ok(());
+	}
+
+	let other_object = ctx
+		.garage
+		.object_table
+		.get(&ctx.bucket_id, &other_key)
+		.await?;
+	let other_exists = other_object
+		.map(|obj| obj.versions().iter().any(|v| v.is_data()))
+		.unwrap_or(false);
+	if other_exists {
+		return Err(Error::KeyConflict(other_key));
+	}
+	Ok(())
+}
+
+/// Get the hashes of the data blocks of an upload, as supplied by the client in the
+/// x-garage-block-hashes header (comma-separated hex-encoded blake2 hashes).
+/// Only keys that are explicitly trusted to do so can supply block hashes.
+pub(crate) fn request_block_hashes(
+	ctx: &ReqCtx,
+	headers: &HeaderMap,
+) -> Result<Option<Vec<Hash>>, Error> {
+	let value = match headers.get(X_GARAGE_BLOCK_HASHES) {
+		Some(v) => v.to_str()?,
+		None => return Ok(None),
+	};
+	let trusted = ctx
+		.api_key
+		.params()
+		.map(|p| *p.allow_trusted_block_hashes.get())
+		.unwrap_or(false);
+	if !trusted {
+		return Err(Error::forbidden(
+			"This key is not allowed to supply block hashes",
+		));
+	}
+	value
+		.split(',')
+		.map(|h| {
+			hex::decode(h.trim())
+				.ok()
+				.and_then(|h| Hash::try_from(&h[..]))
+				.ok_or_bad_request("Invalid x-garage-block-hashes header")
+				.map_err(Error::from)
+		})
+		.collect::<Result<Vec<_>, Error>>()
+		.map(Some)
+}
+
+/// Check that the hashes of data blocks can be supplied for an upload. The data
+/// of blocks whose supplied hash is trusted is discarded, so the upload must not
+/// have an md5 digest or a checksum that would be computed from that data.
+pub(crate) fn check_block_hashes_allowed(
+	expected: &ExpectedChecksums,
+	require_md5: bool,
+	checksum_algorithm: Option<ChecksumAlgorithm>,
+) -> Result<(), Error> {
+	if expected.block_hashes.is_some()
+		&& (require_md5
+			|| expected.md5.is_some()
+			|| expected.extra.is_some()
+			|| expected.algorithm.is_some()
+			|| checksum_algorithm.is_some())
+	{
+		return Err(Error::bad_request(
+			"Block hashes cannot be supplied for uploads that compute an md5 digest or a checksum",
+		));
+	}
+	Ok(())
+}
+
+/// Check that the request includes a content-md5 header,
+/// if the bucket requires one for all uploads
+pub(crate) fn check_content_md5_required(
+	ctx: &ReqCtx,
+	expected_checksums: &ExpectedChecksums,
+) -> Result<(), Error> {
+	if ctx.bucket_params.options.get().require_content_md5 && expected_checksums.md5.is_none() {
+		return Err(Error::bad_request(
+			"Missing required header for this request: Content-MD5",
+		));
+	}
+	Ok(())
+}
+
+/// Check that an object of the given size does not exceed the maximum
+/// object size set in the configuration
+pub(crate) fn check_object_size(garage: &Garage, size: u64) -> Result<(), Error> {
+	match garage.config.s3_api.max_object_size {
+		Some(max_size) if size > max_size => Err(Error::EntityTooLarge),
+		_ => Ok(()),
+	}
+}
+
+/// Make a stream fail with `EntityTooLarge` as soon as more than
+/// `max_size` bytes have been read from it
+pub(crate) fn limit_stream_size<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
+	stream: S,
+	max_size: Option<u64>,
+) -> impl Stream<Item = Result<Bytes, Error>> + Unpin {
+	let mut read_size = 0u64;
+	stream.map(move |chunk| {
+		let chunk = chunk?;
+		read_size += chunk.len() as u64;
+		match max_size {
+			Some(max_size) if read_size > max_size => Err(Error::EntityTooLarge),
+			_ => Ok(chunk),
+		}
+	})
+}
+
+/// Check bucket quotas for an object of the given size, before any of its
+/// data has been received. The final check is still done by `check_quotas`
+/// once the actual size of the object is known.
+pub(crate) async fn check_quotas_preflight(
+	ctx: &ReqCtx,
+	key: &str,
+	size: u64,
+) -> Result<(), Error> {
+	let existing_object = ctx
+		.garage
+		.object_table
+		.get(&ctx.bucket_id, &key.to_string())
+		.await?;
+	check_quotas(ctx, size, existing_object.as_ref()).await
+}
+
+/// Returns the size of the object's data as declared by the client, i.e.
+/// `x-amz-decoded-content-length` for aws-chunked bodies, and `content-length`
+/// otherwise.
+pub(crate) fn declared_content_length(headers: &HeaderMap) -> Result<Option<u64>, Error> {
+	let value = match headers.get(X_AMZ_DECODED_CONTENT_LENGTH) {
+		Some(x) => x,
+		None => match headers.get(CONTENT_LENGTH) {
+			Some(x) => x,
+			None => return Ok(None),
+		},
+	};
+	let size = value
+		.to_str()
+		.ok()
+		.and_then(|x| x.parse::<u64>().ok())
+		.ok_or_bad_request("Invalid content length")?;
+	Ok(Some(size))
+}
+
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	ctx: &ReqCtx,
 	version: &Version,
 	encryption: EncryptionParams,
 	part_number: u64,
+	offset: u64,
 	first_block: Bytes,
 	chunker: &mut StreamChunker<S>,
 	checksummer: Checksummer,
-) -> Result<(u64, Checksums, Hash), Error> {
+	content_encoded: bool,
+	block_hashes: Option<&[Hash]>,
+	durability: Durability,
+	progress: Option<&watch::Sender<u64>>,
+) -> Result<(u64, u64, Checksums, Hash), Error> {
 	let tracer = opentelemetry::global::tracer("garage");
 
-	let (block_tx, mut block_rx) = mpsc::channel::<Result<Bytes, Error>>(2);
+	if block_hashes.is_some() && encryption.is_encrypted() {
+		return Err(Error::bad_request(
+			"Block hashes cannot be supplied for encrypted objects",
+		));
+	}
+
+	// The channel between the client and the hashing stage holds one more block,
+	// so that the default depth of 1 keeps channel capacities of 2, 1 and 1
+	let depth = ctx.garage.config.s3_api.put_pipeline_depth;
+	let budget = BlockBudget::new(&ctx.garage, chunker.block_size);
+	let (block_tx, block_rx) = StageSender::<Result<Bytes, Error>>::channel(depth + 1, "hash");
 	let read_blocks = async {
+		budget.hold(budget.reserve().await);
 		block_tx.send(Ok(first_block)).await?;
 		loop {
+			let permit = budget.reserve().await;
 			let res = chunker
 				.next()
 				.with_context(Context::current_with_span(
@@ -347,7 +1524,10 @@ pub(crate) async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> +
 				))
 				.await;
 			match res {
-				Ok(Some(block)) => block_tx.send(Ok(block)).await?,
+				Ok(Some(block)) => {
+					budget.hold(permit);
+					block_tx.send(Ok(block)).await?
+				}
 				Ok(None) => break,
 				Err(e) => {
 					block_tx.send(Err(e)).await?;
@@ -359,8 +1539,12 @@ pub(crate) async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> +
 		Ok::<_, mpsc::error::SendError<_>>(())
 	};
 
-	let (block_tx2, mut block_rx2) = mpsc::channel::<Result<Bytes, Error>>(1);
+	let (block_tx2, block_rx2) = StageSender::<Result<Bytes, Error>>::channel(depth, "encrypt");
+	// Each stage owns the receiving half of its input channel, so that it is
+	// dropped when the stage stops because of an error: the previous stages
+	// then fail to send instead of waiting forever.
 	let hash_stream = async {
+		let mut block_rx = block_rx;
 		let mut checksummer = checksummer;
 		while let Some(next) = block_rx.recv().await {
 			match next {
@@ -386,124 +1570,383 @@ pub(crate) async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> +
 		Ok::<_, mpsc::error::SendError<_>>(checksummer)
 	};
 
-	let (block_tx3, mut block_rx3) = mpsc::channel::<Result<(Bytes, u64, Hash), Error>>(1);
+	let (block_tx3, block_rx3) = StageSender::<Result<HashedBlock, Error>>::channel(depth, "put");
 	let encrypt_hash_blocks = async {
+		let supplied_hashes = block_hashes.map(|hashes| SuppliedBlockHashes {
+			hashes,
+			garage: &ctx.garage,
+			bucket_id: ctx.bucket_id,
+		});
+		let mut blocks = encrypt_and_hash_blocks(
+			block_rx2,
+			encryption,
+			supplied_hashes,
+			ctx.garage.config.s3_api.put_hash_parallelism,
+		);
 		let mut first_block_hash = None;
-		while let Some(next) = block_rx2.recv().await {
-			match next {
-				Ok(block) => {
-					let unencrypted_len = block.len() as u64;
-					let res = tokio::task::spawn_blocking(move || {
-						let block = encryption.encrypt_block(block)?;
-						let hash = blake2sum(&block);
-						Ok((block, hash))
-					})
-					.with_context(Context::current_with_span(
-						tracer.start("Encrypt and hash (blake2) block"),
-					))
-					.await
-					.unwrap();
-					match res {
-						Ok((block, hash)) => {
-							if first_block_hash.is_none() {
-								first_block_hash = Some(hash);
-							}
-							block_tx3.send(Ok((block, unencrypted_len, hash))).await?;
-						}
-						Err(e) => {
-							block_tx3.send(Err(e)).await?;
-							break;
-						}
-					}
+		let mut block_count = 0;
+		while let Some(res) = blocks.next().await {
+			match res {
+				Ok((block, unencrypted_len, hash)) => {
+					first_block_hash.get_or_insert(hash);
+					block_count += 1;
+					block_tx3.send(Ok((block, unencrypted_len, hash))).await?;
 				}
 				Err(e) => {
 					block_tx3.send(Err(e)).await?;
-					break;
+					drop(block_tx3);
+					return Ok(first_block_hash);
 				}
 			}
 		}
+		if let Some(hashes) = block_hashes {
+			if block_count < hashes.len() {
+				block_tx3
+					.send(Err(Error::InvalidDigest(format!(
+						"Fewer data blocks ({}) than supplied block hashes ({})",
+						block_count,
+						hashes.len()
+					))))
+					.await?;
+			}
+		}
 		drop(block_tx3);
-		Ok::<_, mpsc::error::SendError<_>>(first_block_hash.unwrap())
+		Ok::<_, mpsc::error::SendError<_>>(first_block_hash)
 	};
 
-	let put_blocks = async {
-		// Structure for handling several concurrent writes to storage nodes
-		let order_stream = OrderTag::stream();
-		let mut write_futs = FuturesOrdered::new();
-		let mut written_bytes = 0u64;
-		loop {
-			// Simultaneously write blocks to storage nodes & await for next block to be written
-			let currently_running = write_futs.len();
-			let write_futs_next = async {
-				if write_futs.is_empty() {
-					futures::future::pending().await
-				} else {
-					write_futs.next().await.unwrap()
-				}
-			};
-			let recv_next = async {
-				// If more than a maximum number of writes are in progress, don't add more for now
-				if currently_running >= PUT_BLOCKS_MAX_PARALLEL {
-					futures::future::pending().await
-				} else {
-					block_rx3.recv().await
-				}
-			};
-			let (block, unencrypted_len, hash) = tokio::select! {
-				result = write_futs_next => {
-					result?;
-					continue;
-				},
-				recv = recv_next => match recv {
-					Some(next) => next?,
-					None => break,
-				},
-			};
-
-			// For next block to be written: count its size and spawn future to write it
-			write_futs.push_back(put_block_and_meta(
+	let put_blocks = put_blocks_ordered(
+		block_rx3,
+		offset,
+		progress,
+		|offset, block, size, hash, order_tag| {
+			let permit = budget.release_next();
+			let put = put_block_and_meta(
 				ctx,
 				version,
 				part_number,
-				written_bytes,
+				offset,
 				hash,
 				block,
-				unencrypted_len,
+				size,
 				encryption.is_encrypted(),
-				order_stream.order(written_bytes),
-			));
-			written_bytes += unencrypted_len;
-		}
-		while let Some(res) = write_futs.next().await {
-			res?;
-		}
-		Ok::<_, Error>(written_bytes)
-	};
+				// Don't compress encrypted blocks, or data that the client
+				// has already compressed
+				encryption.is_encrypted() || content_encoded,
+				order_tag,
+				durability,
+			);
+			async move {
+				let res = put.await;
+				drop(permit);
+				res
+			}
+		},
+	);
 
 	let (_, stream_hash_result, block_hash_result, final_result) =
 		futures::join!(read_blocks, hash_stream, encrypt_hash_blocks, put_blocks);
 
-	let total_size = final_result?;
+	let (total_size, stored_size) = final_result?;
 	// unwrap here is ok, because if hasher failed, it is because something failed
 	// later in the pipeline which already caused a return at the ? on previous line
-	let first_block_hash = block_hash_result.unwrap();
+	// (the first block hash is missing only if the first block could not be
+	// encrypted or hashed, which also caused a return at the ? on previous line)
+	let first_block_hash = block_hash_result.unwrap().unwrap();
 	let checksums = stream_hash_result.unwrap().finalize();
 
-	Ok((total_size, checksums, first_block_hash))
+	Ok((total_size, stored_size, checksums, first_block_hash))
+}
+
+/// Write the data blocks received from a channel to storage nodes using `put_block`,
+/// with up to PUT_BLOCKS_MAX_PARALLEL writes in progress at the same time.
+/// Blocks without data are already stored, `put_block` only references them.
+/// The first block is at the given offset in its part. The order tags given to
+/// blocks all belong to the same stream and follow their offsets, so that storage
+/// nodes receive them in order even though they are sent concurrently.
+/// If a progress channel is given, it is updated with the number of bytes of the
+/// blocks whose write is complete, up to the total size once all are written.
+/// Returns the total size of the blocks, and the number of bytes that `put_block`
+/// reported as stored for them.
+async fn put_blocks_ordered<F, Fut>(
+	mut block_rx: mpsc::Receiver<Result<HashedBlock, Error>>,
+	offset: u64,
+	progress: Option<&watch::Sender<u64>>,
+	mut put_block: F,
+) -> Result<(u64, u64), Error>
+where
+	F: FnMut(u64, Option<Bytes>, u64, Hash, OrderTag) -> Fut,
+	Fut: Future<Output = Result<u64, GarageError>>,
+{
+	// Structure for handling several concurrent writes to storage nodes
+	let order_stream = OrderTag::stream();
+	let mut write_futs = FuturesOrdered::new();
+	let mut written_bytes = offset;
+	let mut stored_bytes = 0;
+	let mut completed_bytes = 0;
+	let mut complete = |len: u64| {
+		completed_bytes += len;
+		if let Some(progress) = progress {
+			progress.send_replace(completed_bytes);
+		}
+	};
+	loop {
+		// Simultaneously write blocks to storage nodes & await for next block to be written
+		let currently_running = write_futs.len();
+		let write_futs_next = async {
+			if write_futs.is_empty() {
+				futures::future::pending().await
+			} else {
+				write_futs.next().await.unwrap()
+			}
+		};
+		let recv_next = async {
+			// If more than a maximum number of writes are in progress, don't add more for now
+			if currently_running >= PUT_BLOCKS_MAX_PARALLEL {
+				futures::future::pending().await
+			} else {
+				block_rx.recv().await
+			}
+		};
+		let (block, unencrypted_len, hash) = tokio::select! {
+			result = write_futs_next => {
+				let (stored, len) = result?;
+				stored_bytes += stored;
+				complete(len);
+				continue;
+			},
+			recv = recv_next => match recv {
+				Some(next) => next?,
+				None => break,
+			},
+		};
+
+		// For next block to be written: count its size and spawn future to write it
+		let order_tag = order_stream.order(written_bytes);
+		trace!(
+			"Writing block {:?} at offset {} with order tag {:?}",
+			hash,
+			written_bytes,
+			order_tag
+		);
+		let write = put_block(written_bytes, block, unencrypted_len, hash, order_tag);
+		write_futs.push_back(write.map_ok(move |stored| (stored, unencrypted_len)));
+		written_bytes += unencrypted_len;
+	}
+	while let Some(res) = write_futs.next().await {
+		let (stored, len) = res?;
+		stored_bytes += stored;
+		complete(len);
+	}
+	if let Some(progress) = progress {
+		progress.send_replace(written_bytes - offset);
+	}
+	Ok((written_bytes - offset, stored_bytes))
+}
+
+/// A data block ready to be written: its data (`None` if it is already stored),
+/// its size and its hash
+type HashedBlock = (Option<Bytes>, u64, Hash);
+
+/// Hashes of the data blocks of an upload, as supplied by the client
+#[derive(Clone, Copy)]
+struct SuppliedBlockHashes<'a> {
+	hashes: &'a [Hash],
+	/// Used to check whether a block is already stored and referenced
+	garage: &'a Garage,
+	/// Bucket of the object being uploaded
+	bucket_id: Uuid,
+}
+
+/// Encrypt the data blocks received from a channel and compute their blake2 hash,
+/// or check the hashes supplied by the client. Up to `parallelism` blocks are
+/// processed at the same time on the blocking thread pool, and the resulting
+/// stream yields them in the order in which they were received.
+///
+/// A supplied hash is trusted without hashing the block only if a block with that
+/// hash is already stored on this node and referenced by an object of the same
+/// bucket. The data received for such a block is not yielded, so that it is never
+/// written under a hash that it may not match.
+fn encrypt_and_hash_blocks(
+	block_rx: mpsc::Receiver<Result<Bytes, Error>>,
+	encryption: EncryptionParams,
+	block_hashes: Option<SuppliedBlockHashes<'_>>,
+	parallelism: usize,
+) -> impl Stream<Item = Result<HashedBlock, Error>> + Unpin + '_ {
+	let tracer = opentelemetry::global::tracer("garage");
+	let mut block_index = 0;
+	tokio_stream::wrappers::ReceiverStream::new(block_rx)
+		.map(move |next| {
+			let index = block_index;
+			block_index += 1;
+			let span = tracer.start("Encrypt and hash (blake2) block");
+			async move {
+				let block = next?;
+				let unencrypted_len = block.len() as u64;
+				let supplied_hash = match block_hashes {
+					Some(supplied) => Some(*supplied.hashes.get(index).ok_or_else(|| {
+						Error::InvalidDigest(format!(
+							"More data blocks than supplied block hashes ({})",
+							supplied.hashes.len()
+						))
+					})?),
+					None => None,
+				};
+				let spot_check =
+					index == 0 || rand::random::<u32>() % BLOCK_HASH_SPOT_CHECK_RATIO == 0;
+				if let (Some(supplied), Some(hash), false) =
+					(block_hashes, supplied_hash, spot_check)
+				{
+					if supplied.garage.block_manager.get_block_rc(&hash)? > 0
+						&& is_block_referenced_by_bucket(
+							&supplied.garage.block_ref_table,
+							&hash,
+							supplied.bucket_id,
+						)
+						.await?
+					{
+						return Ok((None, unencrypted_len, hash));
+					}
+				}
+				let (block, hash) = tokio::task::spawn_blocking(move || {
+					let block = encryption.encrypt_block(block)?;
+					let hash = blake2sum(&block);
+					if supplied_hash.map(|s| s != hash).unwrap_or(false) {
+						return Err(Error::InvalidDigest(format!(
+							"Supplied hash of block {} does not match its data",
+							index
+						)));
+					}
+					Ok((block, hash))
+				})
+				.with_context(Context::current_with_span(span))
+				.await
+				.unwrap()?;
+				Ok((Some(block), unencrypted_len, hash))
+			}
+		})
+		.buffered(parallelism)
+		.boxed()
+}
+
+/// Sending half of a channel between two stages of the pipeline of
+/// `read_and_put_blocks`, which counts the times a send has to wait
+/// because the next stage is not keeping up
+struct StageSender<T> {
+	tx: mpsc::Sender<T>,
+	/// Name of the stage that receives from this channel
+	stage: &'static str,
+	backpressure: AtomicU64,
+}
+
+impl<T> StageSender<T> {
+	fn channel(buffer: usize, stage: &'static str) -> (Self, mpsc::Receiver<T>) {
+		let (tx, rx) = mpsc::channel(buffer);
+		let sender = StageSender {
+			tx,
+			stage,
+			backpressure: AtomicU64::new(0),
+		};
+		(sender, rx)
+	}
+
+	async fn send(&self, value: T) -> Result<(), mpsc::error::SendError<T>> {
+		match self.tx.try_send(value) {
+			Ok(()) => Ok(()),
+			Err(mpsc::error::TrySendError::Full(value)) => {
+				self.backpressure.fetch_add(1, Ordering::Relaxed);
+				S3ApiMetrics::get()
+					.put_pipeline_backpressure
+					.add(1, &[KeyValue::new("stage", self.stage)]);
+				self.tx.send(value).await
+			}
+			Err(mpsc::error::TrySendError::Closed(value)) => Err(mpsc::error::SendError(value)),
+		}
+	}
+
+	#[cfg(test)]
+	fn backpressure(&self) -> u64 {
+		self.backpressure.load(Ordering::Relaxed)
+	}
+}
+
+/// Share of the memory budget of all uploads (s3_api.put_memory_budget)
+/// taken by the data blocks of an upload. The share of a block is reserved
+/// before it is read from the client, and released once the block has been
+/// written to the storage nodes, so that uploads slow down instead of
+/// buffering more blocks when the budget is exhausted.
+struct BlockBudget {
+	semaphore: Option<Arc<Semaphore>>,
+	/// Bytes reserved for each block
+	block_permits: u32,
+	/// Shares of the blocks that are in the pipeline, oldest first
+	held: Mutex<VecDeque<OwnedSemaphorePermit>>,
+}
+
+impl BlockBudget {
+	fn new(garage: &Garage, block_size: usize) -> Self {
+		let budget = garage.config.s3_api.put_memory_budget.unwrap_or_default();
+		// A block never reserves more than the whole budget, so that uploads
+		// with a larger block size than the configured one can still proceed
+		let block_permits = std::cmp::min(block_size as u64, budget);
+		Self::with_semaphore(
+			garage.put_memory_budget.clone(),
+			std::cmp::min(block_permits, u32::MAX as u64) as u32,
+		)
+	}
+
+	fn with_semaphore(semaphore: Option<Arc<Semaphore>>, block_permits: u32) -> Self {
+		Self {
+			semaphore,
+			block_permits,
+			held: Mutex::new(VecDeque::new()),
+		}
+	}
+
+	/// Wait until the share of a block can be taken from the budget
+	async fn reserve(&self) -> Option<OwnedSemaphorePermit> {
+		let semaphore = self.semaphore.clone()?;
+		let permit = semaphore
+			.acquire_many_owned(self.block_permits)
+			.await
+			.expect("put memory budget is never closed");
+		Some(permit)
+	}
+
+	/// Keep the share of a block that enters the pipeline
+	fn hold(&self, permit: Option<OwnedSemaphorePermit>) {
+		if let Some(permit) = permit {
+			self.held.lock().unwrap().push_back(permit);
+		}
+	}
+
+	/// Take the share of the oldest block in the pipeline,
+	/// which is given back to the budget when dropped
+	fn release_next(&self) -> Option<OwnedSemaphorePermit> {
+		self.held.lock().unwrap().pop_front()
+	}
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn put_block_and_meta(
 	ctx: &ReqCtx,
 	version: &Version,
 	part_number: u64,
 	offset: u64,
 	hash: Hash,
-	block: Bytes,
+	block: Option<Bytes>,
 	size: u64,
-	is_encrypted: bool,
+	encrypted: bool,
+	prevent_compression: bool,
 	order_tag: OrderTag,
-) -> Result<(), GarageError> {
-	let ReqCtx { garage, .. } = ctx;
+	durability: Durability,
+) -> Result<u64, GarageError> {
+	let ReqCtx {
+		garage,
+		bucket_id,
+		bucket_params,
+		..
+	} = ctx;
+	let allow_degraded = bucket_params.options.get().allow_degraded_writes;
 
 	let mut version = version.clone();
 	version.blocks.put(
@@ -518,23 +1961,93 @@ async fn put_block_and_meta(
 		block: hash,
 		version: version.uuid,
 		deleted: false.into(),
+		bucket_id: Some(*bucket_id),
+	};
+
+	let metrics_tags = [KeyValue::new("encrypted", encrypted)];
+
+	let stored_size = match block {
+		Some(block) => {
+			retry_transient(garage.config.s3_api.put_block_retries, || async {
+				let start = Instant::now();
+				let res = garage
+					.block_manager
+					.rpc_put_block(
+						hash,
+						block.clone(),
+						prevent_compression,
+						Some(order_tag),
+						allow_degraded,
+						durability == Durability::Strict,
+					)
+					.await;
+				S3ApiMetrics::get()
+					.put_block_duration
+					.record(start.elapsed().as_secs_f64() * 1000.0, &metrics_tags);
+				res
+			})
+			.await?
+		}
+		// The block is already stored, it is only referenced by the new version
+		None => size,
 	};
 
+	// The block is referenced only once it has been stored
 	futures::try_join!(
-		garage
-			.block_manager
-			.rpc_put_block(hash, block, is_encrypted, Some(order_tag)),
 		garage.version_table.insert(&version),
 		garage.block_ref_table.insert(&block_ref),
 	)?;
-	Ok(())
+
+	// Encrypted blocks can't be compressed, their stored size is counted
+	// as the size of their plaintext, like for objects stored inline
+	if encrypted {
+		Ok(size)
+	} else {
+		Ok(stored_size)
+	}
+}
+
+/// Call `f` until it succeeds, retrying at most `retries` times
+/// with an exponential backoff if it fails with a transient error
+async fn retry_transient<T, F, Fut>(retries: usize, mut f: F) -> Result<T, GarageError>
+where
+	F: FnMut() -> Fut,
+	Fut: Future<Output = Result<T, GarageError>>,
+{
+	let mut delay = PUT_BLOCK_RETRY_DELAY;
+	let mut attempt = 0;
+	loop {
+		match f().await {
+			Err(e) if e.is_transient() && attempt < retries => {
+				attempt += 1;
+				warn!(
+					"Block write failed, retrying in {:?} ({}/{}): {}",
+					delay, attempt, retries, e
+				);
+				tokio::time::sleep(delay).await;
+				delay *= 2;
+			}
+			res => return res,
+		}
+	}
 }
 
+/// Splits a stream of bytes into blocks of `block_size` bytes. All blocks are
+/// exactly `block_size` bytes long except the last one, which is never empty,
+/// whatever the size of the chunks of the underlying stream: data block
+/// boundaries only depend on the content of the stream.
+///
+/// With content-defined chunking, blocks end where a rolling hash of the
+/// data matches a pattern instead, so that inserting or removing data in
+/// an object only changes the blocks around the modification. Blocks are
+/// then at most `block_size` bytes long, and all blocks except the last
+/// one are at least a quarter of that.
 pub(crate) struct StreamChunker<S: Stream<Item = Result<Bytes, Error>>> {
 	stream: S,
 	read_all: bool,
 	block_size: usize,
 	buf: BytesBuf,
+	content_defined: Option<ContentDefinedChunking>,
 }
 
 impl<S: Stream<Item = Result<Bytes, Error>> + Unpin> StreamChunker<S> {
@@ -544,9 +2057,20 @@ impl<S: Stream<Item = Result<Bytes, Error>> + Unpin> StreamChunker<S> {
 			read_all: false,
 			block_size,
 			buf: BytesBuf::new(),
+			content_defined: None,
 		}
 	}
 
+	pub(crate) fn with_content_defined_chunking(self) -> Self {
+		Self {
+			content_defined: Some(ContentDefinedChunking::new(self.block_size)),
+			..self
+		}
+	}
+
+	/// Returns the next block, or None once the whole stream has been read.
+	/// A short block can only be returned once the end of the stream has
+	/// been reached, and then it is the last one.
 	pub(crate) async fn next(&mut self) -> Result<Option<Bytes>, Error> {
 		while !self.read_all && self.buf.len() < self.block_size {
 			if let Some(block) = self.stream.next().await {
@@ -559,10 +2083,75 @@ impl<S: Stream<Item = Result<Bytes, Error>> + Unpin> StreamChunker<S> {
 		}
 
 		if self.buf.is_empty() {
-			Ok(None)
-		} else {
-			Ok(Some(self.buf.take_max(self.block_size)))
+			return Ok(None);
+		}
+
+		let block = self.buf.take_max(self.block_size);
+		match &self.content_defined {
+			Some(cdc) => {
+				let end = cdc.boundary(&block);
+				self.buf.prepend(block.slice(end..));
+				Ok(Some(block.slice(..end)))
+			}
+			None => Ok(Some(block)),
+		}
+	}
+}
+
+/// Random values of the Gear rolling hash used by content-defined chunking,
+/// generated with splitmix64 from a fixed seed. The boundaries of the data
+/// blocks of objects depend on them, so they must never change.
+const GEAR_TABLE: [u64; 256] = {
+	let mut table = [0u64; 256];
+	let mut state = 0x6761_7261_6765_6364u64;
+	let mut i = 0;
+	while i < 256 {
+		state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		let mut z = state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		table[i] = z ^ (z >> 31);
+		i += 1;
+	}
+	table
+};
+
+/// Parameters of content-defined chunking for a given maximum block size
+struct ContentDefinedChunking {
+	min_size: usize,
+	mask: u64,
+}
+
+impl ContentDefinedChunking {
+	fn new(block_size: usize) -> Self {
+		// A first block shorter than INLINE_THRESHOLD is stored inline as
+		// the whole object, so blocks can't be shorter than that
+		let min_size = std::cmp::min(block_size, std::cmp::max(block_size / 4, INLINE_THRESHOLD));
+		// Boundaries are found on average every min_size bytes after the
+		// minimum size, i.e. blocks are half of the maximum size on average
+		let bits = std::cmp::max(min_size, 1).ilog2();
+		let mask = match bits {
+			0 => 0,
+			_ => u64::MAX << (64 - bits),
+		};
+		Self { min_size, mask }
+	}
+
+	/// Returns the length of the block at the start of `data`: the first
+	/// position after the minimum block size where the hash of the previous
+	/// bytes matches the mask, or the length of `data` if there is none
+	fn boundary(&self, data: &[u8]) -> usize {
+		// Each byte is shifted out of the hash after 64 others,
+		// so the bytes before that don't need to be hashed
+		let start = self.min_size.saturating_sub(64);
+		let mut hash = 0u64;
+		for (i, b) in data.iter().enumerate().skip(start) {
+			hash = (hash << 1).wrapping_add(GEAR_TABLE[*b as usize]);
+			if i + 1 >= self.min_size && hash & self.mask == 0 {
+				return i + 1;
+			}
 		}
+		data.len()
 	}
 }
 
@@ -579,28 +2168,119 @@ impl InterruptedCleanup {
 	fn cancel(&mut self) {
 		drop(self.0.take());
 	}
+	/// Mark the version as aborted now, instead of in the background when dropped
+	async fn abort(mut self) {
+		if let Some(info) = self.0.take() {
+			info.abort().await;
+		}
+	}
+}
+impl InterruptedCleanupInner {
+	async fn abort(self) {
+		let object_version = ObjectVersion {
+			uuid: self.version_uuid,
+			timestamp: self.version_timestamp,
+			created_at: None,
+			versioned: false,
+			state: ObjectVersionState::Aborted,
+		};
+		let object = Object::new(self.bucket_id, self.key, vec![object_version]);
+		if let Err(e) = self.garage.object_table.insert(&object).await {
+			warn!("Cannot cleanup after aborted PutObject: {}", e);
+			return;
+		}
+		if self.garage.config.s3_api.eager_abort_gc {
+			if let Err(e) = delete_version_block_refs(&self.garage, self.version_uuid).await {
+				warn!("Cannot delete block references of aborted PutObject: {}", e);
+			}
+		}
+	}
 }
 impl Drop for InterruptedCleanup {
 	fn drop(&mut self) {
 		if let Some(info) = self.0.take() {
-			tokio::spawn(async move {
-				let object_version = ObjectVersion {
-					uuid: info.version_uuid,
-					timestamp: info.version_timestamp,
-					state: ObjectVersionState::Aborted,
-				};
-				let object = Object::new(info.bucket_id, info.key, vec![object_version]);
-				if let Err(e) = info.garage.object_table.insert(&object).await {
-					warn!("Cannot cleanup after aborted PutObject: {}", e);
+			tokio::spawn(info.abort());
+		}
+	}
+}
+
+/// Mark the references to the blocks of an aborted version as deleted, so that
+/// the blocks are decref'd now and not when the deletion of the version is
+/// propagated. The version is read back from the version table, where all of
+/// its blocks have been written by the time the upload is aborted.
+async fn delete_version_block_refs(garage: &Garage, version_uuid: Uuid) -> Result<(), Error> {
+	let version = match garage.version_table.get(&version_uuid, &EmptyKey).await? {
+		Some(v) => v,
+		None => return Ok(()),
+	};
+	let block_refs = version
+		.blocks
+		.items()
+		.iter()
+		.map(|(_, vb)| BlockRef {
+			block: vb.hash,
+			version: version_uuid,
+			deleted: true.into(),
+			bucket_id: None,
+		})
+		.collect::<Vec<_>>();
+	garage.block_ref_table.insert_many(&block_refs[..]).await?;
+	Ok(())
+}
+
+/// Slot taken by an upload in the limit of concurrent uploads of its bucket
+/// (s3_api.max_concurrent_uploads), released when dropped
+struct UploadSlot<'a> {
+	garage: &'a Garage,
+	bucket_id: Option<Uuid>,
+}
+
+impl<'a> UploadSlot<'a> {
+	fn acquire(garage: &'a Garage, bucket_id: Uuid) -> Result<Self, Error> {
+		let max = match garage.config.s3_api.max_concurrent_uploads {
+			Some(max) => max,
+			None => {
+				return Ok(Self {
+					garage,
+					bucket_id: None,
+				})
+			}
+		};
+		let mut uploads = garage.uploads_in_progress.lock().unwrap();
+		let count = uploads.entry(bucket_id).or_default();
+		if *count >= max {
+			return Err(Error::SlowDown);
+		}
+		*count += 1;
+		Ok(Self {
+			garage,
+			bucket_id: Some(bucket_id),
+		})
+	}
+}
+
+impl<'a> Drop for UploadSlot<'a> {
+	fn drop(&mut self) {
+		if let Some(bucket_id) = self.bucket_id {
+			let mut uploads = self.garage.uploads_in_progress.lock().unwrap();
+			if let Some(count) = uploads.get_mut(&bucket_id) {
+				*count -= 1;
+				if *count == 0 {
+					uploads.remove(&bucket_id);
 				}
-			});
+			}
 		}
 	}
 }
 
 // ============ helpers ============
 
-pub(crate) fn get_headers(headers: &HeaderMap<HeaderValue>) -> Result<HeaderList, Error> {
+/// Extract the headers of a request that are stored in the metadata of the
+/// object, checking that user metadata does not exceed `max_metadata_size`
+pub(crate) fn get_headers(
+	headers: &HeaderMap<HeaderValue>,
+	max_metadata_size: usize,
+) -> Result<HeaderList, Error> {
 	let mut ret = Vec::new();
 
 	// Preserve standard headers
@@ -618,9 +2298,23 @@ pub(crate) fn get_headers(headers: &HeaderMap<HeaderValue>) -> Result<HeaderList
 		}
 	}
 
+	// Preserve the storage class, which must be a known one
+	if let Some(value) = headers.get(X_AMZ_STORAGE_CLASS) {
+		let value = value.to_str()?;
+		if !STORAGE_CLASSES.contains(&value) {
+			return Err(Error::InvalidStorageClass(value.to_string()));
+		}
+		ret.push((X_AMZ_STORAGE_CLASS.to_string(), value.to_string()));
+	}
+
 	// Preserve x-amz-meta- headers
+	let mut metadata_size = 0;
 	for (name, value) in headers.iter() {
-		if name.as_str().starts_with("x-amz-meta-") {
+		if let Some(meta_name) = name.as_str().strip_prefix("x-amz-meta-") {
+			metadata_size += meta_name.len() + value.len();
+			if metadata_size > max_metadata_size {
+				return Err(Error::MetadataTooLarge(max_metadata_size));
+			}
 			ret.push((
 				name.to_string(),
 				std::str::from_utf8(value.as_bytes())?.to_string(),
@@ -631,10 +2325,851 @@ pub(crate) fn get_headers(headers: &HeaderMap<HeaderValue>) -> Result<HeaderList
 	Ok(ret)
 }
 
-pub(crate) fn next_timestamp(existing_object: Option<&Object>) -> u64 {
-	existing_object
+/// Returns true if the object's data has a content-encoding (e.g. gzip),
+/// meaning that it is already compressed and that compressing it again
+/// in data blocks would be a waste of CPU time
+pub(crate) fn is_content_encoded(headers: &HeaderList) -> bool {
+	headers
+		.iter()
+		.filter(|(name, _)| name.as_str() == hyper::header::CONTENT_ENCODING.as_str())
+		.flat_map(|(_, value)| value.split(','))
+		.map(str::trim)
+		.any(|enc| {
+			!enc.is_empty()
+				&& !enc.eq_ignore_ascii_case("identity")
+				&& !enc.eq_ignore_ascii_case("aws-chunked")
+		})
+}
+
+pub(crate) async fn next_timestamp(
+	existing_object: Option<&Object>,
+	clock: &MonotonicClock,
+) -> Result<u64, GarageError> {
+	let prev = existing_object
 		.as_ref()
-		.and_then(|obj| obj.versions().iter().map(|v| v.timestamp).max())
-		.map(|t| std::cmp::max(t + 1, now_msec()))
-		.unwrap_or_else(now_msec)
+		.and_then(|obj| obj.versions().iter().map(|v| v.timestamp).max());
+	clock.next(prev).await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use garage_model::bucket_table::Bucket;
+	use garage_model::key_table::Key;
+	use garage_rpc::test_util::*;
+
+	/// Quota provider that records the deltas it is asked about,
+	/// and refuses uploads that would add more than `max_size_delta` bytes
+	struct MockQuotaProvider {
+		max_size_delta: i64,
+		calls: Mutex<Vec<(Uuid, i64, i64)>>,
+	}
+
+	#[async_trait::async_trait]
+	impl QuotaProvider for MockQuotaProvider {
+		async fn check(
+			&self,
+			bucket_id: Uuid,
+			_quotas: &BucketQuotas,
+			size_delta: i64,
+			object_delta: i64,
+		) -> Result<(), QuotaError> {
+			self.calls
+				.lock()
+				.unwrap()
+				.push((bucket_id, size_delta, object_delta));
+			if size_delta > self.max_size_delta {
+				return Err(QuotaError::Size("rejected by billing".into()));
+			}
+			Ok(())
+		}
+	}
+
+	struct RequireMetaHook;
+
+	impl PutHook for RequireMetaHook {
+		fn before_commit(
+			&self,
+			_bucket_id: Uuid,
+			key: &str,
+			headers: &mut HeaderList,
+		) -> Result<(), PutHookError> {
+			if !headers.iter().any(|(k, _)| k == "x-amz-meta-owner") {
+				return Err(PutHookError(format!("{} has no owner", key)));
+			}
+			headers.push(("x-amz-meta-checked".into(), "yes".into()));
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn test_put_hook() {
+		let bucket_id = gen_uuid();
+
+		let mut headers = vec![("content-type".to_string(), "text/plain".to_string())];
+		match apply_put_hook(&RequireMetaHook, bucket_id, "key", &mut headers) {
+			Err(e @ Error::Common(_)) => {
+				assert_eq!(e.aws_code(), "InvalidRequest");
+				assert!(e.to_string().contains("key has no owner"));
+			}
+			r => panic!("unexpected result: {:?}", r),
+		}
+
+		headers.push(("x-amz-meta-owner".into(), "alice".into()));
+		apply_put_hook(&RequireMetaHook, bucket_id, "key", &mut headers).unwrap();
+		assert!(headers.contains(&("x-amz-meta-checked".into(), "yes".into())));
+
+		// The default hook accepts everything as is
+		let mut headers = vec![];
+		apply_put_hook(&NoopPutHook, bucket_id, "key", &mut headers).unwrap();
+		assert!(headers.is_empty());
+	}
+
+	#[tokio::test]
+	async fn test_check_quotas_provider() {
+		let provider = MockQuotaProvider {
+			max_size_delta: 100,
+			calls: Mutex::new(vec![]),
+		};
+		let bucket_id = gen_uuid();
+		let quotas = BucketQuotas::default();
+
+		// The provider is consulted even if the bucket has no quotas
+		assert!(check_quotas_with(&provider, bucket_id, &quotas, 50, None)
+			.await
+			.is_ok());
+		match check_quotas_with(&provider, bucket_id, &quotas, 150, None).await {
+			Err(Error::QuotaExceeded(QuotaType::Size, msg)) => {
+				assert_eq!(msg, "rejected by billing")
+			}
+			r => panic!("unexpected result: {:?}", r),
+		}
+
+		// Replacing an object only counts the difference of sizes
+		let prev_object = Object::new(
+			bucket_id,
+			"key".into(),
+			vec![ObjectVersion {
+				uuid: gen_uuid(),
+				timestamp: 1,
+				created_at: None,
+				versioned: false,
+				state: ObjectVersionState::Complete(ObjectVersionData::FirstBlock(
+					ObjectVersionMeta {
+						encryption: ObjectVersionEncryption::Plaintext {
+							inner: ObjectVersionMetaInner {
+								headers: vec![],
+								checksum: None,
+								tags: Default::default(),
+								checksum_type: Default::default(),
+							},
+						},
+						size: 100,
+						etag: "etag".into(),
+					},
+					Hash::from([0u8; 32]),
+				)),
+			}],
+		);
+		assert!(
+			check_quotas_with(&provider, bucket_id, &quotas, 150, Some(&prev_object))
+				.await
+				.is_ok()
+		);
+
+		assert_eq!(
+			*provider.calls.lock().unwrap(),
+			vec![(bucket_id, 50, 1), (bucket_id, 150, 1), (bucket_id, 50, 0)]
+		);
+	}
+
+	#[tokio::test]
+	async fn test_check_quotas_preflight_provider() {
+		let (_dir, garage) = test_garage("").await;
+		let provider = Arc::new(MockQuotaProvider {
+			max_size_delta: 100,
+			calls: Mutex::new(vec![]),
+		});
+		garage.set_quota_provider(provider.clone());
+		let bucket = Bucket::new();
+		let ctx = ReqCtx {
+			garage,
+			bucket_id: bucket.id,
+			bucket_name: "bucket".into(),
+			bucket_params: bucket.params().unwrap().clone(),
+			api_key: Key::new("key"),
+		};
+
+		// The provider is consulted before the body is read, even if the
+		// bucket has no quotas
+		check_quotas_preflight(&ctx, "key", 50).await.unwrap();
+		match check_quotas_preflight(&ctx, "key", 150).await {
+			Err(Error::QuotaExceeded(QuotaType::Size, msg)) => {
+				assert_eq!(msg, "rejected by billing")
+			}
+			r => panic!("unexpected result: {:?}", r),
+		}
+		assert_eq!(
+			*provider.calls.lock().unwrap(),
+			vec![(ctx.bucket_id, 50, 1), (ctx.bucket_id, 150, 1)]
+		);
+	}
+
+	#[test]
+	fn test_get_headers_metadata_size() {
+		let mut headers = HeaderMap::new();
+		headers.insert("content-type", HeaderValue::from_static("text/plain"));
+		headers.insert("x-amz-meta-abc", HeaderValue::from_static("12345"));
+		headers.insert("x-amz-meta-de", HeaderValue::from_static("67"));
+
+		// Only the names without prefix and the values of x-amz-meta-*
+		// headers are counted: 3 + 5 + 2 + 2 bytes
+		let stored = get_headers(&headers, 12).unwrap();
+		assert_eq!(stored.len(), 3);
+		assert!(matches!(
+			get_headers(&headers, 11),
+			Err(Error::MetadataTooLarge(11))
+		));
+	}
+
+	#[test]
+	fn test_check_block_hashes_allowed() {
+		let with_hashes = || ExpectedChecksums {
+			sha256: Some(blake2sum(b"signed payload")),
+			block_hashes: Some(vec![blake2sum(b"block")]),
+			..Default::default()
+		};
+		assert!(check_block_hashes_allowed(&with_hashes(), false, None).is_ok());
+		assert!(check_block_hashes_allowed(&with_hashes(), true, None).is_err());
+		assert!(
+			check_block_hashes_allowed(&with_hashes(), false, Some(ChecksumAlgorithm::Crc32))
+				.is_err()
+		);
+		let with_md5 = ExpectedChecksums {
+			md5: Some("XUFAKrxLKna5cZ2REBfFkg==".into()),
+			..with_hashes()
+		};
+		assert!(check_block_hashes_allowed(&with_md5, false, None).is_err());
+		let with_extra = ExpectedChecksums {
+			extra: Some(ChecksumValue::Crc32([0; 4])),
+			..with_hashes()
+		};
+		assert!(check_block_hashes_allowed(&with_extra, false, None).is_err());
+		let with_algorithm = ExpectedChecksums {
+			algorithm: Some(ChecksumAlgorithm::Sha1),
+			..with_hashes()
+		};
+		assert!(check_block_hashes_allowed(&with_algorithm, false, None).is_err());
+
+		// Without block hashes, anything goes
+		let without_hashes = ExpectedChecksums {
+			block_hashes: None,
+			..with_md5
+		};
+		assert!(check_block_hashes_allowed(&without_hashes, true, None).is_ok());
+	}
+
+	#[test]
+	fn test_check_object_timestamp_skew() {
+		let now = 1_700_000_000_000;
+		let max_skew = Duration::from_secs(300);
+		assert!(check_object_timestamp_skew(1, now, max_skew).is_ok());
+		assert!(check_object_timestamp_skew(now + 300_000, now, max_skew).is_ok());
+		assert!(check_object_timestamp_skew(now + 300_001, now, max_skew).is_err());
+		assert!(check_object_timestamp_skew(u64::MAX, now, max_skew).is_err());
+	}
+
+	#[test]
+	fn test_validate_object_key() {
+		assert!(validate_object_key(&"a".repeat(1024), 1024).is_ok());
+		assert!(matches!(
+			validate_object_key(&"a".repeat(1025), 1024),
+			Err(Error::KeyTooLong(1024))
+		));
+
+		// The length is counted in bytes of the UTF-8 encoding, not in characters
+		assert!(validate_object_key(&"é".repeat(512), 1024).is_ok());
+		assert!(matches!(
+			validate_object_key(&"é".repeat(513), 1024),
+			Err(Error::KeyTooLong(1024))
+		));
+
+		assert!(validate_object_key("", 1024).is_ok());
+		for key in ["nul\0key", "tab\tkey", "del\x7fkey", "c1\u{85}key"] {
+			assert!(matches!(
+				validate_object_key(key, 1024),
+				Err(Error::InvalidObjectKey(_))
+			));
+		}
+	}
+
+	fn block_channel(blocks: &[Bytes]) -> mpsc::Receiver<Result<Bytes, Error>> {
+		let (tx, rx) = mpsc::channel(blocks.len());
+		for block in blocks {
+			tx.try_send(Ok(block.clone())).unwrap();
+		}
+		rx
+	}
+
+	fn test_blocks(count: usize, size: usize) -> Vec<Bytes> {
+		(0..count)
+			.map(|i| Bytes::from(vec![i as u8; size]))
+			.collect()
+	}
+
+	#[tokio::test]
+	async fn test_put_blocks_order_tags() {
+		let blocks = test_blocks(5, 1000);
+		let (tx, rx) = mpsc::channel(blocks.len());
+		for block in blocks.iter() {
+			tx.try_send(Ok((
+				Some(block.clone()),
+				block.len() as u64,
+				blake2sum(block),
+			)))
+			.unwrap();
+		}
+		drop(tx);
+
+		// Writes of earlier blocks take longer, so that they complete out of order
+		let assigned = Mutex::new(vec![]);
+		let running = AtomicU64::new(0);
+		let max_running = AtomicU64::new(0);
+		let sizes = put_blocks_ordered(rx, 0, None, |offset, _block, size, hash, order_tag| {
+			assigned.lock().unwrap().push((offset, hash, order_tag));
+			let (running, max_running) = (&running, &max_running);
+			async move {
+				let n = running.fetch_add(1, Ordering::SeqCst) + 1;
+				max_running.fetch_max(n, Ordering::SeqCst);
+				tokio::time::sleep(Duration::from_millis(50 - offset / 100)).await;
+				running.fetch_sub(1, Ordering::SeqCst);
+				Ok(size / 2)
+			}
+		})
+		.await
+		.unwrap();
+		assert_eq!(sizes, (5000, 2500));
+		assert_eq!(
+			max_running.load(Ordering::SeqCst),
+			PUT_BLOCKS_MAX_PARALLEL as u64
+		);
+
+		// Tags are assigned in the order of the blocks, from the same stream,
+		// and their order follows the offsets of the blocks
+		let assigned = assigned.into_inner().unwrap();
+		assert_eq!(assigned.len(), 5);
+		for (i, (offset, hash, order_tag)) in assigned.iter().enumerate() {
+			assert_eq!(*offset, i as u64 * 1000);
+			assert_eq!(*hash, blake2sum(&blocks[i]));
+			assert_eq!(order_tag.order(), *offset);
+			assert!(order_tag.same_stream(&assigned[0].2));
+		}
+		assert!(assigned.windows(2).all(|w| w[0].2.order() < w[1].2.order()));
+	}
+
+	#[tokio::test]
+	async fn test_put_blocks_progress() {
+		let blocks = test_blocks(20, 1000);
+		let (tx, rx) = mpsc::channel(blocks.len());
+		for block in blocks.iter() {
+			tx.try_send(Ok((
+				Some(block.clone()),
+				block.len() as u64,
+				blake2sum(block),
+			)))
+			.unwrap();
+		}
+		drop(tx);
+
+		let (progress_tx, mut progress_rx) = watch::channel(0);
+		let observer = tokio::spawn(async move {
+			let mut observed = vec![];
+			while progress_rx.changed().await.is_ok() {
+				observed.push(*progress_rx.borrow_and_update());
+			}
+			observed
+		});
+
+		let sizes = put_blocks_ordered(
+			rx,
+			5000,
+			Some(&progress_tx),
+			|offset, _block, size, _hash, _order_tag| async move {
+				tokio::time::sleep(Duration::from_millis(offset % 7)).await;
+				Ok(size)
+			},
+		)
+		.await
+		.unwrap();
+		assert_eq!(sizes, (20000, 20000));
+		drop(progress_tx);
+
+		// Progress is reported relative to the offset of the first block,
+		// and only grows until it reaches the total size
+		let observed = observer.await.unwrap();
+		assert!(observed.len() > 1);
+		assert!(observed.windows(2).all(|w| w[0] <= w[1]));
+		assert_eq!(observed.last(), Some(&20000));
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_encrypt_and_hash_blocks_parallel() {
+		let blocks = test_blocks(10, 100_000);
+		let expected = blocks
+			.iter()
+			.map(|b| (Some(b.clone()), b.len() as u64, blake2sum(b)))
+			.collect::<Vec<_>>();
+
+		for parallelism in [1, 3, 16] {
+			let rx = block_channel(&blocks);
+			let res = encrypt_and_hash_blocks(rx, EncryptionParams::Plaintext, None, parallelism)
+				.try_collect::<Vec<_>>()
+				.await
+				.unwrap();
+			assert_eq!(res, expected, "parallelism = {}", parallelism);
+		}
+	}
+
+	/// Run with `cargo test -- --ignored` on a machine with several cores
+	#[tokio::test(flavor = "multi_thread")]
+	#[ignore]
+	async fn bench_encrypt_and_hash_blocks_parallel() {
+		let blocks = test_blocks(8, 16 << 20);
+
+		let mut results = vec![];
+		for parallelism in [1, 4] {
+			let rx = block_channel(&blocks);
+			let start = Instant::now();
+			let res = encrypt_and_hash_blocks(rx, EncryptionParams::Plaintext, None, parallelism)
+				.map_ok(|(_, _, hash)| hash)
+				.try_collect::<Vec<_>>()
+				.await
+				.unwrap();
+			results.push((res, start.elapsed()));
+		}
+
+		assert_eq!(results[0].0, results[1].0);
+		let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+		if cores > 1 {
+			assert!(results[1].1 < results[0].1);
+		}
+	}
+
+	/// Run with `cargo test -- --ignored`
+	#[tokio::test]
+	#[ignore]
+	async fn bench_put_pipeline_depth() {
+		// The reading stage is slow for the first group of 4 blocks, the writing
+		// stage for the next one, and so on: a deeper channel between them absorbs
+		// these variations instead of having each stage wait for the other
+		let delay = |i: usize, stage: usize| {
+			Duration::from_millis(if (i / 4) % 2 == stage { 20 } else { 0 })
+		};
+		let blocks = test_blocks(32, 1000);
+
+		let mut results = vec![];
+		for depth in [1, 4] {
+			let (tx, mut rx) = StageSender::<Bytes>::channel(depth, "put");
+			let start = Instant::now();
+			let read = async {
+				for (i, block) in blocks.iter().enumerate() {
+					tokio::time::sleep(delay(i, 0)).await;
+					tx.send(block.clone()).await.unwrap();
+				}
+				drop(tx);
+			};
+			let write = async {
+				let mut written = 0;
+				while let Some(block) = rx.recv().await {
+					tokio::time::sleep(delay(written, 1)).await;
+					assert_eq!(block, blocks[written]);
+					written += 1;
+				}
+				written
+			};
+			let (_, written) = futures::join!(read, write);
+			assert_eq!(written, blocks.len());
+			results.push(start.elapsed());
+		}
+
+		assert!(results[1] < results[0]);
+	}
+
+	#[tokio::test]
+	async fn test_limit_stream_size() {
+		let chunks = || {
+			futures::stream::iter(
+				vec![&b"0123"[..], b"4567", b"89"]
+					.into_iter()
+					.map(|x| Ok(Bytes::from_static(x))),
+			)
+		};
+
+		let read_all = limit_stream_size(chunks(), Some(10))
+			.try_collect::<Vec<_>>()
+			.await
+			.unwrap();
+		assert_eq!(read_all.concat(), b"0123456789");
+
+		let read_all = limit_stream_size(chunks(), None)
+			.try_collect::<Vec<_>>()
+			.await
+			.unwrap();
+		assert_eq!(read_all.concat(), b"0123456789");
+
+		// The error is raised in the middle of the stream, as soon as
+		// the limit is exceeded
+		let mut limited = limit_stream_size(chunks(), Some(6));
+		assert_eq!(limited.next().await.unwrap().unwrap(), &b"0123"[..]);
+		assert!(matches!(
+			limited.next().await,
+			Some(Err(Error::EntityTooLarge))
+		));
+	}
+
+	#[tokio::test]
+	async fn test_stream_chunker_full_blocks() {
+		let block_size = 64;
+		for len in [0usize, 1, 63, 64, 65, 640, 1000] {
+			let data = (0..len).map(|i| (i * 7) as u8).collect::<Vec<u8>>();
+
+			// Chunks of irregular sizes, some of them empty or larger than a block
+			let mut chunks = vec![];
+			let mut rest = &data[..];
+			for chunk_len in [1, 0, 7, 130, 3, 0, 64, 1, 63, 17].iter().cycle() {
+				if rest.is_empty() {
+					break;
+				}
+				let (chunk, next) = rest.split_at(std::cmp::min(*chunk_len, rest.len()));
+				chunks.push(Ok(Bytes::copy_from_slice(chunk)));
+				rest = next;
+			}
+
+			let mut chunker = StreamChunker::new(futures::stream::iter(chunks), block_size);
+			let mut blocks = vec![];
+			while let Some(block) = chunker.next().await.unwrap() {
+				blocks.push(block);
+			}
+			assert!(chunker.next().await.unwrap().is_none());
+
+			assert_eq!(blocks.concat(), data);
+			assert_eq!(blocks.len(), len.div_ceil(block_size));
+			if let Some((last, full)) = blocks.split_last() {
+				assert!(full.iter().all(|b| b.len() == block_size));
+				assert!(!last.is_empty() && last.len() <= block_size);
+			}
+		}
+	}
+
+	#[tokio::test]
+	async fn test_stream_chunker_content_defined() {
+		let block_size = 64 * 1024;
+		let chunk = |data: Vec<u8>| async move {
+			let chunks = data
+				.chunks(1000)
+				.map(|c| Ok(Bytes::copy_from_slice(c)))
+				.collect::<Vec<_>>();
+			let mut chunker = StreamChunker::new(futures::stream::iter(chunks), block_size)
+				.with_content_defined_chunking();
+			let mut blocks = vec![];
+			while let Some(block) = chunker.next().await.unwrap() {
+				blocks.push(block);
+			}
+			assert_eq!(blocks.concat(), data);
+			blocks
+		};
+
+		// Pseudo-random data, so that boundaries are found where expected
+		let mut state = 42u64;
+		let data = (0..2_000_000)
+			.map(|_| {
+				state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+				(state >> 56) as u8
+			})
+			.collect::<Vec<u8>>();
+
+		let blocks = chunk(data.clone()).await;
+		assert!(blocks.len() > 2_000_000 / block_size);
+		let (_, full) = blocks.split_last().unwrap();
+		assert!(full
+			.iter()
+			.all(|b| b.len() >= block_size / 4 && b.len() <= block_size));
+
+		// Inserting data at the start of the object only changes the first blocks
+		let modified = [b"inserted".as_slice(), &data].concat();
+		let modified_blocks = chunk(modified).await;
+		assert_ne!(modified_blocks[0], blocks[0]);
+		let common = blocks
+			.iter()
+			.rev()
+			.zip(modified_blocks.iter().rev())
+			.take_while(|(a, b)| a == b)
+			.count();
+		assert!(common >= blocks.len() - 1);
+
+		// Whereas with fixed-size blocks, all blocks are shifted
+		let fixed = |data: &[u8]| data.chunks(block_size).map(blake2sum).collect::<Vec<_>>();
+		let modified = [b"inserted".as_slice(), &data].concat();
+		assert!(fixed(&data)
+			.iter()
+			.zip(fixed(&modified).iter())
+			.all(|(a, b)| a != b));
+	}
+
+	#[tokio::test]
+	async fn test_upload_trace_context() {
+		let mut headers = HeaderMap::new();
+		assert!(request_trace_id(&headers).unwrap().is_none());
+		for invalid in ["xyz", "0123", "00000000000000000000000000000000"] {
+			headers.insert(X_GARAGE_TRACE_ID, HeaderValue::from_static(invalid));
+			assert!(request_trace_id(&headers).is_err());
+		}
+		headers.insert(
+			X_GARAGE_TRACE_ID,
+			HeaderValue::from_static("4bf92f3577b34da6a3ce929d0e0e4736"),
+		);
+		let trace_id = request_trace_id(&headers).unwrap().unwrap();
+		assert_eq!(trace_id.to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+
+		// Spans started while processing the upload belong to its trace
+		let child_trace_id = async {
+			let tracer = opentelemetry::global::tracer("garage");
+			let cx = Context::current_with_span(tracer.start("Write block"));
+			cx.span().span_context().trace_id()
+		}
+		.with_context(upload_trace_context(trace_id))
+		.await;
+		assert_eq!(child_trace_id, trace_id);
+	}
+
+	#[tokio::test]
+	async fn test_retry_transient() {
+		// Simulates a block write that fails with the given errors, then
+		// succeeds, and returns the number of attempts made
+		async fn put_block(retries: usize, errors: Vec<GarageError>) -> (usize, bool) {
+			let errors = std::sync::Mutex::new(errors.into_iter());
+			let attempts = std::sync::atomic::AtomicUsize::new(0);
+			let res = retry_transient(retries, || {
+				attempts.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+				let res = errors.lock().unwrap().next().map_or(Ok(()), Err);
+				async move { res }
+			})
+			.await;
+			(attempts.into_inner(), res.is_ok())
+		}
+
+		assert_eq!(put_block(2, vec![]).await, (1, true));
+		assert_eq!(put_block(2, vec![GarageError::Timeout]).await, (2, true));
+		assert_eq!(
+			put_block(
+				2,
+				vec![
+					GarageError::Timeout,
+					GarageError::Quorum(2, None, 1, 3, vec![])
+				]
+			)
+			.await,
+			(3, true)
+		);
+
+		// Retries are bounded
+		let timeouts = || (0..3).map(|_| GarageError::Timeout).collect::<Vec<_>>();
+		assert_eq!(put_block(2, timeouts()).await, (3, false));
+		assert_eq!(put_block(0, timeouts()).await, (1, false));
+
+		// Fatal errors are not retried
+		assert_eq!(
+			put_block(2, vec![GarageError::Quorum(2, None, 0, 1, vec![])]).await,
+			(1, false)
+		);
+		assert_eq!(
+			put_block(2, vec![GarageError::Message("disk full".into())]).await,
+			(1, false)
+		);
+	}
+
+	/// Start a single-node Garage whose workers are not running, so that
+	/// table updates are not propagated in the background
+	async fn test_garage(extra_s3_config: &str) -> (mktemp::Temp, Arc<Garage>) {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let config = test_config(&dir, 1, extra_s3_config);
+		let garage = Garage::new(config).unwrap();
+		assign_test_layout(&garage.system, &[garage.system.id]).await;
+
+		(dir, garage)
+	}
+
+	#[tokio::test]
+	async fn test_upload_slots() {
+		let (_dir, garage) = test_garage("max_concurrent_uploads = 2").await;
+		let (bucket_a, bucket_b) = (gen_uuid(), gen_uuid());
+
+		let slot1 = UploadSlot::acquire(&garage, bucket_a).unwrap();
+		let _slot2 = UploadSlot::acquire(&garage, bucket_a).unwrap();
+		assert!(matches!(
+			UploadSlot::acquire(&garage, bucket_a),
+			Err(Error::SlowDown)
+		));
+		// The limit applies to each bucket separately
+		let _slot3 = UploadSlot::acquire(&garage, bucket_b).unwrap();
+
+		drop(slot1);
+		let _slot4 = UploadSlot::acquire(&garage, bucket_a).unwrap();
+
+		// Another node has its own count of uploads in progress
+		let (_dir2, garage2) = test_garage("max_concurrent_uploads = 2").await;
+		let _slot5 = UploadSlot::acquire(&garage2, bucket_a).unwrap();
+	}
+
+	#[tokio::test]
+	async fn test_eager_abort_gc() {
+		for eager in [false, true] {
+			let (_dir, garage) = test_garage(&format!("eager_abort_gc = {}", eager)).await;
+			let (bucket_id, version_uuid) = (gen_uuid(), gen_uuid());
+			let hash = blake2sum(b"block of an aborted upload");
+
+			// State of an upload that has written one block before being aborted
+			let object = Object::new(
+				bucket_id,
+				"key".into(),
+				vec![ObjectVersion {
+					uuid: version_uuid,
+					timestamp: 1,
+					created_at: None,
+					versioned: false,
+					state: ObjectVersionState::Uploading {
+						checksum_algorithm: None,
+						encryption: ObjectVersionEncryption::Plaintext {
+							inner: ObjectVersionMetaInner {
+								headers: vec![],
+								checksum: None,
+								tags: Default::default(),
+								checksum_type: Default::default(),
+							},
+						},
+						multipart: false,
+					},
+				}],
+			);
+			garage.object_table.insert(&object).await.unwrap();
+			let mut version = Version::new(
+				version_uuid,
+				VersionBacklink::Object {
+					bucket_id,
+					key: "key".into(),
+				},
+				false,
+			);
+			version.blocks.put(
+				VersionBlockKey {
+					part_number: 1,
+					offset: 0,
+				},
+				VersionBlock { hash, size: 100 },
+			);
+			garage.version_table.insert(&version).await.unwrap();
+			let block_ref = BlockRef {
+				block: hash,
+				version: version_uuid,
+				deleted: false.into(),
+				bucket_id: Some(bucket_id),
+			};
+			garage.block_ref_table.insert(&block_ref).await.unwrap();
+			assert_eq!(garage.block_manager.get_block_rc(&hash).unwrap(), 1);
+
+			InterruptedCleanupInner {
+				garage: garage.clone(),
+				bucket_id,
+				key: "key".into(),
+				version_uuid,
+				version_timestamp: 1,
+			}
+			.abort()
+			.await;
+
+			// Without eager GC, the block is only decref'd when the deletion
+			// of the version is propagated by the insert queue worker
+			let expected_rc = if eager { 0 } else { 1 };
+			assert_eq!(
+				garage.block_manager.get_block_rc(&hash).unwrap(),
+				expected_rc
+			);
+		}
+	}
+
+	#[tokio::test]
+	async fn test_block_budget_shared_by_uploads() {
+		const BLOCK_SIZE: u32 = 1000;
+		const BUDGET: usize = 3 * BLOCK_SIZE as usize;
+		let semaphore = Arc::new(Semaphore::new(BUDGET));
+		let in_memory = Arc::new(AtomicU64::new(0));
+		let max_in_memory = Arc::new(AtomicU64::new(0));
+
+		// Two uploads of 10 blocks share the budget. Each one reads blocks
+		// as fast as possible and writes them slowly, so without the budget
+		// they would buffer all their blocks at the same time.
+		let uploads = (0..2).map(|_| {
+			let budget = Arc::new(BlockBudget::with_semaphore(
+				Some(semaphore.clone()),
+				BLOCK_SIZE,
+			));
+			let (in_memory, max_in_memory) = (in_memory.clone(), max_in_memory.clone());
+			let (tx, mut rx) = mpsc::unbounded_channel();
+
+			let budget2 = budget.clone();
+			let in_memory2 = in_memory.clone();
+			let read_blocks = async move {
+				for i in 0..10 {
+					budget2.hold(budget2.reserve().await);
+					let now = in_memory2.fetch_add(BLOCK_SIZE as u64, Ordering::SeqCst);
+					max_in_memory.fetch_max(now + BLOCK_SIZE as u64, Ordering::SeqCst);
+					tx.send(i).unwrap();
+				}
+			};
+			let put_blocks = async move {
+				let mut written = vec![];
+				while let Some(i) = rx.recv().await {
+					tokio::time::sleep(Duration::from_millis(5)).await;
+					in_memory.fetch_sub(BLOCK_SIZE as u64, Ordering::SeqCst);
+					drop(budget.release_next().unwrap());
+					written.push(i);
+				}
+				written
+			};
+			tokio::spawn(async move { futures::join!(read_blocks, put_blocks).1 })
+		});
+
+		for written in futures::future::join_all(uploads).await {
+			assert_eq!(written.unwrap(), (0..10).collect::<Vec<_>>());
+		}
+		assert!(max_in_memory.load(Ordering::SeqCst) <= BUDGET as u64);
+		assert_eq!(semaphore.available_permits(), BUDGET);
+	}
+
+	#[tokio::test]
+	async fn test_stage_sender_backpressure() {
+		let (tx, mut rx) = StageSender::<u64>::channel(1, "put");
+
+		// A slow put-blocks stage that takes some time to accept each block
+		let put_blocks = tokio::spawn(async move {
+			let mut received = vec![];
+			loop {
+				tokio::time::sleep(Duration::from_millis(50)).await;
+				match rx.recv().await {
+					Some(block) => received.push(block),
+					None => break received,
+				}
+			}
+		});
+
+		// The first block fits in the channel, the following ones have to wait
+		tx.send(1).await.unwrap();
+		assert_eq!(tx.backpressure(), 0);
+		tx.send(2).await.unwrap();
+		tx.send(3).await.unwrap();
+		assert_eq!(tx.backpressure(), 2);
+
+		drop(tx);
+		assert_eq!(put_blocks.await.unwrap(), vec![1, 2, 3]);
+	}
 }
@@ -0,0 +1,26 @@
+//! Function related to the restoration of archived objects
+use hyper::{Response, StatusCode};
+
+use crate::helpers::*;
+use crate::s3::api_server::ResBody;
+use crate::s3::error::*;
+
+/// Garage has no archival storage class, so all objects are always available.
+/// Restore requests are acknowledged for any existing object and do nothing,
+/// for the benefit of tools that require them to succeed.
+pub async fn handle_restore(ctx: ReqCtx, key: &str) -> Result<Response<ResBody>, Error> {
+	let object = ctx
+		.garage
+		.object_table
+		.get(&ctx.bucket_id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)?;
+
+	if !object.versions().iter().any(|v| v.is_data()) {
+		return Err(Error::NoSuchKey);
+	}
+
+	Ok(Response::builder()
+		.status(StatusCode::ACCEPTED)
+		.body(empty_body())?)
+}
@@ -337,7 +337,8 @@ impl Endpoint {
 		}
 
 		let key = percent_encoding::percent_decode_str(key)
-			.decode_utf8()?
+			.decode_utf8()
+			.map_err(|_| Error::InvalidObjectKey("it is not valid UTF-8".into()))?
 			.into_owned();
 
 		let mut query = QueryParameters::from_query(query.unwrap_or_default())?;
@@ -563,6 +564,41 @@ impl Endpoint {
 		}
 	}
 
+	/// Get a mutable reference to the key the request target, e.g. to normalize it.
+	/// Returns None for requests which don't use a key.
+	pub fn get_key_mut(&mut self) -> Option<&mut String> {
+		router_match! {
+			@extract
+			self,
+			key,
+			[
+				AbortMultipartUpload,
+				CompleteMultipartUpload,
+				CopyObject,
+				CreateMultipartUpload,
+				DeleteObject,
+				DeleteObjectTagging,
+				GetObject,
+				GetObjectAcl,
+				GetObjectLegalHold,
+				GetObjectRetention,
+				GetObjectTagging,
+				GetObjectTorrent,
+				HeadObject,
+				ListParts,
+				PutObject,
+				PutObjectAcl,
+				PutObjectLegalHold,
+				PutObjectRetention,
+				PutObjectTagging,
+				RestoreObject,
+				SelectObjectContent,
+				UploadPart,
+				UploadPartCopy,
+			]
+		}
+	}
+
 	/// Get the kind of authorization which is required to perform the operation.
 	pub fn authorization_type(&self) -> Authorization {
 		if let Endpoint::ListBuckets = self {
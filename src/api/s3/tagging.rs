@@ -0,0 +1,372 @@
+//! Functions related to object tagging
+use http_body_util::BodyExt;
+use hyper::{HeaderMap, Request, Response, StatusCode};
+use quick_xml::de::from_reader;
+use serde::{Deserialize, Serialize};
+
+use garage_model::s3::object_table::*;
+use garage_util::data::*;
+
+use crate::helpers::*;
+use crate::s3::api_server::{ReqBody, ResBody};
+use crate::s3::copy::handle_copy_metaonly;
+use crate::s3::encryption::EncryptionParams;
+use crate::s3::error::*;
+use crate::s3::put::SaveStreamResult;
+use crate::s3::xml::{to_xml_with_header, xmlns_tag, Value};
+use crate::signature::verify_signed_content;
+
+pub const X_AMZ_TAGGING: &str = "x-amz-tagging";
+pub const X_AMZ_TAGGING_COUNT: &str = "x-amz-tagging-count";
+
+/// Limits on object tags, as defined by AWS S3
+const MAX_TAGS_PER_OBJECT: usize = 10;
+const MAX_TAG_KEY_LENGTH: usize = 128;
+const MAX_TAG_VALUE_LENGTH: usize = 256;
+
+pub async fn handle_get_object_tagging(
+	ctx: ReqCtx,
+	req: &Request<ReqBody>,
+	key: &str,
+) -> Result<Response<ResBody>, Error> {
+	let object = get_object(&ctx, key).await?;
+	let (object_version, _, version_meta) = latest_version_info(&object)?;
+
+	let (_, meta_inner) =
+		EncryptionParams::check_decrypt(&ctx.garage, req.headers(), &version_meta.encryption)?;
+
+	let tagging = Tagging::from_object_tags(&meta_inner.tags);
+	let xml = to_xml_with_header(&tagging)?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header("x-amz-version-id", hex::encode(object_version.uuid))
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(string_body(xml))?)
+}
+
+pub async fn handle_put_object_tagging(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+	key: &str,
+	version_id: Option<&str>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<ResBody>, Error> {
+	check_no_version_id(version_id)?;
+
+	let (req_head, req_body) = req.into_parts();
+	let body = BodyExt::collect(req_body).await?.to_bytes();
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let tagging: Tagging = from_reader(&body as &[u8])?;
+	let tags = tagging.into_object_tags()?;
+
+	let res = update_object_tags(ctx, &req_head.headers, key, tags).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header("x-amz-version-id", hex::encode(res.version_uuid))
+		.body(empty_body())?)
+}
+
+pub async fn handle_delete_object_tagging(
+	ctx: ReqCtx,
+	req: &Request<ReqBody>,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<ResBody>, Error> {
+	check_no_version_id(version_id)?;
+
+	let res = update_object_tags(ctx, req.headers(), key, ObjectTags::new()).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.header("x-amz-version-id", hex::encode(res.version_uuid))
+		.body(empty_body())?)
+}
+
+/// Replace the tags of an object. Like a CopyObject call that only changes
+/// metadata, this writes a new object version that references the same data
+/// blocks, so concurrent updates are resolved by keeping the most recent one.
+/// The new version replaces the previous one instead of being added to the
+/// versions of the object, so the tags of a version stored while versioning
+/// was enabled, which cannot be replaced, cannot be changed.
+async fn update_object_tags(
+	ctx: ReqCtx,
+	headers: &HeaderMap,
+	key: &str,
+	tags: ObjectTags,
+) -> Result<SaveStreamResult, Error> {
+	let object = get_object(&ctx, key).await?;
+	let (object_version, version_data, version_meta) = latest_version_info(&object)?;
+	if object_version.versioned {
+		return Err(Error::NotImplemented(
+			"Tags cannot be changed on an object version stored while versioning was enabled"
+				.into(),
+		));
+	}
+
+	let (encryption, meta_inner) =
+		EncryptionParams::check_decrypt(&ctx.garage, headers, &version_meta.encryption)?;
+	let new_meta_inner = ObjectVersionMetaInner {
+		tags,
+		..meta_inner.into_owned()
+	};
+
+	handle_copy_metaonly(
+		ctx,
+		key,
+		new_meta_inner,
+		encryption,
+		object_version,
+		version_data,
+		version_meta,
+		false,
+	)
+	.await
+}
+
+/// Only the tags of the latest version of an object can be changed
+fn check_no_version_id(version_id: Option<&str>) -> Result<(), Error> {
+	match version_id {
+		Some(_) => Err(Error::NotImplemented(
+			"Tags can only be changed on the latest version of an object".into(),
+		)),
+		None => Ok(()),
+	}
+}
+
+async fn get_object(ctx: &ReqCtx, key: &str) -> Result<Object, Error> {
+	ctx.garage
+		.object_table
+		.get(&ctx.bucket_id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)
+}
+
+fn latest_version_info(
+	object: &Object,
+) -> Result<(&ObjectVersion, &ObjectVersionData, &ObjectVersionMeta), Error> {
+	let object_version = object
+		.versions()
+		.iter()
+		.rev()
+		.find(|v| v.is_data())
+		.ok_or(Error::NoSuchKey)?;
+
+	match &object_version.state {
+		ObjectVersionState::Complete(data @ ObjectVersionData::Inline(meta, _))
+		| ObjectVersionState::Complete(data @ ObjectVersionData::InlineRef(meta, _))
+		| ObjectVersionState::Complete(data @ ObjectVersionData::FirstBlock(meta, _)) => {
+			Ok((object_version, data, meta))
+		}
+		_ => unreachable!(),
+	}
+}
+
+/// Parse the tags given in the x-amz-tagging header, which are encoded
+/// as an URL query string (e.g. `k1=v1&k2=v2`)
+pub(crate) fn request_object_tags(headers: &HeaderMap) -> Result<ObjectTags, Error> {
+	let value = match headers.get(X_AMZ_TAGGING) {
+		Some(v) => v.to_str()?,
+		None => return Ok(ObjectTags::new()),
+	};
+
+	let mut tags = ObjectTags::new();
+	for (k, v) in form_urlencoded::parse(value.as_bytes()) {
+		if tags.insert(k.to_string(), v.to_string()).is_some() {
+			return Err(Error::InvalidTag(format!("duplicate tag key: {}", k)));
+		}
+	}
+	check_object_tags(&tags)?;
+
+	Ok(tags)
+}
+
+/// Check that a set of tags satisfies the limits on object tags
+pub(crate) fn check_object_tags(tags: &ObjectTags) -> Result<(), Error> {
+	if tags.len() > MAX_TAGS_PER_OBJECT {
+		return Err(Error::InvalidTag(format!(
+			"an object can have at most {} tags",
+			MAX_TAGS_PER_OBJECT
+		)));
+	}
+	for (k, v) in tags.iter() {
+		if k.is_empty() || k.chars().count() > MAX_TAG_KEY_LENGTH {
+			return Err(Error::InvalidTag(format!(
+				"tag key must be between 1 and {} characters long",
+				MAX_TAG_KEY_LENGTH
+			)));
+		}
+		if v.chars().count() > MAX_TAG_VALUE_LENGTH {
+			return Err(Error::InvalidTag(format!(
+				"tag value must be at most {} characters long",
+				MAX_TAG_VALUE_LENGTH
+			)));
+		}
+	}
+	Ok(())
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename = "Tagging")]
+pub struct Tagging {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "TagSet")]
+	pub tag_set: TagSet,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TagSet {
+	#[serde(rename = "Tag", default)]
+	pub tags: Vec<Tag>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Tag {
+	#[serde(rename = "Key")]
+	pub key: Value,
+	#[serde(rename = "Value")]
+	pub value: Value,
+}
+
+impl Tagging {
+	pub fn from_object_tags(tags: &ObjectTags) -> Self {
+		Tagging {
+			xmlns: (),
+			tag_set: TagSet {
+				tags: tags
+					.iter()
+					.map(|(k, v)| Tag {
+						key: Value(k.clone()),
+						value: Value(v.clone()),
+					})
+					.collect(),
+			},
+		}
+	}
+
+	pub fn into_object_tags(self) -> Result<ObjectTags, Error> {
+		let mut tags = ObjectTags::new();
+		for tag in self.tag_set.tags {
+			if tags.insert(tag.key.0, tag.value.0).is_some() {
+				return Err(Error::InvalidTag("duplicate tag key".into()));
+			}
+		}
+		check_object_tags(&tags)?;
+		Ok(tags)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn tagging_headers(value: &str) -> HeaderMap {
+		let mut headers = HeaderMap::new();
+		headers.insert(X_AMZ_TAGGING, value.parse().unwrap());
+		headers
+	}
+
+	#[test]
+	fn test_request_object_tags() {
+		assert!(request_object_tags(&HeaderMap::new()).unwrap().is_empty());
+
+		let tags = request_object_tags(&tagging_headers("k1=v1&k%202=v%26%3D2&k3=")).unwrap();
+		assert_eq!(
+			tags.into_iter().collect::<Vec<_>>(),
+			vec![
+				("k 2".to_string(), "v&=2".to_string()),
+				("k1".to_string(), "v1".to_string()),
+				("k3".to_string(), "".to_string()),
+			]
+		);
+
+		let eleven = (0..11)
+			.map(|i| format!("k{}=v", i))
+			.collect::<Vec<_>>()
+			.join("&");
+		let too_long_key = format!("{}=v", "k".repeat(129));
+		let too_long_value = format!("k={}", "v".repeat(257));
+		for invalid in ["k=v1&k=v2", "=v", &eleven, &too_long_key, &too_long_value] {
+			assert!(matches!(
+				request_object_tags(&tagging_headers(invalid)),
+				Err(Error::InvalidTag(_))
+			));
+		}
+
+		// Lengths are counted in characters, not bytes
+		let max_key = format!("{}=v", "%C3%A9".repeat(128));
+		assert_eq!(
+			request_object_tags(&tagging_headers(&max_key))
+				.unwrap()
+				.len(),
+			1
+		);
+	}
+
+	#[test]
+	fn test_serialize_tagging() -> Result<(), Error> {
+		let mut tags = ObjectTags::new();
+		tags.insert("project".into(), "garage".into());
+		tags.insert("env".into(), "test".into());
+
+		let xml = to_xml_with_header(&Tagging::from_object_tags(&tags))?;
+		assert_eq!(
+			xml,
+			"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+<Tagging xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+<TagSet>\
+<Tag><Key>env</Key><Value>test</Value></Tag>\
+<Tag><Key>project</Key><Value>garage</Value></Tag>\
+</TagSet>\
+</Tagging>"
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn test_deserialize_tagging() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Tagging xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <TagSet>
+    <Tag>
+      <Key>project</Key>
+      <Value>garage</Value>
+    </Tag>
+    <Tag>
+      <Key>env</Key>
+      <Value></Value>
+    </Tag>
+  </TagSet>
+</Tagging>"#;
+		let tags = from_reader::<_, Tagging>(message.as_bytes())?.into_object_tags()?;
+		assert_eq!(
+			tags.into_iter().collect::<Vec<_>>(),
+			vec![
+				("env".to_string(), "".to_string()),
+				("project".to_string(), "garage".to_string()),
+			]
+		);
+
+		let empty = r#"<Tagging><TagSet></TagSet></Tagging>"#;
+		let tags = from_reader::<_, Tagging>(empty.as_bytes())?.into_object_tags()?;
+		assert!(tags.is_empty());
+
+		let duplicate = r#"<Tagging><TagSet>
+<Tag><Key>k</Key><Value>1</Value></Tag>
+<Tag><Key>k</Key><Value>2</Value></Tag>
+</TagSet></Tagging>"#;
+		assert!(matches!(
+			from_reader::<_, Tagging>(duplicate.as_bytes())?.into_object_tags(),
+			Err(Error::InvalidTag(_))
+		));
+		Ok(())
+	}
+}
@@ -247,7 +247,9 @@ fn verify_signed_headers(headers: &HeaderMap, signed_headers: &[HeaderName]) ->
 		return Err(Error::bad_request("Header `Host` should be signed"));
 	}
 	for (name, _) in headers.iter() {
-		if name.as_str().starts_with("x-amz-") {
+		// x-garage-block-hashes replaces the hashing of uploaded data by Garage,
+		// so it must not be possible to tamper with it either
+		if name.as_str().starts_with("x-amz-") || name.as_str() == "x-garage-block-hashes" {
 			if !signed_headers.contains(name) {
 				return Err(Error::bad_request(format!(
 					"Header `{}` should be signed",
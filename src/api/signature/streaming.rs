@@ -336,7 +336,9 @@ where
 mod tests {
 	use futures::prelude::*;
 
-	use super::{SignedPayloadStream, SignedPayloadStreamError};
+	use super::{
+		compute_streaming_payload_signature, SignedPayloadStream, SignedPayloadStreamError,
+	};
 
 	#[tokio::test]
 	async fn test_interrupted_signed_payload_stream() {
@@ -370,4 +372,82 @@ mod tests {
 			),
 		}
 	}
+
+	#[tokio::test]
+	async fn test_decode_signed_payload_stream() {
+		use chrono::{DateTime, Utc};
+
+		use garage_util::data::{sha256sum, Hash};
+
+		let datetime = DateTime::parse_from_rfc3339("2021-12-13T13:12:42+01:00")
+			.unwrap()
+			.with_timezone(&Utc);
+		let secret_key = "test";
+		let region = "test";
+		let scope = crate::signature::compute_scope(&datetime, region, "s3");
+		let signing_hmac =
+			crate::signature::signing_hmac(&datetime, secret_key, region, "s3").unwrap();
+		let seed_signature = Hash::default();
+
+		// Build an aws-chunked body: `<hexsize>;chunk-signature=<sig>\r\n<data>\r\n`,
+		// where each signature is chained to the previous one, terminated by a
+		// 0-sized chunk
+		let chunks: &[&[u8]] = &[b"hello, ", b"aws-chunked ", b"world", b""];
+		let mut body = vec![];
+		let mut previous_signature = seed_signature;
+		for chunk in chunks {
+			let signature = compute_streaming_payload_signature(
+				&signing_hmac,
+				datetime,
+				&scope,
+				previous_signature,
+				sha256sum(chunk),
+			)
+			.unwrap();
+			body.extend_from_slice(
+				format!(
+					"{:x};chunk-signature={}\r\n",
+					chunk.len(),
+					hex::encode(signature)
+				)
+				.as_bytes(),
+			);
+			body.extend_from_slice(chunk);
+			body.extend_from_slice(b"\r\n");
+			previous_signature = signature;
+		}
+
+		// Feed the body in small pieces that do not match the chunk boundaries
+		let pieces = body
+			.chunks(7)
+			.map(|x| Ok(x.to_vec().into()))
+			.collect::<Vec<_>>();
+		let stream = SignedPayloadStream::new(
+			futures::stream::iter(pieces),
+			signing_hmac.clone(),
+			datetime,
+			&scope,
+			seed_signature,
+		);
+		let decoded = stream.try_collect::<Vec<_>>().await.unwrap().concat();
+		assert_eq!(&decoded[..], b"hello, aws-chunked world");
+
+		// Any modification of the data invalidates the signature of its chunk
+		let mut tampered = body.clone();
+		let pos = tampered.windows(5).position(|w| w == b"world").unwrap();
+		tampered[pos] = b'W';
+		let mut stream = SignedPayloadStream::new(
+			futures::stream::iter(vec![Ok(tampered.into())]),
+			signing_hmac,
+			datetime,
+			&scope,
+			seed_signature,
+		);
+		assert!(stream.try_next().await.unwrap().is_some());
+		assert!(stream.try_next().await.unwrap().is_some());
+		assert!(matches!(
+			stream.try_next().await,
+			Err(SignedPayloadStreamError::InvalidSignature)
+		));
+	}
 }
@@ -8,6 +8,7 @@ pub mod resync;
 mod block;
 mod layout;
 mod metrics;
+mod rate_limit;
 mod rc;
 
 pub use block::zstd_encode;
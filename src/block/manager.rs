@@ -39,6 +39,7 @@ use garage_table::replication::{TableReplication, TableShardedReplication};
 use crate::block::*;
 use crate::layout::*;
 use crate::metrics::*;
+use crate::rate_limit::WriteRateLimiter;
 use crate::rc::*;
 use crate::repair::*;
 use crate::resync::*;
@@ -67,6 +68,12 @@ pub enum BlockRpc {
 	NeedBlockQuery(Hash),
 	/// Response : whether the node do require that block
 	NeedBlockReply(bool),
+	/// Same as PutBlock, but the block must be synced to disk
+	/// before the write is acknowledged
+	PutBlockSync {
+		hash: Hash,
+		header: DataBlockHeader,
+	},
 }
 
 impl Rpc for BlockRpc {
@@ -95,6 +102,7 @@ pub struct BlockManager {
 	pub(crate) system: Arc<System>,
 	pub(crate) endpoint: Arc<Endpoint<BlockRpc, Self>>,
 	buffer_kb_semaphore: Arc<Semaphore>,
+	write_rate_limiter: Option<WriteRateLimiter>,
 
 	pub(crate) metrics: BlockManagerMetrics,
 
@@ -155,9 +163,11 @@ impl BlockManager {
 			.endpoint("garage_block/manager.rs/Rpc".to_string());
 
 		let buffer_kb_semaphore = Arc::new(Semaphore::new(config.block_ram_buffer_max / 1024));
+		let write_rate_limiter = config.block_write_rate.map(WriteRateLimiter::new);
 
 		let metrics = BlockManagerMetrics::new(
 			config.compression_level,
+			config.block_write_rate,
 			rc.rc_table.clone(),
 			resync.queue.clone(),
 			resync.errors.clone(),
@@ -182,6 +192,7 @@ impl BlockManager {
 			system,
 			endpoint,
 			buffer_kb_semaphore,
+			write_rate_limiter,
 			metrics,
 			scrub_persister,
 			tx_scrub_command: ArcSwapOption::new(None),
@@ -341,6 +352,18 @@ impl BlockManager {
 
 	// ---- Public interface ----
 
+	/// Same as `rpc_get_block_streaming`, but the data is checked against
+	/// the hash of the block as it is streamed. If it doesn't match, the stream
+	/// ends with an error instead of terminating normally.
+	pub async fn rpc_get_block_streaming_verified(
+		&self,
+		hash: &Hash,
+		order_tag: Option<OrderTag>,
+	) -> Result<ByteStream, Error> {
+		let stream = self.rpc_get_block_streaming(hash, order_tag).await?;
+		Ok(verify_block_stream(*hash, stream))
+	}
+
 	/// Ask nodes that might have a block for it, return it as a stream
 	pub async fn rpc_get_block_streaming(
 		&self,
@@ -364,48 +387,85 @@ impl BlockManager {
 	}
 
 	/// Send block to nodes that should have it
+	///
+	/// If `allow_degraded` is set and the write quorum can't be reached,
+	/// the write succeeds as soon as one node of each write set has stored
+	/// the block, which is then marked to be re-replicated by the resync
+	/// worker. Until this is done, losing that node means losing the block.
+	///
+	/// If `sync` is set, the write is acknowledged by storage nodes only once
+	/// the block has been synced to disk, even if `data_fsync` is not enabled.
+	///
+	/// Returns the number of bytes of the block as it is stored by each node,
+	/// i.e. after compression.
 	pub async fn rpc_put_block(
 		&self,
 		hash: Hash,
 		data: Bytes,
 		prevent_compression: bool,
 		order_tag: Option<OrderTag>,
-	) -> Result<(), Error> {
+		allow_degraded: bool,
+		sync: bool,
+	) -> Result<u64, Error> {
 		let who = self.replication.write_sets(&hash);
 
 		let compression_level = self.compression_level.filter(|_| !prevent_compression);
 		let (header, bytes) = DataBlock::from_buffer(data, compression_level)
 			.await
 			.into_parts();
+		let stored_len = bytes.len() as u64;
+		let buffer_kb = (bytes.len() / 1024).try_into().unwrap();
 
-		let permit = self
-			.buffer_kb_semaphore
-			.clone()
-			.acquire_many_owned((bytes.len() / 1024).try_into().unwrap())
-			.await
-			.ok_or_message("could not reserve space for buffer of data to send to remote nodes")?;
-
-		let put_block_rpc =
-			Req::new(BlockRpc::PutBlock { hash, header })?.with_stream_from_buffer(bytes);
+		let msg = if sync {
+			BlockRpc::PutBlockSync { hash, header }
+		} else {
+			BlockRpc::PutBlock { hash, header }
+		};
+		let put_block_rpc = Req::new(msg)?.with_stream_from_buffer(bytes);
 		let put_block_rpc = if let Some(tag) = order_tag {
 			put_block_rpc.with_order_tag(tag)
 		} else {
 			put_block_rpc
 		};
 
-		self.system
-			.rpc_helper()
-			.try_write_many_sets(
-				&self.endpoint,
-				who.as_ref(),
-				put_block_rpc,
-				RequestStrategy::with_priority(PRIO_NORMAL | PRIO_SECONDARY)
-					.with_drop_on_completion(permit)
-					.with_quorum(self.replication.write_quorum()),
-			)
-			.await?;
+		let (who, put_block_rpc) = (&who, &put_block_rpc);
+		let put_with_quorum = |quorum: usize| async move {
+			let permit = self
+				.buffer_kb_semaphore
+				.clone()
+				.acquire_many_owned(buffer_kb)
+				.await
+				.ok_or_message(
+					"could not reserve space for buffer of data to send to remote nodes",
+				)?;
+			self.system
+				.rpc_helper()
+				.try_write_many_sets(
+					&self.endpoint,
+					who.as_ref(),
+					put_block_rpc.clone(),
+					RequestStrategy::with_priority(PRIO_NORMAL | PRIO_SECONDARY)
+						.with_drop_on_completion(permit)
+						.with_quorum(quorum),
+				)
+				.await
+		};
 
-		Ok(())
+		match put_with_quorum(self.replication.write_quorum()).await {
+			Err(e @ Error::Quorum(..)) if allow_degraded => {
+				warn!(
+					"Block {:?}: write quorum not reached ({}), writing in degraded mode",
+					hash, e
+				);
+				put_with_quorum(1).await?;
+				self.resync.mark_degraded(&hash)?;
+			}
+			res => {
+				res?;
+			}
+		}
+
+		Ok(stored_len)
 	}
 
 	/// Get number of items in the refcount table
@@ -507,20 +567,37 @@ impl BlockManager {
 		hash: Hash,
 		header: DataBlockHeader,
 		stream: Option<ByteStream>,
+		sync: bool,
 	) -> Result<(), Error> {
 		let stream = stream.ok_or_message("missing stream")?;
 		let bytes = read_stream_to_end(stream).await?.into_bytes();
+
+		// Slow down the sender if blocks arrive faster than they are
+		// allowed to be written to disk
+		if let Some(limiter) = &self.write_rate_limiter {
+			limiter
+				.acquire(bytes.len())
+				.bound_record_duration(&self.metrics.write_throttle_duration)
+				.await;
+		}
+
 		let data = DataBlock::from_parts(header, bytes);
-		self.write_block(&hash, &data).await
+		self.write_block(&hash, &data, sync).await
 	}
 
-	/// Write a block to disk
-	pub(crate) async fn write_block(&self, hash: &Hash, data: &DataBlock) -> Result<(), Error> {
+	/// Write a block to disk. If `sync` is set, the block is synced to disk
+	/// before returning, even if `data_fsync` is not enabled.
+	pub(crate) async fn write_block(
+		&self,
+		hash: &Hash,
+		data: &DataBlock,
+		sync: bool,
+	) -> Result<(), Error> {
 		let tracer = opentelemetry::global::tracer("garage");
 
 		self.lock_mutate(hash)
 			.await
-			.write_block(hash, data, self)
+			.write_block(hash, data, self, sync)
 			.bound_record_duration(&self.metrics.block_write_duration)
 			.with_context(Context::current_with_span(
 				tracer.start("BlockManagerLocked::write_block"),
@@ -693,7 +770,12 @@ impl StreamingEndpointHandler<BlockRpc> for BlockManager {
 	async fn handle(self: &Arc<Self>, mut message: Req<BlockRpc>, _from: NodeID) -> Resp<BlockRpc> {
 		match message.msg() {
 			BlockRpc::PutBlock { hash, header } => Resp::new(
-				self.handle_put_block(*hash, *header, message.take_stream())
+				self.handle_put_block(*hash, *header, message.take_stream(), false)
+					.await
+					.map(|()| BlockRpc::Ok),
+			),
+			BlockRpc::PutBlockSync { hash, header } => Resp::new(
+				self.handle_put_block(*hash, *header, message.take_stream(), true)
 					.await
 					.map(|()| BlockRpc::Ok),
 			),
@@ -712,9 +794,11 @@ impl BlockManagerLocked {
 		hash: &Hash,
 		data: &DataBlock,
 		mgr: &BlockManager,
+		sync: bool,
 	) -> Result<(), Error> {
 		let existing_path = mgr.find_block(hash).await;
-		self.write_block_inner(hash, data, mgr, existing_path).await
+		self.write_block_inner(hash, data, mgr, existing_path, sync)
+			.await
 	}
 
 	async fn write_block_inner(
@@ -723,6 +807,7 @@ impl BlockManagerLocked {
 		data: &DataBlock,
 		mgr: &BlockManager,
 		existing_path: Option<DataBlockPath>,
+		sync: bool,
 	) -> Result<(), Error> {
 		let (header, data) = data.as_parts_ref();
 		let compressed = header.is_compressed();
@@ -748,12 +833,17 @@ impl BlockManagerLocked {
 
 			// If the block is already stored compressed,
 			// keep the stored copy, we have nothing to do
-			(Some((DataBlockHeader::Compressed, _)), _) => return Ok(()),
+			// (except syncing it if a synced write was requested)
+			(Some((DataBlockHeader::Compressed, p)), _) => {
+				return sync_existing_block(&p, sync, mgr).await
+			}
 
 			// If the block is already stored not compressed,
 			// and we don't have a compressed copy either,
 			// keep the stored copy, we have nothing to do
-			(Some((DataBlockHeader::Plain, _)), false) => return Ok(()),
+			(Some((DataBlockHeader::Plain, p)), false) => {
+				return sync_existing_block(&p, sync, mgr).await
+			}
 
 			// If the block isn't stored already, just store what is given to us
 			(None, _) => None,
@@ -768,12 +858,15 @@ impl BlockManagerLocked {
 
 		let mut delete_on_drop = DeleteOnDrop(Some(path_tmp.clone()));
 
+		let sync = sync || mgr.data_fsync;
+
 		let mut f = fs::File::create(&path_tmp).await?;
 		f.write_all(data).await?;
 		mgr.metrics.bytes_written.add(data.len() as u64);
 
-		if mgr.data_fsync {
+		if sync {
 			f.sync_all().await?;
+			mgr.metrics.synced_writes.add(1);
 		}
 
 		drop(f);
@@ -786,7 +879,7 @@ impl BlockManagerLocked {
 			fs::remove_file(to_delete).await?;
 		}
 
-		if mgr.data_fsync {
+		if sync {
 			// We want to ensure that when this function returns, data is properly persisted
 			// to disk. The first step is the sync_all above that does an fsync on the data file.
 			// Now, we do an fsync on the containing directory, to ensure that the rename
@@ -837,12 +930,71 @@ impl BlockManagerLocked {
 		mgr: &BlockManager,
 	) -> Result<usize, Error> {
 		let data = mgr.read_block_from(hash, &wrong_path).await?;
-		self.write_block_inner(hash, &data, mgr, Some(wrong_path))
+		self.write_block_inner(hash, &data, mgr, Some(wrong_path), false)
 			.await?;
 		Ok(data.as_parts_ref().1.len())
 	}
 }
 
+/// Sync a block that is already stored to disk, if a synced write was requested,
+/// as it might have been written without syncing it
+async fn sync_existing_block(path: &PathBuf, sync: bool, mgr: &BlockManager) -> Result<(), Error> {
+	if sync {
+		fs::File::open(path).await?.sync_all().await?;
+		mgr.metrics.synced_writes.add(1);
+	}
+	Ok(())
+}
+
+/// Wrap a stream of block data so that its blake2 hash is computed on the fly
+/// and checked against the expected hash once the stream is exhausted.
+/// The last chunk is held back until the check has passed, so that a client
+/// can never receive the complete content of a corrupted block.
+fn verify_block_stream(hash: Hash, stream: ByteStream) -> ByteStream {
+	use futures::StreamExt;
+
+	struct State {
+		stream: ByteStream,
+		hasher: Blake2Hasher,
+		pending: Option<Bytes>,
+	}
+
+	let state = Some(State {
+		stream,
+		hasher: Blake2Hasher::new(),
+		pending: None,
+	});
+	Box::pin(futures::stream::unfold(state, move |state| async move {
+		let mut state = state?;
+		loop {
+			match state.stream.next().await {
+				Some(Ok(bytes)) => {
+					state.hasher.update(&bytes);
+					if let Some(prev) = state.pending.replace(bytes) {
+						return Some((Ok(prev), Some(state)));
+					}
+				}
+				Some(Err(e)) => return Some((Err(e), None)),
+				None => {
+					let computed = state.hasher.finalize();
+					if computed == hash {
+						return state.pending.map(|bytes| (Ok(bytes), None));
+					}
+					error!(
+						"Block {:?} is corrupted: data read has hash {:?}",
+						hash, computed
+					);
+					let err = std::io::Error::new(
+						std::io::ErrorKind::InvalidData,
+						format!("block {:?} failed integrity check", hash),
+					);
+					return Some((Err(err), None));
+				}
+			}
+		}
+	}))
+}
+
 struct DeleteOnDrop(Option<PathBuf>);
 
 impl DeleteOnDrop {
@@ -862,3 +1014,55 @@ impl Drop for DeleteOnDrop {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use garage_rpc::test_util::*;
+
+	use super::*;
+
+	#[tokio::test]
+	async fn test_degraded_write() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let config = test_config(&dir, 2, "");
+		let system = test_system(&config);
+		let db = test_db(&config);
+
+		// Blocks are stored on this node and on another one, which is down
+		assign_test_layout(&system, &[system.id, [1u8; 32].into()]).await;
+
+		let replication = TableShardedReplication {
+			system: system.clone(),
+			replication_factor: 2,
+			read_quorum: 1,
+			write_quorum: 2,
+		};
+		let manager = BlockManager::new(&db, &config, replication, system).unwrap();
+
+		let data = Bytes::from_static(b"degraded write test block");
+		let hash = blake2sum(&data);
+
+		assert!(manager
+			.rpc_put_block(hash, data.clone(), false, None, false, false)
+			.await
+			.is_err());
+		assert!(!manager.resync.is_degraded(&hash).unwrap());
+
+		// In degraded mode, the write succeeds and the block is marked for repair
+		manager
+			.rpc_put_block(hash, data, false, None, true, false)
+			.await
+			.unwrap();
+		assert!(manager.find_block(&hash).await.is_some());
+		assert!(manager.resync.is_degraded(&hash).unwrap());
+		assert_eq!(manager.resync.degraded_len().unwrap(), 1);
+
+		// The block can't be re-replicated as long as the other node is down
+		assert!(manager
+			.resync
+			.repair_degraded_block(&manager, &hash)
+			.await
+			.is_err());
+		assert!(manager.resync.is_degraded(&hash).unwrap());
+	}
+}
@@ -9,6 +9,7 @@ use garage_db as db;
 /// TableMetrics reference all counter used for metrics
 pub struct BlockManagerMetrics {
 	pub(crate) _compression_level: ValueObserver<u64>,
+	pub(crate) _write_rate_limit: ValueObserver<u64>,
 	pub(crate) _rc_size: ValueObserver<u64>,
 	pub(crate) _resync_queue_len: ValueObserver<u64>,
 	pub(crate) _resync_errored_blocks: ValueObserver<u64>,
@@ -24,6 +25,8 @@ pub struct BlockManagerMetrics {
 	pub(crate) block_read_duration: BoundValueRecorder<f64>,
 	pub(crate) bytes_written: BoundCounter<u64>,
 	pub(crate) block_write_duration: BoundValueRecorder<f64>,
+	pub(crate) write_throttle_duration: BoundValueRecorder<f64>,
+	pub(crate) synced_writes: BoundCounter<u64>,
 	pub(crate) delete_counter: BoundCounter<u64>,
 
 	pub(crate) corruption_counter: BoundCounter<u64>,
@@ -32,6 +35,7 @@ pub struct BlockManagerMetrics {
 impl BlockManagerMetrics {
 	pub fn new(
 		compression_level: Option<i32>,
+		write_rate: Option<u64>,
 		rc_tree: db::Tree,
 		resync_queue: db::Tree,
 		resync_errors: db::Tree,
@@ -48,6 +52,14 @@ impl BlockManagerMetrics {
 				})
 				.with_description("Garage compression level for node")
 				.init(),
+			_write_rate_limit: meter
+				.u64_value_observer("block.write_rate_limit", move |observer| {
+					observer.observe(write_rate.unwrap_or(0), &[])
+				})
+				.with_description(
+					"Maximum rate in bytes per second at which PutObject blocks are written to disk (0 if unlimited)",
+				)
+				.init(),
 			_rc_size: meter
 				.u64_value_observer("block.rc_size", move |observer| {
 					if let Ok(value) = rc_tree.len() {
@@ -129,6 +141,18 @@ impl BlockManagerMetrics {
 				.with_description("Duration of block write operations")
 				.init()
 				.bind(&[]),
+			write_throttle_duration: meter
+				.f64_value_recorder("block.write_throttle_duration")
+				.with_description(
+					"Time spent waiting before writing blocks because of the write rate limit",
+				)
+				.init()
+				.bind(&[]),
+			synced_writes: meter
+				.u64_counter("block.synced_writes")
+				.with_description("Number of block writes synced to disk before being acknowledged")
+				.init()
+				.bind(&[]),
 			delete_counter: meter
 				.u64_counter("block.delete_counter")
 				.with_description("Number of blocks deleted")
@@ -0,0 +1,74 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Token bucket limiting the number of bytes per second of data blocks
+/// written to the disks of this node. Writers wait for their turn instead
+/// of failing, which slows uploads down to the rate the disks can sustain.
+pub(crate) struct WriteRateLimiter {
+	rate: u64,
+	bucket: Mutex<TokenBucket>,
+}
+
+struct TokenBucket {
+	/// Number of bytes that can be written right away, negative when
+	/// writers are waiting for their turn
+	tokens: f64,
+	last_refill: Instant,
+}
+
+impl WriteRateLimiter {
+	/// Create a limiter that lets `rate` bytes through each second,
+	/// allowing bursts of at most one second worth of writes
+	pub(crate) fn new(rate: u64) -> Self {
+		Self {
+			rate,
+			bucket: Mutex::new(TokenBucket {
+				tokens: rate as f64,
+				last_refill: Instant::now(),
+			}),
+		}
+	}
+
+	/// Wait until `len` bytes can be written. The tokens are taken right
+	/// away, so that writers are served in the order they arrive and
+	/// writes bigger than the bucket are possible.
+	pub(crate) async fn acquire(&self, len: usize) {
+		let wait = {
+			let mut bucket = self.bucket.lock().unwrap();
+			let now = Instant::now();
+			let elapsed = now.saturating_duration_since(bucket.last_refill);
+			bucket.tokens =
+				(bucket.tokens + elapsed.as_secs_f64() * self.rate as f64).min(self.rate as f64);
+			bucket.last_refill = now;
+			bucket.tokens -= len as f64;
+			Duration::from_secs_f64((-bucket.tokens).max(0.0) / self.rate as f64)
+		};
+		if !wait.is_zero() {
+			tokio::time::sleep(wait).await;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn test_write_rate_limit() {
+		const RATE: u64 = 1_000_000;
+		let limiter = WriteRateLimiter::new(RATE);
+
+		// A burst of one second worth of writes goes through right away
+		let start = Instant::now();
+		for _ in 0..10 {
+			limiter.acquire(100_000).await;
+		}
+		assert!(start.elapsed() < Duration::from_millis(500));
+
+		// Then the throughput of concurrent writers is capped by the rate
+		let start = Instant::now();
+		futures::future::join_all((0..15).map(|_| limiter.acquire(100_000))).await;
+		let throughput = 1_500_000. / start.elapsed().as_secs_f64();
+		assert!(throughput <= RATE as f64 * 1.05, "{} bytes/s", throughput);
+	}
+}
@@ -49,6 +49,7 @@ pub struct BlockResyncManager {
 	pub(crate) queue: db::Tree,
 	pub(crate) notify: Arc<Notify>,
 	pub(crate) errors: db::Tree,
+	pub(crate) degraded: db::Tree,
 
 	busy_set: BusySet,
 
@@ -94,12 +95,17 @@ impl BlockResyncManager {
 			.open_tree("block_local_resync_errors")
 			.expect("Unable to open block_local_resync_errors tree");
 
+		let degraded = db
+			.open_tree("block_local_degraded_writes")
+			.expect("Unable to open block_local_degraded_writes tree");
+
 		let persister = PersisterShared::new(&system.metadata_dir, "resync_cfg");
 
 		Self {
 			queue,
 			notify: Arc::new(Notify::new()),
 			errors,
+			degraded,
 			busy_set: Arc::new(Mutex::new(HashSet::new())),
 			persister,
 		}
@@ -115,6 +121,25 @@ impl BlockResyncManager {
 		Ok(self.errors.len()?)
 	}
 
+	/// Get number of blocks written in degraded mode that are not yet re-replicated
+	pub fn degraded_len(&self) -> Result<usize, Error> {
+		Ok(self.degraded.len()?)
+	}
+
+	/// Check whether a block was written in degraded mode and is not yet re-replicated
+	pub fn is_degraded(&self, hash: &Hash) -> Result<bool, Error> {
+		Ok(self.degraded.get(hash.as_ref())?.is_some())
+	}
+
+	/// Record that a block was stored on less nodes than its target replication
+	/// level, and schedule its re-replication
+	pub(crate) fn mark_degraded(&self, hash: &Hash) -> Result<(), Error> {
+		self.degraded
+			.insert(hash.as_ref(), u64::to_be_bytes(now_msec()))?;
+		self.put_to_resync(hash, RESYNC_RETRY_DELAY)?;
+		Ok(())
+	}
+
 	/// Clear the error counter for a block and put it in queue immediately
 	pub fn clear_backoff(&self, hash: &Hash) -> Result<(), Error> {
 		let now = now_msec();
@@ -352,6 +377,10 @@ impl BlockResyncManager {
 	}
 
 	async fn resync_block(&self, manager: &BlockManager, hash: &Hash) -> Result<(), Error> {
+		if self.is_degraded(hash)? {
+			self.repair_degraded_block(manager, hash).await?;
+		}
+
 		let existing_path = manager.find_block(hash).await;
 		let exists = existing_path.is_some();
 		let rc = manager.rc.get_block_rc(hash)?;
@@ -474,13 +503,55 @@ impl BlockResyncManager {
 
 			manager.metrics.resync_recv_counter.add(1);
 
-			manager.write_block(hash, &block_data).await?;
+			manager.write_block(hash, &block_data, false).await?;
 		}
 
 		Ok(())
 	}
 }
 
+impl BlockResyncManager {
+	/// Send a block that was written in degraded mode to all of its storage
+	/// nodes. The marker is only cleared once they have all stored it, so this
+	/// is retried with the usual backoff as long as some of them are unavailable.
+	pub(crate) async fn repair_degraded_block(
+		&self,
+		manager: &BlockManager,
+		hash: &Hash,
+	) -> Result<(), Error> {
+		info!(
+			"Resync block {:?}: re-replicating block written in degraded mode",
+			hash
+		);
+
+		let who = manager.replication.storage_nodes(hash);
+		let block = manager
+			.rpc_get_raw_block(hash, PRIO_BACKGROUND | PRIO_SECONDARY, None)
+			.await?;
+		let (header, bytes) = block.into_parts();
+		let put_block_message = Req::new(BlockRpc::PutBlock {
+			hash: *hash,
+			header,
+		})?
+		.with_stream_from_buffer(bytes);
+		manager
+			.system
+			.rpc_helper()
+			.try_call_many(
+				&manager.endpoint,
+				&who,
+				put_block_message,
+				RequestStrategy::with_priority(PRIO_BACKGROUND | PRIO_SECONDARY)
+					.with_quorum(who.len()),
+			)
+			.await
+			.err_context("PutBlock RPC")?;
+
+		self.degraded.remove(hash.as_ref())?;
+		Ok(())
+	}
+}
+
 impl Drop for BusyBlock {
 	fn drop(&mut self) {
 		let mut busy = self.busy_set.lock().unwrap();
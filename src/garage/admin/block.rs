@@ -1,8 +1,10 @@
 use garage_util::data::*;
+use garage_util::time::now_msec;
 
 use garage_table::*;
 
 use garage_model::helper::error::{Error, OkOrBadRequest};
+use garage_model::s3::block_ref_table::*;
 use garage_model::s3::object_table::*;
 use garage_model::s3::version_table::*;
 
@@ -17,6 +19,7 @@ impl AdminRpcHandler {
 				self.garage.block_manager.list_resync_errors()?,
 			)),
 			BlockOperation::Info { hash } => self.handle_block_info(hash).await,
+			BlockOperation::Refs { hash, limit } => self.handle_block_refs(hash, *limit).await,
 			BlockOperation::RetryNow { all, blocks } => {
 				self.handle_block_retry_now(*all, blocks).await
 			}
@@ -27,20 +30,11 @@ impl AdminRpcHandler {
 	async fn handle_block_info(&self, hash: &String) -> Result<AdminRpc, Error> {
 		let hash = self.find_block_hash_by_prefix(hash)?;
 		let refcount = self.garage.block_manager.get_block_rc(&hash)?;
-		let block_refs = self
-			.garage
-			.block_ref_table
-			.get_range(&hash, None, None, 10000, Default::default())
-			.await?;
+		let block_versions = versions_for_block(&self.garage.block_ref_table, &hash).await?;
 		let mut versions = vec![];
 		let mut uploads = vec![];
-		for br in block_refs {
-			if let Some(v) = self
-				.garage
-				.version_table
-				.get(&br.version, &EmptyKey)
-				.await?
-			{
+		for (version, _deleted) in block_versions {
+			if let Some(v) = self.garage.version_table.get(&version, &EmptyKey).await? {
 				if let VersionBacklink::MultipartUpload { upload_id } = &v.backlink {
 					if let Some(u) = self.garage.mpu_table.get(upload_id, &EmptyKey).await? {
 						uploads.push(u);
@@ -48,7 +42,7 @@ impl AdminRpcHandler {
 				}
 				versions.push(Ok(v));
 			} else {
-				versions.push(Err(br.version));
+				versions.push(Err(version));
 			}
 		}
 		Ok(AdminRpc::BlockInfo {
@@ -59,6 +53,13 @@ impl AdminRpcHandler {
 		})
 	}
 
+	async fn handle_block_refs(&self, hash: &String, limit: usize) -> Result<AdminRpc, Error> {
+		let hash = self.find_block_hash_by_prefix(hash)?;
+		let count = count_block_refs(&self.garage.block_ref_table, &hash).await?;
+		let refs = list_block_refs(&self.garage.block_ref_table, &hash, limit).await?;
+		Ok(AdminRpc::BlockRefs { hash, count, refs })
+	}
+
 	async fn handle_block_retry_now(
 		&self,
 		all: bool,
@@ -177,6 +178,8 @@ impl AdminRpcHandler {
 						vec![ObjectVersion {
 							uuid: del_uuid,
 							timestamp: ov.timestamp + 1,
+							created_at: Some(now_msec()),
+							versioned: false,
 							state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
 						}],
 					);
@@ -28,6 +28,7 @@ impl AdminRpcHandler {
 			BucketOperation::Deny(query) => self.handle_bucket_deny(query).await,
 			BucketOperation::Website(query) => self.handle_bucket_website(query).await,
 			BucketOperation::SetQuotas(query) => self.handle_bucket_set_quotas(query).await,
+			BucketOperation::SetOptions(query) => self.handle_bucket_set_options(query).await,
 			BucketOperation::CleanupIncompleteUploads(query) => {
 				self.handle_bucket_cleanup_incomplete_uploads(query).await
 			}
@@ -463,6 +464,130 @@ impl AdminRpcHandler {
 		)))
 	}
 
+	async fn handle_bucket_set_options(&self, query: &SetOptionsOpt) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.admin_get_existing_matching_bucket(&query.bucket)
+			.await?;
+
+		let mut bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		let bucket_state = bucket.state.as_option_mut().unwrap();
+
+		if query.blake2_etag.is_none()
+			&& query.verify_on_read.is_none()
+			&& query.require_content_md5.is_none()
+			&& query.compress_on_read.is_none()
+			&& query.allow_degraded_writes.is_none()
+			&& query.mfa_delete_required.is_none()
+			&& query.key_normalization.is_none()
+			&& query.reject_key_conflicts.is_none()
+			&& query.default_cache_control.is_none()
+			&& query.worm.is_none()
+			&& query.compact_inline.is_none()
+			&& query.force_block_storage.is_none()
+			&& query.disable_inline.is_none()
+			&& query.serialize_puts.is_none()
+			&& query.strict_delete_missing.is_none()
+			&& query.content_defined_chunking.is_none()
+			&& query.max_versions_per_key.is_none()
+		{
+			return Err(Error::BadRequest(
+				"You must specify at least one option to change for this command to do something."
+					.to_string(),
+			));
+		}
+
+		let mut options = bucket_state.options.get().clone();
+
+		if let Some(blake2_etag) = query.blake2_etag {
+			options.blake2_etag = blake2_etag;
+		}
+		if let Some(verify_on_read) = query.verify_on_read {
+			options.verify_on_read = verify_on_read;
+		}
+		if let Some(require_content_md5) = query.require_content_md5 {
+			options.require_content_md5 = require_content_md5;
+		}
+		if let Some(compress_on_read) = query.compress_on_read {
+			options.compress_on_read = compress_on_read;
+		}
+		if let Some(allow_degraded_writes) = query.allow_degraded_writes {
+			options.allow_degraded_writes = allow_degraded_writes;
+		}
+		if let Some(mfa_delete_required) = query.mfa_delete_required {
+			options.mfa_delete_required = mfa_delete_required;
+		}
+		if let Some(key_normalization) = query.key_normalization {
+			// Existing objects whose key is not normalized would be unreachable
+			if key_normalization != options.key_normalization
+				&& !self
+					.garage
+					.bucket_helper()
+					.is_bucket_empty(bucket_id)
+					.await?
+			{
+				return Err(Error::BadRequest(format!(
+					"Bucket {} is not empty, its key normalization cannot be changed",
+					query.bucket
+				)));
+			}
+			options.key_normalization = key_normalization;
+		}
+		if let Some(reject_key_conflicts) = query.reject_key_conflicts {
+			options.reject_key_conflicts = reject_key_conflicts;
+		}
+		if let Some(default_cache_control) = &query.default_cache_control {
+			let valid = default_cache_control
+				.chars()
+				.all(|c| c == '\t' || (' '..='~').contains(&c));
+			if !valid {
+				return Err(Error::BadRequest(format!(
+					"Invalid Cache-Control value: {}",
+					default_cache_control
+				)));
+			}
+			options.default_cache_control =
+				Some(default_cache_control.clone()).filter(|v| !v.is_empty());
+		}
+		if let Some(worm) = query.worm {
+			options.worm = worm;
+		}
+		if let Some(compact_inline) = query.compact_inline {
+			options.compact_inline = compact_inline;
+		}
+		if let Some(force_block_storage) = query.force_block_storage {
+			options.force_block_storage = force_block_storage;
+		}
+		if let Some(disable_inline) = query.disable_inline {
+			options.disable_inline = disable_inline;
+		}
+		if let Some(serialize_puts) = query.serialize_puts {
+			options.serialize_puts = serialize_puts;
+		}
+		if let Some(strict_delete_missing) = query.strict_delete_missing {
+			options.strict_delete_missing = strict_delete_missing;
+		}
+		if let Some(content_defined_chunking) = query.content_defined_chunking {
+			options.content_defined_chunking = content_defined_chunking;
+		}
+		if let Some(max_versions_per_key) = query.max_versions_per_key {
+			options.max_versions_per_key = Some(max_versions_per_key).filter(|m| *m > 0);
+		}
+
+		bucket_state.options.update(options);
+		self.garage.bucket_table.insert(&bucket).await?;
+
+		Ok(AdminRpc::Ok(format!(
+			"Options updated for {}",
+			&query.bucket
+		)))
+	}
+
 	async fn handle_bucket_cleanup_incomplete_uploads(
 		&self,
 		query: &CleanupIncompleteUploadsOpt,
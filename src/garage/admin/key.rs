@@ -106,6 +106,12 @@ impl AdminRpcHandler {
 		if query.create_bucket {
 			key.params_mut().unwrap().allow_create_bucket.update(true);
 		}
+		if query.trusted_block_hashes {
+			key.params_mut()
+				.unwrap()
+				.allow_trusted_block_hashes
+				.update(true);
+		}
 		self.garage.key_table.insert(&key).await?;
 		self.key_info_result(key).await
 	}
@@ -119,6 +125,12 @@ impl AdminRpcHandler {
 		if query.create_bucket {
 			key.params_mut().unwrap().allow_create_bucket.update(false);
 		}
+		if query.trusted_block_hashes {
+			key.params_mut()
+				.unwrap()
+				.allow_trusted_block_hashes
+				.update(false);
+		}
 		self.garage.key_table.insert(&key).await?;
 		self.key_info_result(key).await
 	}
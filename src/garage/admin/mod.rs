@@ -27,6 +27,7 @@ use garage_model::bucket_table::*;
 use garage_model::garage::Garage;
 use garage_model::helper::error::{Error, OkOrBadRequest};
 use garage_model::key_table::*;
+use garage_model::s3::block_ref_table::BlockRef;
 use garage_model::s3::mpu_table::MultipartUpload;
 use garage_model::s3::version_table::Version;
 
@@ -70,6 +71,11 @@ pub enum AdminRpc {
 		versions: Vec<Result<Version, Uuid>>,
 		uploads: Vec<MultipartUpload>,
 	},
+	BlockRefs {
+		hash: Hash,
+		count: usize,
+		refs: Vec<BlockRef>,
+	},
 }
 
 impl Rpc for AdminRpc {
@@ -232,6 +238,12 @@ impl AdminRpcHandler {
 			self.garage.block_manager.resync.errors_len()?
 		)
 		.unwrap();
+		writeln!(
+			&mut ret,
+			"  blocks written in degraded mode: {}",
+			self.garage.block_manager.resync.degraded_len()?
+		)
+		.unwrap();
 
 		if !opt.skip_global {
 			write!(&mut ret, "\n{}", self.gather_cluster_stats()).unwrap();
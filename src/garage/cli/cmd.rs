@@ -257,6 +257,9 @@ pub async fn cmd_admin(
 		} => {
 			print_block_info(hash, refcount, versions, uploads);
 		}
+		AdminRpc::BlockRefs { hash, count, refs } => {
+			print_block_refs(hash, count, refs);
+		}
 		r => {
 			error!("Unexpected response: {:?}", r);
 		}
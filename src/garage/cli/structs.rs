@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 
+use garage_model::bucket_table::KeyNormalization;
 use garage_util::version::garage_version;
 
 use crate::cli::convert_db;
@@ -232,6 +233,10 @@ pub enum BucketOperation {
 	#[structopt(name = "set-quotas", version = garage_version())]
 	SetQuotas(SetQuotasOpt),
 
+	/// Set optional behaviors for this bucket
+	#[structopt(name = "set-options", version = garage_version())]
+	SetOptions(SetOptionsOpt),
+
 	/// Clean up (abort) old incomplete multipart uploads
 	#[structopt(name = "cleanup-incomplete-uploads", version = garage_version())]
 	CleanupIncompleteUploads(CleanupIncompleteUploadsOpt),
@@ -336,6 +341,109 @@ pub struct SetQuotasOpt {
 	pub max_objects: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct SetOptionsOpt {
+	/// Bucket name
+	pub bucket: String,
+
+	/// Generate blake2-based etags instead of md5-based etags for new objects
+	/// (`true` or `false`). Such etags cannot be checked against the md5 of
+	/// the object by clients.
+	#[structopt(long = "blake2-etag")]
+	pub blake2_etag: Option<bool>,
+
+	/// Verify the hash of data blocks when objects are read (`true` or `false`).
+	/// Reads of corrupted blocks are interrupted instead of returning bad data.
+	#[structopt(long = "verify-on-read")]
+	pub verify_on_read: Option<bool>,
+
+	/// Reject uploads (PutObject and UploadPart) that do not include
+	/// a content-md5 header (`true` or `false`)
+	#[structopt(long = "require-content-md5")]
+	pub require_content_md5: Option<bool>,
+
+	/// Compress objects of a compressible content type (text, json, xml...)
+	/// with gzip when they are read by clients that accept it (`true` or `false`)
+	#[structopt(long = "compress-on-read")]
+	pub compress_on_read: Option<bool>,
+
+	/// Accept uploads whose data blocks could be stored on less nodes than the
+	/// write quorum, re-replicating them in the background (`true` or `false`).
+	/// Such data is lost if these nodes fail before re-replication is done.
+	#[structopt(long = "allow-degraded-writes")]
+	pub allow_degraded_writes: Option<bool>,
+
+	/// Reject object deletions that do not include the x-amz-mfa header
+	/// (`true` or `false`). The MFA code itself is not verified.
+	#[structopt(long = "mfa-delete-required")]
+	pub mfa_delete_required: Option<bool>,
+
+	/// Normalize object keys before using them (`none`, `nfc` for Unicode
+	/// normalization form C, or `lowercase` for NFC and lowercasing).
+	/// Can only be changed while the bucket is empty.
+	#[structopt(long = "key-normalization")]
+	pub key_normalization: Option<KeyNormalization>,
+
+	/// Reject the creation of an object `a/b` when an object `a/b/` exists
+	/// (i.e. a "folder" created by some clients), and vice versa (`true` or `false`)
+	#[structopt(long = "reject-key-conflicts")]
+	pub reject_key_conflicts: Option<bool>,
+
+	/// Cache-Control header to return when reading objects that were stored
+	/// without one, e.g. `"public, max-age=3600"` (an empty value removes it)
+	#[structopt(long = "default-cache-control")]
+	pub default_cache_control: Option<String>,
+
+	/// Make the bucket write-once: complete objects can no longer be
+	/// overwritten or deleted (`true` or `false`)
+	#[structopt(long = "worm")]
+	pub worm: Option<bool>,
+
+	/// Store the data of small objects apart from the object table, which
+	/// keeps it compact for buckets with many tiny objects (`true` or `false`).
+	/// This only applies to objects written after the option is set.
+	/// All nodes must be upgraded first, older versions cannot read these objects.
+	#[structopt(long = "compact-inline")]
+	pub compact_inline: Option<bool>,
+
+	/// Store the data of small objects in data blocks instead of inline in
+	/// the metadata (`true` or `false`). This only applies to objects
+	/// uploaded with PutObject or PostObject after the option is set.
+	#[structopt(long = "force-block-storage")]
+	pub force_block_storage: Option<bool>,
+
+	/// Never store the data of objects inline in the metadata, including
+	/// empty objects which are then stored without any data block
+	/// (`true` or `false`)
+	#[structopt(long = "disable-inline")]
+	pub disable_inline: Option<bool>,
+
+	/// Reject uploads of an object with a SlowDown error while another
+	/// upload of the same object is running on the node that receives
+	/// them (`true` or `false`)
+	#[structopt(long = "serialize-puts")]
+	pub serialize_puts: Option<bool>,
+
+	/// Return a NoSuchKey error when deleting an object that does not
+	/// exist, instead of succeeding as if it had been deleted
+	/// (`true` or `false`)
+	#[structopt(long = "strict-delete-missing")]
+	pub strict_delete_missing: Option<bool>,
+
+	/// Split the data of objects into blocks of variable size, at
+	/// boundaries that depend on their content, to deduplicate data
+	/// between similar objects. Only applies to data uploaded after the
+	/// option is set (`true` or `false`)
+	#[structopt(long = "content-defined-chunking")]
+	pub content_defined_chunking: Option<bool>,
+
+	/// Maximum number of versions, including delete markers, kept for
+	/// each object key; the oldest versions are deleted when a new one
+	/// is uploaded (`0` removes the limit)
+	#[structopt(long = "max-versions-per-key")]
+	pub max_versions_per_key: Option<u64>,
+}
+
 #[derive(Serialize, Deserialize, StructOpt, Debug)]
 pub struct CleanupIncompleteUploadsOpt {
 	/// Abort multipart uploads older than this value
@@ -425,6 +533,12 @@ pub struct KeyPermOpt {
 	/// Flag that allows key to create buckets using S3's CreateBucket call
 	#[structopt(long = "create-bucket")]
 	pub create_bucket: bool,
+
+	/// Flag that allows key to supply the hashes of the data blocks of its
+	/// uploads in the x-garage-block-hashes header, instead of having them
+	/// computed by Garage. Only give it to trusted clients.
+	#[structopt(long = "trusted-block-hashes")]
+	pub trusted_block_hashes: bool,
 }
 
 #[derive(Serialize, Deserialize, StructOpt, Debug)]
@@ -598,6 +712,15 @@ pub enum BlockOperation {
 		/// Hash of the block for which to retrieve information
 		hash: String,
 	},
+	/// Count the object versions that reference a block (deduplication report)
+	#[structopt(name = "refs", version = garage_version())]
+	Refs {
+		/// Hash of the block
+		hash: String,
+		/// Maximum number of referencing versions to list
+		#[structopt(long = "limit", default_value = "100")]
+		limit: usize,
+	},
 	/// Retry now the resync of one or many blocks
 	#[structopt(name = "retry-now", version = garage_version())]
 	RetryNow {
@@ -12,6 +12,7 @@ use garage_block::manager::BlockResyncErrorInfo;
 
 use garage_model::bucket_table::*;
 use garage_model::key_table::*;
+use garage_model::s3::block_ref_table::BlockRef;
 use garage_model::s3::mpu_table::{self, MultipartUpload};
 use garage_model::s3::object_table;
 use garage_model::s3::version_table::*;
@@ -83,6 +84,9 @@ pub fn print_key_info(key: &Key, relevant_buckets: &HashMap<Uuid, Bucket>) {
 			println!("Key ID: {}", key.key_id);
 			println!("Secret key: {}", p.secret_key);
 			println!("Can create buckets: {}", p.allow_create_bucket.get());
+			if *p.allow_trusted_block_hashes.get() {
+				println!("Can supply trusted block hashes: true");
+			}
 			println!("\nKey-specific bucket aliases:");
 			let mut table = vec![];
 			for (alias_name, _, alias) in p.local_aliases.items().iter() {
@@ -194,6 +198,37 @@ pub fn print_bucket_info(
 				}
 			}
 
+			let options = p.options.get();
+			if *options != BucketOptions::default() {
+				println!("\nOptions:");
+				println!(" blake2 etags: {}", options.blake2_etag);
+				println!(" verify on read: {}", options.verify_on_read);
+				println!(" require content-md5: {}", options.require_content_md5);
+				println!(" compress on read: {}", options.compress_on_read);
+				println!(" allow degraded writes: {}", options.allow_degraded_writes);
+				println!(" mfa delete required: {}", options.mfa_delete_required);
+				println!(" key normalization: {}", options.key_normalization);
+				println!(" reject key conflicts: {}", options.reject_key_conflicts);
+				println!(
+					" default cache-control: {}",
+					options.default_cache_control.as_deref().unwrap_or("none")
+				);
+				println!(" write-once (worm): {}", options.worm);
+				println!(" compact inline data: {}", options.compact_inline);
+				println!(" force block storage: {}", options.force_block_storage);
+				println!(" disable inline: {}", options.disable_inline);
+				println!(" serialize puts: {}", options.serialize_puts);
+				println!(" strict delete missing: {}", options.strict_delete_missing);
+				println!(
+					" content-defined chunking: {}",
+					options.content_defined_chunking
+				);
+				match options.max_versions_per_key {
+					Some(max) => println!(" max versions per key: {}", max),
+					None => println!(" max versions per key: none"),
+				}
+			}
+
 			println!("\nGlobal aliases:");
 			for (alias, _, active) in p.aliases.items().iter() {
 				if *active {
@@ -455,3 +490,22 @@ pub fn print_block_info(
 		);
 	}
 }
+
+pub fn print_block_refs(hash: Hash, count: usize, refs: Vec<BlockRef>) {
+	println!("Block hash: {}", hex::encode(hash.as_slice()));
+	println!("Referenced by {} non-deleted versions", count);
+	println!();
+
+	let mut table = vec!["Version\tBucket".into()];
+	for r in refs.iter() {
+		table.push(format!(
+			"{:?}\t{}",
+			r.version,
+			r.bucket_id.map(hex::encode).unwrap_or_default()
+		));
+	}
+	format_table(table);
+	if refs.len() < count {
+		println!("... and {} more", count - refs.len());
+	}
+}
@@ -1,3 +1,5 @@
+use std::time::{Duration, Instant};
+
 use crate::common;
 use crate::common::ext::*;
 
@@ -51,3 +53,113 @@ async fn test_admin_bucket_perms() {
 
 	assert!(hb().await.is_err());
 }
+
+#[tokio::test]
+async fn test_admin_block_refs() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("admin-block-refs");
+
+	// Same content stored under three keys: the data block is shared
+	let body = vec![42u8; 10_000];
+	let hash = hex::encode(garage_util::data::blake2sum(&body));
+	for key in ["one", "two", "three"] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(aws_sdk_s3::primitives::ByteStream::from(body.clone()))
+			.send()
+			.await
+			.unwrap();
+	}
+
+	let block_refs = |limit: &str| {
+		let output = ctx
+			.garage
+			.command()
+			.args(["block", "refs", &hash, "--limit", limit])
+			.expect_success_output("Could not get block refs");
+		String::from_utf8(output.stdout).unwrap()
+	};
+
+	let out = block_refs("100");
+	assert!(out.contains("Referenced by 3 non-deleted versions"));
+	let out = block_refs("1");
+	assert!(out.contains("... and 2 more"));
+
+	// References from deleted versions are not counted
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("two")
+		.send()
+		.await
+		.unwrap();
+	// The deletion of the version is propagated asynchronously
+	let expected = "Referenced by 2 non-deleted versions";
+	let out = poll_output(|| block_refs("100"), |out| out.contains(expected)).await;
+	assert!(out.contains(expected), "{}", out);
+}
+
+/// Runs `get` until its output satisfies `done`, for at most 10 seconds,
+/// and returns the last output
+async fn poll_output(get: impl Fn() -> String, done: impl Fn(&str) -> bool) -> String {
+	let deadline = Instant::now() + Duration::from_secs(10);
+	loop {
+		let out = get();
+		if done(&out) || Instant::now() > deadline {
+			return out;
+		}
+		tokio::time::sleep(Duration::from_millis(100)).await;
+	}
+}
+
+#[tokio::test]
+async fn test_admin_block_info_deleted_versions() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("admin-block-info-deleted");
+
+	// One block referenced by two versions, one of which is then deleted
+	let body = vec![43u8; 10_000];
+	let hash = hex::encode(garage_util::data::blake2sum(&body));
+	for key in ["kept", "deleted"] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(aws_sdk_s3::primitives::ByteStream::from(body.clone()))
+			.send()
+			.await
+			.unwrap();
+	}
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("deleted")
+		.send()
+		.await
+		.unwrap();
+
+	// The deletion of the version is propagated asynchronously
+	let block_info = || {
+		let output = ctx
+			.garage
+			.command()
+			.args(["block", "info", &hash])
+			.expect_success_output("Could not get block info");
+		String::from_utf8(output.stdout).unwrap()
+	};
+	let out = poll_output(block_info, |out| out.contains("Refcount: 1")).await;
+	let rows = out
+		.lines()
+		.filter(|l| l.trim_end().ends_with("true") || l.trim_end().ends_with("false"))
+		.collect::<Vec<_>>();
+	assert_eq!(rows.len(), 2, "{}", out);
+	assert!(rows
+		.iter()
+		.any(|l| l.contains("kept") && l.ends_with("false")));
+	assert!(rows
+		.iter()
+		.any(|l| l.contains("deleted") && l.ends_with("true")));
+	assert!(out.contains("Refcount: 1"), "{}", out);
+}
@@ -2,14 +2,13 @@ use aws_sdk_s3::config::BehaviorVersion;
 use aws_sdk_s3::config::Credentials;
 use aws_sdk_s3::{Client, Config};
 
-use super::garage::Key;
-use crate::common::garage::DEFAULT_PORT;
+use super::garage::{Instance, Key};
 
-pub fn build_client(key: &Key) -> Client {
+pub fn build_client(instance: &Instance, key: &Key) -> Client {
 	let credentials = Credentials::new(&key.id, &key.secret, None, None, "garage-integ-test");
 
 	let config = Config::builder()
-		.endpoint_url(format!("http://127.0.0.1:{}", DEFAULT_PORT))
+		.endpoint_url(format!("http://127.0.0.1:{}", instance.s3_port))
 		.region(super::REGION)
 		.credentials_provider(credentials)
 		.behavior_version(BehaviorVersion::v2023_11_09())
@@ -198,10 +198,12 @@ impl<'a> RequestBuilder<'a> {
 		);
 		all_headers.insert(HOST, HeaderValue::from_str(&host).unwrap());
 
-		let body_sha = match self.body_signature {
+		let body_sha = match &self.body_signature {
 			BodySignature::Unsigned => "UNSIGNED-PAYLOAD".to_owned(),
 			BodySignature::Classic => hex::encode(garage_util::data::sha256sum(&self.body)),
+			BodySignature::ClassicSha256(sha256) => sha256.clone(),
 			BodySignature::Streaming(size) => {
+				let size = *size;
 				all_headers.insert(
 					CONTENT_ENCODING,
 					HeaderValue::from_str("aws-chunked").unwrap(),
@@ -308,6 +310,9 @@ impl<'a> RequestBuilder<'a> {
 pub enum BodySignature {
 	Unsigned,
 	Classic,
+	/// Like `Classic`, but with the given x-amz-content-sha256 value,
+	/// which doesn't need to match the body
+	ClassicSha256(String),
 	Streaming(usize),
 }
 
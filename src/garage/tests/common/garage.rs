@@ -11,6 +11,8 @@ pub const DEFAULT_PORT: u16 = 49995;
 static GARAGE_TEST_SECRET: &str =
 	"c3ea8cb80333d04e208d136698b1a01ae370d463f0d435ab2177510b3478bf44";
 
+pub static GARAGE_TEST_ADMIN_TOKEN: &str = "garage-test-admin-token";
+
 #[derive(Debug, Default, Clone)]
 pub struct Key {
 	pub name: Option<String>,
@@ -29,19 +31,9 @@ pub struct Instance {
 }
 
 impl Instance {
-	fn new() -> Instance {
+	fn new(port: u16, path: PathBuf, extra_s3_config: &str) -> Instance {
 		use std::{env, fs};
 
-		let port = env::var("GARAGE_TEST_INTEGRATION_PORT")
-			.map(|value| value.parse().expect("Invalid port provided"))
-			.ok()
-			.unwrap_or(DEFAULT_PORT);
-
-		let path = env::var("GARAGE_TEST_INTEGRATION_PATH")
-			.map(PathBuf::from)
-			.ok()
-			.unwrap_or_else(|| env::temp_dir().join(format!("garage-integ-test-{}", port)));
-
 		let db_engine = env::var("GARAGE_TEST_INTEGRATION_DB_ENGINE")
 			.ok()
 			.unwrap_or_else(|| "lmdb".into());
@@ -68,6 +60,12 @@ rpc_secret = "{secret}"
 s3_region = "{region}"
 api_bind_addr = "127.0.0.1:{s3_port}"
 root_domain = ".s3.garage"
+put_max_duration = "20s"
+max_concurrent_uploads = 4
+multipart_min_part_size = "1MiB"
+put_memory_budget = "64MiB"
+max_key_length = 256
+{extra_s3_config}
 
 [k2v_api]
 api_bind_addr = "127.0.0.1:{k2v_port}"
@@ -79,15 +77,18 @@ index = "index.html"
 
 [admin]
 api_bind_addr = "127.0.0.1:{admin_port}"
+admin_token = "{admin_token}"
 "#,
 			path = path.display(),
 			secret = GARAGE_TEST_SECRET,
+			admin_token = GARAGE_TEST_ADMIN_TOKEN,
 			region = super::REGION,
 			s3_port = port,
 			k2v_port = port + 1,
 			rpc_port = port + 2,
 			web_port = port + 3,
 			admin_port = port + 4,
+			extra_s3_config = extra_s3_config,
 		);
 		fs::write(path.join("config.toml"), config).expect("Could not write garage config file");
 
@@ -220,6 +221,12 @@ api_bind_addr = "127.0.0.1:{admin_port}"
 	}
 }
 
+impl Drop for Instance {
+	fn drop(&mut self) {
+		self.terminate();
+	}
+}
+
 static mut INSTANCE: MaybeUninit<Instance> = MaybeUninit::uninit();
 static INSTANCE_INIT: Once = Once::new();
 
@@ -236,7 +243,19 @@ extern "C" fn terminate_instance() {
 
 pub fn instance() -> &'static Instance {
 	INSTANCE_INIT.call_once(|| unsafe {
-		let mut instance = Instance::new();
+		use std::env;
+
+		let port = env::var("GARAGE_TEST_INTEGRATION_PORT")
+			.map(|value| value.parse().expect("Invalid port provided"))
+			.ok()
+			.unwrap_or(DEFAULT_PORT);
+
+		let path = env::var("GARAGE_TEST_INTEGRATION_PATH")
+			.map(PathBuf::from)
+			.ok()
+			.unwrap_or_else(|| env::temp_dir().join(format!("garage-integ-test-{}", port)));
+
+		let mut instance = Instance::new(port, path, "");
 		instance.setup();
 
 		INSTANCE.write(instance);
@@ -247,6 +266,16 @@ pub fn instance() -> &'static Instance {
 	unsafe { INSTANCE.assume_init_ref() }
 }
 
+/// Start an instance for a single test that needs a specific configuration,
+/// with `extra_s3_config` added to the `[s3_api]` section. Its ports start
+/// at `port`, which must not be used by other tests. It is stopped when dropped.
+pub fn dedicated_instance(port: u16, extra_s3_config: &str) -> Instance {
+	let path = std::env::temp_dir().join(format!("garage-integ-test-{}", port));
+	let mut instance = Instance::new(port, path, extra_s3_config);
+	instance.setup();
+	instance
+}
+
 pub fn command(config_path: &Path) -> process::Command {
 	use std::env;
 
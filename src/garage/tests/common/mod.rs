@@ -17,8 +17,8 @@ use custom_requester::CustomRequester;
 const REGION: Region = Region::from_static("garage-integ-test");
 
 #[derive(Clone)]
-pub struct Context {
-	pub garage: &'static garage::Instance,
+pub struct Context<'a> {
+	pub garage: &'a garage::Instance,
 	pub key: garage::Key,
 	pub client: Client,
 	pub custom_request: CustomRequester,
@@ -31,11 +31,10 @@ pub struct K2VContext {
 	pub request: CustomRequester,
 }
 
-impl Context {
-	fn new() -> Self {
-		let garage = garage::instance();
+impl<'a> Context<'a> {
+	fn new(garage: &'a garage::Instance) -> Self {
 		let key = garage.key(None);
-		let client = client::build_client(&key);
+		let client = client::build_client(garage, &key);
 		let custom_request = CustomRequester::new_s3(garage, &key);
 		#[cfg(feature = "k2v")]
 		let k2v_request = CustomRequester::new_k2v(garage, &key);
@@ -90,6 +89,11 @@ impl Context {
 	}
 }
 
-pub fn context() -> Context {
-	Context::new()
+pub fn context() -> Context<'static> {
+	Context::new(garage::instance())
+}
+
+/// Context for a test that uses its own instance, see `garage::dedicated_instance`
+pub fn context_for(garage: &garage::Instance) -> Context<'_> {
+	Context::new(garage)
 }
@@ -1,4 +1,5 @@
 use crate::common;
+use crate::common::ext::CommandExt;
 
 const KEYS: [&str; 8] = ["a", "a/a", "a/b", "a/c", "a/d/a", "a/é", "b", "c"];
 const KEYS_MULTIPART: [&str; 5] = ["a", "a", "c", "c/a", "c/b"];
@@ -220,6 +221,87 @@ async fn test_listobjectsv2() {
 	}
 }
 
+#[tokio::test]
+async fn test_listobjectsv2_common_prefixes() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("listobjectsv2-common-prefixes");
+
+	for k in ["a/b", "a/c/d", "a/c/e", "b"] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(k)
+			.send()
+			.await
+			.unwrap();
+	}
+
+	// Keys below a/c/ are rolled up into a common prefix
+	let r = ctx
+		.client
+		.list_objects_v2()
+		.bucket(&bucket)
+		.prefix("a/")
+		.delimiter("/")
+		.send()
+		.await
+		.unwrap();
+	let contents = r
+		.contents()
+		.iter()
+		.map(|o| o.key().unwrap())
+		.collect::<Vec<_>>();
+	let prefixes = r
+		.common_prefixes()
+		.iter()
+		.map(|p| p.prefix().unwrap())
+		.collect::<Vec<_>>();
+	assert_eq!(contents, vec!["a/b"]);
+	assert_eq!(prefixes, vec!["a/c/"]);
+	assert_eq!(r.key_count, Some(2));
+
+	// With one entry per page, the common prefix is returned once,
+	// and the listing continues after all the keys it contains
+	let (ctx, bucket) = (&ctx, &bucket);
+	let list_pages = |prefix: Option<&'static str>| async move {
+		let mut pages = vec![];
+		let mut next = None;
+		loop {
+			let r = ctx
+				.client
+				.list_objects_v2()
+				.bucket(bucket)
+				.set_prefix(prefix.map(String::from))
+				.delimiter("/")
+				.max_keys(1)
+				.set_continuation_token(next)
+				.send()
+				.await
+				.unwrap();
+			let mut page = r
+				.contents()
+				.iter()
+				.map(|o| o.key().unwrap().to_string())
+				.collect::<Vec<_>>();
+			page.extend(
+				r.common_prefixes()
+					.iter()
+					.map(|p| p.prefix().unwrap().to_string()),
+			);
+			pages.push(page);
+			next = r.next_continuation_token;
+			if next.is_none() {
+				break pages;
+			}
+		}
+	};
+	assert_eq!(list_pages(None).await, vec![vec!["a/"], vec!["b"]]);
+	assert_eq!(
+		list_pages(Some("a/")).await,
+		vec![vec!["a/b"], vec!["a/c/"]]
+	);
+}
+
 #[tokio::test]
 async fn test_listobjectsv1() {
 	let ctx = common::context();
@@ -673,3 +755,405 @@ async fn test_multichar_delimiter() {
 		assert_eq!(common_prefixes[1].prefix.as_deref().unwrap(), "a/c/b/");
 	}
 }
+
+#[tokio::test]
+async fn test_listobjectversions() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("listobjectversions");
+
+	// "a" is overwritten and "c" is deleted, "d" is only being uploaded
+	for k in ["a", "a", "b", "c"] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(k)
+			.send()
+			.await
+			.unwrap();
+	}
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("c")
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("d")
+		.send()
+		.await
+		.unwrap();
+
+	let r = ctx
+		.client
+		.list_object_versions()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	assert!(!r.is_truncated.unwrap());
+	let versions = r
+		.versions()
+		.iter()
+		.map(|v| (v.key().unwrap(), v.is_latest().unwrap(), v.size().unwrap()))
+		.collect::<Vec<_>>();
+	assert_eq!(versions, vec![("a", true, 0), ("b", true, 0)]);
+	let delete_markers = r
+		.delete_markers()
+		.iter()
+		.map(|v| (v.key().unwrap(), v.is_latest().unwrap()))
+		.collect::<Vec<_>>();
+	assert_eq!(delete_markers, vec![("c", true)]);
+
+	// Version ids are those returned at upload time
+	let put = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("b")
+		.send()
+		.await
+		.unwrap();
+	let r = ctx
+		.client
+		.list_object_versions()
+		.bucket(&bucket)
+		.prefix("b")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(r.versions().len(), 1);
+	assert_eq!(r.versions()[0].version_id(), put.version_id());
+
+	// Pagination
+	let mut listed = vec![];
+	let mut key_marker = None;
+	let mut version_id_marker = None;
+	loop {
+		let r = ctx
+			.client
+			.list_object_versions()
+			.bucket(&bucket)
+			.max_keys(1)
+			.set_key_marker(key_marker)
+			.set_version_id_marker(version_id_marker)
+			.send()
+			.await
+			.unwrap();
+		let page = r
+			.versions()
+			.iter()
+			.map(|v| v.key().unwrap().to_string())
+			.chain(
+				r.delete_markers()
+					.iter()
+					.map(|v| v.key().unwrap().to_string()),
+			)
+			.collect::<Vec<_>>();
+		assert_eq!(page.len(), 1);
+		listed.extend(page);
+		if !r.is_truncated.unwrap() {
+			break;
+		}
+		key_marker = r.next_key_marker;
+		version_id_marker = r.next_version_id_marker;
+	}
+	assert_eq!(listed, vec!["a", "b", "c"]);
+}
+
+#[tokio::test]
+async fn test_bucket_versioning() {
+	use aws_sdk_s3::types::{BucketVersioningStatus, VersioningConfiguration};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("bucket-versioning");
+
+	let put = |body: &'static str| {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key("a")
+			.body(body.as_bytes().to_vec().into())
+			.send()
+	};
+	let set_versioning = |status: BucketVersioningStatus| {
+		ctx.client
+			.put_bucket_versioning()
+			.bucket(&bucket)
+			.versioning_configuration(VersioningConfiguration::builder().status(status).build())
+			.send()
+	};
+	let get_versioning = || ctx.client.get_bucket_versioning().bucket(&bucket).send();
+	let list_versions = || async {
+		let r = ctx
+			.client
+			.list_object_versions()
+			.bucket(&bucket)
+			.send()
+			.await
+			.unwrap();
+		let mut versions = r
+			.versions()
+			.iter()
+			.map(|v| v.version_id().unwrap().to_string())
+			.collect::<Vec<_>>();
+		versions.sort();
+		let delete_markers = r.delete_markers().len();
+		(versions, delete_markers)
+	};
+	let sorted = |mut v: Vec<String>| {
+		v.sort();
+		v
+	};
+
+	// Without versioning, a new version replaces the previous one
+	assert!(get_versioning().await.unwrap().status.is_none());
+	put("unversioned 1").await.unwrap();
+	let v = put("unversioned 2").await.unwrap().version_id.unwrap();
+	assert_eq!(list_versions().await, (vec![v.clone()], 0));
+
+	// With versioning enabled, all versions are kept
+	set_versioning(BucketVersioningStatus::Enabled)
+		.await
+		.unwrap();
+	assert_eq!(
+		get_versioning().await.unwrap().status,
+		Some(BucketVersioningStatus::Enabled)
+	);
+	let e1 = put("enabled 1").await.unwrap().version_id.unwrap();
+	let e2 = put("enabled 2").await.unwrap().version_id.unwrap();
+	assert_eq!(
+		list_versions().await,
+		(sorted(vec![v.clone(), e1.clone(), e2.clone()]), 0)
+	);
+
+	// Older versions can still be read
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("a")
+		.version_id(&e1)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.body.collect().await.unwrap().into_bytes(), "enabled 1");
+
+	// With versioning suspended, a new version replaces the version stored
+	// while versioning was not enabled, but not those stored while it was
+	set_versioning(BucketVersioningStatus::Suspended)
+		.await
+		.unwrap();
+	assert_eq!(
+		get_versioning().await.unwrap().status,
+		Some(BucketVersioningStatus::Suspended)
+	);
+	let s1 = put("suspended 1").await.unwrap().version_id.unwrap();
+	assert_eq!(
+		list_versions().await,
+		(sorted(vec![e1.clone(), e2.clone(), s1.clone()]), 0)
+	);
+	let s2 = put("suspended 2").await.unwrap().version_id.unwrap();
+	assert_eq!(
+		list_versions().await,
+		(sorted(vec![e1.clone(), e2.clone(), s2.clone()]), 0)
+	);
+
+	// A deletion also replaces that version with a delete marker
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("a")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(
+		list_versions().await,
+		(sorted(vec![e1.clone(), e2.clone()]), 1)
+	);
+
+	// Only Enabled and Suspended are valid statuses
+	let r = ctx
+		.client
+		.put_bucket_versioning()
+		.bucket(&bucket)
+		.versioning_configuration(
+			VersioningConfiguration::builder()
+				.status(BucketVersioningStatus::from("Disabled"))
+				.build(),
+		)
+		.send()
+		.await;
+	assert!(r.is_err());
+}
+
+#[tokio::test]
+async fn test_max_versions_per_key() {
+	use aws_sdk_s3::types::{BucketVersioningStatus, VersioningConfiguration};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("max-versions-per-key");
+
+	ctx.client
+		.put_bucket_versioning()
+		.bucket(&bucket)
+		.versioning_configuration(
+			VersioningConfiguration::builder()
+				.status(BucketVersioningStatus::Enabled)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+	ctx.garage
+		.command()
+		.args(["bucket", "set-options", &bucket])
+		.args(["--max-versions-per-key", "3"])
+		.quiet()
+		.expect_success_status("Could not set bucket options");
+
+	let put = |body: &'static str| {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key("a")
+			.body(body.as_bytes().to_vec().into())
+			.send()
+	};
+	let list_versions = || async {
+		let r = ctx
+			.client
+			.list_object_versions()
+			.bucket(&bucket)
+			.send()
+			.await
+			.unwrap();
+		let mut versions = r
+			.versions()
+			.iter()
+			.map(|v| v.version_id().unwrap().to_string())
+			.collect::<Vec<_>>();
+		versions.sort();
+		(versions, r.delete_markers().len())
+	};
+	let sorted = |mut v: Vec<String>| {
+		v.sort();
+		v
+	};
+
+	let v1 = put("1").await.unwrap().version_id.unwrap();
+	let v2 = put("2").await.unwrap().version_id.unwrap();
+	let v3 = put("3").await.unwrap().version_id.unwrap();
+	assert_eq!(
+		list_versions().await,
+		(sorted(vec![v1.clone(), v2.clone(), v3.clone()]), 0)
+	);
+
+	// The 4th version replaces the oldest one
+	let v4 = put("4").await.unwrap().version_id.unwrap();
+	assert_eq!(
+		list_versions().await,
+		(sorted(vec![v2.clone(), v3.clone(), v4.clone()]), 0)
+	);
+	let r = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("a")
+		.version_id(&v1)
+		.send()
+		.await;
+	assert!(r.is_err());
+
+	// Delete markers count as versions
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("a")
+		.send()
+		.await
+		.unwrap();
+	let v5 = put("5").await.unwrap().version_id.unwrap();
+	assert_eq!(list_versions().await, (sorted(vec![v4, v5]), 1));
+}
+
+#[tokio::test]
+async fn test_listmultipart_in_progress_only() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("listmultipart-in-progress");
+
+	let mut upload_ids = vec![];
+	for k in ["completed", "aborted", "pending-1", "pending-2"] {
+		let up = ctx
+			.client
+			.create_multipart_upload()
+			.bucket(&bucket)
+			.key(k)
+			.send()
+			.await
+			.unwrap();
+		upload_ids.push(up.upload_id.unwrap());
+	}
+
+	let part = ctx
+		.client
+		.upload_part()
+		.bucket(&bucket)
+		.key("completed")
+		.upload_id(&upload_ids[0])
+		.part_number(1)
+		.body(aws_sdk_s3::primitives::ByteStream::from_static(
+			b"listmultipart-in-progress",
+		))
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.complete_multipart_upload()
+		.bucket(&bucket)
+		.key("completed")
+		.upload_id(&upload_ids[0])
+		.multipart_upload(
+			aws_sdk_s3::types::CompletedMultipartUpload::builder()
+				.parts(
+					aws_sdk_s3::types::CompletedPart::builder()
+						.part_number(1)
+						.e_tag(part.e_tag.unwrap())
+						.build(),
+				)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.abort_multipart_upload()
+		.bucket(&bucket)
+		.key("aborted")
+		.upload_id(&upload_ids[1])
+		.send()
+		.await
+		.unwrap();
+
+	// Only the two uploads that are still in progress are listed
+	let r = ctx
+		.client
+		.list_multipart_uploads()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	let uploads = r.uploads.unwrap();
+	assert_eq!(uploads.len(), 2);
+	for (upload, (key, upload_id)) in uploads
+		.iter()
+		.zip(["pending-1", "pending-2"].iter().zip(&upload_ids[2..]))
+	{
+		assert_eq!(upload.key.as_deref(), Some(*key));
+		assert_eq!(upload.upload_id.as_ref(), Some(upload_id));
+		assert!(upload.initiated.is_some());
+	}
+	assert_eq!(r.is_truncated, Some(false));
+}
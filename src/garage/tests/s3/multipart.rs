@@ -1,4 +1,6 @@
 use crate::common;
+use crate::common::ext::CommandExt;
+use aws_sdk_s3::error::ProvideErrorMetadata;
 use aws_sdk_s3::primitives::ByteStream;
 use aws_sdk_s3::types::{ChecksumAlgorithm, CompletedMultipartUpload, CompletedPart};
 use base64::prelude::*;
@@ -335,6 +337,43 @@ async fn test_multipart_with_checksum() {
 		.unwrap();
 
 	assert_eq!(res.checksum_sha1, Some(expected_checksum));
+
+	// The checksum of the multipart object is a checksum of the checksums of
+	// its parts, while that of an object uploaded in one part is computed on
+	// the whole data
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("b")
+		.checksum_sha1(&ck1)
+		.body(ByteStream::from(u1))
+		.send()
+		.await
+		.unwrap();
+
+	let checksum_type = |method: hyper::Method, key: &'static str| {
+		let mut req = ctx.custom_request.builder(bucket.clone());
+		req.method(method)
+			.path(key)
+			.signed_header("x-amz-checksum-mode", "ENABLED");
+		async move {
+			let res = req.send().await.unwrap();
+			assert!(res.status().is_success());
+			res.headers()
+				.get("x-amz-checksum-type")
+				.map(|v| v.to_str().unwrap().to_string())
+		}
+	};
+	for method in [hyper::Method::HEAD, hyper::Method::GET] {
+		assert_eq!(
+			checksum_type(method.clone(), "a").await.as_deref(),
+			Some("COMPOSITE")
+		);
+		assert_eq!(
+			checksum_type(method, "b").await.as_deref(),
+			Some("FULL_OBJECT")
+		);
+	}
 }
 
 #[tokio::test]
@@ -780,3 +819,466 @@ fn calculate_sha1(bytes: &[u8]) -> String {
 	hasher.update(bytes);
 	BASE64_STANDARD.encode(&hasher.finalize()[..])
 }
+
+#[tokio::test]
+async fn test_uploadpart_replace() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("uploadpart-replace");
+
+	let old_part1 = vec![0xa1; SZ_5MB];
+	let part1 = vec![0xa2; SZ_5MB];
+	let part2 = vec![0xa3; 1000];
+
+	let up = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("a")
+		.send()
+		.await
+		.unwrap();
+	let uid = up.upload_id.as_ref().unwrap();
+
+	let mut etags = vec![];
+	for (part_number, body) in [(1, &old_part1), (1, &part1), (2, &part2)] {
+		let p = ctx
+			.client
+			.upload_part()
+			.bucket(&bucket)
+			.key("a")
+			.upload_id(uid)
+			.part_number(part_number)
+			.body(ByteStream::from(body.clone()))
+			.send()
+			.await
+			.unwrap();
+		etags.push(p.e_tag.unwrap());
+	}
+
+	// The blocks of the part that was replaced are no longer referenced
+	let old_hash = hex::encode(garage_util::data::blake2sum(&old_part1[..1024 * 1024]));
+	let output = ctx
+		.garage
+		.command()
+		.args(["block", "refs", &old_hash])
+		.expect_success_output("Could not get block refs");
+	assert!(String::from_utf8(output.stdout)
+		.unwrap()
+		.contains("Referenced by 0 non-deleted versions"));
+
+	// The replaced part cannot be used to complete the upload
+	let cmp = CompletedMultipartUpload::builder()
+		.parts(
+			CompletedPart::builder()
+				.part_number(1)
+				.e_tag(&etags[0])
+				.build(),
+		)
+		.parts(
+			CompletedPart::builder()
+				.part_number(2)
+				.e_tag(&etags[2])
+				.build(),
+		)
+		.build();
+	let err = ctx
+		.client
+		.complete_multipart_upload()
+		.bucket(&bucket)
+		.key("a")
+		.upload_id(uid)
+		.multipart_upload(cmp)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("InvalidPart"));
+
+	let cmp = CompletedMultipartUpload::builder()
+		.parts(
+			CompletedPart::builder()
+				.part_number(1)
+				.e_tag(&etags[1])
+				.build(),
+		)
+		.parts(
+			CompletedPart::builder()
+				.part_number(2)
+				.e_tag(&etags[2])
+				.build(),
+		)
+		.build();
+	ctx.client
+		.complete_multipart_upload()
+		.bucket(&bucket)
+		.key("a")
+		.upload_id(uid)
+		.multipart_upload(cmp)
+		.send()
+		.await
+		.unwrap();
+
+	// Each part of the object has the content of its last upload
+	for (part_number, body) in [(1, &part1), (2, &part2)] {
+		let o = ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key("a")
+			.part_number(part_number)
+			.send()
+			.await
+			.unwrap();
+		assert_bytes_eq!(o.body, &body[..]);
+	}
+}
+
+#[tokio::test]
+async fn test_multipart_part_too_small() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("mpu-part-too-small");
+
+	let up = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("a")
+		.send()
+		.await
+		.unwrap();
+	let uid = up.upload_id.as_ref().unwrap();
+
+	let mut cmp = CompletedMultipartUpload::builder();
+	for part_number in [1, 2] {
+		let p = ctx
+			.client
+			.upload_part()
+			.bucket(&bucket)
+			.key("a")
+			.upload_id(uid)
+			.part_number(part_number)
+			.body(ByteStream::from(vec![0xa4; 1000]))
+			.send()
+			.await
+			.unwrap();
+		cmp = cmp.parts(
+			CompletedPart::builder()
+				.part_number(part_number)
+				.e_tag(p.e_tag.unwrap())
+				.build(),
+		);
+	}
+
+	let err = ctx
+		.client
+		.complete_multipart_upload()
+		.bucket(&bucket)
+		.key("a")
+		.upload_id(uid)
+		.multipart_upload(cmp.build())
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("EntityTooSmall"));
+}
+
+#[tokio::test]
+async fn test_multipart_min_part_size() {
+	// The test configuration lowers multipart_min_part_size to 1MiB,
+	// so non-final parts smaller than the 5MB AWS floor are accepted
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("mpu-min-part-size");
+
+	let up = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("a")
+		.send()
+		.await
+		.unwrap();
+	let uid = up.upload_id.as_ref().unwrap();
+
+	let parts = [vec![0xc1; 2 * 1024 * 1024], vec![0xc2; 1000]];
+	let mut cmp = CompletedMultipartUpload::builder();
+	for (i, body) in parts.iter().enumerate() {
+		let part_number = i as i32 + 1;
+		let p = ctx
+			.client
+			.upload_part()
+			.bucket(&bucket)
+			.key("a")
+			.upload_id(uid)
+			.part_number(part_number)
+			.body(ByteStream::from(body.clone()))
+			.send()
+			.await
+			.unwrap();
+		cmp = cmp.parts(
+			CompletedPart::builder()
+				.part_number(part_number)
+				.e_tag(p.e_tag.unwrap())
+				.build(),
+		);
+	}
+
+	ctx.client
+		.complete_multipart_upload()
+		.bucket(&bucket)
+		.key("a")
+		.upload_id(uid)
+		.multipart_upload(cmp.build())
+		.send()
+		.await
+		.unwrap();
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("a")
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, &parts.concat()[..]);
+}
+
+#[tokio::test]
+async fn test_multipart_etag() {
+	use md5::{Digest, Md5};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("mpu-etag");
+
+	let u1 = vec![0xb1; SZ_5MB];
+	let u2 = vec![0xb2; 1000];
+
+	let up = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("a")
+		.send()
+		.await
+		.unwrap();
+	let uid = up.upload_id.as_ref().unwrap();
+
+	let mut parts_md5 = vec![];
+	let mut cmp = CompletedMultipartUpload::builder();
+	for (part_number, body) in [(1, &u1), (2, &u2)] {
+		let p = ctx
+			.client
+			.upload_part()
+			.bucket(&bucket)
+			.key("a")
+			.upload_id(uid)
+			.part_number(part_number)
+			.body(ByteStream::from(body.clone()))
+			.send()
+			.await
+			.unwrap();
+
+		// The etag of a part is the md5 of its content
+		let md5 = Md5::digest(&body[..]);
+		assert_eq!(
+			p.e_tag.as_ref().unwrap(),
+			&format!("\"{}\"", hex::encode(md5))
+		);
+		parts_md5.extend_from_slice(&md5[..]);
+
+		cmp = cmp.parts(
+			CompletedPart::builder()
+				.part_number(part_number)
+				.e_tag(p.e_tag.unwrap())
+				.build(),
+		);
+	}
+
+	// Listing a part that was never uploaded is refused
+	let cmp_missing = cmp
+		.clone()
+		.parts(
+			CompletedPart::builder()
+				.part_number(3)
+				.e_tag("\"00000000000000000000000000000000\"")
+				.build(),
+		)
+		.build();
+	let err = ctx
+		.client
+		.complete_multipart_upload()
+		.bucket(&bucket)
+		.key("a")
+		.upload_id(uid)
+		.multipart_upload(cmp_missing)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("InvalidPart"));
+
+	// The etag of the object is the md5 of the concatenated md5 of its parts,
+	// followed by the number of parts
+	let expected_etag = format!("\"{}-2\"", hex::encode(Md5::digest(&parts_md5)));
+
+	let r = ctx
+		.client
+		.complete_multipart_upload()
+		.bucket(&bucket)
+		.key("a")
+		.upload_id(uid)
+		.multipart_upload(cmp.build())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(r.e_tag.unwrap(), expected_etag);
+
+	// As it is not the md5 of the object, reads return it as a weak etag
+	let o = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("a")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.e_tag.unwrap(), format!("W/{}", expected_etag));
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("a")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.e_tag.unwrap(), format!("W/{}", expected_etag));
+}
+
+#[tokio::test]
+async fn test_multipart_abort() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("mpu-abort");
+
+	let refcount = |hash: &str| {
+		let output = ctx
+			.garage
+			.command()
+			.args(["block", "info", hash])
+			.expect_success_output("Could not get block info");
+		String::from_utf8(output.stdout)
+			.unwrap()
+			.lines()
+			.find_map(|l| {
+				l.strip_prefix("Refcount: ")
+					.map(|x| x.parse::<u64>().unwrap())
+			})
+			.unwrap()
+	};
+
+	let up = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("aborted")
+		.send()
+		.await
+		.unwrap();
+	let uid = up.upload_id.unwrap();
+
+	let part = (0..20_000u32)
+		.map(|i| (i % 239) as u8 ^ 0x5a)
+		.collect::<Vec<u8>>();
+	let hash = hex::encode(garage_util::data::blake2sum(&part));
+	ctx.client
+		.upload_part()
+		.bucket(&bucket)
+		.key("aborted")
+		.upload_id(&uid)
+		.part_number(1)
+		.body(ByteStream::from(part))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(refcount(&hash), 1);
+
+	ctx.client
+		.abort_multipart_upload()
+		.bucket(&bucket)
+		.key("aborted")
+		.upload_id(&uid)
+		.send()
+		.await
+		.unwrap();
+
+	// The references to the blocks of the upload are removed in the background
+	let mut rc = refcount(&hash);
+	for _ in 0..50 {
+		if rc == 0 {
+			break;
+		}
+		tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+		rc = refcount(&hash);
+	}
+	assert_eq!(rc, 0);
+
+	// The upload can't be aborted twice
+	let err = ctx
+		.client
+		.abort_multipart_upload()
+		.bucket(&bucket)
+		.key("aborted")
+		.upload_id(&uid)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("NoSuchUpload"));
+
+	// Nor can an upload that was completed
+	let up = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("completed")
+		.send()
+		.await
+		.unwrap();
+	let uid = up.upload_id.unwrap();
+	let p = ctx
+		.client
+		.upload_part()
+		.bucket(&bucket)
+		.key("completed")
+		.upload_id(&uid)
+		.part_number(1)
+		.body(ByteStream::from(vec![0xa7; 1000]))
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.complete_multipart_upload()
+		.bucket(&bucket)
+		.key("completed")
+		.upload_id(&uid)
+		.multipart_upload(
+			CompletedMultipartUpload::builder()
+				.parts(
+					CompletedPart::builder()
+						.part_number(1)
+						.e_tag(p.e_tag.unwrap())
+						.build(),
+				)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+	let err = ctx
+		.client
+		.abort_multipart_upload()
+		.bucket(&bucket)
+		.key("completed")
+		.upload_id(&uid)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("NoSuchUpload"));
+}
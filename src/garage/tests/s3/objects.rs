@@ -1,12 +1,33 @@
 use crate::common;
+use crate::common::custom_requester::BodySignature;
+use crate::common::ext::CommandExt;
+use aws_sdk_s3::error::ProvideErrorMetadata;
 use aws_sdk_s3::primitives::ByteStream;
 use aws_sdk_s3::types::{Delete, ObjectIdentifier};
+use http_body_util::BodyExt;
+use hyper::{Method, StatusCode};
 
 const STD_KEY: &str = "hello world";
-const CTRL_KEY: &str = "\x00\x01\x02\x00";
+// NUL characters are not allowed in keys, but other control characters are
+const CTRL_KEY: &str = "\x01\x02\x03\x01";
 const UTF8_KEY: &str = "\u{211D}\u{1F923}\u{1F44B}";
 const BODY: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
 
+/// Find the file in which a data block is stored, given the hex encoding of its hash
+fn find_block_file(dir: &std::path::Path, hash: &str) -> Option<std::path::PathBuf> {
+	for entry in std::fs::read_dir(dir).unwrap() {
+		let path = entry.unwrap().path();
+		if path.is_dir() {
+			if let Some(found) = find_block_file(&path, hash) {
+				return Some(found);
+			}
+		} else if path.file_stem().unwrap().to_str() == Some(hash) {
+			return Some(path);
+		}
+	}
+	None
+}
+
 #[tokio::test]
 async fn test_putobject() {
 	let ctx = common::context();
@@ -187,6 +208,48 @@ async fn test_getobject() {
 	}
 }
 
+#[tokio::test]
+async fn test_getobject_response_content_disposition() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("getobject-response-content-disposition");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.body(ByteStream::from_static(BODY))
+		.content_disposition("inline")
+		.send()
+		.await
+		.unwrap();
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.response_content_disposition("attachment; filename=x.pdf")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(
+		o.content_disposition.as_deref(),
+		Some("attachment; filename=x.pdf")
+	);
+	assert_bytes_eq!(o.body, BODY);
+
+	// The stored metadata is unchanged
+	let o = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.content_disposition.as_deref(), Some("inline"));
+}
+
 #[tokio::test]
 async fn test_metadata() {
 	let ctx = common::context();
@@ -404,3 +467,4836 @@ async fn test_deleteobject() {
 		.await
 		.unwrap();
 }
+
+#[tokio::test]
+async fn test_putobject_invalid_content_md5() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-invalid-md5");
+	let key = "invalid-md5";
+
+	for md5 in ["not-base64", "AAAA", "Rs8YqbRHmRtF DK0/rPWTfg=="] {
+		let res = ctx
+			.custom_request
+			.builder(bucket.clone())
+			.method(Method::PUT)
+			.path(key.to_owned())
+			.unsigned_header("content-md5", md5)
+			.body(BODY.to_vec())
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+		let body = res.into_body().collect().await.unwrap().to_bytes();
+		assert!(std::str::from_utf8(&body)
+			.unwrap()
+			.contains("<Code>InvalidDigest</Code>"));
+	}
+
+	// No object should have been created
+	let r = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key(key)
+		.send()
+		.await;
+	assert!(r.is_err());
+
+	// A valid content-md5, quoted or not, is still accepted
+	for md5 in ["Rs8YqbRHmRtFDK0/rPWTfg==", "\"Rs8YqbRHmRtFDK0/rPWTfg==\""] {
+		let res = ctx
+			.custom_request
+			.builder(bucket.clone())
+			.method(Method::PUT)
+			.path(key.to_owned())
+			.unsigned_header("content-md5", md5)
+			.body(BODY.to_vec())
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(res.status(), StatusCode::OK);
+	}
+}
+
+#[tokio::test]
+async fn test_putobject_checksum_mismatch() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-checksum-mismatch");
+	let key = "checksum-mismatch";
+
+	// Valid md5 of another body
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path(key.to_owned())
+		.unsigned_header("content-md5", "1B2M2Y8AsgTpgAmY7PhCfg==")
+		.body(BODY.to_vec())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+	let body = res.into_body().collect().await.unwrap().to_bytes();
+	let body = std::str::from_utf8(&body).unwrap();
+	assert!(body.contains("<Code>BadDigest</Code>"));
+	assert!(body.contains("expected 1B2M2Y8AsgTpgAmY7PhCfg=="));
+
+	// Signed sha256 of another body
+	let other_sha256 = hex::encode(garage_util::data::sha256sum(b"other body"));
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path(key.to_owned())
+		.body_signature(BodySignature::ClassicSha256(other_sha256.clone()))
+		.body(BODY.to_vec())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+	let body = res.into_body().collect().await.unwrap().to_bytes();
+	let body = std::str::from_utf8(&body).unwrap();
+	assert!(body.contains("<Code>XAmzContentSHA256Mismatch</Code>"));
+	assert!(body.contains(&format!(
+		"expected {}, computed {}",
+		other_sha256,
+		hex::encode(garage_util::data::sha256sum(BODY))
+	)));
+
+	// No object should have been created
+	let r = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key(key)
+		.send()
+		.await;
+	assert!(r.is_err());
+}
+
+#[tokio::test]
+async fn test_putobject_large_inline_checksum() {
+	use base64::prelude::*;
+	use md5::{Digest, Md5};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-large-inline-checksum");
+	let key = "large-inline";
+
+	// Largest body that is still stored inline in the object table
+	let body = (0..garage_block::manager::INLINE_THRESHOLD - 1)
+		.map(|i| (i % 253) as u8)
+		.collect::<Vec<u8>>();
+	let md5 = BASE64_STANDARD.encode(Md5::digest(&body));
+	let bad_md5 = BASE64_STANDARD.encode(Md5::digest(&body[1..]));
+
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path(key.to_owned())
+		.unsigned_header("content-md5", &bad_md5)
+		.body(body.clone())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+	let res_body = res.into_body().collect().await.unwrap().to_bytes();
+	assert!(std::str::from_utf8(&res_body)
+		.unwrap()
+		.contains("<Code>BadDigest</Code>"));
+
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path(key.to_owned())
+		.unsigned_header("content-md5", &md5)
+		.body(body.clone())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(key)
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, &body[..]);
+}
+
+#[tokio::test]
+async fn test_putobject_quota_preflight() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-quota-preflight");
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-quotas", &bucket])
+		.args(["--max-objects", "1"])
+		.quiet()
+		.expect_success_status("Could not set bucket quotas");
+
+	let r = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("first")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await;
+	assert!(r.is_ok());
+
+	// The bucket is full: a new object is rejected based on its declared size
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("second".to_owned())
+		.body(BODY.to_vec())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::FORBIDDEN);
+	assert_eq!(res.headers().get("x-garage-quota-type").unwrap(), "objects");
+	let body = res.into_body().collect().await.unwrap().to_bytes();
+	assert!(std::str::from_utf8(&body)
+		.unwrap()
+		.contains("<Code>QuotaExceeded</Code>"));
+
+	let r = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("second")
+		.send()
+		.await;
+	assert!(r.is_err());
+
+	// Overwriting the existing object does not add to the object count
+	let r = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("first")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await;
+	assert!(r.is_ok());
+}
+
+#[tokio::test]
+async fn test_putobject_blake2_etag() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-blake2-etag");
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-options", &bucket])
+		.args(["--blake2-etag", "true"])
+		.quiet()
+		.expect_success_status("Could not set bucket options");
+
+	// Inline object and object stored in data blocks
+	let big_body = BODY.repeat(100);
+	for (key, body) in [("small", BODY.to_vec()), ("big", big_body)] {
+		// Blake2-based etags are not md5 digests, so they are weak etags
+		let expected_etag = format!(
+			"W/\"b2-{}\"",
+			hex::encode(garage_util::data::blake2sum(&body))
+		);
+
+		let r = ctx
+			.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(ByteStream::from(body))
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(r.e_tag.unwrap(), expected_etag);
+
+		let o = ctx
+			.client
+			.head_object()
+			.bucket(&bucket)
+			.key(key)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(o.e_tag.unwrap(), expected_etag);
+	}
+}
+
+#[tokio::test]
+async fn test_putobject_require_content_md5() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-require-content-md5");
+	let body_md5 = "Rs8YqbRHmRtFDK0/rPWTfg==";
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-options", &bucket])
+		.args(["--require-content-md5", "true"])
+		.quiet()
+		.expect_success_status("Could not set bucket options");
+
+	let put = |md5: Option<&'static str>, query: Option<(u64, String)>| {
+		let mut req = ctx.custom_request.builder(bucket.clone());
+		req.method(Method::PUT)
+			.path("required-md5".to_owned())
+			.body(BODY.to_vec());
+		if let Some(md5) = md5 {
+			req.unsigned_header("content-md5", md5);
+		}
+		if let Some((part_number, upload_id)) = query {
+			req.query_param("partNumber", Some(part_number.to_string()))
+				.query_param("uploadId", Some(upload_id));
+		}
+		async move { req.send().await.unwrap() }
+	};
+
+	// PutObject
+	let res = put(None, None).await;
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+	let body = res.into_body().collect().await.unwrap().to_bytes();
+	assert!(std::str::from_utf8(&body)
+		.unwrap()
+		.contains("<Code>InvalidRequest</Code>"));
+	let r = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("required-md5")
+		.send()
+		.await;
+	assert!(r.is_err());
+
+	assert_eq!(put(Some(body_md5), None).await.status(), StatusCode::OK);
+
+	// UploadPart
+	let upload_id = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("required-md5")
+		.send()
+		.await
+		.unwrap()
+		.upload_id
+		.unwrap();
+	let res = put(None, Some((1, upload_id.clone()))).await;
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+	let res = put(Some(body_md5), Some((1, upload_id))).await;
+	assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_getobject_range_multiblock() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("getobject-range-multiblock");
+	let key = "multiblock";
+
+	// Object spanning 3 data blocks (block size is 1MiB)
+	let body = (0..(5 * 1024 * 1024 / 2))
+		.map(|i| (i % 251) as u8)
+		.collect::<Vec<u8>>();
+	let size = body.len();
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key(key)
+		.body(ByteStream::from(body.clone()))
+		.send()
+		.await
+		.unwrap();
+
+	let ranges = [
+		// Within the last block
+		(
+			format!("bytes={}-{}", 2 * 1024 * 1024 + 10, 2 * 1024 * 1024 + 20),
+			2 * 1024 * 1024 + 10,
+			2 * 1024 * 1024 + 21,
+		),
+		// Spanning all three blocks
+		(format!("bytes=1000-{}", size - 1000), 1000, size - 999),
+		// Suffix range
+		("bytes=-500".to_string(), size - 500, size),
+	];
+	for (range, begin, end) in ranges {
+		let res = ctx
+			.custom_request
+			.builder(bucket.clone())
+			.method(Method::GET)
+			.path(key.to_owned())
+			.unsigned_header("range", &range)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+		assert_eq!(
+			res.headers().get("content-range").unwrap(),
+			format!("bytes {}-{}/{}", begin, end - 1, size).as_str()
+		);
+		assert_eq!(
+			res.headers().get("content-length").unwrap(),
+			(end - begin).to_string().as_str()
+		);
+		let res_body = res.into_body().collect().await.unwrap().to_bytes();
+		assert_eq!(&res_body[..], &body[begin..end]);
+	}
+
+	// Range starting past the end of the object
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::GET)
+		.path(key.to_owned())
+		.unsigned_header("range", &format!("bytes={}-", size + 10))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+	assert_eq!(
+		res.headers().get("content-range").unwrap(),
+		format!("bytes */{}", size).as_str()
+	);
+}
+
+#[tokio::test]
+async fn test_putobject_content_encoding_not_compressed() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-content-encoding");
+	let data_dir = ctx.garage.path.join("data");
+
+	for (key, content_encoding, byte) in [("plain", None, 1u8), ("gzipped", Some("gzip"), 2u8)] {
+		// Highly compressible data, stored in a data block
+		let body = vec![byte; 100_000];
+		let hash = hex::encode(garage_util::data::blake2sum(&body));
+
+		let mut req = ctx
+			.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(ByteStream::from(body.clone()));
+		if let Some(ce) = content_encoding {
+			req = req.content_encoding(ce);
+		}
+		req.send().await.unwrap();
+
+		let block_file = find_block_file(&data_dir, &hash).expect("block file not found");
+		let compressed = block_file.extension().and_then(|x| x.to_str()) == Some("zst");
+		assert_eq!(compressed, content_encoding.is_none());
+
+		let o = ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(key)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(o.content_encoding.as_deref(), content_encoding);
+		assert_bytes_eq!(o.body, &body[..]);
+	}
+}
+
+#[tokio::test]
+async fn test_getobject_verify_on_read() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("getobject-verify-on-read");
+	let data_dir = ctx.garage.path.join("data");
+
+	// Two compressible objects: their blocks are stored compressed, so
+	// swapping them on disk is not detected when the blocks are read.
+	// Their content must not be shared with other tests, as blocks are deduplicated.
+	let good = vec![0x86u8; 100_000];
+	let other = vec![0x87u8; 100_000];
+	for (key, body) in [("good", &good), ("other", &other)] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(ByteStream::from(body.clone()))
+			.send()
+			.await
+			.unwrap();
+	}
+
+	let good_block =
+		find_block_file(&data_dir, &hex::encode(garage_util::data::blake2sum(&good))).unwrap();
+	let other_block = find_block_file(
+		&data_dir,
+		&hex::encode(garage_util::data::blake2sum(&other)),
+	)
+	.unwrap();
+	std::fs::copy(&other_block, &good_block).unwrap();
+
+	// Without verification, the wrong data is returned
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("good")
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, &other[..]);
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-options", &bucket])
+		.args(["--verify-on-read", "true"])
+		.quiet()
+		.expect_success_status("Could not set bucket options");
+
+	// With verification, the transfer is interrupted
+	let res = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("good")
+		.send()
+		.await;
+	if let Ok(o) = res {
+		assert!(o.body.collect().await.is_err());
+	}
+
+	// Objects whose blocks are intact are still readable
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("other")
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, &other[..]);
+}
+
+#[tokio::test]
+async fn test_deleteobjects_duplicate_keys() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("deleteobjects-duplicate");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("dup")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+
+	let to_del = Delete::builder()
+		.objects(ObjectIdentifier::builder().key("dup").build().unwrap())
+		.objects(ObjectIdentifier::builder().key("dup").build().unwrap())
+		.build()
+		.unwrap();
+	let r = ctx
+		.client
+		.delete_objects()
+		.bucket(&bucket)
+		.delete(to_del)
+		.send()
+		.await
+		.unwrap();
+
+	let deleted = r.deleted.unwrap();
+	assert_eq!(deleted.len(), 1);
+	assert_eq!(deleted[0].key.as_deref(), Some("dup"));
+	assert!(deleted[0].delete_marker_version_id.is_some());
+	assert!(r.errors.is_none());
+}
+
+#[tokio::test]
+async fn test_putobject_expiration_header() {
+	use aws_sdk_s3::types::{
+		BucketLifecycleConfiguration, ExpirationStatus, LifecycleExpiration, LifecycleRule,
+		LifecycleRuleFilter,
+	};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-expiration");
+
+	let rule = LifecycleRule::builder()
+		.id("expire-logs")
+		.status(ExpirationStatus::Enabled)
+		.filter(LifecycleRuleFilter::Prefix("logs/".to_string()))
+		.expiration(LifecycleExpiration::builder().days(7).build())
+		.build()
+		.unwrap();
+	ctx.client
+		.put_bucket_lifecycle_configuration()
+		.bucket(&bucket)
+		.lifecycle_configuration(
+			BucketLifecycleConfiguration::builder()
+				.rules(rule)
+				.build()
+				.unwrap(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	// Objects expire at midnight UTC, 7 days after the day of their creation
+	let expiry_date = chrono::Utc::now().date_naive() + chrono::Duration::days(8);
+	let expected = format!(
+		"expiry-date=\"{}\", rule-id=\"expire-logs\"",
+		expiry_date.format("%a, %d %b %Y 00:00:00 GMT")
+	);
+
+	let r = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("logs/today")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(r.expiration.as_deref(), Some(expected.as_str()));
+
+	// No rule applies to this object
+	let r = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("data/today")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+	assert!(r.expiration.is_none());
+}
+
+#[tokio::test]
+async fn test_putobject_block_duration_metric() {
+	use http_body_util::Full as FullBody;
+	use hyper::body::Bytes;
+	use hyper_util::client::legacy::Client;
+	use hyper_util::rt::TokioExecutor;
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-block-metric");
+
+	// Big enough to be stored in a data block
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("big")
+		.body(ByteStream::from(BODY.repeat(100)))
+		.send()
+		.await
+		.unwrap();
+
+	let client = Client::builder(TokioExecutor::new()).build_http();
+	let req = hyper::Request::builder()
+		.method(Method::GET)
+		.uri(format!(
+			"http://127.0.0.1:{}/metrics",
+			ctx.garage.admin_port
+		))
+		.body(FullBody::new(Bytes::new()))
+		.unwrap();
+	let resp = client.request(req).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+
+	let metrics = resp.into_body().collect().await.unwrap().to_bytes();
+	let metrics = String::from_utf8(metrics.to_vec()).unwrap();
+	assert!(metrics.contains("garage_s3_put_block_duration_ms_count{encrypted=\"false\"}"));
+}
+
+#[tokio::test]
+async fn test_putobject_durable_strict() {
+	use http_body_util::Full as FullBody;
+	use hyper::body::Bytes;
+	use hyper_util::client::legacy::Client;
+	use hyper_util::rt::TokioExecutor;
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-durable");
+
+	let synced_writes = || async {
+		let client = Client::builder(TokioExecutor::new()).build_http();
+		let req = hyper::Request::builder()
+			.method(Method::GET)
+			.uri(format!(
+				"http://127.0.0.1:{}/metrics",
+				ctx.garage.admin_port
+			))
+			.body(FullBody::new(Bytes::new()))
+			.unwrap();
+		let resp = client.request(req).await.unwrap();
+		assert_eq!(resp.status(), StatusCode::OK);
+
+		let metrics = resp.into_body().collect().await.unwrap().to_bytes();
+		let metrics = String::from_utf8(metrics.to_vec()).unwrap();
+		metrics
+			.lines()
+			.find_map(|l| l.strip_prefix("block_synced_writes "))
+			.map(|v| v.parse::<u64>().unwrap())
+			.unwrap_or(0)
+	};
+
+	let before = synced_writes().await;
+
+	// Data block content that is not stored yet by any other test
+	let body = [b"durable-strict".as_slice(), &BODY.repeat(100)].concat();
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("durable".to_owned())
+		.signed_header("x-garage-durable", "strict")
+		.body(body.clone())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	assert!(synced_writes().await > before);
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("durable")
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, &body);
+
+	// Only strict and relaxed durability levels exist
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("durable".to_owned())
+		.signed_header("x-garage-durable", "eventually")
+		.body(b"invalid".to_vec())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+/// Configuration of the instances of the tests of max_object_size
+const MAX_OBJECT_SIZE_CONFIG: &str = r#"max_object_size = "1MiB""#;
+
+#[tokio::test]
+async fn test_putobject_max_object_size() {
+	let garage = common::garage::dedicated_instance(49985, MAX_OBJECT_SIZE_CONFIG);
+	let ctx = common::context_for(&garage);
+	let bucket = ctx.create_bucket("putobject-max-size");
+
+	// An upload whose declared size exceeds the maximum object size
+	// is rejected before its body is read
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("too-large".to_owned())
+		.signed_header("x-amz-decoded-content-length", (2 << 20).to_string())
+		.body(BODY.to_vec())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+	let body = res.into_body().collect().await.unwrap().to_bytes();
+	assert!(std::str::from_utf8(&body)
+		.unwrap()
+		.contains("<Code>EntityTooLarge</Code>"));
+
+	let r = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("too-large")
+		.send()
+		.await;
+	assert!(r.is_err());
+
+	// Objects below the limit are accepted
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("small-enough")
+		.body(ByteStream::from(vec![0x91; 1 << 20]))
+		.send()
+		.await
+		.unwrap();
+}
+
+#[tokio::test]
+async fn test_putobject_max_object_size_mid_stream() {
+	use http_body_util::Full as FullBody;
+	use hyper::body::Bytes;
+	use hyper_util::client::legacy::Client;
+	use hyper_util::rt::TokioExecutor;
+	use std::time::{Duration, Instant};
+
+	let garage = common::garage::dedicated_instance(49990, MAX_OBJECT_SIZE_CONFIG);
+	let ctx = common::context_for(&garage);
+	let bucket = ctx.create_bucket("putobject-max-size-mid-stream");
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["bucket", "info", &bucket])
+		.expect_success_output("Could not get bucket info");
+	let bucket_id = String::from_utf8(output.stdout)
+		.unwrap()
+		.lines()
+		.find_map(|l| l.strip_prefix("Bucket: ").map(str::to_string))
+		.expect("bucket id not found");
+
+	// The client declares a size below the limit but sends more data: the
+	// upload fails once the first block has been stored, when the limit is
+	// exceeded while reading the rest of the body
+	let body = (0..(1usize << 20) + 1024)
+		.map(|i| (i % 241) as u8)
+		.collect::<Vec<u8>>();
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("lying".to_owned())
+		.signed_header("x-amz-decoded-content-length", "1024")
+		.body(body)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+	let body = res.into_body().collect().await.unwrap().to_bytes();
+	assert!(std::str::from_utf8(&body)
+		.unwrap()
+		.contains("<Code>EntityTooLarge</Code>"));
+
+	let r = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("lying")
+		.send()
+		.await;
+	assert!(r.is_err());
+
+	// The version of the interrupted upload is aborted in the background
+	let client = Client::builder(TokioExecutor::new()).build_http();
+	let inspect = || async {
+		let req = hyper::Request::builder()
+			.method(Method::GET)
+			.uri(format!(
+				"http://127.0.0.1:{}/v1/bucket/inspect-object?id={}&key=lying",
+				ctx.garage.admin_port, bucket_id
+			))
+			.header(
+				"authorization",
+				format!("Bearer {}", common::garage::GARAGE_TEST_ADMIN_TOKEN),
+			)
+			.body(FullBody::new(Bytes::new()))
+			.unwrap();
+		let resp = client.request(req).await.unwrap();
+		assert_eq!(resp.status(), StatusCode::OK);
+		let body = resp.into_body().collect().await.unwrap().to_bytes();
+		let res: serde_json::Value = serde_json::from_slice(&body).unwrap();
+		res["versions"].as_array().unwrap().clone()
+	};
+	let deadline = Instant::now() + Duration::from_secs(10);
+	let versions = loop {
+		let versions = inspect().await;
+		let aborted = !versions.is_empty() && versions.iter().all(|v| v["state"] == "aborted");
+		if aborted || Instant::now() > deadline {
+			break versions;
+		}
+		tokio::time::sleep(Duration::from_millis(100)).await;
+	};
+	assert_eq!(versions.len(), 1, "{:?}", versions);
+	assert_eq!(versions[0]["state"], "aborted");
+}
+
+#[tokio::test]
+async fn test_putobject_block_size_header() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-block-size");
+	let data_dir = ctx.garage.path.join("data");
+
+	// 2MiB of data in which all 256KiB chunks are different
+	let body = (0..2usize << 20)
+		.map(|i| (i % 251) as u8 ^ (i >> 16) as u8)
+		.collect::<Vec<u8>>();
+	let stored_blocks = |block_size: usize| {
+		body.chunks(block_size)
+			.filter(|chunk| {
+				let hash = hex::encode(garage_util::data::blake2sum(chunk));
+				find_block_file(&data_dir, &hash).is_some()
+			})
+			.count()
+	};
+
+	// Without the header, or with an out of range value, the block size
+	// of the configuration (1MiB) is used
+	for (key, block_size) in [("default", None), ("out-of-range", Some("1024"))] {
+		let mut req = ctx.custom_request.builder(bucket.clone());
+		req.method(Method::PUT)
+			.path(key.to_owned())
+			.body(body.clone());
+		if let Some(block_size) = block_size {
+			req.unsigned_header("x-garage-block-size", block_size);
+		}
+		let res = req.send().await.unwrap();
+		assert_eq!(res.status(), StatusCode::OK);
+	}
+	assert_eq!(stored_blocks(1 << 20), 2);
+	assert_eq!(stored_blocks(256 << 10), 0);
+
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("small-blocks".to_owned())
+		.unsigned_header("x-garage-block-size", (256 << 10).to_string())
+		.body(body.clone())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	assert_eq!(stored_blocks(256 << 10), 8);
+
+	// Reads are not affected by the block size
+	for key in ["default", "out-of-range", "small-blocks"] {
+		let o = ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(key)
+			.send()
+			.await
+			.unwrap();
+		assert_bytes_eq!(o.body, &body[..]);
+	}
+}
+
+#[tokio::test]
+async fn test_putobject_tagging() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-tagging");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.tagging("project=garage&description=hello%20world")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+
+	let o = ctx
+		.client
+		.get_object_tagging()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	let tags = o
+		.tag_set()
+		.iter()
+		.map(|t| (t.key(), t.value()))
+		.collect::<Vec<_>>();
+	assert_eq!(
+		tags,
+		vec![("description", "hello world"), ("project", "garage")]
+	);
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.tag_count, Some(2));
+
+	// Tags that exceed the limits are rejected
+	let too_many = (0..11)
+		.map(|i| format!("k{}=v", i))
+		.collect::<Vec<_>>()
+		.join("&");
+	let too_long_value = format!("k={}", "v".repeat(257));
+	for tagging in [too_many, too_long_value] {
+		let err = ctx
+			.client
+			.put_object()
+			.bucket(&bucket)
+			.key("invalid-tags")
+			.tagging(tagging)
+			.body(ByteStream::from_static(BODY))
+			.send()
+			.await
+			.unwrap_err();
+		assert_eq!(err.into_service_error().code(), Some("InvalidTag"));
+	}
+}
+
+#[tokio::test]
+async fn test_object_tagging() {
+	use aws_sdk_s3::types::{Tag, Tagging};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("object-tagging");
+
+	let get_tags = |key: &'static str| {
+		let ctx = &ctx;
+		let bucket = &bucket;
+		async move {
+			ctx.client
+				.get_object_tagging()
+				.bucket(bucket)
+				.key(key)
+				.send()
+				.await
+				.unwrap()
+				.tag_set()
+				.iter()
+				.map(|t| (t.key().to_string(), t.value().to_string()))
+				.collect::<Vec<_>>()
+		}
+	};
+
+	// An object stored inline, and one stored in data blocks
+	let big_body = (0..100_000usize)
+		.map(|i| (i % 241) as u8 ^ 0x95)
+		.collect::<Vec<u8>>();
+	for (key, body) in [("small", BODY.to_vec()), ("big", big_body.clone())] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.content_type("text/plain")
+			.tagging("a=1")
+			.body(ByteStream::from(body.clone()))
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(get_tags(key).await, vec![("a".into(), "1".into())]);
+
+		let tagging = Tagging::builder()
+			.tag_set(Tag::builder().key("b").value("2").build().unwrap())
+			.tag_set(Tag::builder().key("c").value("").build().unwrap())
+			.build()
+			.unwrap();
+		ctx.client
+			.put_object_tagging()
+			.bucket(&bucket)
+			.key(key)
+			.tagging(tagging)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(
+			get_tags(key).await,
+			vec![("b".into(), "2".into()), ("c".into(), "".into())]
+		);
+
+		// Data and other metadata are not changed
+		let o = ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(key)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(o.content_type.as_deref(), Some("text/plain"));
+		assert_eq!(o.tag_count, Some(2));
+		assert_bytes_eq!(o.body, &body[..]);
+
+		ctx.client
+			.delete_object_tagging()
+			.bucket(&bucket)
+			.key(key)
+			.send()
+			.await
+			.unwrap();
+		assert!(get_tags(key).await.is_empty());
+
+		let o = ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(key)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(o.tag_count, None);
+		assert_bytes_eq!(o.body, &body[..]);
+	}
+
+	// Tagging a non-existent object fails
+	let err = ctx
+		.client
+		.delete_object_tagging()
+		.bucket(&bucket)
+		.key("does-not-exist")
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("NoSuchKey"));
+}
+
+#[tokio::test]
+async fn test_object_tagging_versioning() {
+	use aws_sdk_s3::types::{BucketVersioningStatus, Tag, Tagging, VersioningConfiguration};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("object-tagging-versioning");
+
+	let tagging = Tagging::builder()
+		.tag_set(Tag::builder().key("a").value("1").build().unwrap())
+		.build()
+		.unwrap();
+	let count_versions = || {
+		let ctx = &ctx;
+		let bucket = &bucket;
+		async move {
+			ctx.client
+				.list_object_versions()
+				.bucket(bucket)
+				.send()
+				.await
+				.unwrap()
+				.versions
+				.unwrap_or_default()
+				.len()
+		}
+	};
+
+	// The version stored before versioning is enabled is replaced when
+	// its tags are changed, no other version is created
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("null")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.put_bucket_versioning()
+		.bucket(&bucket)
+		.versioning_configuration(
+			VersioningConfiguration::builder()
+				.status(BucketVersioningStatus::Enabled)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.put_object_tagging()
+		.bucket(&bucket)
+		.key("null")
+		.tagging(tagging.clone())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(count_versions().await, 1);
+
+	// The tags of a versioned version cannot be changed
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("null")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+	let err = ctx
+		.client
+		.put_object_tagging()
+		.bucket(&bucket)
+		.key("null")
+		.tagging(tagging.clone())
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("NotImplemented"));
+	assert_eq!(count_versions().await, 2);
+
+	// A specific version cannot be tagged or deleted
+	let err = ctx
+		.client
+		.delete_object_tagging()
+		.bucket(&bucket)
+		.key("null")
+		.version_id("0123")
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("NotImplemented"));
+	let err = ctx
+		.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("null")
+		.version_id("0123")
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("NotImplemented"));
+	assert_eq!(count_versions().await, 2);
+}
+
+#[tokio::test]
+async fn test_getobject_conditional() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("getobject-conditional");
+	let key = "conditional";
+
+	let r = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key(key)
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+	let etag = r.e_tag.unwrap();
+	let unquoted_etag = etag.trim_matches('"').to_string();
+
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::GET)
+		.path(key.to_owned())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	let last_modified = res.headers()["last-modified"].to_str().unwrap().to_string();
+
+	let cases = [
+		("if-none-match", etag.clone(), StatusCode::NOT_MODIFIED),
+		("if-none-match", unquoted_etag, StatusCode::NOT_MODIFIED),
+		("if-none-match", "*".into(), StatusCode::NOT_MODIFIED),
+		(
+			"if-none-match",
+			format!("\"abc\", {}", etag),
+			StatusCode::NOT_MODIFIED,
+		),
+		("if-none-match", "\"abc\"".into(), StatusCode::OK),
+		("if-modified-since", last_modified, StatusCode::NOT_MODIFIED),
+		(
+			"if-modified-since",
+			"Mon, 01 Jan 2001 00:00:00 GMT".into(),
+			StatusCode::OK,
+		),
+	];
+	for (name, value, status) in cases {
+		let res = ctx
+			.custom_request
+			.builder(bucket.clone())
+			.method(Method::GET)
+			.path(key.to_owned())
+			.unsigned_header(name, &value)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(res.status(), status, "{}: {}", name, value);
+		let body = res.into_body().collect().await.unwrap().to_bytes();
+		if status == StatusCode::OK {
+			assert_eq!(&body[..], BODY);
+		} else {
+			assert!(body.is_empty());
+		}
+	}
+}
+
+#[tokio::test]
+async fn test_putobject_max_duration() {
+	use aws_sdk_s3::presigning::PresigningConfig;
+	use std::time::{Duration, SystemTime};
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-max-duration");
+	let key = "slow-upload";
+
+	let psc = PresigningConfig::builder()
+		.start_time(SystemTime::now() - Duration::from_secs(60))
+		.expires_in(Duration::from_secs(3600))
+		.build()
+		.unwrap();
+	let req = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key(key)
+		.presigned(psc)
+		.await
+		.unwrap();
+	let uri = req.uri().parse::<hyper::Uri>().unwrap();
+
+	// Send the first part of the body, and then stall without
+	// closing the connection: the server must give up after
+	// the put_max_duration of the test configuration (20s)
+	let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", ctx.garage.s3_port))
+		.await
+		.unwrap();
+	let head = format!(
+		"PUT {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n\r\n",
+		uri.path_and_query().unwrap(),
+		uri.authority().unwrap(),
+		4 << 20,
+	);
+	stream.write_all(head.as_bytes()).await.unwrap();
+	let partial_body = (0..3usize << 19)
+		.map(|i| (i % 239) as u8 ^ 0x97)
+		.collect::<Vec<u8>>();
+	stream.write_all(&partial_body).await.unwrap();
+
+	let mut response = vec![0u8; 1024];
+	let n = tokio::time::timeout(Duration::from_secs(60), stream.read(&mut response))
+		.await
+		.expect("upload was not interrupted")
+		.unwrap();
+	let response = std::str::from_utf8(&response[..n]).unwrap();
+	assert!(response.starts_with("HTTP/1.1 408"), "{}", response);
+	assert!(response.contains("<Code>RequestTimeout</Code>"));
+
+	// The interrupted upload is not visible
+	let r = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key(key)
+		.send()
+		.await;
+	assert!(r.is_err());
+	let r = ctx
+		.client
+		.list_objects_v2()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	assert!(r.contents().is_empty());
+}
+
+#[tokio::test]
+async fn test_putobject_block_ref_bucket_id() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-block-ref-bucket");
+	let copy_bucket = ctx.create_bucket("putobject-block-ref-bucket-copy");
+
+	let bucket_id = |name: &str| {
+		let output = ctx
+			.garage
+			.command()
+			.args(["bucket", "info", name])
+			.expect_success_output("Could not get bucket info");
+		String::from_utf8(output.stdout)
+			.unwrap()
+			.lines()
+			.find_map(|l| l.strip_prefix("Bucket: ").map(str::to_string))
+			.expect("bucket id not found")
+	};
+
+	// Content not shared with other tests, as blocks are deduplicated
+	let body = vec![0x91u8; 100_000];
+	let hash = hex::encode(garage_util::data::blake2sum(&body));
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("original")
+		.body(ByteStream::from(body))
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.copy_object()
+		.bucket(&copy_bucket)
+		.key("copy")
+		.copy_source(format!("{}/original", bucket))
+		.send()
+		.await
+		.unwrap();
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["block", "refs", &hash])
+		.expect_success_output("Could not list block refs");
+	let refs = String::from_utf8(output.stdout).unwrap();
+	assert!(refs.contains("Referenced by 2 non-deleted versions"));
+	assert!(refs.contains(&bucket_id(&bucket)));
+	assert!(refs.contains(&bucket_id(&copy_bucket)));
+}
+
+#[tokio::test]
+async fn test_headobject() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("headobject");
+
+	// An inline object and an object stored in a data block
+	for (key, body) in [("inline", BODY.to_vec()), ("block", vec![0x92u8; 100_000])] {
+		let res = ctx
+			.custom_request
+			.builder(bucket.clone())
+			.method(Method::PUT)
+			.path(key.to_owned())
+			.signed_header("content-type", "text/plain")
+			.signed_header("cache-control", "no-cache")
+			.signed_header("x-amz-meta-color", "blue")
+			.body(body.clone())
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(res.status(), StatusCode::OK);
+		let etag = res.headers().get("etag").unwrap().clone();
+
+		let res = ctx
+			.custom_request
+			.builder(bucket.clone())
+			.method(Method::HEAD)
+			.path(key.to_owned())
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(res.status(), StatusCode::OK);
+		let headers = res.headers();
+		assert_eq!(
+			headers.get("content-length").unwrap(),
+			body.len().to_string().as_str()
+		);
+		assert_eq!(headers.get("accept-ranges").unwrap(), "bytes");
+		assert_eq!(headers.get("etag").unwrap(), &etag);
+		assert!(headers.get("last-modified").is_some());
+		assert_eq!(headers.get("content-type").unwrap(), "text/plain");
+		assert_eq!(headers.get("cache-control").unwrap(), "no-cache");
+		assert_eq!(headers.get("x-amz-meta-color").unwrap(), "blue");
+		assert!(headers.get("x-amz-delete-marker").is_none());
+		assert!(res
+			.into_body()
+			.collect()
+			.await
+			.unwrap()
+			.to_bytes()
+			.is_empty());
+	}
+
+	// Once deleted, the key is reported as a delete marker
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("block")
+		.send()
+		.await
+		.unwrap();
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::HEAD)
+		.path("block".to_owned())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::NOT_FOUND);
+	assert_eq!(res.headers().get("x-amz-delete-marker").unwrap(), "true");
+	assert!(res.headers().get("x-amz-version-id").is_some());
+
+	// A key that never existed is not
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::HEAD)
+		.path("never-existed".to_owned())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::NOT_FOUND);
+	assert!(res.headers().get("x-amz-delete-marker").is_none());
+}
+
+#[tokio::test]
+async fn test_upload_progress() {
+	use aws_sdk_s3::presigning::PresigningConfig;
+	use http_body_util::Full as FullBody;
+	use hyper::body::Bytes;
+	use hyper_util::client::legacy::Client;
+	use hyper_util::rt::TokioExecutor;
+	use std::time::{Duration, SystemTime};
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("upload-progress");
+	let key = "in-progress";
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["bucket", "info", &bucket])
+		.expect_success_output("Could not get bucket info");
+	let bucket_id = String::from_utf8(output.stdout)
+		.unwrap()
+		.lines()
+		.find_map(|l| l.strip_prefix("Bucket: ").map(str::to_string))
+		.expect("bucket id not found");
+
+	let client = Client::builder(TokioExecutor::new()).build_http();
+	let upload_progress = || async {
+		let req = hyper::Request::builder()
+			.method(Method::GET)
+			.uri(format!(
+				"http://127.0.0.1:{}/v1/bucket/upload-progress?id={}&key={}",
+				ctx.garage.admin_port, bucket_id, key
+			))
+			.header(
+				"authorization",
+				format!("Bearer {}", common::garage::GARAGE_TEST_ADMIN_TOKEN),
+			)
+			.body(FullBody::new(Bytes::new()))
+			.unwrap();
+		let resp = client.request(req).await.unwrap();
+		assert_eq!(resp.status(), StatusCode::OK);
+		let body = resp.into_body().collect().await.unwrap().to_bytes();
+		let res: serde_json::Value = serde_json::from_slice(&body).unwrap();
+		assert_eq!(res["bucketId"], bucket_id.as_str());
+		assert_eq!(res["key"], key);
+		res["uploads"]
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|u| u["bytesReceived"].as_u64().unwrap())
+			.collect::<Vec<_>>()
+	};
+
+	// No upload in progress
+	assert!(upload_progress().await.is_empty());
+
+	let psc = PresigningConfig::builder()
+		.start_time(SystemTime::now() - Duration::from_secs(60))
+		.expires_in(Duration::from_secs(3600))
+		.build()
+		.unwrap();
+	let req = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key(key)
+		.presigned(psc)
+		.await
+		.unwrap();
+	let uri = req.uri().parse::<hyper::Uri>().unwrap();
+
+	// Content not shared with other tests, as blocks are deduplicated
+	let body = (0..4usize << 20)
+		.map(|i| (i % 241) as u8 ^ 0x98)
+		.collect::<Vec<u8>>();
+	let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", ctx.garage.s3_port))
+		.await
+		.unwrap();
+	let head = format!(
+		"PUT {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n\r\n",
+		uri.path_and_query().unwrap(),
+		uri.authority().unwrap(),
+		body.len(),
+	);
+	stream.write_all(head.as_bytes()).await.unwrap();
+
+	// Send two and a half blocks of 1MiB: the two complete blocks
+	// are stored and reported, the incomplete one is not
+	let (first_part, second_part) = body.split_at(5 << 19);
+	stream.write_all(first_part).await.unwrap();
+	let mut progress = vec![];
+	for _ in 0..50 {
+		progress = upload_progress().await;
+		if progress == [2 << 20] {
+			break;
+		}
+		tokio::time::sleep(Duration::from_millis(100)).await;
+	}
+	assert_eq!(progress, [2 << 20]);
+
+	stream.write_all(second_part).await.unwrap();
+	let mut response = vec![0u8; 1024];
+	let n = stream.read(&mut response).await.unwrap();
+	let response = std::str::from_utf8(&response[..n]).unwrap();
+	assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+	// The upload is complete
+	assert!(upload_progress().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_inspect_object() {
+	use base64::prelude::*;
+	use http_body_util::Full as FullBody;
+	use hyper::body::Bytes;
+	use hyper_util::client::legacy::Client;
+	use hyper_util::rt::TokioExecutor;
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("inspect-object");
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["bucket", "info", &bucket])
+		.expect_success_output("Could not get bucket info");
+	let bucket_id = String::from_utf8(output.stdout)
+		.unwrap()
+		.lines()
+		.find_map(|l| l.strip_prefix("Bucket: ").map(str::to_string))
+		.expect("bucket id not found");
+
+	let client = Client::builder(TokioExecutor::new()).build_http();
+	let inspect = |key: &'static str| {
+		let client = &client;
+		let ctx = &ctx;
+		let bucket_id = &bucket_id;
+		async move {
+			let req = hyper::Request::builder()
+				.method(Method::GET)
+				.uri(format!(
+					"http://127.0.0.1:{}/v1/bucket/inspect-object?id={}&key={}",
+					ctx.garage.admin_port, bucket_id, key
+				))
+				.header(
+					"authorization",
+					format!("Bearer {}", common::garage::GARAGE_TEST_ADMIN_TOKEN),
+				)
+				.body(FullBody::new(Bytes::new()))
+				.unwrap();
+			let resp = client.request(req).await.unwrap();
+			assert_eq!(resp.status(), StatusCode::OK);
+			let body = resp.into_body().collect().await.unwrap().to_bytes();
+			let res: serde_json::Value = serde_json::from_slice(&body).unwrap();
+			assert_eq!(res["bucketId"], bucket_id.as_str());
+			assert_eq!(res["key"], key);
+			(body, res["versions"].as_array().unwrap().clone())
+		}
+	};
+
+	// A complete object stored in two blocks, followed by an aborted
+	// multipart upload and a multipart upload in progress
+	let key = "inspected";
+	let body = (0..3usize << 19)
+		.map(|i| (i % 239) as u8 ^ 0x5a)
+		.collect::<Vec<u8>>();
+	let put = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key(key)
+		.body(ByteStream::from(body.clone()))
+		.send()
+		.await
+		.unwrap();
+	let aborted = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key(key)
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.abort_multipart_upload()
+		.bucket(&bucket)
+		.key(key)
+		.upload_id(aborted.upload_id.unwrap())
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key(key)
+		.send()
+		.await
+		.unwrap();
+
+	let (_, versions) = inspect(key).await;
+	assert_eq!(versions.len(), 3);
+
+	let complete = &versions[0];
+	assert_eq!(complete["versionId"], put.version_id.unwrap().as_str());
+	assert_eq!(complete["state"], "complete");
+	assert!(complete.get("multipart").is_none());
+	assert_eq!(complete["inline"], false);
+	assert_eq!(complete["deleteMarker"], false);
+	assert_eq!(complete["encrypted"], false);
+	assert_eq!(complete["size"], body.len() as u64);
+	assert_eq!(
+		format!("\"{}\"", complete["etag"].as_str().unwrap()),
+		put.e_tag.unwrap()
+	);
+	let blocks = complete["blocks"].as_array().unwrap();
+	assert_eq!(blocks.len(), 2);
+	assert_eq!(complete["firstBlock"], blocks[0]["hash"]);
+	assert_eq!(blocks[0]["partNumber"], 1);
+	assert_eq!(blocks[0]["offset"], 0);
+	assert_eq!(blocks[0]["size"], 1u64 << 20);
+	assert_eq!(blocks[1]["offset"], 1u64 << 20);
+	assert_eq!(blocks[1]["size"], 1u64 << 19);
+
+	assert_eq!(versions[1]["state"], "aborted");
+	assert!(versions[1]["size"].is_null());
+
+	let uploading = &versions[2];
+	assert_eq!(uploading["state"], "uploading");
+	assert_eq!(uploading["multipart"], true);
+	assert!(uploading["etag"].is_null());
+	assert!(uploading["blocks"].as_array().unwrap().is_empty());
+
+	// The data of inline objects is not returned
+	let inline_data = b"inspect-object: inline data";
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("inspected-inline")
+		.body(ByteStream::from_static(inline_data))
+		.send()
+		.await
+		.unwrap();
+
+	let (raw, versions) = inspect("inspected-inline").await;
+	assert_eq!(versions.len(), 1);
+	assert_eq!(versions[0]["state"], "complete");
+	assert_eq!(versions[0]["inline"], true);
+	assert_eq!(versions[0]["size"], inline_data.len() as u64);
+	assert!(versions[0]["firstBlock"].is_null());
+	assert!(versions[0]["blocks"].as_array().unwrap().is_empty());
+	let raw = String::from_utf8(raw.to_vec()).unwrap();
+	assert!(!raw.contains("inline data"));
+	assert!(!raw.contains(&BASE64_STANDARD.encode(inline_data)));
+
+	// Unknown objects have no versions
+	let (_, versions) = inspect("not-there").await;
+	assert!(versions.is_empty());
+}
+
+#[tokio::test]
+async fn test_cleanup_stale_uploads() {
+	use aws_sdk_s3::presigning::PresigningConfig;
+	use http_body_util::Full as FullBody;
+	use hyper::body::Bytes;
+	use hyper_util::client::legacy::Client;
+	use hyper_util::rt::TokioExecutor;
+	use std::time::{Duration, SystemTime, UNIX_EPOCH};
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("cleanup-stale-uploads");
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["bucket", "info", &bucket])
+		.expect_success_output("Could not get bucket info");
+	let bucket_id = String::from_utf8(output.stdout)
+		.unwrap()
+		.lines()
+		.find_map(|l| l.strip_prefix("Bucket: ").map(str::to_string))
+		.expect("bucket id not found");
+
+	let create_upload = |key: &'static str| {
+		ctx.client
+			.create_multipart_upload()
+			.bucket(&bucket)
+			.key(key)
+			.send()
+	};
+	create_upload("old").await.unwrap();
+	tokio::time::sleep(Duration::from_secs(3)).await;
+	create_upload("recent").await.unwrap();
+
+	// A PutObject in progress whose timestamp, chosen by the client, is
+	// older than the threshold: its first block is sent, but not the rest
+	let psc = PresigningConfig::builder()
+		.start_time(SystemTime::now() - Duration::from_secs(60))
+		.expires_in(Duration::from_secs(3600))
+		.build()
+		.unwrap();
+	let req = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("client-timestamp")
+		.presigned(psc)
+		.await
+		.unwrap();
+	let uri = req.uri().parse::<hyper::Uri>().unwrap();
+	let old_timestamp = (SystemTime::now() - Duration::from_secs(3600))
+		.duration_since(UNIX_EPOCH)
+		.unwrap()
+		.as_millis();
+	let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", ctx.garage.s3_port))
+		.await
+		.unwrap();
+	let head = format!(
+		"PUT {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nx-garage-object-timestamp: {}\r\n\r\n",
+		uri.path_and_query().unwrap(),
+		uri.authority().unwrap(),
+		2 << 20,
+		old_timestamp,
+	);
+	stream.write_all(head.as_bytes()).await.unwrap();
+	stream.write_all(&vec![0x42; 3 << 19]).await.unwrap();
+
+	let client = Client::builder(TokioExecutor::new()).build_http();
+	let mut uploading = false;
+	for _ in 0..50 {
+		let req = hyper::Request::builder()
+			.method(Method::GET)
+			.uri(format!(
+				"http://127.0.0.1:{}/v1/bucket/upload-progress?id={}&key=client-timestamp",
+				ctx.garage.admin_port, bucket_id
+			))
+			.header(
+				"authorization",
+				format!("Bearer {}", common::garage::GARAGE_TEST_ADMIN_TOKEN),
+			)
+			.body(FullBody::new(Bytes::new()))
+			.unwrap();
+		let resp = client.request(req).await.unwrap();
+		let body = resp.into_body().collect().await.unwrap().to_bytes();
+		let res: serde_json::Value = serde_json::from_slice(&body).unwrap();
+		uploading = !res["uploads"].as_array().unwrap().is_empty();
+		if uploading {
+			break;
+		}
+		tokio::time::sleep(Duration::from_millis(100)).await;
+	}
+	assert!(uploading);
+
+	// Only the upload started more than 2 seconds ago is aborted
+	let req = hyper::Request::builder()
+		.method(Method::POST)
+		.uri(format!(
+			"http://127.0.0.1:{}/v1/bucket/cleanup-stale-uploads",
+			ctx.garage.admin_port
+		))
+		.header(
+			"authorization",
+			format!("Bearer {}", common::garage::GARAGE_TEST_ADMIN_TOKEN),
+		)
+		.body(FullBody::new(Bytes::from(format!(
+			r#"{{"olderThanSecs": 2, "bucketId": "{}"}}"#,
+			bucket_id
+		))))
+		.unwrap();
+	let resp = client.request(req).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body = resp.into_body().collect().await.unwrap().to_bytes();
+	let res: serde_json::Value = serde_json::from_slice(&body).unwrap();
+	assert_eq!(res["uploadsAborted"], 1);
+
+	let r = ctx
+		.client
+		.list_multipart_uploads()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	let keys = r
+		.uploads
+		.unwrap_or_default()
+		.into_iter()
+		.map(|u| u.key.unwrap())
+		.collect::<Vec<_>>();
+	assert_eq!(keys, vec!["recent"]);
+
+	// The PutObject was not aborted and can be completed
+	stream.write_all(&vec![0x42; 1 << 19]).await.unwrap();
+	let mut response = vec![0u8; 1024];
+	let len = stream.read(&mut response).await.unwrap();
+	assert!(response[..len].starts_with(b"HTTP/1.1 200"));
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("client-timestamp")
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, &vec![0x42; 2 << 20][..]);
+}
+
+#[tokio::test]
+async fn test_putobject_max_concurrent_uploads() {
+	use aws_sdk_s3::presigning::PresigningConfig;
+	use http_body_util::Full as FullBody;
+	use hyper::body::Bytes;
+	use hyper_util::client::legacy::Client;
+	use hyper_util::rt::TokioExecutor;
+	use std::time::{Duration, SystemTime};
+	use tokio::io::AsyncWriteExt;
+
+	// The test instance is configured with max_concurrent_uploads = 4
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("concurrent-uploads");
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["bucket", "info", &bucket])
+		.expect_success_output("Could not get bucket info");
+	let bucket_id = String::from_utf8(output.stdout)
+		.unwrap()
+		.lines()
+		.find_map(|l| l.strip_prefix("Bucket: ").map(str::to_string))
+		.expect("bucket id not found");
+
+	let client = Client::builder(TokioExecutor::new()).build_http();
+	let is_uploading = |key: String| {
+		let client = &client;
+		let ctx = &ctx;
+		let bucket_id = &bucket_id;
+		async move {
+			let req = hyper::Request::builder()
+				.method(Method::GET)
+				.uri(format!(
+					"http://127.0.0.1:{}/v1/bucket/upload-progress?id={}&key={}",
+					ctx.garage.admin_port, bucket_id, key
+				))
+				.header(
+					"authorization",
+					format!("Bearer {}", common::garage::GARAGE_TEST_ADMIN_TOKEN),
+				)
+				.body(FullBody::new(Bytes::new()))
+				.unwrap();
+			let resp = client.request(req).await.unwrap();
+			assert_eq!(resp.status(), StatusCode::OK);
+			let body = resp.into_body().collect().await.unwrap().to_bytes();
+			let res: serde_json::Value = serde_json::from_slice(&body).unwrap();
+			!res["uploads"].as_array().unwrap().is_empty()
+		}
+	};
+
+	// Start uploads whose first block is received, but that are not complete
+	let mut in_progress = vec![];
+	for i in 0..4 {
+		let psc = PresigningConfig::builder()
+			.start_time(SystemTime::now() - Duration::from_secs(60))
+			.expires_in(Duration::from_secs(3600))
+			.build()
+			.unwrap();
+		let req = ctx
+			.client
+			.put_object()
+			.bucket(&bucket)
+			.key(format!("in-progress-{}", i))
+			.presigned(psc)
+			.await
+			.unwrap();
+		let uri = req.uri().parse::<hyper::Uri>().unwrap();
+
+		let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", ctx.garage.s3_port))
+			.await
+			.unwrap();
+		let head = format!(
+			"PUT {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n\r\n",
+			uri.path_and_query().unwrap(),
+			uri.authority().unwrap(),
+			2 << 20,
+		);
+		stream.write_all(head.as_bytes()).await.unwrap();
+		stream.write_all(&vec![i as u8; 3 << 19]).await.unwrap();
+		in_progress.push(stream);
+
+		// Wait for the upload to be registered, which happens once its
+		// first block is received and it has taken a slot
+		let mut uploading = false;
+		for _ in 0..50 {
+			uploading = is_uploading(format!("in-progress-{}", i)).await;
+			if uploading {
+				break;
+			}
+			tokio::time::sleep(Duration::from_millis(100)).await;
+		}
+		assert!(uploading);
+	}
+
+	let put = |key: &'static str, body: Vec<u8>| {
+		let ctx = &ctx;
+		let bucket = bucket.clone();
+		async move {
+			ctx.client
+				.put_object()
+				.bucket(&bucket)
+				.key(key)
+				.body(ByteStream::from(body))
+				.send()
+				.await
+		}
+	};
+
+	// Another upload is rejected
+	let err = put("rejected", vec![0x42; 8192])
+		.await
+		.unwrap_err()
+		.into_service_error();
+	assert_eq!(err.code(), Some("SlowDown"));
+
+	// Small objects stored inline are not limited
+	put("inline", b"small".to_vec()).await.unwrap();
+
+	// Slots are released when uploads are interrupted
+	drop(in_progress);
+	let mut res = Ok(());
+	for _ in 0..50 {
+		res = put("accepted", vec![0x42; 8192]).await.map(|_| ());
+		if res.is_ok() {
+			break;
+		}
+		tokio::time::sleep(Duration::from_millis(100)).await;
+	}
+	res.unwrap();
+}
+
+#[tokio::test]
+async fn test_getobject_compress_on_read() {
+	use std::io::Read;
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("getobject-compress-on-read");
+	let text = "compress-on-read: the quick brown fox jumps over the lazy dog\n".repeat(100);
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-options", &bucket])
+		.args(["--compress-on-read", "true"])
+		.quiet()
+		.expect_success_status("Could not set bucket options");
+
+	let put = |key: &'static str, content_type: &'static str, content_encoding: Option<&str>| {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.content_type(content_type)
+			.set_content_encoding(content_encoding.map(String::from))
+			.body(ByteStream::from(text.clone().into_bytes()))
+			.send()
+	};
+	let get = |key: &'static str, accept_encoding: Option<&'static str>| {
+		let mut req = ctx.custom_request.builder(bucket.clone());
+		req.method(Method::GET).path(key.to_owned());
+		if let Some(accept_encoding) = accept_encoding {
+			req.unsigned_header("accept-encoding", accept_encoding);
+		}
+		async move { req.send().await.unwrap() }
+	};
+
+	put("text", "text/plain; charset=utf-8", None)
+		.await
+		.unwrap();
+	put("binary", "application/octet-stream", None)
+		.await
+		.unwrap();
+	put("encoded", "text/plain", Some("br")).await.unwrap();
+
+	// Gzip-negotiated read of a text object
+	let res = get("text", Some("br;q=1.0, gzip;q=0.8")).await;
+	assert_eq!(res.status(), StatusCode::OK);
+	assert_eq!(res.headers()["content-encoding"], "gzip");
+	assert_eq!(res.headers()["vary"], "Accept-Encoding");
+	assert!(res.headers().get("content-length").is_none());
+	assert!(res.headers()["etag"].to_str().unwrap().starts_with("W/\""));
+	let body = res.into_body().collect().await.unwrap().to_bytes();
+	assert!(body.len() < text.len());
+	let mut decoded = String::new();
+	flate2::read::GzDecoder::new(&body[..])
+		.read_to_string(&mut decoded)
+		.unwrap();
+	assert_eq!(decoded, text);
+
+	// Clients that do not accept gzip get the object as it is stored
+	for accept_encoding in [None, Some("identity"), Some("gzip;q=0, *")] {
+		let res = get("text", accept_encoding).await;
+		assert_eq!(res.status(), StatusCode::OK);
+		assert!(res.headers().get("content-encoding").is_none());
+		assert_eq!(res.headers()["vary"], "Accept-Encoding");
+		let body = res.into_body().collect().await.unwrap().to_bytes();
+		assert_eq!(body, text.as_bytes());
+	}
+
+	// Objects of a non-compressible type or already encoded are not compressed
+	let res = get("binary", Some("gzip")).await;
+	assert!(res.headers().get("content-encoding").is_none());
+	let body = res.into_body().collect().await.unwrap().to_bytes();
+	assert_eq!(body, text.as_bytes());
+
+	let res = get("encoded", Some("gzip")).await;
+	assert_eq!(res.headers()["content-encoding"], "br");
+	let body = res.into_body().collect().await.unwrap().to_bytes();
+	assert_eq!(body, text.as_bytes());
+}
+
+#[tokio::test]
+async fn test_putobject_decode_content() {
+	use md5::{Digest, Md5};
+	use std::io::Write;
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-decode-content");
+	let text = "decode-content: the quick brown fox jumps over the lazy dog\n".repeat(100);
+
+	let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+	encoder.write_all(text.as_bytes()).unwrap();
+	let gzipped = encoder.finish().unwrap();
+
+	let put = |key: &'static str, body: Vec<u8>, decode: bool| {
+		let mut req = ctx.custom_request.builder(bucket.clone());
+		req.method(Method::PUT)
+			.path(key.to_owned())
+			.signed_header("content-encoding", "gzip")
+			.body(body);
+		if decode {
+			req.signed_header("x-garage-decode-content", "gzip");
+		}
+		async move { req.send().await.unwrap() }
+	};
+	let get = |key: &'static str| ctx.client.get_object().bucket(&bucket).key(key).send();
+
+	// A decoded body is stored as plaintext, with the etag of the plaintext
+	let res = put("decoded", gzipped.clone(), true).await;
+	assert_eq!(res.status(), StatusCode::OK);
+	let expected_etag = format!("\"{}\"", hex::encode(Md5::digest(text.as_bytes())));
+	assert_eq!(res.headers()["etag"], expected_etag.as_str());
+
+	let o = get("decoded").await.unwrap();
+	assert_eq!(o.e_tag.as_deref(), Some(expected_etag.as_str()));
+	assert_eq!(o.content_length, Some(text.len() as i64));
+	assert!(o.content_encoding.is_none());
+	assert_eq!(
+		o.body.collect().await.unwrap().into_bytes(),
+		text.as_bytes()
+	);
+
+	// By default, the body is stored as it is received
+	let res = put("raw", gzipped.clone(), false).await;
+	assert_eq!(res.status(), StatusCode::OK);
+	let o = get("raw").await.unwrap();
+	assert_eq!(o.content_encoding.as_deref(), Some("gzip"));
+	assert_eq!(o.body.collect().await.unwrap().into_bytes(), gzipped);
+
+	// Truncated or invalid gzip data is rejected, and no object is stored
+	for body in [
+		gzipped[..gzipped.len() / 2].to_vec(),
+		text.clone().into_bytes(),
+	] {
+		let res = put("invalid", body, true).await;
+		assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+	}
+	let err = get("invalid").await.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("NoSuchKey"));
+}
+
+#[tokio::test]
+async fn test_putobject_idempotency_key() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-idempotency-key");
+	let body_a = b"idempotent put: first content".to_vec();
+	let body_b = b"idempotent put: second content".to_vec();
+
+	let put = |key: &'static str, body: &Vec<u8>, idempotency_key: Option<&'static str>| {
+		let mut req = ctx.custom_request.builder(bucket.clone());
+		req.method(Method::PUT)
+			.path(key.to_owned())
+			.body(body.clone());
+		if let Some(idempotency_key) = idempotency_key {
+			req.unsigned_header("x-garage-idempotency-key", idempotency_key);
+		}
+		async move { req.send().await.unwrap() }
+	};
+	let version_and_etag = |res: &hyper::Response<_>| {
+		(
+			res.headers()["x-amz-version-id"].clone(),
+			res.headers()["etag"].clone(),
+		)
+	};
+
+	let res = put("object", &body_a, Some("retry-1")).await;
+	assert_eq!(res.status(), StatusCode::OK);
+	let first = version_and_etag(&res);
+
+	// Overwrite the object without an idempotency key
+	assert_eq!(put("object", &body_b, None).await.status(), StatusCode::OK);
+
+	// A duplicate PUT returns the first version, and does not store the object again
+	let res = put("object", &body_a, Some("retry-1")).await;
+	assert_eq!(res.status(), StatusCode::OK);
+	assert_eq!(version_and_etag(&res), first);
+
+	let mut req = ctx.custom_request.builder(bucket.clone());
+	req.method(Method::PUT)
+		.path("object".to_owned())
+		.body_signature(BodySignature::Unsigned)
+		.unsigned_header("x-garage-idempotency-key", "retry-1")
+		.body(body_a.clone());
+	let res = req.send().await.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	assert_eq!(version_and_etag(&res), first);
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("object")
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, &body_b[..]);
+
+	// Reusing the key with a different content or for another object is a conflict
+	let res = put("object", &body_b, Some("retry-1")).await;
+	assert_eq!(res.status(), StatusCode::CONFLICT);
+	let res = put("other-object", &body_a, Some("retry-1")).await;
+	assert_eq!(res.status(), StatusCode::CONFLICT);
+	let r = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("other-object")
+		.send()
+		.await;
+	assert!(r.is_err());
+
+	// Another idempotency key creates a new version
+	let res = put("object", &body_a, Some("retry-2")).await;
+	assert_eq!(res.status(), StatusCode::OK);
+	assert_ne!(version_and_etag(&res).0, first.0);
+}
+
+#[tokio::test]
+async fn test_deleteobjects_xml_errors() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("deleteobjects-xml-errors");
+
+	let delete_objects = |body: &'static str| {
+		let mut req = ctx.custom_request.builder(bucket.clone());
+		req.method(Method::POST)
+			.query_param("delete", None::<String>)
+			.body(body.as_bytes().to_vec());
+		async move {
+			let res = req.send().await.unwrap();
+			let status = res.status();
+			let body = res.into_body().collect().await.unwrap().to_bytes();
+			(status, String::from_utf8(body.to_vec()).unwrap())
+		}
+	};
+
+	let (status, body) = delete_objects("<Delete><Object><Key>a</Key></Delete>").await;
+	assert_eq!(status, StatusCode::BAD_REQUEST);
+	assert!(body.contains("<Code>MalformedXML</Code>"));
+	assert!(body.contains("at 1:29"), "{}", body);
+
+	let (status, body) = delete_objects("<Delete><Object></Object></Delete>").await;
+	assert_eq!(status, StatusCode::BAD_REQUEST);
+	assert!(body.contains("<Code>MalformedXML</Code>"));
+
+	for empty in [
+		"<Delete></Delete>",
+		"<Delete>\n  <Quiet>true</Quiet>\n</Delete>",
+	] {
+		let (status, body) = delete_objects(empty).await;
+		assert_eq!(status, StatusCode::OK);
+		assert!(body.contains("<DeleteResult"));
+		assert!(!body.contains("<Deleted>"));
+		assert!(!body.contains("<Error>"));
+	}
+}
+
+#[tokio::test]
+async fn test_delete_mfa_delete_required() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("delete-mfa-delete-required");
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-options", &bucket])
+		.args(["--mfa-delete-required", "true"])
+		.quiet()
+		.expect_success_status("Could not set bucket options");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("protected")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+
+	let delete = |query: &'static str, body: Vec<u8>, mfa: Option<&'static str>| {
+		let mut req = ctx.custom_request.builder(bucket.clone());
+		match query {
+			"delete" => req
+				.method(Method::POST)
+				.query_param("delete", None::<String>),
+			key => req.method(Method::DELETE).path(key.to_owned()),
+		};
+		req.body(body);
+		if let Some(mfa) = mfa {
+			req.signed_header("x-amz-mfa", mfa);
+		}
+		async move {
+			let res = req.send().await.unwrap();
+			let status = res.status();
+			let body = res.into_body().collect().await.unwrap().to_bytes();
+			(status, String::from_utf8(body.to_vec()).unwrap())
+		}
+	};
+	let delete_xml = b"<Delete><Object><Key>protected</Key></Object></Delete>".to_vec();
+
+	// Deletions without the x-amz-mfa header are rejected
+	let (status, body) = delete("protected", vec![], None).await;
+	assert_eq!(status, StatusCode::FORBIDDEN);
+	assert!(body.contains("<Code>AccessDenied</Code>"));
+	let (status, body) = delete("delete", delete_xml.clone(), None).await;
+	assert_eq!(status, StatusCode::FORBIDDEN);
+	assert!(body.contains("<Code>AccessDenied</Code>"));
+
+	ctx.client
+		.head_object()
+		.bucket(&bucket)
+		.key("protected")
+		.send()
+		.await
+		.unwrap();
+
+	// With the header, objects can be deleted
+	let (status, body) = delete("delete", delete_xml, Some("arn:mfa 123456")).await;
+	assert_eq!(status, StatusCode::OK);
+	assert!(body.contains("<Key>protected</Key>"));
+	let (status, _) = delete("protected", vec![], Some("arn:mfa 123456")).await;
+	assert_eq!(status, StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn test_getobject_inline() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("getobject-inline");
+	let body = b"a small object stored inline in the object table";
+
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("inline".to_owned())
+		.signed_header("content-type", "text/plain")
+		.signed_header("content-disposition", "inline")
+		.signed_header("x-amz-meta-shape", "round")
+		.body(body.to_vec())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	let etag = res.headers().get("etag").unwrap().clone();
+
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::GET)
+		.path("inline".to_owned())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	let headers = res.headers();
+	assert_eq!(
+		headers.get("content-length").unwrap(),
+		body.len().to_string().as_str()
+	);
+	assert_eq!(headers.get("etag").unwrap(), &etag);
+	assert_eq!(headers.get("content-type").unwrap(), "text/plain");
+	assert_eq!(headers.get("content-disposition").unwrap(), "inline");
+	assert_eq!(headers.get("x-amz-meta-shape").unwrap(), "round");
+	let data = res.into_body().collect().await.unwrap().to_bytes();
+	assert_eq!(&data[..], &body[..]);
+
+	// Once the latest version is a delete marker, the object is not found
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("inline")
+		.send()
+		.await
+		.unwrap();
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::GET)
+		.path("inline".to_owned())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::NOT_FOUND);
+	assert_eq!(res.headers().get("x-amz-delete-marker").unwrap(), "true");
+}
+
+#[tokio::test]
+async fn test_putobject_object_timestamp() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-object-timestamp");
+	let now = std::time::SystemTime::now()
+		.duration_since(std::time::UNIX_EPOCH)
+		.unwrap()
+		.as_millis() as u64;
+
+	let put = |timestamp: u64, body: &'static [u8]| {
+		let bucket = bucket.clone();
+		let ctx = &ctx;
+		async move {
+			ctx.custom_request
+				.builder(bucket)
+				.method(Method::PUT)
+				.path("lww".to_owned())
+				.signed_header("x-garage-object-timestamp", timestamp.to_string())
+				.body(body.to_vec())
+				.send()
+				.await
+		}
+	};
+
+	// A write with a timestamp in the future wins over the existing version
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("lww")
+		.body(ByteStream::from_static(b"received first"))
+		.send()
+		.await
+		.unwrap();
+	let res = put(now + 240_000, b"newer").await.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+
+	// A write with an older timestamp is rejected, even if it arrives later
+	let res = put(now + 120_000, b"older").await.unwrap();
+	assert_eq!(res.status(), StatusCode::CONFLICT);
+	let res = put(now + 240_000, b"same").await.unwrap();
+	assert_eq!(res.status(), StatusCode::CONFLICT);
+
+	// A timestamp further in the future than the allowed clock skew is rejected
+	let res = put(now + 3_600_000, b"far future").await.unwrap();
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+	let res = put(u64::MAX, b"far future").await.unwrap();
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("lww")
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, b"newer");
+
+	// The header must contain a number
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("lww".to_owned())
+		.signed_header("x-garage-object-timestamp", "yesterday")
+		.body(b"invalid".to_vec())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_putobject_last_modified() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-last-modified");
+	let now_secs = || {
+		std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH)
+			.unwrap()
+			.as_secs() as i64
+	};
+
+	// A version with a timestamp a few minutes in the future forces the
+	// ordering timestamps of the next versions to be bumped after it
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("bumped".to_owned())
+		.signed_header(
+			"x-garage-object-timestamp",
+			((now_secs() + 240) * 1000).to_string(),
+		)
+		.body(b"future".to_vec())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+
+	let start = now_secs();
+	for body in [&b"first rapid put"[..], b"second rapid put"] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key("bumped")
+			.body(ByteStream::from_static(body))
+			.send()
+			.await
+			.unwrap();
+	}
+	let end = now_secs();
+
+	// The versions are still ordered, but their Last-Modified date is the
+	// time at which they were uploaded
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("bumped")
+		.send()
+		.await
+		.unwrap();
+	let get_last_modified = o.last_modified.unwrap().secs();
+	assert_bytes_eq!(o.body, b"second rapid put");
+	assert!(get_last_modified >= start - 1 && get_last_modified <= end);
+
+	let list = ctx
+		.client
+		.list_objects_v2()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	let contents = list.contents.unwrap();
+	assert_eq!(contents.len(), 1);
+	assert_eq!(contents[0].last_modified.unwrap().secs(), get_last_modified);
+}
+
+#[tokio::test]
+async fn test_key_normalization_lowercase() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("key-normalization-lowercase");
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-options", &bucket])
+		.args(["--key-normalization", "lowercase"])
+		.quiet()
+		.expect_success_status("Could not set bucket options");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("Foo.txt")
+		.body(ByteStream::from_static(b"case-insensitive"))
+		.send()
+		.await
+		.unwrap();
+
+	// All spellings of the key reach the same object
+	for key in ["Foo.txt", "foo.txt", "FOO.TXT"] {
+		let o = ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(key)
+			.send()
+			.await
+			.unwrap();
+		assert_bytes_eq!(o.body, b"case-insensitive");
+	}
+
+	// The normalization can no longer be changed, as the keys of
+	// existing objects would not be normalized in the same way
+	let status = ctx
+		.garage
+		.command()
+		.args(["bucket", "set-options", &bucket])
+		.args(["--key-normalization", "none"])
+		.quiet()
+		.status()
+		.unwrap();
+	assert!(!status.success());
+
+	// The object is listed under its normalized key
+	let r = ctx
+		.client
+		.list_objects_v2()
+		.bucket(&bucket)
+		.prefix("FOO")
+		.send()
+		.await
+		.unwrap();
+	let keys = r
+		.contents
+		.unwrap_or_default()
+		.into_iter()
+		.map(|o| o.key.unwrap())
+		.collect::<Vec<_>>();
+	assert_eq!(keys, vec!["foo.txt".to_string()]);
+
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("fOO.tXT")
+		.send()
+		.await
+		.unwrap();
+	let err = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("foo.txt")
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("NoSuchKey"));
+}
+
+#[tokio::test]
+async fn test_putobject_quota_size() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-quota-size");
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-quotas", &bucket])
+		.args(["--max-size", "100"])
+		.quiet()
+		.expect_success_status("Could not set bucket quotas");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("first")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+
+	// A second object would bring the bucket over its size quota
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("second".to_owned())
+		.body(BODY.to_vec())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::FORBIDDEN);
+	assert_eq!(res.headers().get("x-garage-quota-type").unwrap(), "size");
+	let body = res.into_body().collect().await.unwrap().to_bytes();
+	assert!(std::str::from_utf8(&body)
+		.unwrap()
+		.contains("<Code>QuotaExceeded</Code>"));
+}
+
+#[tokio::test]
+async fn test_putobject_trusted_block_hashes() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-trusted-block-hashes");
+	let other_bucket = ctx.create_bucket("putobject-trusted-block-hashes-other");
+	let md5_bucket = ctx.create_bucket("putobject-trusted-block-hashes-md5");
+
+	// The etag of objects is the md5 of their data unless the bucket
+	// uses blake2-based etags
+	for b in [&bucket, &other_bucket] {
+		ctx.garage
+			.command()
+			.args(["bucket", "set-options", b])
+			.args(["--blake2-etag", "true"])
+			.quiet()
+			.expect_success_status("Could not set bucket options");
+	}
+
+	// Four blocks of 64KiB, the last one incomplete
+	let body = (0..200_000u32)
+		.map(|i| (i % 251) as u8 ^ 0x3c)
+		.collect::<Vec<u8>>();
+	let hashes = body
+		.chunks(64 * 1024)
+		.map(|b| hex::encode(garage_util::data::blake2sum(b)))
+		.collect::<Vec<_>>();
+
+	let put_in = |bucket: &String, key: &'static str, block_hashes: String, body: Vec<u8>| {
+		let bucket = bucket.clone();
+		let ctx = &ctx;
+		async move {
+			ctx.custom_request
+				.builder(bucket)
+				.method(Method::PUT)
+				.path(key.to_owned())
+				.signed_header("x-garage-block-size", "65536")
+				.signed_header("x-garage-block-hashes", block_hashes)
+				.body(body)
+				.send()
+				.await
+				.unwrap()
+		}
+	};
+	let put =
+		|key: &'static str, block_hashes: String| put_in(&bucket, key, block_hashes, body.clone());
+
+	// Keys must be explicitly trusted to supply block hashes
+	// (the request is rejected before its body is read, so keep it small)
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("untrusted".to_owned())
+		.signed_header("x-garage-block-hashes", hashes[0].clone())
+		.body(BODY.to_vec())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+	ctx.garage
+		.command()
+		.args(["key", "allow", "--trusted-block-hashes", &ctx.key.id])
+		.quiet()
+		.expect_success_status("Could not allow key to supply block hashes");
+
+	let res = put("correct", hashes.join(",")).await;
+	assert_eq!(res.status(), StatusCode::OK);
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("correct")
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, &body[..]);
+
+	// The hash of the first block is always checked
+	let mut wrong = hashes.clone();
+	wrong[0] = hex::encode(garage_util::data::blake2sum(b"not the first block"));
+	let res = put("incorrect", wrong.join(",")).await;
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+	// There must be exactly one hash per block
+	let res = put("missing", hashes[..3].join(",")).await;
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+	let res = put("extra", [&hashes[..], &hashes[..1]].concat().join(",")).await;
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+	// The md5 of the data cannot be computed when the data of blocks is not read
+	let res = put_in(&md5_bucket, "md5", hashes.join(","), body.clone()).await;
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+	// Blocks that are only referenced by objects of other buckets are always
+	// hashed, so data that does not match the hash of one of them is rejected
+	let mut altered = body.clone();
+	altered[3 * 64 * 1024] ^= 0xff;
+	let res = put_in(&other_bucket, "altered", hashes.join(","), altered).await;
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+	let res = put_in(&other_bucket, "correct", hashes.join(","), body.clone()).await;
+	assert_eq!(res.status(), StatusCode::OK);
+
+	for (bucket, key) in [
+		(&bucket, "untrusted"),
+		(&bucket, "incorrect"),
+		(&bucket, "missing"),
+		(&bucket, "extra"),
+		(&md5_bucket, "md5"),
+		(&other_bucket, "altered"),
+	] {
+		let r = ctx
+			.client
+			.head_object()
+			.bucket(bucket)
+			.key(key)
+			.send()
+			.await;
+		assert!(r.is_err(), "{} should not have been stored", key);
+	}
+}
+
+#[tokio::test]
+async fn test_putobject_checksum_crc64nvme() {
+	// CRC-64/NVME check value for "123456789" is 0xae8b14860a799888
+	const BODY: &[u8] = b"123456789";
+	const CRC64NVME: &str = "rosUhgp5mIg=";
+	const WRONG_CRC64NVME: &str = "AAAAAAAAAAA=";
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-checksum-crc64nvme");
+
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("good".to_owned())
+		.signed_header("x-amz-checksum-crc64nvme", CRC64NVME)
+		.body(BODY.to_vec())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	assert_eq!(res.headers()["x-amz-checksum-crc64nvme"], CRC64NVME);
+
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::GET)
+		.path("good".to_owned())
+		.signed_header("x-amz-checksum-mode", "ENABLED")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	assert_eq!(res.headers()["x-amz-checksum-crc64nvme"], CRC64NVME);
+
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("bad".to_owned())
+		.signed_header("x-amz-checksum-crc64nvme", WRONG_CRC64NVME)
+		.body(BODY.to_vec())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+	let r = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("bad")
+		.send()
+		.await;
+	assert!(r.is_err());
+}
+
+#[tokio::test]
+async fn test_putobject_content_range() {
+	use http_body_util::Full as FullBody;
+	use hyper::body::Bytes;
+	use hyper_util::client::legacy::Client;
+	use hyper_util::rt::TokioExecutor;
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-content-range");
+	const MIB: usize = 1 << 20;
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["bucket", "info", &bucket])
+		.expect_success_output("Could not get bucket info");
+	let bucket_id = String::from_utf8(output.stdout)
+		.unwrap()
+		.lines()
+		.find_map(|l| l.strip_prefix("Bucket: ").map(str::to_string))
+		.expect("bucket id not found");
+
+	// Hashes of the blocks of the current version of the object
+	let client = Client::builder(TokioExecutor::new()).build_http();
+	let block_hashes = || async {
+		let req = hyper::Request::builder()
+			.method(Method::GET)
+			.uri(format!(
+				"http://127.0.0.1:{}/v1/bucket/inspect-object?id={}&key=patched",
+				ctx.garage.admin_port, bucket_id
+			))
+			.header(
+				"authorization",
+				format!("Bearer {}", common::garage::GARAGE_TEST_ADMIN_TOKEN),
+			)
+			.body(FullBody::new(Bytes::new()))
+			.unwrap();
+		let resp = client.request(req).await.unwrap();
+		assert_eq!(resp.status(), StatusCode::OK);
+		let body = resp.into_body().collect().await.unwrap().to_bytes();
+		let res: serde_json::Value = serde_json::from_slice(&body).unwrap();
+		let versions = res["versions"].as_array().unwrap();
+		let current = versions.iter().rev().find(|v| v["state"] == "complete");
+		current.unwrap()["blocks"]
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|b| b["hash"].as_str().unwrap().to_string())
+			.collect::<Vec<_>>()
+	};
+
+	// Content not shared with other tests, as blocks are deduplicated
+	let original = (0..10 * MIB)
+		.map(|i| (i % 251) as u8 ^ 0x3c)
+		.collect::<Vec<u8>>();
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("patched")
+		.content_type("application/x-patched")
+		.body(ByteStream::from(original.clone()))
+		.send()
+		.await
+		.unwrap();
+	let original_blocks = block_hashes().await;
+	assert_eq!(original_blocks.len(), 10);
+
+	let patch = |range: String, body: Vec<u8>| {
+		let ctx = &ctx;
+		let bucket = bucket.clone();
+		async move {
+			ctx.custom_request
+				.builder(bucket)
+				.method(Method::PUT)
+				.path("patched".to_owned())
+				.signed_header("content-range", range)
+				.body(body)
+				.send()
+				.await
+				.unwrap()
+		}
+	};
+
+	// Overwrite the middle 1MiB of the object
+	let new_data = (0..MIB).map(|i| (i % 13) as u8).collect::<Vec<u8>>();
+	let res = patch(
+		format!("bytes {}-{}/*", 4 * MIB, 5 * MIB - 1),
+		new_data.clone(),
+	)
+	.await;
+	assert_eq!(res.status(), StatusCode::OK);
+	let etag = res.headers()["etag"].to_str().unwrap().to_string();
+	assert!(etag.ends_with("-10\""), "{}", etag);
+
+	// Only the block in the range was replaced
+	let patched_blocks = block_hashes().await;
+	assert_eq!(patched_blocks.len(), 10);
+	for (i, (original, patched)) in original_blocks.iter().zip(&patched_blocks).enumerate() {
+		assert_eq!(original == patched, i != 4);
+	}
+
+	let mut expected = original.clone();
+	expected[4 * MIB..5 * MIB].copy_from_slice(&new_data);
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("patched")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.content_length, Some(10 * MIB as i64));
+	assert_eq!(o.content_type.as_deref(), Some("application/x-patched"));
+	assert_eq!(o.e_tag.as_deref(), Some(etag.as_str()));
+	let data = o.body.collect().await.unwrap().into_bytes();
+	assert!(data[..] == expected[..]);
+
+	// Ranged reads return the patched data
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("patched")
+		.range(format!("bytes={}-{}", 5 * MIB - 10, 5 * MIB + 9))
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, &expected[5 * MIB - 10..5 * MIB + 10]);
+
+	// Ranges must be aligned on blocks, within the object,
+	// and of the length of the body
+	for range in [
+		format!("bytes {}-{}/*", 10, MIB + 9),
+		format!("bytes {}-{}/*", 9 * MIB, 11 * MIB - 1),
+		format!("bytes {}-{}/*", 0, MIB - 1),
+		"bytes 0-/*".to_string(),
+	] {
+		let res = patch(range, vec![0u8; 100]).await;
+		assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+	}
+
+	// Objects that don't exist can't be patched
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("not-there".to_owned())
+		.signed_header("content-range", "bytes 0-99/*")
+		.body(vec![0u8; 100])
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("patched")
+		.send()
+		.await
+		.unwrap();
+	let data = o.body.collect().await.unwrap().into_bytes();
+	assert!(data[..] == expected[..]);
+
+	// Of two concurrent patches, one may be rejected because the version
+	// it patches is no longer the current one, but none is lost
+	let (res_a, res_b) = tokio::join!(
+		patch(format!("bytes {}-{}/*", MIB, 2 * MIB - 1), vec![0xaa; MIB]),
+		patch(
+			format!("bytes {}-{}/*", 7 * MIB, 8 * MIB - 1),
+			vec![0xbb; MIB]
+		),
+	);
+	for res in [&res_a, &res_b] {
+		assert!(
+			res.status() == StatusCode::OK || res.status() == StatusCode::PRECONDITION_FAILED,
+			"{:?}",
+			res.status()
+		);
+	}
+	assert!(res_a.status() == StatusCode::OK || res_b.status() == StatusCode::OK);
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("patched")
+		.send()
+		.await
+		.unwrap();
+	let data = o.body.collect().await.unwrap().into_bytes();
+	if res_a.status() == StatusCode::OK {
+		assert!(data[MIB..2 * MIB].iter().all(|x| *x == 0xaa));
+	}
+	if res_b.status() == StatusCode::OK {
+		assert!(data[7 * MIB..8 * MIB].iter().all(|x| *x == 0xbb));
+	}
+}
+
+#[tokio::test]
+async fn test_putobject_chunked_interrupted() {
+	use aws_sdk_s3::presigning::PresigningConfig;
+	use http_body_util::Full as FullBody;
+	use hyper::body::Bytes;
+	use hyper_util::client::legacy::Client;
+	use hyper_util::rt::TokioExecutor;
+	use std::time::{Duration, SystemTime};
+	use tokio::io::AsyncWriteExt;
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-chunked-interrupted");
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["bucket", "info", &bucket])
+		.expect_success_output("Could not get bucket info");
+	let bucket_id = String::from_utf8(output.stdout)
+		.unwrap()
+		.lines()
+		.find_map(|l| l.strip_prefix("Bucket: ").map(str::to_string))
+		.expect("bucket id not found");
+
+	// States of the versions of an object, as returned by the admin API
+	let client = Client::builder(TokioExecutor::new()).build_http();
+	let version_states = |key: &'static str| {
+		let client = &client;
+		let ctx = &ctx;
+		let bucket_id = &bucket_id;
+		async move {
+			let req = hyper::Request::builder()
+				.method(Method::GET)
+				.uri(format!(
+					"http://127.0.0.1:{}/v1/bucket/inspect-object?id={}&key={}",
+					ctx.garage.admin_port, bucket_id, key
+				))
+				.header(
+					"authorization",
+					format!("Bearer {}", common::garage::GARAGE_TEST_ADMIN_TOKEN),
+				)
+				.body(FullBody::new(Bytes::new()))
+				.unwrap();
+			let resp = client.request(req).await.unwrap();
+			if resp.status() == StatusCode::NOT_FOUND {
+				return vec![];
+			}
+			assert_eq!(resp.status(), StatusCode::OK);
+			let body = resp.into_body().collect().await.unwrap().to_bytes();
+			let res: serde_json::Value = serde_json::from_slice(&body).unwrap();
+			res["versions"]
+				.as_array()
+				.unwrap()
+				.iter()
+				.map(|v| v["state"].as_str().unwrap().to_string())
+				.collect::<Vec<_>>()
+		}
+	};
+
+	// Send a chunked upload without Content-Length, stopping after more
+	// than one block so that an uploading version has been written
+	let start_upload = |key: &'static str, fill: u8| {
+		let ctx = &ctx;
+		let bucket = bucket.clone();
+		async move {
+			let psc = PresigningConfig::builder()
+				.start_time(SystemTime::now() - Duration::from_secs(60))
+				.expires_in(Duration::from_secs(3600))
+				.build()
+				.unwrap();
+			let req = ctx
+				.client
+				.put_object()
+				.bucket(&bucket)
+				.key(key)
+				.presigned(psc)
+				.await
+				.unwrap();
+			let uri = req.uri().parse::<hyper::Uri>().unwrap();
+
+			let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", ctx.garage.s3_port))
+				.await
+				.unwrap();
+			let head = format!(
+				"PUT {} HTTP/1.1\r\nHost: {}\r\nTransfer-Encoding: chunked\r\n\r\n",
+				uri.path_and_query().unwrap(),
+				uri.authority().unwrap(),
+			);
+			stream.write_all(head.as_bytes()).await.unwrap();
+			for _ in 0..3 {
+				let chunk = vec![fill; 1 << 19];
+				stream
+					.write_all(format!("{:x}\r\n", chunk.len()).as_bytes())
+					.await
+					.unwrap();
+				stream.write_all(&chunk).await.unwrap();
+				stream.write_all(b"\r\n").await.unwrap();
+			}
+			stream
+		}
+	};
+
+	let wait_for_state = |key: &'static str, state: &'static str| async move {
+		for _ in 0..50 {
+			let states = version_states(key).await;
+			assert!(!states.iter().any(|s| s == "complete"), "{:?}", states);
+			if states.iter().any(|s| s == state) {
+				return states;
+			}
+			tokio::time::sleep(Duration::from_millis(100)).await;
+		}
+		panic!("no {} version for {}", state, key);
+	};
+
+	// A stream error is injected partway with an invalid chunk header
+	let mut stream = start_upload("invalid-chunk", 0xc1).await;
+	wait_for_state("invalid-chunk", "uploading").await;
+	stream.write_all(b"not-a-chunk-size\r\n").await.unwrap();
+	let states = wait_for_state("invalid-chunk", "aborted").await;
+	assert!(states.iter().all(|s| s == "aborted"), "{:?}", states);
+
+	// The connection is dropped in the middle of the upload
+	let stream = start_upload("dropped", 0xc2).await;
+	wait_for_state("dropped", "uploading").await;
+	drop(stream);
+	let states = wait_for_state("dropped", "aborted").await;
+	assert!(states.iter().all(|s| s == "aborted"), "{:?}", states);
+
+	for key in ["invalid-chunk", "dropped"] {
+		let err = ctx
+			.client
+			.head_object()
+			.bucket(&bucket)
+			.key(key)
+			.send()
+			.await
+			.unwrap_err();
+		assert_eq!(err.raw_response().unwrap().status().as_u16(), 404);
+	}
+}
+
+#[tokio::test]
+async fn test_putobject_worm() {
+	use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart, MetadataDirective};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-worm");
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-options", &bucket])
+		.args(["--worm", "true"])
+		.quiet()
+		.expect_success_status("Could not set bucket options");
+
+	let put = |key: &'static str, body: &'static [u8]| {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(ByteStream::from_static(body))
+			.send()
+	};
+
+	// Objects can be created, but not overwritten
+	put("log", BODY).await.unwrap();
+	let err = put("log", b"overwritten").await.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("AccessDenied"));
+
+	let err = ctx
+		.client
+		.copy_object()
+		.bucket(&bucket)
+		.key("log")
+		.copy_source(format!("{}/log", bucket))
+		.metadata_directive(MetadataDirective::Replace)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("AccessDenied"));
+
+	// A multipart upload can't complete over an existing object,
+	// but it can be aborted
+	let mpu = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("log")
+		.send()
+		.await
+		.unwrap();
+	let upload_id = mpu.upload_id.unwrap();
+	let part = ctx
+		.client
+		.upload_part()
+		.bucket(&bucket)
+		.key("log")
+		.upload_id(&upload_id)
+		.part_number(1)
+		.body(ByteStream::from_static(b"overwritten"))
+		.send()
+		.await
+		.unwrap();
+	let err = ctx
+		.client
+		.complete_multipart_upload()
+		.bucket(&bucket)
+		.key("log")
+		.upload_id(&upload_id)
+		.multipart_upload(
+			CompletedMultipartUpload::builder()
+				.parts(
+					CompletedPart::builder()
+						.part_number(1)
+						.e_tag(part.e_tag.unwrap())
+						.build(),
+				)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("AccessDenied"));
+	ctx.client
+		.abort_multipart_upload()
+		.bucket(&bucket)
+		.key("log")
+		.upload_id(&upload_id)
+		.send()
+		.await
+		.unwrap();
+
+	// Objects can't be deleted
+	let err = ctx
+		.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("log")
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("AccessDenied"));
+
+	let err = ctx
+		.client
+		.delete_objects()
+		.bucket(&bucket)
+		.delete(
+			Delete::builder()
+				.objects(ObjectIdentifier::builder().key("log").build().unwrap())
+				.build()
+				.unwrap(),
+		)
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("AccessDenied"));
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("log")
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, BODY);
+}
+
+#[tokio::test]
+async fn test_putobject_reject_key_conflicts() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-reject-key-conflicts");
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-options", &bucket])
+		.args(["--reject-key-conflicts", "true"])
+		.quiet()
+		.expect_success_status("Could not set bucket options");
+
+	let put = |key: &'static str| {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(ByteStream::from_static(BODY))
+			.send()
+	};
+
+	// A "folder" object prevents creating a file object with the same name
+	put("dir/").await.unwrap();
+	put("dir/child").await.unwrap();
+	let err = put("dir").await.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("KeyConflict"));
+
+	// ... and vice versa
+	put("file").await.unwrap();
+	let err = put("file/").await.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("KeyConflict"));
+
+	// Overwriting the existing objects is still allowed
+	put("dir/").await.unwrap();
+	put("file").await.unwrap();
+
+	// Once the conflicting object is deleted, the key can be used
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("file")
+		.send()
+		.await
+		.unwrap();
+	put("file/").await.unwrap();
+
+	for (key, exists) in [
+		("dir", false),
+		("dir/", true),
+		("file", false),
+		("file/", true),
+	] {
+		let r = ctx
+			.client
+			.head_object()
+			.bucket(&bucket)
+			.key(key)
+			.send()
+			.await;
+		assert_eq!(r.is_ok(), exists, "{}", key);
+	}
+}
+
+#[tokio::test]
+async fn test_putobject_max_key_length() {
+	// The test instance is configured with max_key_length = 256
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-max-key-length");
+
+	let put = |key: String| {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(ByteStream::from_static(BODY))
+			.send()
+	};
+
+	put("a".repeat(256)).await.unwrap();
+	let err = put("a".repeat(257)).await.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("KeyTooLongError"));
+
+	// The length is counted in bytes, not in characters
+	put("é".repeat(128)).await.unwrap();
+	let err = put("é".repeat(129)).await.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("KeyTooLongError"));
+
+	let err = ctx
+		.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("a".repeat(257))
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("KeyTooLongError"));
+
+	let err = put("nul\0key".to_string()).await.unwrap_err();
+	assert_eq!(err.raw_response().unwrap().status().as_u16(), 400);
+
+	let r = ctx
+		.client
+		.list_objects_v2()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	let keys = r
+		.contents
+		.unwrap_or_default()
+		.into_iter()
+		.map(|o| o.key.unwrap())
+		.collect::<Vec<_>>();
+	assert_eq!(keys, vec!["a".repeat(256), "é".repeat(128)]);
+}
+
+#[tokio::test]
+async fn test_putobject_invalid_key() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-invalid-key");
+
+	// Keys with control characters are rejected by all write operations
+	let err = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("ctrl\x01key")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.raw_response().unwrap().status().as_u16(), 400);
+	assert_eq!(err.into_service_error().code(), Some("InvalidArgument"));
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("source")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+	let err = ctx
+		.client
+		.copy_object()
+		.bucket(&bucket)
+		.key("new\nline")
+		.copy_source(format!("{}/source", bucket))
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("InvalidArgument"));
+
+	let err = ctx
+		.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("ctrl\x01key")
+		.send()
+		.await
+		.unwrap_err();
+	assert_eq!(err.into_service_error().code(), Some("InvalidArgument"));
+
+	// So are keys whose percent-encoding is not valid UTF-8
+	let mut req = ctx.custom_request.builder(bucket.clone());
+	req.method(Method::PUT)
+		.path("bad%FFkey")
+		.body(BODY.to_vec());
+	let res = req.send().await.unwrap();
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+	let res_body = res.into_body().collect().await.unwrap().to_bytes();
+	let res_body = std::str::from_utf8(&res_body).unwrap();
+	assert!(res_body.contains("<Code>InvalidArgument</Code>"));
+
+	let r = ctx
+		.client
+		.list_objects_v2()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(r.key_count, Some(1));
+}
+
+#[tokio::test]
+async fn test_putobject_max_metadata_size() {
+	// The test instance uses the default max_metadata_size of 2KiB
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-max-metadata-size");
+
+	// Names are counted without their x-amz-meta- prefix: 4 + 2044 bytes
+	let put = |value_len: usize| {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key("meta")
+			.metadata("big1", "v".repeat(value_len))
+			.body(ByteStream::from_static(BODY))
+			.send()
+	};
+
+	put(2044).await.unwrap();
+	let err = put(2045).await.unwrap_err().into_service_error();
+	assert_eq!(err.code(), Some("MetadataTooLarge"));
+
+	let err = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("meta-mpu")
+		.metadata("a", "v".repeat(1024))
+		.metadata("b", "v".repeat(1024))
+		.send()
+		.await
+		.unwrap_err()
+		.into_service_error();
+	assert_eq!(err.code(), Some("MetadataTooLarge"));
+
+	// The object stored before the rejected request is unchanged
+	let o = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("meta")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.metadata.unwrap()["big1"].len(), 2044);
+}
+
+#[tokio::test]
+async fn test_getobject_version_id() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("getobject-version-id");
+
+	let put = |body: &'static [u8]| {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key("versioned")
+			.body(ByteStream::from_static(body))
+			.send()
+	};
+	let get = |version_id: Option<String>| {
+		ctx.client
+			.get_object()
+			.bucket(&bucket)
+			.key("versioned")
+			.set_version_id(version_id)
+			.send()
+	};
+
+	let first = put(b"first").await.unwrap().version_id.unwrap();
+
+	// The latest version is read by default, and its id is returned
+	let o = get(None).await.unwrap();
+	assert_eq!(o.version_id.as_deref(), Some(first.as_str()));
+	let h = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("versioned")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(h.version_id.as_deref(), Some(first.as_str()));
+
+	// A specific version can be requested
+	let o = get(Some(first.clone())).await.unwrap();
+	assert_eq!(o.version_id.as_deref(), Some(first.as_str()));
+	assert_eq!(
+		o.body.collect().await.unwrap().into_bytes().as_ref(),
+		b"first"
+	);
+	let h = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("versioned")
+		.version_id(&first)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(h.version_id.as_deref(), Some(first.as_str()));
+
+	// Versions that have been overwritten are no longer available
+	let second = put(b"second").await.unwrap().version_id.unwrap();
+	let o = get(Some(second.clone())).await.unwrap();
+	assert_eq!(o.version_id.as_deref(), Some(second.as_str()));
+	let err = get(Some(first)).await.unwrap_err();
+	assert_eq!(err.raw_response().unwrap().status().as_u16(), 404);
+	assert_eq!(err.into_service_error().code(), Some("NoSuchVersion"));
+
+	let err = get(Some(hex::encode([0u8; 32]))).await.unwrap_err();
+	assert_eq!(err.raw_response().unwrap().status().as_u16(), 404);
+	let err = get(Some("not-a-version".into())).await.unwrap_err();
+	assert_eq!(err.raw_response().unwrap().status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn test_restoreobject() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("restoreobject");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("archived")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+
+	let restore = |key: &'static str| {
+		let ctx = &ctx;
+		let bucket = bucket.clone();
+		async move {
+			ctx.custom_request
+				.builder(bucket)
+				.method(Method::POST)
+				.path(key.to_owned())
+				.query_param("restore", None::<String>)
+				.body(b"<RestoreRequest><Days>1</Days></RestoreRequest>".to_vec())
+				.send()
+				.await
+				.unwrap()
+		}
+	};
+
+	let res = restore("archived").await;
+	assert_eq!(res.status(), StatusCode::ACCEPTED);
+
+	// The object is not changed by the request
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("archived")
+		.send()
+		.await
+		.unwrap();
+	assert_bytes_eq!(o.body, BODY);
+
+	let res = restore("missing").await;
+	assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("archived")
+		.send()
+		.await
+		.unwrap();
+	let res = restore("archived").await;
+	assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_getobject_default_cache_control() {
+	const DEFAULT: &str = "public, max-age=3600";
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("getobject-default-cache-control");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("without")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("with")
+		.cache_control("no-cache")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+
+	let get_cache_control = |key: &'static str| {
+		let ctx = &ctx;
+		let bucket = bucket.clone();
+		async move {
+			let get = ctx
+				.client
+				.get_object()
+				.bucket(&bucket)
+				.key(key)
+				.send()
+				.await
+				.unwrap();
+			let head = ctx
+				.client
+				.head_object()
+				.bucket(&bucket)
+				.key(key)
+				.send()
+				.await
+				.unwrap();
+			assert_eq!(get.cache_control, head.cache_control);
+			get.cache_control
+		}
+	};
+
+	assert_eq!(get_cache_control("without").await, None);
+	assert_eq!(get_cache_control("with").await.as_deref(), Some("no-cache"));
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-options", &bucket])
+		.args(["--default-cache-control", DEFAULT])
+		.quiet()
+		.expect_success_status("Could not set bucket options");
+
+	// The stored Cache-Control always takes precedence over the default one
+	assert_eq!(get_cache_control("without").await.as_deref(), Some(DEFAULT));
+	assert_eq!(get_cache_control("with").await.as_deref(), Some("no-cache"));
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-options", &bucket])
+		.args(["--default-cache-control", ""])
+		.quiet()
+		.expect_success_status("Could not set bucket options");
+
+	assert_eq!(get_cache_control("without").await, None);
+}
+
+#[tokio::test]
+async fn test_putobject_compact_inline() {
+	use aws_sdk_s3::types::{Tag, Tagging};
+
+	const COMPACT_BODY: &[u8] = b"small object stored in the compact inline data table";
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-compact-inline");
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-options", &bucket])
+		.args(["--compact-inline", "true"])
+		.quiet()
+		.expect_success_status("Could not set bucket options");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("small")
+		.body(ByteStream::from_static(COMPACT_BODY))
+		.send()
+		.await
+		.unwrap();
+
+	let get_body = |key: &'static str, range: Option<&'static str>| {
+		let ctx = &ctx;
+		let bucket = bucket.clone();
+		async move {
+			let o = ctx
+				.client
+				.get_object()
+				.bucket(&bucket)
+				.key(key)
+				.set_range(range.map(String::from))
+				.send()
+				.await
+				.unwrap();
+			o.body.collect().await.unwrap().into_bytes()
+		}
+	};
+
+	assert_eq!(get_body("small", None).await.as_ref(), COMPACT_BODY);
+	assert_eq!(
+		get_body("small", Some("bytes=6-11")).await.as_ref(),
+		&COMPACT_BODY[6..12]
+	);
+
+	let head = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("small")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(head.content_length, Some(COMPACT_BODY.len() as i64));
+
+	// Copies and tag updates read the referenced data and store it again
+	ctx.client
+		.copy_object()
+		.bucket(&bucket)
+		.key("copy")
+		.copy_source(format!("{}/small", bucket))
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.put_object_tagging()
+		.bucket(&bucket)
+		.key("small")
+		.tagging(
+			Tagging::builder()
+				.tag_set(Tag::builder().key("k").value("v").build().unwrap())
+				.build()
+				.unwrap(),
+		)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(get_body("small", None).await.as_ref(), COMPACT_BODY);
+
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("small")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(get_body("copy", None).await.as_ref(), COMPACT_BODY);
+}
+
+#[tokio::test]
+async fn test_getobject_follow() {
+	use aws_sdk_s3::presigning::PresigningConfig;
+	use http_body_util::Empty;
+	use hyper::body::Bytes;
+	use hyper_util::client::legacy::Client;
+	use hyper_util::rt::TokioExecutor;
+	use std::time::{Duration, SystemTime};
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("getobject-follow");
+	let client = Client::builder(TokioExecutor::new()).build_http::<Empty<Bytes>>();
+	let psc = || {
+		PresigningConfig::builder()
+			.start_time(SystemTime::now() - Duration::from_secs(60))
+			.expires_in(Duration::from_secs(3600))
+			.build()
+			.unwrap()
+	};
+	let content = |key: &str| {
+		(0..(3 << 20) + 1234)
+			.map(|i: usize| (i % 251) as u8 ^ key.as_bytes()[0])
+			.collect::<Vec<u8>>()
+	};
+
+	// Send the first part of a chunked upload without Content-Length
+	let start_upload = |key: &'static str, data: Vec<u8>| {
+		let ctx = &ctx;
+		let bucket = bucket.clone();
+		async move {
+			let req = ctx
+				.client
+				.put_object()
+				.bucket(&bucket)
+				.key(key)
+				.presigned(psc())
+				.await
+				.unwrap();
+			let uri = req.uri().parse::<hyper::Uri>().unwrap();
+			let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", ctx.garage.s3_port))
+				.await
+				.unwrap();
+			let head = format!(
+				"PUT {} HTTP/1.1\r\nHost: {}\r\nTransfer-Encoding: chunked\r\n\r\n",
+				uri.path_and_query().unwrap(),
+				uri.authority().unwrap(),
+			);
+			stream.write_all(head.as_bytes()).await.unwrap();
+			send_chunk(&mut stream, &data).await;
+			stream
+		}
+	};
+	async fn send_chunk(stream: &mut tokio::net::TcpStream, data: &[u8]) {
+		stream
+			.write_all(format!("{:x}\r\n", data.len()).as_bytes())
+			.await
+			.unwrap();
+		stream.write_all(data).await.unwrap();
+		stream.write_all(b"\r\n").await.unwrap();
+	}
+
+	// Start to follow the version being uploaded, once it exists
+	let follow = |key: &'static str| {
+		let ctx = &ctx;
+		let client = &client;
+		let bucket = bucket.clone();
+		async move {
+			for _ in 0..50 {
+				let req = ctx
+					.client
+					.get_object()
+					.bucket(&bucket)
+					.key(key)
+					.presigned(psc())
+					.await
+					.unwrap();
+				let req = hyper::Request::builder()
+					.uri(req.uri())
+					.header("x-garage-follow", "1")
+					.body(Empty::new())
+					.unwrap();
+				let resp = client.request(req).await.unwrap();
+				if resp.status() == StatusCode::OK {
+					return resp.into_body();
+				}
+				assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+				tokio::time::sleep(Duration::from_millis(100)).await;
+			}
+			panic!("no version of {} to follow", key);
+		}
+	};
+
+	// The data is received while the upload is in progress, and the
+	// response ends when the upload completes
+	let data = content("live");
+	let mut stream = start_upload("live", data[..3 << 19].to_vec()).await;
+	let mut body = follow("live").await;
+	let mut received = vec![];
+	while received.len() < 1 << 20 {
+		let frame = tokio::time::timeout(Duration::from_secs(10), body.frame())
+			.await
+			.expect("no data received while the upload is in progress")
+			.unwrap()
+			.unwrap();
+		if let Ok(data) = frame.into_data() {
+			received.extend_from_slice(&data);
+		}
+	}
+	assert_eq!(received[..], data[..received.len()]);
+
+	send_chunk(&mut stream, &data[3 << 19..]).await;
+	stream.write_all(b"0\r\n\r\n").await.unwrap();
+	let mut resp = [0u8; 12];
+	stream.read_exact(&mut resp).await.unwrap();
+	assert_eq!(&resp, b"HTTP/1.1 200");
+
+	let rest = tokio::time::timeout(Duration::from_secs(10), body.collect())
+		.await
+		.unwrap()
+		.unwrap()
+		.to_bytes();
+	received.extend_from_slice(&rest);
+	assert!(received == data, "followed data differs from uploaded data");
+
+	// The response is interrupted if the upload is aborted
+	let data = content("aborted");
+	let stream = start_upload("aborted", data[..3 << 19].to_vec()).await;
+	let body = follow("aborted").await;
+	drop(stream);
+	let res = tokio::time::timeout(Duration::from_secs(10), body.collect())
+		.await
+		.unwrap();
+	assert!(res.is_err());
+}
+
+#[tokio::test]
+async fn test_putobject_force_block_storage() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-force-block-storage");
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-options", &bucket])
+		.args(["--force-block-storage", "true"])
+		.quiet()
+		.expect_success_status("Could not set bucket options");
+
+	// A 1KB object, that would otherwise be stored inline
+	let body = b"force-block-storage ".repeat(50);
+	let hash = hex::encode(garage_util::data::blake2sum(&body));
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("small")
+		.body(ByteStream::from(body.clone()))
+		.send()
+		.await
+		.unwrap();
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["block", "refs", &hash])
+		.expect_success_output("Could not list block refs");
+	let refs = String::from_utf8(output.stdout).unwrap();
+	assert!(refs.contains("Referenced by 1 non-deleted versions"));
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("small")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.body.collect().await.unwrap().into_bytes(), body);
+
+	// Empty objects can be stored too
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("empty")
+		.send()
+		.await
+		.unwrap();
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("empty")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.content_length, Some(0));
+	assert!(o.body.collect().await.unwrap().into_bytes().is_empty());
+}
+
+#[tokio::test]
+async fn test_putobject_disable_inline() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-disable-inline");
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-options", &bucket])
+		.args(["--disable-inline", "true"])
+		.quiet()
+		.expect_success_status("Could not set bucket options");
+
+	// A tiny object is stored in a data block
+	let body = b"disable inline".to_vec();
+	let hash = hex::encode(garage_util::data::blake2sum(&body));
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("small")
+		.body(ByteStream::from(body.clone()))
+		.send()
+		.await
+		.unwrap();
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["block", "refs", &hash])
+		.expect_success_output("Could not list block refs");
+	let refs = String::from_utf8(output.stdout).unwrap();
+	assert!(refs.contains("Referenced by 1 non-deleted versions"));
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("small")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.body.collect().await.unwrap().into_bytes(), body);
+
+	// An empty object has no data block, but can still be read
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("empty")
+		.send()
+		.await
+		.unwrap();
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("empty")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.content_length, Some(0));
+	assert_eq!(
+		o.e_tag.as_deref(),
+		Some("\"d41d8cd98f00b204e9800998ecf8427e\"")
+	);
+	assert!(o.body.collect().await.unwrap().into_bytes().is_empty());
+
+	// Its only part is empty, other parts do not exist
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("empty")
+		.part_number(1)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.content_length, Some(0));
+	assert_eq!(o.parts_count, Some(1));
+	assert!(o.body.collect().await.unwrap().into_bytes().is_empty());
+	let h = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("empty")
+		.part_number(1)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(h.content_length, Some(0));
+	assert_eq!(h.parts_count, Some(1));
+	let r = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("empty")
+		.part_number(2)
+		.send()
+		.await;
+	assert!(r.is_err());
+}
+
+#[tokio::test]
+async fn test_putobject_sdk_checksum_algorithm() {
+	use base64::prelude::*;
+	use sha2::{Digest, Sha256};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-sdk-checksum-algorithm");
+
+	// The SHA256 checksum is computed and stored although no
+	// x-amz-checksum-sha256 value was given in the request
+	let body = b"sdk checksum algorithm ".repeat(10);
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("sha256")
+		.signed_header("x-amz-sdk-checksum-algorithm", "SHA256")
+		.body(body.clone())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+
+	let o = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("sha256")
+		.checksum_mode(aws_sdk_s3::types::ChecksumMode::Enabled)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(
+		o.checksum_sha256.as_deref(),
+		Some(BASE64_STANDARD.encode(Sha256::digest(&body)).as_str())
+	);
+	assert!(o.checksum_crc32.is_none());
+
+	// A value for another algorithm is rejected
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("mismatch")
+		.signed_header("x-amz-sdk-checksum-algorithm", "SHA256")
+		.signed_header("x-amz-checksum-crc32", "AAAAAA==")
+		.body(body.clone())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+	// Unsupported algorithms are rejected before anything is stored
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("md5")
+		.signed_header("x-amz-sdk-checksum-algorithm", "MD5")
+		.body(body.clone())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+	let res_body = res.into_body().collect().await.unwrap().to_bytes();
+	let res_body = std::str::from_utf8(&res_body).unwrap();
+	assert!(res_body.contains("InvalidRequest"));
+	assert!(res_body.contains("unsupported checksum algorithm: MD5"));
+
+	let res = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("md5")
+		.send()
+		.await;
+	assert!(res.is_err());
+}
+
+#[tokio::test]
+async fn test_copyobject_replace_headers_shares_blocks() {
+	use aws_sdk_s3::types::{BucketVersioningStatus, MetadataDirective, VersioningConfiguration};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("copyobject-replace-headers");
+
+	ctx.client
+		.put_bucket_versioning()
+		.bucket(&bucket)
+		.versioning_configuration(
+			VersioningConfiguration::builder()
+				.status(BucketVersioningStatus::Enabled)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	// A 10KB object, that is stored in a data block
+	let body = b"replace headers, keep blocks ".repeat(350);
+	let hash = hex::encode(garage_util::data::blake2sum(&body));
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("obj")
+		.content_type("text/plain")
+		.body(ByteStream::from(body.clone()))
+		.send()
+		.await
+		.unwrap();
+
+	ctx.client
+		.copy_object()
+		.bucket(&bucket)
+		.key("obj")
+		.copy_source(format!("{}/obj", bucket))
+		.metadata_directive(MetadataDirective::Replace)
+		.content_type("application/json")
+		.send()
+		.await
+		.unwrap();
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("obj")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.content_type.as_deref(), Some("application/json"));
+	assert_eq!(o.body.collect().await.unwrap().into_bytes(), body);
+
+	// Both versions reference the same block, which was not rewritten
+	let output = ctx
+		.garage
+		.command()
+		.args(["block", "refs", &hash])
+		.expect_success_output("Could not list block refs");
+	let refs = String::from_utf8(output.stdout).unwrap();
+	assert!(refs.contains("Referenced by 2 non-deleted versions"));
+
+	let versions = ctx
+		.client
+		.list_object_versions()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(versions.versions.unwrap_or_default().len(), 2);
+}
+
+#[tokio::test]
+async fn test_putobject_expect_continue() {
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-expect-continue");
+
+	// An unauthenticated request is rejected without the client being
+	// asked to send its body
+	let mut conn = tokio::net::TcpStream::connect(("127.0.0.1", ctx.garage.s3_port))
+		.await
+		.unwrap();
+	let request = format!(
+		"PUT /{}/obj HTTP/1.1\r\nHost: 127.0.0.1:{}\r\nContent-Length: 1000000\r\nExpect: 100-continue\r\n\r\n",
+		bucket, ctx.garage.s3_port
+	);
+	conn.write_all(request.as_bytes()).await.unwrap();
+	let mut buf = vec![0u8; 4096];
+	let n = tokio::time::timeout(std::time::Duration::from_secs(10), conn.read(&mut buf))
+		.await
+		.expect("no response before the body was sent")
+		.unwrap();
+	let response = std::str::from_utf8(&buf[..n]).unwrap();
+	assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+	assert!(!response.contains("100 Continue"));
+
+	// If-None-Match: * is checked before the body is read
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("obj")
+		.body(ByteStream::from_static(b"first"))
+		.send()
+		.await
+		.unwrap();
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("obj")
+		.signed_header("if-none-match", "*")
+		.body(b"second".to_vec())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("new")
+		.signed_header("if-none-match", "*")
+		.body(b"second".to_vec())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("obj")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(
+		o.body.collect().await.unwrap().into_bytes().as_ref(),
+		b"first"
+	);
+}
+
+#[tokio::test]
+async fn test_putobject_serialize_puts() {
+	use aws_sdk_s3::presigning::PresigningConfig;
+	use std::time::{Duration, Instant};
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-serialize-puts");
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-options", &bucket])
+		.args(["--serialize-puts", "true"])
+		.quiet()
+		.expect_success_status("Could not set bucket options");
+
+	// Start a first upload, of which only half of the body is sent for now
+	let presigned = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("hot")
+		.presigned(PresigningConfig::expires_in(Duration::from_secs(600)).unwrap())
+		.await
+		.unwrap();
+	let uri: hyper::Uri = presigned.uri().parse().unwrap();
+	let body = b"serialize puts ".repeat(1000);
+	let (first_half, second_half) = body.split_at(body.len() / 2);
+
+	let mut conn = tokio::net::TcpStream::connect(("127.0.0.1", ctx.garage.s3_port))
+		.await
+		.unwrap();
+	let request = format!(
+		"PUT {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n\r\n",
+		uri.path_and_query().unwrap(),
+		uri.authority().unwrap(),
+		body.len()
+	);
+	conn.write_all(request.as_bytes()).await.unwrap();
+	conn.write_all(first_half).await.unwrap();
+	tokio::time::sleep(Duration::from_millis(500)).await;
+
+	// A concurrent upload of the same key is rejected without waiting
+	let start = Instant::now();
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("hot")
+		.body(b"loser".to_vec())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+	assert!(start.elapsed() < Duration::from_secs(2));
+	let res_body = res.into_body().collect().await.unwrap().to_bytes();
+	assert!(std::str::from_utf8(&res_body).unwrap().contains("SlowDown"));
+
+	// Other keys can be uploaded in the meantime
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("cold")
+		.body(ByteStream::from_static(b"cold"))
+		.send()
+		.await
+		.unwrap();
+
+	// Once the first upload completes, the key can be written again
+	conn.write_all(second_half).await.unwrap();
+	let mut buf = vec![0u8; 4096];
+	let n = conn.read(&mut buf).await.unwrap();
+	let response = std::str::from_utf8(&buf[..n]).unwrap();
+	assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("hot")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.body.collect().await.unwrap().into_bytes(), body);
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("hot")
+		.body(ByteStream::from_static(b"winner"))
+		.send()
+		.await
+		.unwrap();
+}
+
+#[tokio::test]
+async fn test_getobject_range_inline() {
+	let ctx = common::context();
+	let inline_bucket = ctx.create_bucket("getobject-range-inline");
+	let compact_bucket = ctx.create_bucket("getobject-range-compact");
+	let block_bucket = ctx.create_bucket("getobject-range-block");
+
+	for (bucket, option) in [
+		(&compact_bucket, "--compact-inline"),
+		(&block_bucket, "--force-block-storage"),
+	] {
+		ctx.garage
+			.command()
+			.args(["bucket", "set-options", bucket])
+			.args([option, "true"])
+			.quiet()
+			.expect_success_status("Could not set bucket options");
+	}
+
+	// Range reads of objects stored inline, in the inline data table and in
+	// a data block give the same results
+	for bucket in [&inline_bucket, &compact_bucket, &block_bucket] {
+		ctx.client
+			.put_object()
+			.bucket(bucket)
+			.key(STD_KEY)
+			.body(ByteStream::from_static(BODY))
+			.send()
+			.await
+			.unwrap();
+
+		for (range, content_range, expected) in [
+			("bytes=0-0", "bytes 0-0/62", &BODY[0..1]),
+			("bytes=10-19", "bytes 10-19/62", &BODY[10..20]),
+			("bytes=50-1000", "bytes 50-61/62", &BODY[50..]),
+			("bytes=-100", "bytes 0-61/62", &BODY[..]),
+			("bytes=61-", "bytes 61-61/62", &BODY[61..]),
+		] {
+			let o = ctx
+				.client
+				.get_object()
+				.bucket(bucket)
+				.key(STD_KEY)
+				.range(range)
+				.send()
+				.await
+				.unwrap();
+			assert_eq!(o.content_range.as_deref(), Some(content_range));
+			assert_eq!(o.content_length, Some(expected.len() as i64));
+			assert_bytes_eq!(o.body, expected);
+		}
+
+		let err = ctx
+			.client
+			.get_object()
+			.bucket(bucket)
+			.key(STD_KEY)
+			.range("bytes=62-")
+			.send()
+			.await
+			.unwrap_err();
+		assert_eq!(err.raw_response().unwrap().status().as_u16(), 416);
+		assert_eq!(err.code(), Some("InvalidRange"));
+	}
+}
+
+#[tokio::test]
+async fn test_deleteobject_strict_delete_missing() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("deleteobject-strict-delete-missing");
+
+	let delete_objects = |keys: &[&str]| {
+		let mut to_del = Delete::builder();
+		for key in keys {
+			to_del = to_del.objects(ObjectIdentifier::builder().key(*key).build().unwrap());
+		}
+		ctx.client
+			.delete_objects()
+			.bucket(&bucket)
+			.delete(to_del.build().unwrap())
+			.send()
+	};
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("present")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+
+	// By default, deleting a missing key succeeds
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("missing")
+		.send()
+		.await
+		.unwrap();
+
+	let r = delete_objects(&["missing", "present"]).await.unwrap();
+	assert!(r.errors.is_none());
+	let deleted = r.deleted.unwrap();
+	assert_eq!(deleted.len(), 2);
+	assert_eq!(deleted[0].key.as_deref(), Some("missing"));
+	assert!(deleted[0].delete_marker_version_id.is_none());
+	assert_eq!(deleted[1].key.as_deref(), Some("present"));
+	assert!(deleted[1].delete_marker_version_id.is_some());
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-options", &bucket])
+		.args(["--strict-delete-missing", "true"])
+		.quiet()
+		.expect_success_status("Could not set bucket options");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("present")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("present")
+		.send()
+		.await
+		.unwrap();
+
+	// In strict mode, missing keys are reported as such, including keys
+	// that only have delete markers
+	for key in ["missing", "present"] {
+		let err = ctx
+			.client
+			.delete_object()
+			.bucket(&bucket)
+			.key(key)
+			.send()
+			.await
+			.unwrap_err();
+		assert_eq!(err.raw_response().unwrap().status().as_u16(), 404);
+		assert_eq!(err.into_service_error().code(), Some("NoSuchKey"));
+	}
+
+	let r = delete_objects(&["missing", "present"]).await.unwrap();
+	assert!(r.deleted.is_none());
+	let errors = r.errors.unwrap();
+	assert_eq!(errors.len(), 2);
+	for (error, key) in errors.iter().zip(["missing", "present"]) {
+		assert_eq!(error.key.as_deref(), Some(key));
+		assert_eq!(error.code.as_deref(), Some("NoSuchKey"));
+	}
+}
+
+#[tokio::test]
+async fn test_putobject_stored_bytes() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-stored-bytes");
+
+	let put = |key: &'static str, body: Vec<u8>| {
+		let mut req = ctx.custom_request.builder(bucket.clone());
+		req.method(Method::PUT).path(key.to_owned()).body(body);
+		async move { req.send().await }
+	};
+	let stored_bytes = |res: &hyper::Response<_>| -> u64 {
+		res.headers()
+			.get("x-garage-stored-bytes")
+			.unwrap()
+			.to_str()
+			.unwrap()
+			.parse()
+			.unwrap()
+	};
+
+	// Highly compressible data uses less space in data blocks
+	let body = b"stored-bytes".repeat(100_000);
+	let res = put("compressible", body.clone()).await.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	assert!(stored_bytes(&res) < body.len() as u64 / 10);
+
+	// Data stored inline is not compressed
+	let res = put("inline", BODY.to_vec()).await.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	assert_eq!(stored_bytes(&res), BODY.len() as u64);
+}
+
+#[tokio::test]
+async fn test_deleteobjects_abort_on_error_threshold() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("deleteobjects-abort-on-error-threshold");
+
+	// Deleting missing keys fails in strict mode
+	ctx.garage
+		.command()
+		.args(["bucket", "set-options", &bucket])
+		.args(["--strict-delete-missing", "true"])
+		.quiet()
+		.expect_success_status("Could not set bucket options");
+
+	for key in ["a", "b", "c"] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(ByteStream::from_static(BODY))
+			.send()
+			.await
+			.unwrap();
+	}
+
+	let body = format!(
+		"<Delete>{}</Delete>",
+		["a", "m1", "m2", "m3", "m4", "m5", "b", "c"]
+			.iter()
+			.map(|k| format!("<Object><Key>{}</Key></Object>", k))
+			.collect::<String>()
+	);
+	let delete_objects = |threshold: &'static str| {
+		let mut req = ctx.custom_request.builder(bucket.clone());
+		req.method(Method::POST)
+			.query_param("delete", None::<String>)
+			.signed_header("x-garage-abort-on-error-threshold", threshold)
+			.body(body.clone().into_bytes());
+		async move { req.send().await }
+	};
+
+	let res = delete_objects("0").await.unwrap();
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+	// Processing stops after 5 errors: the first key is deleted,
+	// the last two are not processed
+	let res = delete_objects("5").await.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	assert_eq!(res.headers().get("x-garage-unprocessed-keys").unwrap(), "2");
+	let res_body = res.into_body().collect().await.unwrap().to_bytes();
+	let res_body = std::str::from_utf8(&res_body).unwrap();
+	assert_eq!(res_body.matches("<Deleted>").count(), 1);
+	assert_eq!(res_body.matches("<Error>").count(), 5);
+	assert!(!res_body.contains("<Key>b</Key>"));
+
+	let l = ctx
+		.client
+		.list_objects_v2()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	let keys = l
+		.contents
+		.unwrap()
+		.into_iter()
+		.map(|o| o.key.unwrap())
+		.collect::<Vec<_>>();
+	assert_eq!(keys, vec!["b", "c"]);
+}
+
+#[tokio::test]
+async fn test_deleteobjects_conditional() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("deleteobjects-conditional");
+
+	let mut etags = vec![];
+	for key in ["a", "b", "c"] {
+		let r = ctx
+			.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(ByteStream::from_static(BODY))
+			.send()
+			.await
+			.unwrap();
+		etags.push(r.e_tag.unwrap());
+	}
+
+	// The expected etag of b is the one of another object
+	let body = format!(
+		concat!(
+			"<Delete>",
+			"<Object><Key>a</Key><ETag>{}</ETag></Object>",
+			"<Object><Key>b</Key><ETag>\"{}\"</ETag></Object>",
+			"<Object><Key>c</Key></Object>",
+			"</Delete>"
+		),
+		etags[0],
+		"0".repeat(32)
+	);
+	let mut req = ctx.custom_request.builder(bucket.clone());
+	req.method(Method::POST)
+		.query_param("delete", None::<String>)
+		.body(body.into_bytes());
+	let res = req.send().await.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	let res_body = res.into_body().collect().await.unwrap().to_bytes();
+	let res_body = std::str::from_utf8(&res_body).unwrap();
+	assert_eq!(res_body.matches("<Deleted>").count(), 2);
+	assert_eq!(res_body.matches("<Error>").count(), 1);
+	assert!(res_body.contains("<Code>PreconditionFailed</Code><Key>b</Key>"));
+
+	let l = ctx
+		.client
+		.list_objects_v2()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	let keys = l
+		.contents
+		.unwrap()
+		.into_iter()
+		.map(|o| o.key.unwrap())
+		.collect::<Vec<_>>();
+	assert_eq!(keys, vec!["b"]);
+}
+
+#[tokio::test]
+async fn test_putobject_storage_class() {
+	use aws_sdk_s3::types::{ObjectStorageClass, StorageClass};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-storage-class");
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("infrequent")
+		.storage_class(StorageClass::StandardIa)
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("standard")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+
+	let h = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("infrequent")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(h.storage_class, Some(StorageClass::StandardIa));
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("infrequent")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.storage_class, Some(StorageClass::StandardIa));
+
+	let l = ctx
+		.client
+		.list_objects_v2()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	let classes = l
+		.contents
+		.unwrap()
+		.into_iter()
+		.map(|o| (o.key.unwrap(), o.storage_class.unwrap()))
+		.collect::<Vec<_>>();
+	assert_eq!(
+		classes,
+		vec![
+			("infrequent".to_string(), ObjectStorageClass::StandardIa),
+			("standard".to_string(), ObjectStorageClass::Standard),
+		]
+	);
+
+	// Unknown storage classes are rejected
+	let res = ctx
+		.custom_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path("unknown".to_owned())
+		.signed_header("x-amz-storage-class", "SUPERFAST")
+		.body(BODY.to_vec())
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+	let res_body = res.into_body().collect().await.unwrap().to_bytes();
+	assert!(std::str::from_utf8(&res_body)
+		.unwrap()
+		.contains("<Code>InvalidStorageClass</Code>"));
+}
@@ -7,7 +7,7 @@ const SSEC_KEY_MD5: &str = "jMGbs3GyZkYjJUP6q5jA7g==";
 const SSEC_KEY2: &str = "XkYVk4Z3vVDO2yJaUqCAEZX6lL10voMxtV06d8my/eU=";
 const SSEC_KEY2_MD5: &str = "kedo2ab8J1MCjHwJuLTJHw==";
 
-const SZ_2MB: usize = 2 * 1024 * 1024;
+const SZ_5MB: usize = 5 * 1024 * 1024;
 
 #[tokio::test]
 async fn test_ssec_object() {
@@ -174,9 +174,9 @@ async fn test_multipart_upload() {
 	let ctx = common::context();
 	let bucket = ctx.create_bucket("test-ssec-mpu");
 
-	let u1 = vec![0x11; SZ_2MB];
-	let u2 = vec![0x22; SZ_2MB];
-	let u3 = vec![0x33; SZ_2MB];
+	let u1 = vec![0x11; SZ_5MB];
+	let u2 = vec![0x22; SZ_5MB];
+	let u3 = vec![0x33; SZ_5MB];
 	let all = [&u1[..], &u2[..], &u3[..]].concat();
 
 	// Test simple encrypted mpu
@@ -301,7 +301,7 @@ async fn test_multipart_upload() {
 			.upload_id(uid)
 			.part_number(2)
 			.copy_source(format!("{}/a", bucket))
-			.copy_source_range("bytes=500-550000")
+			.copy_source_range("bytes=500-5500000")
 			.copy_source_sse_customer_algorithm("AES256")
 			.copy_source_sse_customer_key(SSEC_KEY)
 			.copy_source_sse_customer_key_md5(SSEC_KEY_MD5)
@@ -384,7 +384,7 @@ async fn test_multipart_upload() {
 			.unwrap();
 
 		// (check) Get object
-		let expected = [&u3[..], &all[500..550001], &u2[..], &u1[1500..20501]].concat();
+		let expected = [&u3[..], &all[500..5500001], &u2[..], &u1[1500..20501]].concat();
 		test_read_encrypted(
 			&ctx,
 			&bucket,
@@ -399,6 +399,100 @@ async fn test_multipart_upload() {
 	}
 }
 
+#[tokio::test]
+async fn test_ssec_response_headers() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("sse-c-response-headers");
+	let data = b"response headers of each encryption mode".to_vec();
+
+	// Plaintext objects: no encryption header at all
+	let r = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("plain")
+		.body(ByteStream::new(data.clone().into()))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(r.server_side_encryption, None);
+	assert_eq!(r.sse_customer_algorithm, None);
+	assert_eq!(r.sse_customer_key_md5, None);
+
+	let r = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("plain")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(r.server_side_encryption, None);
+	assert_eq!(r.sse_customer_algorithm, None);
+	assert_eq!(r.sse_customer_key_md5, None);
+
+	let r = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("plain")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(r.server_side_encryption, None);
+	assert_eq!(r.sse_customer_algorithm, None);
+	assert_eq!(r.sse_customer_key_md5, None);
+
+	// SSE-C objects: algorithm and key md5 are echoed, and the
+	// x-amz-server-side-encryption header (server-managed keys) is not set
+	let r = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("ssec")
+		.sse_customer_algorithm("AES256")
+		.sse_customer_key(SSEC_KEY)
+		.sse_customer_key_md5(SSEC_KEY_MD5)
+		.body(ByteStream::new(data.clone().into()))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(r.server_side_encryption, None);
+	assert_eq!(r.sse_customer_algorithm, Some("AES256".into()));
+	assert_eq!(r.sse_customer_key_md5, Some(SSEC_KEY_MD5.into()));
+
+	let r = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("ssec")
+		.sse_customer_algorithm("AES256")
+		.sse_customer_key(SSEC_KEY)
+		.sse_customer_key_md5(SSEC_KEY_MD5)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(r.server_side_encryption, None);
+	assert_eq!(r.sse_customer_algorithm, Some("AES256".into()));
+	assert_eq!(r.sse_customer_key_md5, Some(SSEC_KEY_MD5.into()));
+
+	let r = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("ssec")
+		.sse_customer_algorithm("AES256")
+		.sse_customer_key(SSEC_KEY)
+		.sse_customer_key_md5(SSEC_KEY_MD5)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(r.server_side_encryption, None);
+	assert_eq!(r.sse_customer_algorithm, Some("AES256".into()));
+	assert_eq!(r.sse_customer_key_md5, Some(SSEC_KEY_MD5.into()));
+	assert_bytes_eq!(r.body, &data[..]);
+}
+
 async fn test_read_encrypted(
 	ctx: &Context,
 	bucket: &str,
@@ -3,6 +3,8 @@ use garage_table::*;
 use garage_util::data::*;
 use garage_util::time::*;
 
+use unicode_normalization::UnicodeNormalization;
+
 use crate::permission::BucketKeyPerm;
 
 mod v08 {
@@ -54,6 +56,13 @@ mod v08 {
 		/// Bucket quotas
 		#[serde(default)]
 		pub quotas: crdt::Lww<BucketQuotas>,
+		/// Optional behaviors enabled on this bucket
+		#[serde(default)]
+		pub options: crdt::Lww<BucketOptions>,
+		/// Versioning state of the bucket, None if versioning
+		/// was never configured
+		#[serde(default)]
+		pub versioning: crdt::Lww<Option<VersioningStatus>>,
 	}
 
 	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
@@ -116,6 +125,107 @@ mod v08 {
 		pub max_objects: Option<u64>,
 	}
 
+	#[derive(Default, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+	pub struct BucketOptions {
+		/// Generate blake2-based etags instead of md5-based etags
+		/// for objects uploaded with PutObject
+		#[serde(default)]
+		pub blake2_etag: bool,
+		/// Check the hash of data blocks as they are streamed to clients
+		/// by GetObject
+		#[serde(default)]
+		pub verify_on_read: bool,
+		/// Reject PutObject and UploadPart requests that do not
+		/// include a content-md5 header
+		#[serde(default)]
+		pub require_content_md5: bool,
+		/// Compress objects with gzip when they are read by clients
+		/// that accept it, if they are of a compressible content type
+		#[serde(default)]
+		pub compress_on_read: bool,
+		/// Accept writes of data blocks that could only be stored on less
+		/// nodes than the write quorum, and re-replicate them later
+		#[serde(default)]
+		pub allow_degraded_writes: bool,
+		/// Require the x-amz-mfa header on object deletions
+		#[serde(default)]
+		pub mfa_delete_required: bool,
+		/// Normalization applied to object keys before they are used
+		#[serde(default)]
+		pub key_normalization: KeyNormalization,
+		/// Reject the creation of an object `a/b` when an object `a/b/` exists,
+		/// and vice versa
+		#[serde(default)]
+		pub reject_key_conflicts: bool,
+		/// Cache-Control header returned when reading objects that were
+		/// stored without one
+		#[serde(default)]
+		pub default_cache_control: Option<String>,
+		/// Write-once bucket: complete objects can neither be overwritten
+		/// nor deleted
+		#[serde(default)]
+		pub worm: bool,
+		/// Store the data of small objects in the inline data table
+		/// instead of in the object table
+		#[serde(default)]
+		pub compact_inline: bool,
+		/// Store the data of small objects in data blocks instead of inline
+		/// in the metadata, so that it is replicated and repaired like the
+		/// data of larger objects
+		#[serde(default)]
+		pub force_block_storage: bool,
+		/// Never store the data of objects inline in the metadata, not even
+		/// for empty objects which then have no data blocks at all
+		#[serde(default)]
+		pub disable_inline: bool,
+		/// Reject uploads of an object while another upload of the same
+		/// object is running on the same node, instead of letting them
+		/// all store their data until the most recent one wins
+		#[serde(default)]
+		pub serialize_puts: bool,
+		/// Answer DeleteObject requests for keys that don't exist with a
+		/// NoSuchKey error, instead of a success as for existing keys
+		#[serde(default)]
+		pub strict_delete_missing: bool,
+		/// Split the data of new objects into blocks at boundaries that
+		/// depend on their content instead of at fixed offsets, so that
+		/// blocks are deduplicated between versions of an object even if
+		/// data is inserted or removed
+		#[serde(default)]
+		pub content_defined_chunking: bool,
+		/// Maximum number of complete versions, including delete markers,
+		/// kept for each object key. The oldest versions are deleted when
+		/// a new version is stored. If None, there is no limit
+		#[serde(default)]
+		pub max_versions_per_key: Option<u64>,
+	}
+
+	/// Normalization of the object keys of a bucket, which allows clients
+	/// to reach the same object using several spellings of its key
+	#[derive(
+		Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize,
+	)]
+	pub enum KeyNormalization {
+		/// Keys are used as given by clients
+		#[default]
+		None,
+		/// Keys are converted to Unicode Normalization Form C
+		Nfc,
+		/// Keys are converted to Unicode Normalization Form C and lowercased
+		Lowercase,
+	}
+
+	/// Versioning state of a bucket, as set by PutBucketVersioning
+	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum VersioningStatus {
+		/// All versions of objects are kept
+		Enabled,
+		/// New versions of objects replace the version stored while
+		/// versioning was not enabled, versions stored while it was
+		/// enabled are kept
+		Suspended,
+	}
+
 	impl garage_util::migrate::InitialFormat for Bucket {}
 }
 
@@ -125,6 +235,53 @@ impl AutoCrdt for BucketQuotas {
 	const WARN_IF_DIFFERENT: bool = true;
 }
 
+impl AutoCrdt for BucketOptions {
+	const WARN_IF_DIFFERENT: bool = true;
+}
+
+impl AutoCrdt for VersioningStatus {
+	const WARN_IF_DIFFERENT: bool = true;
+}
+
+impl KeyNormalization {
+	/// Normalize an object key. All operations on the objects of a bucket
+	/// must use normalized keys, as the key of an object determines
+	/// where it is stored.
+	pub fn normalize(&self, key: &str) -> String {
+		match self {
+			Self::None => key.to_string(),
+			Self::Nfc => key.nfc().collect(),
+			Self::Lowercase => key.to_lowercase().nfc().collect(),
+		}
+	}
+}
+
+impl std::str::FromStr for KeyNormalization {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"none" => Ok(Self::None),
+			"nfc" => Ok(Self::Nfc),
+			"lowercase" => Ok(Self::Lowercase),
+			_ => Err(format!(
+				"invalid key normalization: {} (must be none, nfc or lowercase)",
+				s
+			)),
+		}
+	}
+}
+
+impl std::fmt::Display for KeyNormalization {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::None => write!(f, "none"),
+			Self::Nfc => write!(f, "nfc"),
+			Self::Lowercase => write!(f, "lowercase"),
+		}
+	}
+}
+
 impl BucketParams {
 	/// Create an empty BucketParams with no authorized keys and no website accesss
 	fn new() -> Self {
@@ -137,8 +294,16 @@ impl BucketParams {
 			cors_config: crdt::Lww::new(None),
 			lifecycle_config: crdt::Lww::new(None),
 			quotas: crdt::Lww::new(BucketQuotas::default()),
+			options: crdt::Lww::new(BucketOptions::default()),
+			versioning: crdt::Lww::new(None),
 		}
 	}
+
+	/// Whether the versions of objects stored now are kept when they are
+	/// replaced by newer versions
+	pub fn versioning_enabled(&self) -> bool {
+		*self.versioning.get() == Some(VersioningStatus::Enabled)
+	}
 }
 
 impl Crdt for BucketParams {
@@ -153,9 +318,47 @@ impl Crdt for BucketParams {
 		self.cors_config.merge(&o.cors_config);
 		self.lifecycle_config.merge(&o.lifecycle_config);
 		self.quotas.merge(&o.quotas);
+		self.options.merge(&o.options);
+		self.versioning.merge(&o.versioning);
 	}
 }
 
+impl LifecycleRule {
+	/// If this rule applies to an object with the given key and size, returns
+	/// the date at which it expires the version of that object created at
+	/// timestamp `ts` (at midnight UTC, as the lifecycle worker runs daily)
+	pub fn expiration_date(&self, key: &str, size: u64, ts: u64) -> Option<chrono::NaiveDate> {
+		if !self.enabled {
+			return None;
+		}
+		if let Some(pfx) = &self.filter.prefix {
+			if !key.starts_with(pfx) {
+				return None;
+			}
+		}
+		if self.filter.size_gt.map(|gt| size <= gt).unwrap_or(false)
+			|| self.filter.size_lt.map(|lt| size >= lt).unwrap_or(false)
+		{
+			return None;
+		}
+
+		match self.expiration.as_ref()? {
+			LifecycleExpiration::AfterDays(n_days) => {
+				lifecycle_version_date(ts)?.checked_add_days(chrono::Days::new(*n_days as u64))
+			}
+			LifecycleExpiration::AtDate(date) => parse_lifecycle_date(date).ok(),
+		}
+	}
+}
+
+/// Date from which lifecycle rules count the age of an object version created
+/// at timestamp `ts`: the first midnight UTC after its creation
+pub fn lifecycle_version_date(ts: u64) -> Option<chrono::NaiveDate> {
+	chrono::NaiveDateTime::from_timestamp_millis(ts as i64)?
+		.date()
+		.succ_opt()
+}
+
 pub fn parse_lifecycle_date(date: &str) -> Result<chrono::NaiveDate, &'static str> {
 	use chrono::prelude::*;
 
@@ -261,3 +464,36 @@ impl TableSchema for BucketTable {
 		filter.apply(entry.is_deleted())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_key_normalization() {
+		// "é" as a single code point, and as "e" followed by a combining accent
+		let composed = "Caf\u{e9}/Menu.TXT";
+		let decomposed = "Cafe\u{301}/Menu.TXT";
+
+		assert_eq!(KeyNormalization::None.normalize(decomposed), decomposed);
+		assert_eq!(KeyNormalization::Nfc.normalize(decomposed), composed);
+		assert_eq!(KeyNormalization::Nfc.normalize(composed), composed);
+		assert_eq!(
+			KeyNormalization::Lowercase.normalize(decomposed),
+			"caf\u{e9}/menu.txt"
+		);
+		assert_eq!(
+			KeyNormalization::Lowercase.normalize("Foo.txt"),
+			KeyNormalization::Lowercase.normalize("foo.txt")
+		);
+
+		for n in [
+			KeyNormalization::None,
+			KeyNormalization::Nfc,
+			KeyNormalization::Lowercase,
+		] {
+			assert_eq!(n.to_string().parse::<KeyNormalization>(), Ok(n));
+		}
+		assert!("upper".parse::<KeyNormalization>().is_err());
+	}
+}
@@ -1,5 +1,6 @@
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
 
 use garage_net::NetworkKey;
 
@@ -7,8 +8,10 @@ use garage_db as db;
 
 use garage_util::background::*;
 use garage_util::config::*;
+use garage_util::data::Uuid;
 use garage_util::error::*;
 use garage_util::persister::PersisterShared;
+use garage_util::time::MonotonicClock;
 
 use garage_rpc::replication_mode::*;
 use garage_rpc::system::System;
@@ -19,9 +22,13 @@ use garage_table::replication::TableShardedReplication;
 use garage_table::*;
 
 use crate::s3::block_ref_table::*;
+use crate::s3::idempotency_table::*;
+use crate::s3::idempotency_worker;
+use crate::s3::inline_data_table::*;
 use crate::s3::lifecycle_worker;
 use crate::s3::mpu_table::*;
 use crate::s3::object_table::*;
+use crate::s3::stale_uploads_worker;
 use crate::s3::version_table::*;
 
 use crate::bucket_alias_table::*;
@@ -29,10 +36,16 @@ use crate::bucket_table::*;
 use crate::helper;
 use crate::index_counter::*;
 use crate::key_table::*;
+use crate::put_hook::*;
+use crate::quota::*;
 
 #[cfg(feature = "k2v")]
 use crate::k2v::{item_table::*, rpc::*, sub::*};
 
+/// LMDB can't store keys longer than 511 bytes, and the keys of the object table
+/// are made of the 32-byte hash of the bucket id followed by the object key
+const LMDB_MAX_OBJECT_KEY_LENGTH: usize = 511 - 32;
+
 /// An entire Garage full of data
 pub struct Garage {
 	/// The parsed configuration Garage is running
@@ -72,9 +85,28 @@ pub struct Garage {
 	pub version_table: Arc<Table<VersionTable, TableShardedReplication>>,
 	/// Table containing S3 block references (not blocks themselves)
 	pub block_ref_table: Arc<Table<BlockRefTable, TableShardedReplication>>,
+	/// Table containing idempotency keys of recent PutObject requests
+	pub idempotency_key_table: Arc<Table<IdempotencyKeyTable, TableShardedReplication>>,
+	/// Table containing the data of small objects of buckets with the compact_inline option
+	pub inline_data_table: Arc<Table<InlineDataTable, TableShardedReplication>>,
 
 	/// Persister for lifecycle worker info
 	pub lifecycle_persister: PersisterShared<lifecycle_worker::LifecycleWorkerPersisted>,
+	/// Clock used to generate timestamps of new object versions
+	pub version_clock: MonotonicClock,
+	/// Provider deciding whether uploads are allowed by bucket quotas
+	quota_provider: RwLock<Arc<dyn QuotaProvider>>,
+	/// Hook run before new object versions are committed
+	put_hook: RwLock<Arc<dyn PutHook>>,
+	/// Bytes of data blocks that uploads can still keep in memory, if
+	/// s3_api.put_memory_budget is set
+	pub put_memory_budget: Option<Arc<tokio::sync::Semaphore>>,
+	/// Objects being uploaded through this node in buckets with the
+	/// serialize_puts option
+	pub put_key_locks: Mutex<HashSet<(Uuid, String)>>,
+	/// Number of uploads in progress through this node in each bucket that has
+	/// some, if s3_api.max_concurrent_uploads is set
+	pub uploads_in_progress: Mutex<HashMap<Uuid, usize>>,
 
 	#[cfg(feature = "k2v")]
 	pub k2v: GarageK2V,
@@ -92,7 +124,50 @@ pub struct GarageK2V {
 
 impl Garage {
 	/// Create and run garage
-	pub fn new(config: Config) -> Result<Arc<Self>, Error> {
+	pub fn new(mut config: Config) -> Result<Arc<Self>, Error> {
+		if config.s3_api.multipart_min_part_size == 0 {
+			return Err(Error::Message(
+				"s3_api.multipart_min_part_size must not be zero".into(),
+			));
+		}
+		if config.s3_api.put_hash_parallelism == 0 {
+			return Err(Error::Message(
+				"s3_api.put_hash_parallelism must not be zero".into(),
+			));
+		}
+		if config.s3_api.put_pipeline_depth == 0 {
+			return Err(Error::Message(
+				"s3_api.put_pipeline_depth must not be zero".into(),
+			));
+		}
+		if let Some(budget) = config.s3_api.put_memory_budget {
+			if budget < config.block_size as u64 {
+				return Err(Error::Message(format!(
+					"s3_api.put_memory_budget ({}) must be at least block_size ({})",
+					budget, config.block_size
+				)));
+			}
+		}
+		if config.block_write_rate == Some(0) {
+			return Err(Error::Message("block_write_rate must not be zero".into()));
+		}
+		if config.s3_api.max_concurrent_uploads == Some(0) {
+			return Err(Error::Message(
+				"s3_api.max_concurrent_uploads must not be zero".into(),
+			));
+		}
+		if config.s3_api.stale_upload_timeout == Some(std::time::Duration::ZERO) {
+			return Err(Error::Message(
+				"s3_api.stale_upload_timeout must not be zero".into(),
+			));
+		}
+		if config.s3_api.multipart_min_part_size < config.block_size {
+			warn!(
+				"s3_api.multipart_min_part_size ({}) is smaller than block_size ({}), the data of multipart uploads might be stored in many small blocks",
+				config.s3_api.multipart_min_part_size, config.block_size
+			);
+		}
+
 		// Create meta dir and data dir if they don't exist already
 		std::fs::create_dir_all(&config.metadata_dir)
 			.ok_or_message("Unable to create Garage metadata directory")?;
@@ -123,6 +198,13 @@ impl Garage {
 			}
 			db::Engine::Lmdb => {
 				db_path.push("db.lmdb");
+				if config.s3_api.max_key_length > LMDB_MAX_OBJECT_KEY_LENGTH {
+					info!(
+						"Object keys are limited to {} bytes with the LMDB engine (s3_api.max_key_length is {})",
+						LMDB_MAX_OBJECT_KEY_LENGTH, config.s3_api.max_key_length
+					);
+					config.s3_api.max_key_length = LMDB_MAX_OBJECT_KEY_LENGTH;
+				}
 			}
 		}
 		let db_opt = db::OpenOpt {
@@ -222,14 +304,27 @@ impl Garage {
 			&db,
 		);
 
+		info!("Initialize inline_data_table...");
+		let inline_data_table =
+			Table::new(InlineDataTable, meta_rep_param.clone(), system.clone(), &db);
+
 		info!("Initialize object counter table...");
 		let object_counter_table = IndexCounter::new(system.clone(), meta_rep_param.clone(), &db);
+		let put_memory_budget = config.s3_api.put_memory_budget.map(|budget| {
+			let permits = std::cmp::min(budget, tokio::sync::Semaphore::MAX_PERMITS as u64);
+			Arc::new(tokio::sync::Semaphore::new(permits as usize))
+		});
+		let quota_provider: Arc<dyn QuotaProvider> = Arc::new(BucketQuotaProvider {
+			system: system.clone(),
+			object_counter_table: object_counter_table.clone(),
+		});
 
 		info!("Initialize object_table...");
 		#[allow(clippy::redundant_clone)]
 		let object_table = Table::new(
 			ObjectTable {
 				version_table: version_table.clone(),
+				inline_data_table: inline_data_table.clone(),
 				mpu_table: mpu_table.clone(),
 				object_counter_table: object_counter_table.clone(),
 			},
@@ -238,11 +333,22 @@ impl Garage {
 			&db,
 		);
 
+		info!("Initialize idempotency_key_table...");
+		let idempotency_key_table = Table::new(
+			IdempotencyKeyTable,
+			meta_rep_param.clone(),
+			system.clone(),
+			&db,
+		);
+
 		info!("Load lifecycle worker state...");
 		let lifecycle_persister =
 			PersisterShared::new(&system.metadata_dir, "lifecycle_worker_state");
 		lifecycle_worker::register_bg_vars(&lifecycle_persister, &mut bg_vars);
 
+		info!("Load version clock state...");
+		let version_clock = MonotonicClock::new(&system.metadata_dir, "version_clock")?;
+
 		// ---- K2V ----
 		#[cfg(feature = "k2v")]
 		let k2v = GarageK2V::new(system.clone(), &db, meta_rep_param);
@@ -273,7 +379,15 @@ impl Garage {
 			mpu_counter_table,
 			version_table,
 			block_ref_table,
+			idempotency_key_table,
+			inline_data_table,
 			lifecycle_persister,
+			version_clock,
+			quota_provider: RwLock::new(quota_provider),
+			put_hook: RwLock::new(Arc::new(NoopPutHook)),
+			put_memory_budget,
+			put_key_locks: Mutex::new(HashSet::new()),
+			uploads_in_progress: Mutex::new(HashMap::new()),
 			#[cfg(feature = "k2v")]
 			k2v,
 		}))
@@ -292,12 +406,25 @@ impl Garage {
 		self.mpu_counter_table.spawn_workers(bg);
 		self.version_table.spawn_workers(bg);
 		self.block_ref_table.spawn_workers(bg);
+		self.idempotency_key_table.spawn_workers(bg);
+		self.inline_data_table.spawn_workers(bg);
 
 		bg.spawn_worker(lifecycle_worker::LifecycleWorker::new(
 			self.clone(),
 			self.lifecycle_persister.clone(),
 		));
 
+		bg.spawn_worker(idempotency_worker::IdempotencyKeyExpirationWorker::new(
+			self.clone(),
+		));
+
+		if let Some(timeout) = self.config.s3_api.stale_upload_timeout {
+			bg.spawn_worker(stale_uploads_worker::StaleUploadsWorker::new(
+				self.clone(),
+				timeout,
+			));
+		}
+
 		#[cfg(feature = "k2v")]
 		self.k2v.spawn_workers(bg);
 
@@ -327,10 +454,36 @@ impl Garage {
 		helper::key::KeyHelper(self)
 	}
 
+	pub fn object_helper(&self) -> helper::object::ObjectHelper {
+		helper::object::ObjectHelper(self)
+	}
+
 	pub async fn locked_helper(&self) -> helper::locked::LockedHelper {
 		let lock = self.bucket_lock.lock().await;
 		helper::locked::LockedHelper(self, lock)
 	}
+
+	/// Get the provider that checks bucket quotas on uploads
+	pub fn quota_provider(&self) -> Arc<dyn QuotaProvider> {
+		self.quota_provider.read().unwrap().clone()
+	}
+
+	/// Replace the provider that checks bucket quotas on uploads, e.g. to
+	/// consult an external billing system instead of the quotas of buckets
+	pub fn set_quota_provider(&self, provider: Arc<dyn QuotaProvider>) {
+		*self.quota_provider.write().unwrap() = provider;
+	}
+
+	/// Get the hook run before new object versions are committed
+	pub fn put_hook(&self) -> Arc<dyn PutHook> {
+		self.put_hook.read().unwrap().clone()
+	}
+
+	/// Replace the hook run before new object versions are committed, e.g.
+	/// to enforce that objects are uploaded with some metadata headers
+	pub fn set_put_hook(&self, hook: Arc<dyn PutHook>) {
+		*self.put_hook.write().unwrap() = hook;
+	}
 }
 
 #[cfg(feature = "k2v")]
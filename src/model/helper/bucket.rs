@@ -219,11 +219,13 @@ impl<'a> BucketHelper<'a> {
 					let aborted_versions = object
 						.versions()
 						.iter()
-						.filter(|v| v.is_uploading(None) && v.timestamp < older_than)
+						.filter(|v| v.is_uploading(None) && v.upload_started_at() < older_than)
 						.map(|v| ObjectVersion {
 							state: ObjectVersionState::Aborted,
 							uuid: v.uuid,
 							timestamp: v.timestamp,
+							created_at: v.created_at,
+							versioned: v.versioned,
 						})
 						.collect::<Vec<_>>();
 					if !aborted_versions.is_empty() {
@@ -250,4 +252,39 @@ impl<'a> BucketHelper<'a> {
 
 		Ok(ret)
 	}
+
+	/// Deletes the incomplete uploads of all buckets that are older than
+	/// a certain time, as `cleanup_incomplete_uploads` does for a single bucket.
+	/// Returns the number of uploads aborted.
+	pub async fn cleanup_stale_uploads(&self, older_than: Duration) -> Result<usize, Error> {
+		let mut ret = 0usize;
+		let mut start = None;
+
+		loop {
+			let buckets = self
+				.0
+				.bucket_table
+				.get_range(
+					&EmptyKey,
+					start,
+					Some(DeletedFilter::NotDeleted),
+					1000,
+					EnumerationOrder::Forward,
+				)
+				.await?;
+
+			for bucket in buckets.iter() {
+				ret += self
+					.cleanup_incomplete_uploads(&bucket.id, older_than)
+					.await?;
+			}
+
+			match buckets.last().and_then(|b| b.id.increment()) {
+				Some(next) if buckets.len() == 1000 => start = Some(next),
+				_ => break,
+			}
+		}
+
+		Ok(ret)
+	}
 }
@@ -2,3 +2,4 @@ pub mod bucket;
 pub mod error;
 pub mod key;
 pub mod locked;
+pub mod object;
@@ -0,0 +1,217 @@
+use garage_util::data::*;
+use garage_util::error::OkOrMessage;
+use garage_util::time::*;
+
+use garage_table::util::*;
+
+use crate::bucket_table::*;
+use crate::garage::Garage;
+use crate::helper::error::*;
+use crate::s3::block_ref_table::*;
+use crate::s3::inline_data_table::*;
+use crate::s3::object_table::*;
+use crate::s3::version_table::*;
+
+pub struct ObjectHelper<'a>(pub(crate) &'a Garage);
+
+impl<'a> ObjectHelper<'a> {
+	/// Creates a new complete version of object `dest_key` in bucket
+	/// `dest_bucket_id` with metadata `new_meta`, whose data is that of
+	/// version `source_version_uuid`. Inline data is copied, data blocks are
+	/// referenced by the new version and are not rewritten. `new_timestamp`
+	/// must be more recent than all existing versions of the destination object.
+	/// `versioned` is whether the new version is kept when newer versions are
+	/// stored, i.e. usually whether versioning is enabled on the bucket.
+	#[allow(clippy::too_many_arguments)]
+	pub async fn copy_version_data(
+		&self,
+		source_version_uuid: Uuid,
+		source_version_data: &ObjectVersionData,
+		dest_bucket_id: Uuid,
+		dest_bucket_params: &BucketParams,
+		dest_key: &str,
+		new_timestamp: u64,
+		versioned: bool,
+		new_meta: ObjectVersionMeta,
+	) -> Result<ObjectVersion, Error> {
+		let garage = self.0;
+
+		// Generate parameters for the new version
+		let new_uuid = gen_uuid();
+		let created_at = now_msec();
+
+		let dest_version_data = match source_version_data {
+			ObjectVersionData::DeleteMarker => unreachable!(),
+			ObjectVersionData::Inline(..) | ObjectVersionData::InlineRef(..) => {
+				let bytes = match source_version_data {
+					ObjectVersionData::InlineRef(_, uuid) => {
+						garage
+							.inline_data_table
+							.get(uuid, &EmptyKey)
+							.await?
+							.filter(|d| !d.deleted.get())
+							.ok_or_message("inline data of object version not found")?
+							.data
+					}
+					ObjectVersionData::Inline(_, bytes) => bytes.clone(),
+					_ => unreachable!(),
+				};
+				// bytes is either plaintext before&after or encrypted with the
+				// same keys, so it's ok to just copy it as is
+				self.inline_version_data(dest_bucket_params, new_uuid, new_meta, bytes)
+					.await?
+			}
+			ObjectVersionData::FirstBlock(_meta, first_block_hash) => {
+				// Get block list from source version
+				let source_version = garage
+					.version_table
+					.get(&source_version_uuid, &EmptyKey)
+					.await?
+					.ok_or_message("version of object not found")?;
+
+				// Write an "uploading" marker in Object table
+				// This holds a reference to the object in the Version table
+				// so that it won't be deleted, e.g. by repair_versions.
+				let tmp_dest_object_version = ObjectVersion {
+					uuid: new_uuid,
+					timestamp: new_timestamp,
+					created_at: Some(created_at),
+					versioned,
+					state: ObjectVersionState::Uploading {
+						encryption: new_meta.encryption.clone(),
+						checksum_algorithm: None,
+						multipart: false,
+					},
+				};
+				let tmp_dest_object = Object::new(
+					dest_bucket_id,
+					dest_key.to_string(),
+					vec![tmp_dest_object_version],
+				);
+				garage.object_table.insert(&tmp_dest_object).await?;
+
+				// Write version in the version table. Even with empty block list,
+				// this means that the BlockRef entries linked to this version cannot be
+				// marked as deleted (they are marked as deleted only if the Version
+				// doesn't exist or is marked as deleted).
+				let mut dest_version = Version::new(
+					new_uuid,
+					VersionBacklink::Object {
+						bucket_id: dest_bucket_id,
+						key: dest_key.to_string(),
+					},
+					false,
+				);
+				garage.version_table.insert(&dest_version).await?;
+
+				// Fill in block list for version and insert block refs
+				for (bk, bv) in source_version.blocks.items().iter() {
+					dest_version.blocks.put(*bk, *bv);
+				}
+				let dest_block_refs = dest_version
+					.blocks
+					.items()
+					.iter()
+					.map(|b| BlockRef {
+						block: b.1.hash,
+						version: new_uuid,
+						deleted: false.into(),
+						bucket_id: Some(dest_bucket_id),
+					})
+					.collect::<Vec<_>>();
+				futures::try_join!(
+					garage.version_table.insert(&dest_version),
+					garage.block_ref_table.insert_many(&dest_block_refs[..]),
+				)?;
+
+				ObjectVersionData::FirstBlock(new_meta, *first_block_hash)
+			}
+		};
+
+		// Insert final object
+		// We do this last because otherwise there is a race condition in the case where
+		// the copy call has the same source and destination (this happens, rclone does
+		// it to update the modification timestamp for instance). If we did this concurrently
+		// with the stuff before, the block's reference counts could be decremented before
+		// they are incremented again for the new version, leading to data being deleted.
+		let dest_object_version = ObjectVersion {
+			uuid: new_uuid,
+			timestamp: new_timestamp,
+			created_at: Some(created_at),
+			versioned,
+			state: ObjectVersionState::Complete(dest_version_data),
+		};
+		self.insert_object_version(dest_bucket_id, dest_key, dest_object_version.clone())
+			.await?;
+
+		Ok(dest_object_version)
+	}
+
+	/// Inserts a complete version of an object, whose data may have been written
+	/// to the inline data table beforehand. If the insertion fails, the version
+	/// is marked as aborted and its inline data is deleted, so that it is not
+	/// left behind in the inline data table.
+	pub async fn insert_object_version(
+		&self,
+		bucket_id: Uuid,
+		key: &str,
+		object_version: ObjectVersion,
+	) -> Result<(), Error> {
+		let (uuid, timestamp) = (object_version.uuid, object_version.timestamp);
+		let inline_ref = match &object_version.state {
+			ObjectVersionState::Complete(ObjectVersionData::InlineRef(_, id)) => Some(*id),
+			_ => None,
+		};
+
+		let object = Object::new(bucket_id, key.to_string(), vec![object_version]);
+		let err = match self.0.object_table.insert(&object).await {
+			Ok(()) => return Ok(()),
+			Err(e) => e,
+		};
+
+		if let Some(id) = inline_ref {
+			// The object may have been written on some nodes, aborting the version
+			// there deletes its inline data when the object table is updated
+			let aborted_version = ObjectVersion {
+				uuid,
+				timestamp,
+				created_at: None,
+				versioned: false,
+				state: ObjectVersionState::Aborted,
+			};
+			let aborted = Object::new(bucket_id, key.to_string(), vec![aborted_version]);
+			if let Err(e) = self.0.object_table.insert(&aborted).await {
+				warn!("Cannot abort object version that was not stored: {}", e);
+			}
+			let deleted_data = InlineData::new_deleted(id);
+			if let Err(e) = self.0.inline_data_table.insert(&deleted_data).await {
+				warn!(
+					"Cannot delete inline data of object version that was not stored: {}",
+					e
+				);
+			}
+		}
+
+		Err(err.into())
+	}
+
+	/// Returns the data of an object version stored inline, which is moved
+	/// to the inline data table if the bucket has the `compact_inline` option
+	pub async fn inline_version_data(
+		&self,
+		bucket_params: &BucketParams,
+		version_uuid: Uuid,
+		meta: ObjectVersionMeta,
+		inline_data: Vec<u8>,
+	) -> Result<ObjectVersionData, Error> {
+		if bucket_params.options.get().compact_inline {
+			self.0
+				.inline_data_table
+				.insert(&InlineData::new(version_uuid, inline_data))
+				.await?;
+			Ok(ObjectVersionData::InlineRef(meta, version_uuid))
+		} else {
+			Ok(ObjectVersionData::Inline(meta, inline_data))
+		}
+	}
+}
@@ -414,6 +414,7 @@ impl K2VRpcHandler {
 	) -> Result<Option<K2VItem>, Error> {
 		let now = now_msec();
 
+		self.item_table.data.check_sort_key(&item.sort_key)?;
 		self.item_table
 			.data
 			.update_entry_with(&item.partition, &item.sort_key, |tx, ent| {
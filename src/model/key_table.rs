@@ -35,6 +35,11 @@ mod v08 {
 		/// Flag to allow users having this key to create buckets
 		pub allow_create_bucket: crdt::Lww<bool>,
 
+		/// Flag to allow users having this key to supply the hashes of the
+		/// data blocks of their uploads, which are then only spot-checked
+		#[serde(default)]
+		pub allow_trusted_block_hashes: crdt::Lww<bool>,
+
 		/// If the key is present: it gives some permissions,
 		/// a map of bucket IDs (uuids) to permissions.
 		/// Otherwise no permissions are granted to key
@@ -56,6 +61,7 @@ impl KeyParams {
 			secret_key: secret_key.to_string(),
 			name: crdt::Lww::new(name.to_string()),
 			allow_create_bucket: crdt::Lww::new(false),
+			allow_trusted_block_hashes: crdt::Lww::new(false),
 			authorized_buckets: crdt::Map::new(),
 			local_aliases: crdt::LwwMap::new(),
 		}
@@ -66,6 +72,8 @@ impl Crdt for KeyParams {
 	fn merge(&mut self, o: &Self) {
 		self.name.merge(&o.name);
 		self.allow_create_bucket.merge(&o.allow_create_bucket);
+		self.allow_trusted_block_hashes
+			.merge(&o.allow_trusted_block_hashes);
 		self.authorized_buckets.merge(&o.authorized_buckets);
 		self.local_aliases.merge(&o.local_aliases);
 	}
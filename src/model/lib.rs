@@ -15,4 +15,6 @@ pub mod s3;
 
 pub mod garage;
 pub mod helper;
+pub mod put_hook;
+pub mod quota;
 pub mod snapshot;
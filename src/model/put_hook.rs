@@ -0,0 +1,40 @@
+use err_derive::Error;
+
+use garage_util::data::*;
+
+use crate::s3::object_table::HeaderList;
+
+/// Error returned by a put hook to refuse an upload
+#[derive(Debug, Error)]
+#[error(display = "{}", _0)]
+pub struct PutHookError(pub String);
+
+/// Custom logic run before a new object version is committed, e.g. to
+/// require some metadata headers on objects or to add default ones.
+/// The default hook does nothing, another one can be set with
+/// `Garage::set_put_hook`.
+pub trait PutHook: Send + Sync {
+	/// Called once the data of an object has been received and before the
+	/// object is committed, with the headers that will be stored with the
+	/// object, which the hook can modify. Returning an error aborts the upload.
+	fn before_commit(
+		&self,
+		bucket_id: Uuid,
+		key: &str,
+		headers: &mut HeaderList,
+	) -> Result<(), PutHookError>;
+}
+
+/// Put hook that accepts all uploads without changing them
+pub struct NoopPutHook;
+
+impl PutHook for NoopPutHook {
+	fn before_commit(
+		&self,
+		_bucket_id: Uuid,
+		_key: &str,
+		_headers: &mut HeaderList,
+	) -> Result<(), PutHookError> {
+		Ok(())
+	}
+}
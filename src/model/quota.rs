@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use err_derive::Error;
+
+use garage_rpc::system::System;
+use garage_table::EmptyKey;
+use garage_util::data::*;
+use garage_util::error::Error as GarageError;
+
+use crate::bucket_table::BucketQuotas;
+use crate::index_counter::*;
+use crate::s3::object_table::*;
+
+/// Error returned by a quota provider when an upload is refused
+#[derive(Debug, Error)]
+pub enum QuotaError {
+	/// The upload would exceed the maximum number of objects of the bucket
+	#[error(display = "{}", _0)]
+	Objects(String),
+
+	/// The upload would exceed the maximum total size of the bucket
+	#[error(display = "{}", _0)]
+	Size(String),
+
+	/// The quota could not be checked
+	#[error(display = "{}", _0)]
+	Internal(#[error(source)] GarageError),
+}
+
+/// Decides whether uploads to a bucket are allowed with respect to its quotas.
+/// The default provider enforces the quotas configured on the bucket using the
+/// object counters of Garage, another one can be set with
+/// `Garage::set_quota_provider` to consult an external system instead.
+#[async_trait]
+pub trait QuotaProvider: Send + Sync {
+	/// Check that an upload can be done in a bucket. `quotas` are the quotas
+	/// configured on the bucket, `size_delta` and `object_delta` are the changes
+	/// in the total size and the number of objects of the bucket that the
+	/// upload would make (they can be negative when an object is replaced).
+	async fn check(
+		&self,
+		bucket_id: Uuid,
+		quotas: &BucketQuotas,
+		size_delta: i64,
+		object_delta: i64,
+	) -> Result<(), QuotaError>;
+}
+
+/// Quota provider that enforces the quotas configured on buckets,
+/// using the object counter table
+pub struct BucketQuotaProvider {
+	pub(crate) system: Arc<System>,
+	pub(crate) object_counter_table: Arc<IndexCounter<Object>>,
+}
+
+#[async_trait]
+impl QuotaProvider for BucketQuotaProvider {
+	async fn check(
+		&self,
+		bucket_id: Uuid,
+		quotas: &BucketQuotas,
+		size_delta: i64,
+		object_delta: i64,
+	) -> Result<(), QuotaError> {
+		if quotas.max_objects.is_none() && quotas.max_size.is_none() {
+			return Ok(());
+		};
+
+		let counters = self
+			.object_counter_table
+			.table
+			.get(&bucket_id, &EmptyKey)
+			.await?;
+
+		let counters = counters
+			.map(|x| x.filtered_values(&self.system.cluster_layout()))
+			.unwrap_or_default();
+
+		if let Some(mo) = quotas.max_objects {
+			let current_objects = counters.get(OBJECTS).cloned().unwrap_or_default();
+			if object_delta > 0 && current_objects + object_delta > mo as i64 {
+				return Err(QuotaError::Objects(format!(
+					"Object quota is reached, maximum objects for this bucket: {}",
+					mo
+				)));
+			}
+		}
+
+		if let Some(ms) = quotas.max_size {
+			let current_size = counters.get(BYTES).cloned().unwrap_or_default();
+			if size_delta > 0 && current_size + size_delta > ms as i64 {
+				return Err(QuotaError::Size(format!(
+					"Bucket size quota is reached, maximum total size of objects for this bucket: {}. The bucket is already {} bytes, and this object would add {} bytes.",
+					ms, current_size, size_delta
+				)));
+			}
+		}
+
+		Ok(())
+	}
+}
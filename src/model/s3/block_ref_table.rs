@@ -13,6 +13,8 @@ use garage_table::*;
 
 use garage_block::manager::*;
 
+use crate::s3::version_table::VersionTable;
+
 mod v08 {
 	use garage_util::crdt;
 	use garage_util::data::{Hash, Uuid};
@@ -34,7 +36,47 @@ mod v08 {
 	impl garage_util::migrate::InitialFormat for BlockRef {}
 }
 
-pub use v08::*;
+mod v10 {
+	use garage_util::crdt;
+	use garage_util::data::{Hash, Uuid};
+	use serde::{Deserialize, Serialize};
+
+	use super::v08;
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct BlockRef {
+		/// Hash (blake2 sum) of the block, used as partition key
+		pub block: Hash,
+
+		/// Id of the Version for the object containing this block, used as sorting key
+		pub version: Uuid,
+
+		// Keep track of deleted status
+		/// Is the Version that contains this block deleted
+		pub deleted: crdt::Bool,
+
+		/// Id of the bucket of the object containing this block,
+		/// if known (it is not known for block refs written by older versions of Garage)
+		pub bucket_id: Option<Uuid>,
+	}
+
+	impl garage_util::migrate::Migrate for BlockRef {
+		const VERSION_MARKER: &'static [u8] = b"G10s3br";
+
+		type Previous = v08::BlockRef;
+
+		fn migrate(old: v08::BlockRef) -> BlockRef {
+			BlockRef {
+				block: old.block,
+				version: old.version,
+				deleted: old.deleted,
+				bucket_id: None,
+			}
+		}
+	}
+}
+
+pub use v10::*;
 
 impl Entry<Hash, Uuid> for BlockRef {
 	fn partition_key(&self) -> &Hash {
@@ -51,6 +93,7 @@ impl Entry<Hash, Uuid> for BlockRef {
 impl Crdt for BlockRef {
 	fn merge(&mut self, other: &Self) {
 		self.deleted.merge(&other.deleted);
+		self.bucket_id = std::cmp::max(self.bucket_id, other.bucket_id);
 	}
 }
 
@@ -89,6 +132,155 @@ impl TableSchema for BlockRefTable {
 	}
 }
 
+/// Count the versions that reference a block, not including
+/// references from versions that have been deleted
+pub async fn count_block_refs(
+	block_ref_table: &Arc<Table<BlockRefTable, TableShardedReplication>>,
+	block: &Hash,
+) -> Result<usize, Error> {
+	const PAGE_SIZE: usize = 1000;
+
+	let mut count = 0;
+	let mut cursor = None;
+	loop {
+		let refs = block_ref_table
+			.get_range(
+				block,
+				cursor,
+				Some(DeletedFilter::NotDeleted),
+				PAGE_SIZE,
+				EnumerationOrder::Forward,
+			)
+			.await?;
+		count += refs.len();
+		match refs.last().and_then(|br| br.version.increment()) {
+			Some(next) if refs.len() == PAGE_SIZE => cursor = Some(next),
+			_ => break,
+		}
+	}
+	Ok(count)
+}
+
+/// List the uuids of at most `limit` versions that reference a block,
+/// not including versions that have been deleted
+pub async fn list_block_refs(
+	block_ref_table: &Arc<Table<BlockRefTable, TableShardedReplication>>,
+	block: &Hash,
+	limit: usize,
+) -> Result<Vec<BlockRef>, Error> {
+	let refs = block_ref_table
+		.get_range(
+			block,
+			None,
+			Some(DeletedFilter::NotDeleted),
+			limit,
+			EnumerationOrder::Forward,
+		)
+		.await?;
+	Ok(refs)
+}
+
+/// Check whether a block is referenced by a version of an object of the given
+/// bucket, not including versions that have been deleted
+pub async fn is_block_referenced_by_bucket(
+	block_ref_table: &Arc<Table<BlockRefTable, TableShardedReplication>>,
+	block: &Hash,
+	bucket_id: Uuid,
+) -> Result<bool, Error> {
+	const PAGE_SIZE: usize = 1000;
+
+	let mut cursor = None;
+	loop {
+		let refs = block_ref_table
+			.get_range(
+				block,
+				cursor,
+				Some(DeletedFilter::NotDeleted),
+				PAGE_SIZE,
+				EnumerationOrder::Forward,
+			)
+			.await?;
+		if refs.iter().any(|br| br.bucket_id == Some(bucket_id)) {
+			return Ok(true);
+		}
+		match refs.last().and_then(|br| br.version.increment()) {
+			Some(next) if refs.len() == PAGE_SIZE => cursor = Some(next),
+			_ => return Ok(false),
+		}
+	}
+}
+
+/// List all versions that reference a block, including versions that
+/// have been deleted, as pairs of a version uuid and its deleted flag
+pub async fn versions_for_block(
+	block_ref_table: &Arc<Table<BlockRefTable, TableShardedReplication>>,
+	block: &Hash,
+) -> Result<Vec<(Uuid, bool)>, Error> {
+	const PAGE_SIZE: usize = 1000;
+
+	let mut versions = vec![];
+	let mut cursor = None;
+	loop {
+		let refs = block_ref_table
+			.get_range(block, cursor, None, PAGE_SIZE, EnumerationOrder::Forward)
+			.await?;
+		versions.extend(refs.iter().map(|br| (br.version, br.deleted.get())));
+		match refs.last().and_then(|br| br.version.increment()) {
+			Some(next) if refs.len() == PAGE_SIZE => cursor = Some(next),
+			_ => break,
+		}
+	}
+	Ok(versions)
+}
+
+/// A reference to a block from a version that doesn't exist
+/// in the version table
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct DanglingRef {
+	pub block: Hash,
+	pub version: Uuid,
+	pub bucket_id: Option<Uuid>,
+}
+
+/// Find the references to a block that are not deleted but whose
+/// version is absent from the version table. Such references keep
+/// the block alive although no object can ever read it.
+pub async fn audit_block_refs(
+	block_ref_table: &Arc<Table<BlockRefTable, TableShardedReplication>>,
+	version_table: &Arc<Table<VersionTable, TableShardedReplication>>,
+	block: &Hash,
+) -> Result<Vec<DanglingRef>, Error> {
+	const PAGE_SIZE: usize = 1000;
+
+	let mut dangling = vec![];
+	let mut cursor = None;
+	loop {
+		let refs = block_ref_table
+			.get_range(
+				block,
+				cursor,
+				Some(DeletedFilter::NotDeleted),
+				PAGE_SIZE,
+				EnumerationOrder::Forward,
+			)
+			.await?;
+		for br in refs.iter() {
+			if version_table.get(&br.version, &EmptyKey).await?.is_none() {
+				dangling.push(DanglingRef {
+					block: br.block,
+					version: br.version,
+					bucket_id: br.bucket_id,
+				});
+			}
+		}
+		match refs.last().and_then(|br| br.version.increment()) {
+			Some(next) if refs.len() == PAGE_SIZE => cursor = Some(next),
+			_ => break,
+		}
+	}
+	Ok(dangling)
+}
+
 pub fn block_ref_recount_fn(
 	block_ref_table: &Arc<Table<BlockRefTable, TableShardedReplication>>,
 ) -> CalculateRefcount {
@@ -123,3 +315,152 @@ fn calculate_refcount(
 	}
 	Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use garage_rpc::test_util::*;
+	use garage_util::crdt;
+
+	use crate::garage::Garage;
+	use crate::s3::version_table::{Version, VersionBacklink};
+
+	#[test]
+	fn test_migrate_and_merge_bucket_id() {
+		let old = v08::BlockRef {
+			block: blake2sum(b"block"),
+			version: gen_uuid(),
+			deleted: crdt::Bool::new(false),
+		};
+		let mut block_ref = BlockRef::decode(&old.encode().unwrap()).unwrap();
+		assert_eq!(block_ref.version, old.version);
+		assert_eq!(block_ref.bucket_id, None);
+
+		let bucket_id = gen_uuid();
+		let with_bucket = BlockRef {
+			bucket_id: Some(bucket_id),
+			..block_ref.clone()
+		};
+		block_ref.merge(&with_bucket);
+		assert_eq!(block_ref.bucket_id, Some(bucket_id));
+
+		let mut deleted = BlockRef {
+			deleted: crdt::Bool::new(true),
+			bucket_id: None,
+			..block_ref.clone()
+		};
+		deleted.merge(&block_ref);
+		assert!(deleted.deleted.get());
+		assert_eq!(deleted.bucket_id, Some(bucket_id));
+
+		// Merging entries with different bucket ids gives the same
+		// result in both orders
+		let other = BlockRef {
+			bucket_id: Some(gen_uuid()),
+			..block_ref.clone()
+		};
+		let mut a = block_ref.clone();
+		a.merge(&other);
+		let mut b = other.clone();
+		b.merge(&block_ref);
+		assert_eq!(a, b);
+	}
+
+	#[tokio::test]
+	async fn test_audit_block_refs() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let config = test_config(&dir, 1, "");
+		let garage = Garage::new(config).unwrap();
+		assign_test_layout(&garage.system, &[garage.system.id]).await;
+
+		let block = blake2sum(b"audited block");
+		let bucket_id = gen_uuid();
+		let version = Version::new(
+			gen_uuid(),
+			VersionBacklink::Object {
+				bucket_id,
+				key: "key".into(),
+			},
+			false,
+		);
+		garage.version_table.insert(&version).await.unwrap();
+
+		// One live reference, one reference to a version that was never
+		// written and one deleted reference to a missing version
+		let live = BlockRef {
+			block,
+			version: version.uuid,
+			deleted: crdt::Bool::new(false),
+			bucket_id: Some(bucket_id),
+		};
+		let orphan = BlockRef {
+			block,
+			version: gen_uuid(),
+			deleted: crdt::Bool::new(false),
+			bucket_id: Some(bucket_id),
+		};
+		let deleted = BlockRef {
+			block,
+			version: gen_uuid(),
+			deleted: crdt::Bool::new(true),
+			bucket_id: None,
+		};
+		for br in [&live, &orphan, &deleted] {
+			garage.block_ref_table.insert(br).await.unwrap();
+		}
+
+		let dangling = audit_block_refs(&garage.block_ref_table, &garage.version_table, &block)
+			.await
+			.unwrap();
+		assert_eq!(
+			dangling,
+			vec![DanglingRef {
+				block,
+				version: orphan.version,
+				bucket_id: Some(bucket_id),
+			}]
+		);
+	}
+
+	#[tokio::test]
+	async fn test_is_block_referenced_by_bucket() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let config = test_config(&dir, 1, "");
+		let garage = Garage::new(config).unwrap();
+		assign_test_layout(&garage.system, &[garage.system.id]).await;
+
+		let block = blake2sum(b"referenced block");
+		let (bucket_id, other_bucket_id) = (gen_uuid(), gen_uuid());
+		let referenced =
+			|bucket_id| is_block_referenced_by_bucket(&garage.block_ref_table, &block, bucket_id);
+		assert!(!referenced(bucket_id).await.unwrap());
+
+		// Deleted references and references from other buckets do not count
+		let deleted = BlockRef {
+			block,
+			version: gen_uuid(),
+			deleted: crdt::Bool::new(true),
+			bucket_id: Some(bucket_id),
+		};
+		let other = BlockRef {
+			block,
+			version: gen_uuid(),
+			deleted: crdt::Bool::new(false),
+			bucket_id: Some(other_bucket_id),
+		};
+		for br in [&deleted, &other] {
+			garage.block_ref_table.insert(br).await.unwrap();
+		}
+		assert!(!referenced(bucket_id).await.unwrap());
+		assert!(referenced(other_bucket_id).await.unwrap());
+
+		let live = BlockRef {
+			block,
+			version: gen_uuid(),
+			deleted: crdt::Bool::new(false),
+			bucket_id: Some(bucket_id),
+		};
+		garage.block_ref_table.insert(&live).await.unwrap();
+		assert!(referenced(bucket_id).await.unwrap());
+	}
+}
@@ -0,0 +1,88 @@
+use garage_util::data::*;
+use garage_util::time::now_msec;
+
+use garage_table::crdt::*;
+use garage_table::*;
+
+mod v010 {
+	use garage_util::crdt;
+	use garage_util::data::{Hash, Uuid};
+	use serde::{Deserialize, Serialize};
+
+	/// An idempotency key given by a client for a PutObject request,
+	/// which is kept for a limited time to deduplicate retries of that request
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct IdempotencyKey {
+		/// Bucket in which the object was stored
+		pub bucket_id: Uuid,
+		/// The idempotency key given by the client
+		pub key: String,
+
+		/// Key of the object that was stored
+		pub object_key: String,
+		/// Uuid of the object version that was created
+		pub version_uuid: Uuid,
+		/// Timestamp of the object version that was created
+		pub timestamp: u64,
+		/// Etag of the object version that was created
+		pub etag: String,
+		/// Sha256 of the content of the object
+		pub content_sha256: Hash,
+		/// Time (in msec since epoch) after which the key is forgotten
+		pub expires_at: u64,
+		/// Whether the key has been deleted after it expired
+		pub deleted: crdt::Bool,
+	}
+
+	impl garage_util::migrate::InitialFormat for IdempotencyKey {
+		const VERSION_MARKER: &'static [u8] = b"G010s3idk";
+	}
+}
+
+pub use v010::*;
+
+impl IdempotencyKey {
+	pub fn is_expired(&self) -> bool {
+		now_msec() >= self.expires_at
+	}
+}
+
+impl Crdt for IdempotencyKey {
+	fn merge(&mut self, other: &Self) {
+		let mut deleted = self.deleted;
+		deleted.merge(&other.deleted);
+		// If the key was used concurrently by several requests,
+		// the first object version that was created wins
+		if (other.timestamp, other.version_uuid) < (self.timestamp, self.version_uuid) {
+			*self = other.clone();
+		}
+		self.deleted = deleted;
+	}
+}
+
+impl Entry<Uuid, String> for IdempotencyKey {
+	fn partition_key(&self) -> &Uuid {
+		&self.bucket_id
+	}
+	fn sort_key(&self) -> &String {
+		&self.key
+	}
+	fn is_tombstone(&self) -> bool {
+		self.deleted.get()
+	}
+}
+
+pub struct IdempotencyKeyTable;
+
+impl TableSchema for IdempotencyKeyTable {
+	const TABLE_NAME: &'static str = "idempotency_key";
+
+	type P = Uuid;
+	type S = String;
+	type E = IdempotencyKey;
+	type Filter = DeletedFilter;
+
+	fn matches_filter(entry: &Self::E, filter: &Self::Filter) -> bool {
+		filter.apply(entry.deleted.get() || entry.is_expired())
+	}
+}
@@ -0,0 +1,82 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use tokio::sync::watch;
+
+use garage_util::background::*;
+use garage_util::error::Error;
+
+use crate::garage::Garage;
+
+/// Delay between two passes of the worker over the idempotency key table
+const EXPIRATION_CHECK_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Worker that deletes the idempotency keys stored on this node once they
+/// have expired, by writing tombstones that are then garbage collected
+pub struct IdempotencyKeyExpirationWorker {
+	garage: Arc<Garage>,
+	pos: Vec<u8>,
+	next_run: Instant,
+	keys_deleted: usize,
+}
+
+impl IdempotencyKeyExpirationWorker {
+	pub(crate) fn new(garage: Arc<Garage>) -> Self {
+		Self {
+			garage,
+			pos: vec![],
+			next_run: Instant::now() + EXPIRATION_CHECK_INTERVAL,
+			keys_deleted: 0,
+		}
+	}
+}
+
+#[async_trait]
+impl Worker for IdempotencyKeyExpirationWorker {
+	fn name(&self) -> String {
+		"Idempotency key expiration worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			freeform: vec![format!("Expired keys deleted: {}", self.keys_deleted)],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		if Instant::now() < self.next_run {
+			return Ok(WorkerState::Idle);
+		}
+
+		let table = &self.garage.idempotency_key_table;
+
+		// Process a batch of 100 items before yielding to bg task scheduler
+		for _ in 0..100 {
+			let (key_bytes, next_pos) = match table.data.store.get_gt(&self.pos)? {
+				None => {
+					self.pos = vec![];
+					self.next_run = Instant::now() + EXPIRATION_CHECK_INTERVAL;
+					return Ok(WorkerState::Idle);
+				}
+				Some((k, v)) => (v, k),
+			};
+			self.pos = next_pos;
+
+			let mut key = table.data.decode_entry(&key_bytes)?;
+			if key.is_expired() && !key.deleted.get() {
+				key.deleted.set();
+				table.insert(&key).await?;
+				self.keys_deleted += 1;
+			}
+		}
+
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		tokio::time::sleep_until(self.next_run.into()).await;
+		WorkerState::Busy
+	}
+}
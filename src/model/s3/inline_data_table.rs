@@ -0,0 +1,114 @@
+use garage_util::crdt::{self, Crdt};
+use garage_util::data::*;
+
+use garage_table::*;
+
+mod v010 {
+	use garage_util::crdt;
+	use garage_util::data::Uuid;
+	use serde::{Deserialize, Serialize};
+
+	/// Data of a small object version, stored apart from the object table
+	/// for buckets with the `compact_inline` option
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct InlineData {
+		/// Id of the object version, used as partition key
+		pub version: Uuid,
+
+		/// Is the object version deleted. The data is removed when it is.
+		pub deleted: crdt::Bool,
+
+		/// Data of the object version, encrypted in the same way as if it
+		/// was stored inline in the object table
+		#[serde(with = "serde_bytes")]
+		pub data: Vec<u8>,
+	}
+
+	impl garage_util::migrate::InitialFormat for InlineData {
+		const VERSION_MARKER: &'static [u8] = b"G010s3id";
+	}
+}
+
+pub use v010::*;
+
+impl InlineData {
+	pub fn new(version: Uuid, data: Vec<u8>) -> Self {
+		Self {
+			version,
+			deleted: crdt::Bool::new(false),
+			data,
+		}
+	}
+
+	pub fn new_deleted(version: Uuid) -> Self {
+		Self {
+			version,
+			deleted: crdt::Bool::new(true),
+			data: vec![],
+		}
+	}
+}
+
+impl Crdt for InlineData {
+	fn merge(&mut self, other: &Self) {
+		self.deleted.merge(&other.deleted);
+		if self.deleted.get() {
+			self.data.clear();
+		}
+	}
+}
+
+impl Entry<Uuid, EmptyKey> for InlineData {
+	fn partition_key(&self) -> &Uuid {
+		&self.version
+	}
+	fn sort_key(&self) -> &EmptyKey {
+		&EmptyKey
+	}
+	fn is_tombstone(&self) -> bool {
+		self.deleted.get()
+	}
+}
+
+pub struct InlineDataTable;
+
+impl TableSchema for InlineDataTable {
+	const TABLE_NAME: &'static str = "inline_data";
+
+	type P = Uuid;
+	type S = EmptyKey;
+	type E = InlineData;
+	type Filter = DeletedFilter;
+
+	fn matches_filter(entry: &Self::E, filter: &Self::Filter) -> bool {
+		filter.apply(entry.deleted.get())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use garage_util::migrate::Migrate;
+
+	#[test]
+	fn test_merge_deleted() {
+		let version = gen_uuid();
+		let mut data = InlineData::new(version, b"hello".to_vec());
+		assert!(!data.is_tombstone());
+
+		data.merge(&InlineData::new_deleted(version));
+		assert!(data.is_tombstone());
+		assert!(data.data.is_empty());
+
+		// The data cannot be brought back once deleted
+		let mut deleted = InlineData::new_deleted(version);
+		deleted.merge(&InlineData::new(version, b"hello".to_vec()));
+		assert!(deleted.is_tombstone());
+		assert!(deleted.data.is_empty());
+
+		// Encoding round-trip
+		let data = InlineData::new(version, b"hello".to_vec());
+		let decoded = InlineData::decode(&data.encode().unwrap()).unwrap();
+		assert_eq!(decoded, data);
+	}
+}
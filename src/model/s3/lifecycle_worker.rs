@@ -272,6 +272,18 @@ async fn process_object(
 		return Ok(Skip::SkipBucket);
 	}
 
+	// Objects of write-once buckets never expire
+	let worm = bucket
+		.state
+		.as_option()
+		.map(|s| s.options.get().worm)
+		.unwrap_or(false);
+	let versioning_enabled = bucket
+		.state
+		.as_option()
+		.map(|s| s.versioning_enabled())
+		.unwrap_or(false);
+
 	let db = garage.object_table.data.store.db();
 
 	for rule in lifecycle_policy.iter() {
@@ -285,38 +297,30 @@ async fn process_object(
 			}
 		}
 
-		if let Some(expire) = &rule.expiration {
+		if !worm {
 			if let Some(current_version) = object.versions().iter().rev().find(|v| v.is_data()) {
-				let version_date = next_date(current_version.timestamp);
-
-				let current_version_data = match &current_version.state {
-					ObjectVersionState::Complete(c) => c,
+				let size = match &current_version.state {
+					ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+					| ObjectVersionState::Complete(ObjectVersionData::InlineRef(meta, _))
+					| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => meta.size,
 					_ => unreachable!(),
 				};
+				let expiration_date =
+					rule.expiration_date(&object.key, size, current_version.timestamp);
 
-				let size_match = check_size_filter(current_version_data, &rule.filter);
-				let date_match = match expire {
-					LifecycleExpiration::AfterDays(n_days) => {
-						(now_date - version_date) >= chrono::Duration::days(*n_days as i64)
-					}
-					LifecycleExpiration::AtDate(exp_date) => {
-						if let Ok(exp_date) = parse_lifecycle_date(exp_date) {
-							now_date >= exp_date
-						} else {
-							warn!("Invalid expiration date stored in bucket {:?} lifecycle config: {}", bucket.id, exp_date);
-							false
-						}
-					}
-				};
-
-				if size_match && date_match {
+				if expiration_date.map(|d| now_date >= d).unwrap_or(false) {
 					// Delete expired version
 					let deleted_object = Object::new(
 						object.bucket_id,
 						object.key.clone(),
 						vec![ObjectVersion {
 							uuid: gen_uuid(),
-							timestamp: std::cmp::max(now_msec(), current_version.timestamp + 1),
+							timestamp: garage
+								.version_clock
+								.next(Some(current_version.timestamp))
+								.await?,
+							created_at: Some(now_msec()),
+							versioned: versioning_enabled,
 							state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
 						}],
 					);
@@ -335,7 +339,7 @@ async fn process_object(
 				.versions()
 				.iter()
 				.filter_map(|v| {
-					let version_date = next_date(v.timestamp);
+					let version_date = lifecycle_version_date(v.timestamp)?;
 					if (now_date - version_date) >= chrono::Duration::days(*abort_mpu_days as i64)
 						&& matches!(&v.state, ObjectVersionState::Uploading { .. })
 					{
@@ -367,38 +371,12 @@ async fn process_object(
 	Ok(Skip::NextObject)
 }
 
-fn check_size_filter(version_data: &ObjectVersionData, filter: &LifecycleFilter) -> bool {
-	let size = match version_data {
-		ObjectVersionData::Inline(meta, _) | ObjectVersionData::FirstBlock(meta, _) => meta.size,
-		_ => unreachable!(),
-	};
-	if let Some(size_gt) = filter.size_gt {
-		if !(size > size_gt) {
-			return false;
-		}
-	}
-	if let Some(size_lt) = filter.size_lt {
-		if !(size < size_lt) {
-			return false;
-		}
-	}
-	true
-}
-
 fn midnight_ts(date: NaiveDate) -> u64 {
 	date.and_hms_opt(0, 0, 0)
 		.expect("midnight does not exist")
 		.timestamp_millis() as u64
 }
 
-fn next_date(ts: u64) -> NaiveDate {
-	NaiveDateTime::from_timestamp_millis(ts as i64)
-		.expect("bad timestamp")
-		.date()
-		.succ_opt()
-		.expect("no next day")
-}
-
 fn today() -> NaiveDate {
 	Utc::now().naive_utc().date()
 }
@@ -1,6 +1,10 @@
 pub mod block_ref_table;
+pub mod idempotency_table;
+pub mod inline_data_table;
 pub mod mpu_table;
 pub mod object_table;
 pub mod version_table;
 
+pub mod idempotency_worker;
 pub mod lifecycle_worker;
+pub mod stale_uploads_worker;
@@ -10,6 +10,7 @@ use garage_table::replication::TableShardedReplication;
 use garage_table::*;
 
 use crate::index_counter::*;
+use crate::s3::inline_data_table::*;
 use crate::s3::mpu_table::*;
 use crate::s3::version_table::*;
 
@@ -172,6 +173,8 @@ mod v09 {
 }
 
 mod v010 {
+	use std::collections::BTreeMap;
+
 	use garage_util::data::{Hash, Uuid};
 	use serde::{Deserialize, Serialize};
 
@@ -197,6 +200,19 @@ mod v010 {
 		pub uuid: Uuid,
 		/// Timestamp of when the object was created
 		pub timestamp: u64,
+		/// Time at which the upload of the version started, reported as its
+		/// Last-Modified date. The timestamp above can be later than this time,
+		/// as it is increased to order the versions of the object.
+		/// Not set for versions created by older versions of Garage.
+		#[serde(default)]
+		pub created_at: Option<u64>,
+		/// Whether the version was stored while versioning was enabled on
+		/// the bucket. Such versions are kept when newer versions of the
+		/// object are stored, whereas other versions (the "null" version
+		/// of S3) are replaced by the next complete version that is not
+		/// versioned either.
+		#[serde(default)]
+		pub versioned: bool,
 		/// State of the version
 		pub state: ObjectVersionState,
 	}
@@ -231,6 +247,11 @@ mod v010 {
 		/// The object is not short, Hash of first block is stored here, next segments hashes are
 		/// stored in the version table
 		FirstBlock(ObjectVersionMeta, Hash),
+		/// The object is short, but its data is stored in the inline data table
+		/// under the given version id instead of in the object table
+		/// (for buckets with the `compact_inline` option).
+		/// Added after the v0.10 release, older nodes cannot decode it.
+		InlineRef(ObjectVersionMeta, Uuid),
 	}
 
 	/// Metadata about the object version
@@ -269,16 +290,40 @@ mod v010 {
 	pub struct ObjectVersionMetaInner {
 		pub headers: HeaderList,
 		pub checksum: Option<ChecksumValue>,
+		/// Object tags (set with x-amz-tagging or PutObjectTagging)
+		#[serde(default)]
+		pub tags: ObjectTags,
+		/// Whether the checksum is computed over the whole object data
+		/// or over the checksums of its parts
+		#[serde(default)]
+		pub checksum_type: ChecksumType,
 	}
 
 	pub type HeaderList = Vec<(String, String)>;
 
+	/// Map of object tags, from tag key to tag value
+	pub type ObjectTags = BTreeMap<String, String>;
+
+	/// Kind of checksum stored for an object: checksums of objects uploaded with
+	/// PutObject are computed on their whole data, those of objects uploaded with
+	/// multipart uploads are computed on the checksums of their parts
+	#[derive(
+		Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize,
+	)]
+	pub enum ChecksumType {
+		#[default]
+		FullObject,
+		Composite,
+	}
+
 	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
 	pub enum ChecksumAlgorithm {
 		Crc32,
 		Crc32c,
 		Sha1,
 		Sha256,
+		/// Added after the v0.10 release, older nodes cannot decode it
+		Crc64Nvme,
 	}
 
 	/// Checksum value for x-amz-checksum-algorithm
@@ -288,6 +333,7 @@ mod v010 {
 		Crc32c(#[serde(with = "serde_bytes")] [u8; 4]),
 		Sha1(#[serde(with = "serde_bytes")] [u8; 20]),
 		Sha256(#[serde(with = "serde_bytes")] [u8; 32]),
+		Crc64Nvme(#[serde(with = "serde_bytes")] [u8; 8]),
 	}
 
 	impl garage_util::migrate::Migrate for Object {
@@ -308,6 +354,8 @@ mod v010 {
 		ObjectVersion {
 			uuid: old.uuid,
 			timestamp: old.timestamp,
+			created_at: None,
+			versioned: false,
 			state: match old.state {
 				v09::ObjectVersionState::Uploading { multipart, headers } => {
 					ObjectVersionState::Uploading {
@@ -359,6 +407,8 @@ mod v010 {
 			inner: ObjectVersionMetaInner {
 				headers: new_headers,
 				checksum: None,
+				tags: Default::default(),
+				checksum_type: Default::default(),
 			},
 		}
 	}
@@ -439,6 +489,17 @@ impl ObjectVersion {
 		(self.timestamp, self.uuid)
 	}
 
+	/// Time reported as the Last-Modified date of the version
+	pub fn last_modified(&self) -> u64 {
+		self.created_at.unwrap_or(self.timestamp)
+	}
+
+	/// Time at which the upload of the version was started, which unlike its
+	/// timestamp cannot be chosen by the client
+	pub fn upload_started_at(&self) -> u64 {
+		self.created_at.unwrap_or(self.timestamp)
+	}
+
 	/// Is the object version currently being uploaded
 	///
 	/// matches only multipart uploads if check_multipart is Some(true)
@@ -489,6 +550,7 @@ impl ChecksumValue {
 			ChecksumValue::Crc32c(_) => ChecksumAlgorithm::Crc32c,
 			ChecksumValue::Sha1(_) => ChecksumAlgorithm::Sha1,
 			ChecksumValue::Sha256(_) => ChecksumAlgorithm::Sha256,
+			ChecksumValue::Crc64Nvme(_) => ChecksumAlgorithm::Crc64Nvme,
 		}
 	}
 }
@@ -502,7 +564,10 @@ impl Crdt for Object {
 				.binary_search_by(|v| v.cmp_key().cmp(&other_v.cmp_key()))
 			{
 				Ok(i) => {
-					self.versions[i].state.merge(&other_v.state);
+					let v = &mut self.versions[i];
+					v.created_at = std::cmp::max(v.created_at, other_v.created_at);
+					v.versioned |= other_v.versioned;
+					v.state.merge(&other_v.state);
 				}
 				Err(i) => {
 					self.versions.insert(i, other_v.clone());
@@ -511,23 +576,33 @@ impl Crdt for Object {
 		}
 
 		// Remove versions which are obsolete, i.e. those that come
-		// before the last version which .is_complete().
-		let last_complete = self
+		// before the last version which .is_complete(), except versions
+		// that are retained because of bucket versioning: versioned
+		// versions, and versions that come after the last complete
+		// version that is not versioned. Aborted versions are never retained.
+		let last_complete = self.versions.iter().rposition(|v| v.is_complete());
+		let last_complete_null = self
 			.versions
 			.iter()
-			.enumerate()
-			.rev()
-			.find(|(_, v)| v.is_complete())
-			.map(|(vi, _)| vi);
+			.rposition(|v| v.is_complete() && !v.versioned);
 
 		if let Some(last_vi) = last_complete {
-			self.versions = self.versions.drain(last_vi..).collect::<Vec<_>>();
+			let last_null_vi = last_complete_null.unwrap_or(0);
+			let mut vi = 0;
+			self.versions.retain(|v| {
+				let keep = vi >= last_vi
+					|| (!matches!(v.state, ObjectVersionState::Aborted)
+						&& (v.versioned || vi >= last_null_vi));
+				vi += 1;
+				keep
+			});
 		}
 	}
 }
 
 pub struct ObjectTable {
 	pub version_table: Arc<Table<VersionTable, TableShardedReplication>>,
+	pub inline_data_table: Arc<Table<InlineDataTable, TableShardedReplication>>,
 	pub mpu_table: Arc<Table<MultipartUploadTable, TableShardedReplication>>,
 	pub object_counter_table: Arc<IndexCounter<Object>>,
 }
@@ -598,6 +673,20 @@ impl TableSchema for ObjectTable {
 							e
 						);
 					}
+
+					// Delete data stored in the inline data table
+					if let ObjectVersionState::Complete(ObjectVersionData::InlineRef(_, id)) =
+						&v.state
+					{
+						let deleted_data = InlineData::new_deleted(*id);
+						let res = self.inline_data_table.queue_insert(tx, &deleted_data);
+						if let Err(e) = db::unabort(res)? {
+							error!(
+								"Unable to enqueue inline data deletion propagation: {}. A repair will be needed.",
+								e
+							);
+						}
+					}
 				}
 
 				// After abortion or completion of multipart uploads, delete MPU table entry
@@ -676,6 +765,7 @@ impl CountedItem for Object {
 			.iter()
 			.map(|v| match &v.state {
 				ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+				| ObjectVersionState::Complete(ObjectVersionData::InlineRef(meta, _))
 				| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => meta.size,
 				_ => 0,
 			})
@@ -688,3 +778,81 @@ impl CountedItem for Object {
 		]
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn version(timestamp: u64, versioned: bool, state: ObjectVersionState) -> ObjectVersion {
+		ObjectVersion {
+			uuid: gen_uuid(),
+			timestamp,
+			created_at: None,
+			versioned,
+			state,
+		}
+	}
+
+	fn complete(timestamp: u64, versioned: bool) -> ObjectVersion {
+		version(
+			timestamp,
+			versioned,
+			ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+		)
+	}
+
+	/// Merge versions one after the other into an object,
+	/// and return the timestamps of the versions that are kept
+	fn merge_all(versions: &[ObjectVersion]) -> Vec<u64> {
+		let mut object = Object::new(Uuid::from([0u8; 32]), "key".into(), vec![]);
+		for v in versions {
+			object.merge(&Object::new(
+				object.bucket_id,
+				object.key.clone(),
+				vec![v.clone()],
+			));
+		}
+		object.versions().iter().map(|v| v.timestamp).collect()
+	}
+
+	#[test]
+	fn test_merge_versioning() {
+		// Without versioning, only the last complete version is kept
+		assert_eq!(
+			merge_all(&[complete(1, false), complete(2, false), complete(3, false)]),
+			vec![3]
+		);
+
+		// Versioned versions are kept, as well as the version that was
+		// stored before versioning was enabled
+		assert_eq!(
+			merge_all(&[complete(1, false), complete(2, true), complete(3, true)]),
+			vec![1, 2, 3]
+		);
+
+		// When versioning is suspended, the next version that is not versioned
+		// replaces the previous one, but versioned versions are kept
+		assert_eq!(
+			merge_all(&[
+				complete(1, false),
+				complete(2, true),
+				complete(3, false),
+				complete(4, false)
+			]),
+			vec![2, 4]
+		);
+
+		// The result does not depend on the order of the merges
+		let versions = [
+			complete(1, false),
+			complete(2, true),
+			version(3, true, ObjectVersionState::Aborted),
+			complete(4, false),
+			complete(5, true),
+		];
+		let mut reversed = versions.clone();
+		reversed.reverse();
+		assert_eq!(merge_all(&versions), vec![2, 4, 5]);
+		assert_eq!(merge_all(&reversed), vec![2, 4, 5]);
+	}
+}
@@ -0,0 +1,139 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use tokio::sync::watch;
+
+use garage_util::background::*;
+use garage_util::error::Error;
+use garage_util::time::*;
+
+use crate::garage::Garage;
+use crate::s3::object_table::*;
+
+/// Maximum delay between two runs of the worker
+const MAX_RUN_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Worker that periodically aborts the uploads that have been in progress for
+/// longer than s3_api.stale_upload_timeout. Like the lifecycle worker, each node
+/// only goes through the objects of the partitions that it stores locally.
+pub struct StaleUploadsWorker {
+	garage: Arc<Garage>,
+	timeout: Duration,
+	next_run: Instant,
+	state: State,
+	last_aborted: Option<usize>,
+}
+
+enum State {
+	Idle,
+	Running {
+		older_than: u64,
+		pos: Vec<u8>,
+		aborted: usize,
+	},
+}
+
+impl StaleUploadsWorker {
+	pub(crate) fn new(garage: Arc<Garage>, timeout: Duration) -> Self {
+		Self {
+			garage,
+			timeout,
+			next_run: Instant::now() + Self::run_interval(timeout),
+			state: State::Idle,
+			last_aborted: None,
+		}
+	}
+
+	fn run_interval(timeout: Duration) -> Duration {
+		std::cmp::min(timeout, MAX_RUN_INTERVAL)
+	}
+}
+
+#[async_trait]
+impl Worker for StaleUploadsWorker {
+	fn name(&self) -> String {
+		"Stale uploads cleanup worker".into()
+	}
+	fn status(&self) -> WorkerStatus {
+		let mut freeform = match &self.state {
+			State::Idle => vec![format!(
+				"Next run: {}",
+				(chrono::Utc::now() + (self.next_run - Instant::now())).to_rfc3339()
+			)],
+			State::Running { aborted, .. } => {
+				vec![format!("Running, uploads aborted: {}", aborted)]
+			}
+		};
+		if let Some(n) = self.last_aborted {
+			freeform.push(format!("Uploads aborted in last run: {}", n));
+		}
+		WorkerStatus {
+			freeform,
+			..Default::default()
+		}
+	}
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		if let State::Idle = self.state {
+			if Instant::now() < self.next_run {
+				return Ok(WorkerState::Idle);
+			}
+			self.state = State::Running {
+				older_than: now_msec().saturating_sub(self.timeout.as_millis() as u64),
+				pos: vec![],
+				aborted: 0,
+			};
+		}
+		let (older_than, pos, aborted) = match &mut self.state {
+			State::Running {
+				older_than,
+				pos,
+				aborted,
+			} => (*older_than, pos, aborted),
+			State::Idle => unreachable!(),
+		};
+
+		let table = &self.garage.object_table;
+
+		// Process a batch of 100 items before yielding to bg task scheduler
+		for _ in 0..100 {
+			let (object_bytes, next_pos) = match table.data.store.get_gt(&pos)? {
+				None => {
+					if *aborted > 0 {
+						info!("Aborted {} stale uploads", aborted);
+					}
+					self.last_aborted = Some(*aborted);
+					self.state = State::Idle;
+					self.next_run = Instant::now() + Self::run_interval(self.timeout);
+					return Ok(WorkerState::Idle);
+				}
+				Some((k, v)) => (v, k),
+			};
+			*pos = next_pos;
+
+			let object = table.data.decode_entry(&object_bytes)?;
+			let aborted_versions = object
+				.versions()
+				.iter()
+				.filter(|v| v.is_uploading(None) && v.upload_started_at() < older_than)
+				.map(|v| ObjectVersion {
+					state: ObjectVersionState::Aborted,
+					..*v
+				})
+				.collect::<Vec<_>>();
+			if !aborted_versions.is_empty() {
+				let aborted_object =
+					Object::new(object.bucket_id, object.key.clone(), aborted_versions);
+				let db = table.data.store.db();
+				db.transaction(|tx| table.queue_insert(tx, &aborted_object))?;
+				*aborted += 1;
+			}
+		}
+
+		Ok(WorkerState::Busy)
+	}
+	async fn wait_for_work(&mut self) -> WorkerState {
+		tokio::time::sleep_until(self.next_run.into()).await;
+		WorkerState::Busy
+	}
+}
@@ -219,6 +219,7 @@ impl TableSchema for VersionTable {
 					block: vb.hash,
 					version: old_v.uuid,
 					deleted: true.into(),
+					bucket_id: None,
 				});
 				for block_ref in deleted_block_refs {
 					let res = self.block_ref_table.queue_insert(tx, &block_ref);
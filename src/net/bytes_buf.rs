@@ -45,6 +45,14 @@ impl BytesBuf {
 		}
 	}
 
+	/// Adds some bytes back to the left of the buffer
+	pub fn prepend(&mut self, b: Bytes) {
+		if !b.is_empty() {
+			self.buf_len += b.len();
+			self.buf.push_front(b);
+		}
+	}
+
 	/// Takes the whole content of the buffer and returns it as a single Bytes unit
 	pub fn take_all(&mut self) -> Bytes {
 		if self.buf.is_empty() {
@@ -80,6 +80,16 @@ impl OrderTag {
 	pub fn stream() -> OrderTagStream {
 		OrderTagStream(thread_rng().gen())
 	}
+
+	/// Get the position of the message in its stream
+	pub fn order(&self) -> u64 {
+		self.1
+	}
+
+	/// Check whether two order tags were created from the same stream
+	pub fn same_stream(&self, other: &OrderTag) -> bool {
+		self.0 == other.0
+	}
 }
 impl OrderTagStream {
 	/// Create the order tag for message `order` in this stream
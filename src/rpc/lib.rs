@@ -17,4 +17,7 @@ pub mod system;
 
 pub mod rpc_helper;
 
+#[cfg(feature = "testing")]
+pub mod test_util;
+
 pub use rpc_helper::*;
@@ -0,0 +1,93 @@
+//! Helpers to set up a single Garage node in the unit tests of the crates
+//! that depend on this one (enabled by the `testing` feature)
+
+use std::path::Path;
+use std::sync::Arc;
+
+use garage_db as db;
+use garage_net::NetworkKey;
+use garage_util::config::{read_config, Config};
+use garage_util::crdt::Crdt;
+use garage_util::data::Uuid;
+
+use crate::layout::*;
+use crate::replication_mode::{ConsistencyMode, ReplicationFactor};
+use crate::system::System;
+
+/// Writes the configuration of a test node storing everything in `dir` and
+/// reads it back. `extra_s3_config` is added to the `[s3_api]` section.
+pub fn test_config(dir: &Path, replication_factor: usize, extra_s3_config: &str) -> Config {
+	let config_file = dir.join("garage.toml");
+	std::fs::write(
+		&config_file,
+		format!(
+			r#"
+			metadata_dir = "{0}/meta"
+			data_dir = "{0}/data"
+			db_engine = "sqlite"
+			replication_factor = {1}
+			rpc_bind_addr = "127.0.0.1:0"
+			rpc_secret = "{2}"
+
+			[s3_api]
+			s3_region = "garage"
+			api_bind_addr = "127.0.0.1:0"
+			{3}
+			"#,
+			dir.display(),
+			replication_factor,
+			hex::encode([0u8; 32]),
+			extra_s3_config,
+		),
+	)
+	.unwrap();
+	let config = read_config(config_file).unwrap();
+	std::fs::create_dir_all(&config.metadata_dir).unwrap();
+	std::fs::create_dir_all(dir.join("data")).unwrap();
+	config
+}
+
+/// Creates the System of a test node from a configuration made by `test_config`
+pub fn test_system(config: &Config) -> Arc<System> {
+	System::new(
+		NetworkKey::from_slice(&[0u8; 32]).unwrap(),
+		ReplicationFactor::new(config.replication_factor.unwrap()).unwrap(),
+		ConsistencyMode::Consistent,
+		config,
+	)
+	.unwrap()
+}
+
+/// Opens the metadata database of a test node
+pub fn test_db(config: &Config) -> db::Db {
+	db::open_db(
+		&config.metadata_dir.join("db.sqlite"),
+		db::Engine::Sqlite,
+		&db::OpenOpt::default(),
+	)
+	.unwrap()
+}
+
+/// Applies a cluster layout in which each of `nodes` stores data in its own zone.
+/// The other nodes do not need to exist, they are then seen as down.
+pub async fn assign_test_layout(system: &System, nodes: &[Uuid]) {
+	let mut layout = system.cluster_layout().inner().clone();
+	let staging = layout.staging.get_mut();
+	for (i, node) in nodes.iter().enumerate() {
+		let update = staging.roles.update_mutator(
+			*node,
+			NodeRoleV(Some(NodeRole {
+				zone: format!("zone{}", i),
+				capacity: Some(1 << 30),
+				tags: vec![],
+			})),
+		);
+		staging.roles.merge(&update);
+	}
+	let (layout, _) = layout.apply_staged_changes(Some(1)).unwrap();
+	system
+		.layout_manager
+		.update_cluster_layout(&layout)
+		.await
+		.unwrap();
+}
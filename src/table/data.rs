@@ -168,6 +168,8 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 	//   This has to be done atomically with the modification for the merkle updater
 	//   to maintain consistency. The merkle updater must then be notified with todo_notify.
 	// - When an entry is updated to be a tombstone, add it to the gc_todo tree
+	// - When an entry is updated to an entry for which should_gc() is true,
+	//   remove it from the store as if it was deleted
 
 	pub(crate) fn update_many<T: Borrow<ByteBuf>>(&self, entries: &[T]) -> Result<(), Error> {
 		for update_bytes in entries.iter() {
@@ -184,7 +186,7 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 			update.sort_key(),
 			|_tx, ent| match ent {
 				Some(mut ent) => {
-					ent.merge(&update);
+					self.merge_entry(&mut ent, &update);
 					Ok(ent)
 				}
 				None => Ok(update.clone()),
@@ -193,6 +195,18 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 		Ok(())
 	}
 
+	/// Merge `other` into `entry`. Merges with a version that differs from
+	/// `entry` indicate concurrent writes and are counted in the metrics.
+	/// Returns whether such a merge happened.
+	pub(crate) fn merge_entry(&self, entry: &mut F::E, other: &F::E) -> bool {
+		if entry == other {
+			return false;
+		}
+		entry.merge(other);
+		self.metrics.merge_conflict_counter.add(1);
+		true
+	}
+
 	pub fn update_entry_with(
 		&self,
 		partition_key: &F::P,
@@ -211,6 +225,17 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 				None => (None, None, update_fn(tx, None)?),
 			};
 
+			if F::should_gc(&new_entry) {
+				if let Some(old_entry) = old_entry {
+					tx.remove(&self.store, &tree_key)?;
+					tx.insert(&self.merkle_todo, &tree_key, vec![])?;
+
+					self.instance.updated(tx, Some(&old_entry), None)?;
+					return Ok(UpdateResult::Deleted);
+				}
+				return Ok(UpdateResult::Unchanged);
+			}
+
 			// Changed can be true in two scenarios
 			// Scenario 1: the actual represented value changed,
 			//   so of course the messagepack encoding changed as well
@@ -233,35 +258,41 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 				self.instance
 					.updated(tx, old_entry.as_ref(), Some(&new_entry))?;
 
-				Ok(Some((new_entry, new_bytes_hash)))
+				Ok(UpdateResult::Changed(new_entry, new_bytes_hash))
 			} else {
-				Ok(None)
+				Ok(UpdateResult::Unchanged)
 			}
 		})?;
 
-		if let Some((new_entry, new_bytes_hash)) = changed {
-			self.metrics.internal_update_counter.add(1);
-
-			let is_tombstone = new_entry.is_tombstone();
-			self.merkle_todo_notify.notify_one();
-			if is_tombstone {
-				// We are only responsible for GC'ing this item if we are the
-				// "leader" of the partition, i.e. the first node in the
-				// set of nodes that replicates this partition.
-				// This avoids GC loops and does not change the termination properties
-				// of the GC algorithm, as in all cases GC is suspended if
-				// any node of the partition is unavailable.
-				let pk_hash = Hash::try_from(&tree_key[..32]).unwrap();
-				// TODO: this probably breaks when the layout changes
-				let nodes = self.replication.storage_nodes(&pk_hash);
-				if nodes.first() == Some(&self.system.id) {
-					GcTodoEntry::new(tree_key, new_bytes_hash).save(&self.gc_todo)?;
+		match changed {
+			UpdateResult::Changed(new_entry, new_bytes_hash) => {
+				self.metrics.internal_update_counter.add(1);
+
+				let is_tombstone = new_entry.is_tombstone();
+				self.merkle_todo_notify.notify_one();
+				if is_tombstone {
+					// We are only responsible for GC'ing this item if we are the
+					// "leader" of the partition, i.e. the first node in the
+					// set of nodes that replicates this partition.
+					// This avoids GC loops and does not change the termination properties
+					// of the GC algorithm, as in all cases GC is suspended if
+					// any node of the partition is unavailable.
+					let pk_hash = Hash::try_from(&tree_key[..32]).unwrap();
+					// TODO: this probably breaks when the layout changes
+					let nodes = self.replication.storage_nodes(&pk_hash);
+					if nodes.first() == Some(&self.system.id) {
+						GcTodoEntry::new(tree_key, new_bytes_hash).save(&self.gc_todo)?;
+					}
 				}
-			}
 
-			Ok(Some(new_entry))
-		} else {
-			Ok(None)
+				Ok(Some(new_entry))
+			}
+			UpdateResult::Deleted => {
+				self.metrics.internal_delete_counter.add(1);
+				self.merkle_todo_notify.notify_one();
+				Ok(None)
+			}
+			UpdateResult::Unchanged => Ok(None),
 		}
 	}
 
@@ -324,12 +355,14 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 		tx: &mut db::Transaction,
 		ins: &F::E,
 	) -> db::TxResult<(), Error> {
+		self.check_sort_key(ins.sort_key())
+			.map_err(db::TxError::Abort)?;
 		let tree_key = self.tree_key(ins.partition_key(), ins.sort_key());
 
 		let new_entry = match tx.get(&self.insert_queue, &tree_key)? {
 			Some(old_v) => {
 				let mut entry = self.decode_entry(&old_v).map_err(db::TxError::Abort)?;
-				entry.merge(ins);
+				self.merge_entry(&mut entry, ins);
 				entry.encode()
 			}
 			None => ins.encode(),
@@ -347,6 +380,22 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 
 	// ---- Utility functions ----
 
+	/// Check that a sort key is not longer than allowed by the table schema.
+	/// This is only checked for the entries inserted by this node: entries
+	/// received from other nodes are always merged, so that the data of all
+	/// nodes converges even if they do not have the same limit.
+	pub fn check_sort_key(&self, sort_key: &F::S) -> Result<(), Error> {
+		let len = sort_key.sort_key().len();
+		if len > F::MAX_SORT_KEY_LEN {
+			return Err(Error::SortKeyTooLong(
+				F::TABLE_NAME,
+				len,
+				F::MAX_SORT_KEY_LEN,
+			));
+		}
+		Ok(())
+	}
+
 	pub fn tree_key(&self, p: &F::P, s: &F::S) -> Vec<u8> {
 		[p.hash().as_slice(), s.sort_key()].concat()
 	}
@@ -371,3 +420,185 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 		Ok(self.gc_todo.len()?)
 	}
 }
+
+enum UpdateResult<E> {
+	Unchanged,
+	Changed(E, Hash),
+	Deleted,
+}
+
+#[cfg(test)]
+mod tests {
+	use serde::{Deserialize, Serialize};
+
+	use garage_rpc::test_util::*;
+	use garage_util::crdt;
+
+	use super::*;
+	use crate::replication::TableShardedReplication;
+
+	#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+	struct TestEntry {
+		pk: String,
+		sk: String,
+		value: u64,
+		deleted: crdt::Bool,
+	}
+
+	impl garage_util::migrate::InitialFormat for TestEntry {}
+
+	impl Crdt for TestEntry {
+		fn merge(&mut self, other: &Self) {
+			self.value = std::cmp::max(self.value, other.value);
+			self.deleted.merge(&other.deleted);
+		}
+	}
+
+	impl Entry<String, String> for TestEntry {
+		fn partition_key(&self) -> &String {
+			&self.pk
+		}
+		fn sort_key(&self) -> &String {
+			&self.sk
+		}
+	}
+
+	struct TestTable;
+
+	impl TableSchema for TestTable {
+		const TABLE_NAME: &'static str = "test";
+		const MAX_SORT_KEY_LEN: usize = 16;
+
+		type P = String;
+		type S = String;
+		type E = TestEntry;
+		type Filter = ();
+
+		fn matches_filter(_entry: &TestEntry, _filter: &()) -> bool {
+			true
+		}
+
+		fn should_gc(entry: &TestEntry) -> bool {
+			entry.deleted.get()
+		}
+	}
+
+	fn test_entry(sk: &str, value: u64, deleted: bool) -> TestEntry {
+		TestEntry {
+			pk: "pk".into(),
+			sk: sk.into(),
+			value,
+			deleted: crdt::Bool::new(deleted),
+		}
+	}
+
+	fn entry(sk: &str, value: u64, deleted: bool) -> Vec<u8> {
+		test_entry(sk, value, deleted).encode().unwrap()
+	}
+
+	fn test_table_data(
+		dir: &std::path::Path,
+	) -> (
+		Arc<System>,
+		Arc<TableData<TestTable, TableShardedReplication>>,
+	) {
+		let config = test_config(dir, 1, "");
+		let system = test_system(&config);
+		let db = test_db(&config);
+		let replication = TableShardedReplication {
+			system: system.clone(),
+			replication_factor: 1,
+			read_quorum: 1,
+			write_quorum: 1,
+		};
+		let data = TableData::new(system.clone(), TestTable, replication, &db);
+		(system, data)
+	}
+
+	#[tokio::test]
+	async fn test_should_gc_drops_entries() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let (system, data) = test_table_data(&dir);
+		let pk = "pk".to_string();
+
+		// A live entry is stored
+		data.update_entry(&entry("a", 1, false)).unwrap();
+		assert!(data.read_entry(&pk, &"a".to_string()).unwrap().is_some());
+
+		// Once it is merged with a deleted entry, it is dropped
+		// from the store and from the Merkle tree
+		data.update_entry(&entry("a", 2, true)).unwrap();
+		assert!(data.read_entry(&pk, &"a".to_string()).unwrap().is_none());
+		let tree_key = data.tree_key(&pk, &"a".to_string());
+		assert!(data.merkle_todo.get(tree_key).unwrap().unwrap().is_empty());
+
+		// A deleted entry is never stored
+		data.update_entry(&entry("b", 1, true)).unwrap();
+		assert!(data.read_entry(&pk, &"b".to_string()).unwrap().is_none());
+
+		assert_eq!(data.store.len().unwrap(), 0);
+		assert_eq!(data.gc_todo_len().unwrap(), 0);
+
+		system.cleanup();
+	}
+
+	#[tokio::test]
+	async fn test_max_sort_key_len() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let (system, data) = test_table_data(&dir);
+		let pk = "pk".to_string();
+
+		let max_key = "k".repeat(16);
+		let long_key = "k".repeat(17);
+		assert!(data.check_sort_key(&max_key).is_ok());
+		assert!(matches!(
+			data.check_sort_key(&long_key),
+			Err(Error::SortKeyTooLong("test", 17, 16))
+		));
+
+		// Entries received from other nodes are merged whatever the length
+		// of their sort key, and can be deleted
+		data.update_many(&[ByteBuf::from(entry(&long_key, 1, false))])
+			.unwrap();
+		assert!(data.read_entry(&pk, &long_key).unwrap().is_some());
+		data.update_many(&[ByteBuf::from(entry(&long_key, 2, true))])
+			.unwrap();
+		assert!(data.read_entry(&pk, &long_key).unwrap().is_none());
+
+		// Entries inserted by this node are rejected
+		let long_entry = TestEntry {
+			pk: pk.clone(),
+			sk: long_key,
+			value: 1,
+			deleted: crdt::Bool::new(false),
+		};
+		let res = data
+			.store
+			.db()
+			.transaction(|tx| data.queue_insert(tx, &long_entry));
+		assert!(matches!(
+			res,
+			Err(db::TxError::Abort(Error::SortKeyTooLong("test", 17, 16)))
+		));
+		assert_eq!(data.insert_queue.len().unwrap(), 0);
+
+		system.cleanup();
+	}
+
+	#[tokio::test]
+	async fn test_merge_entry_conflicts() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let (system, data) = test_table_data(&dir);
+
+		let mut ent = test_entry("a", 1, false);
+		assert!(!data.merge_entry(&mut ent, &test_entry("a", 1, false)));
+		assert!(data.merge_entry(&mut ent, &test_entry("a", 2, false)));
+		assert_eq!(ent, test_entry("a", 2, false));
+		assert!(data.merge_entry(&mut ent, &test_entry("a", 1, false)));
+		assert_eq!(ent, test_entry("a", 2, false));
+		assert!(data.merge_entry(&mut ent, &test_entry("a", 2, true)));
+		assert!(!data.merge_entry(&mut ent, &test_entry("a", 2, true)));
+
+		system.cleanup();
+	}
+}
@@ -16,6 +16,7 @@ pub struct TableMetrics {
 
 	pub(crate) internal_update_counter: BoundCounter<u64>,
 	pub(crate) internal_delete_counter: BoundCounter<u64>,
+	pub(crate) merge_conflict_counter: BoundCounter<u64>,
 
 	pub(crate) sync_items_sent: Counter<u64>,
 	pub(crate) sync_items_received: Counter<u64>,
@@ -118,6 +119,11 @@ impl TableMetrics {
 				.with_description("Number of value deletions in the tree (due to GC or repartitioning)")
 				.init()
 				.bind(&[KeyValue::new("table_name", table_name)]),
+			merge_conflict_counter: meter
+				.u64_counter("table.merge_conflict_counter")
+				.with_description("Number of merges of an entry with a different version of itself (due to concurrent writes)")
+				.init()
+				.bind(&[KeyValue::new("table_name", table_name)]),
 
 			sync_items_sent: meter
 				.u64_counter("table.sync_items_sent")
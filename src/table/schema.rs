@@ -73,6 +73,10 @@ pub trait TableSchema: Send + Sync + 'static {
 	/// The name of the table in the database
 	const TABLE_NAME: &'static str;
 
+	/// Maximum length in bytes of the sort keys of entries. Entries with a longer
+	/// sort key are rejected when they are inserted by this node.
+	const MAX_SORT_KEY_LEN: usize = 64 * 1024;
+
 	/// The partition key used in that table
 	type P: PartitionKey;
 	/// The sort key used int that table
@@ -100,4 +104,16 @@ pub trait TableSchema: Send + Sync + 'static {
 	}
 
 	fn matches_filter(entry: &Self::E, filter: &Self::Filter) -> bool;
+
+	/// Can an entry be dropped from the local store as soon as it is merged?
+	/// Entries for which this returns true are removed (or never written)
+	/// when they are updated, instead of being stored until the tombstone
+	/// GC deletes them. This is not coordinated with other nodes: it must
+	/// only return true for entries that carry no information, i.e. that
+	/// change nothing when merged into another entry, otherwise a node
+	/// that has not seen the update could resurrect the previous value.
+	/// Default implementation always returns false.
+	fn should_gc(_entry: &Self::E) -> bool {
+		false
+	}
 }
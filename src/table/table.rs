@@ -119,6 +119,7 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 	}
 
 	async fn insert_internal(&self, e: &F::E) -> Result<(), Error> {
+		self.data.check_sort_key(e.sort_key())?;
 		let hash = e.partition_key().hash();
 		let who = self.data.replication.write_sets(&hash);
 
@@ -193,6 +194,7 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 		let mut entries_vec = Vec::new();
 		for entry in entries.into_iter() {
 			let entry = entry.borrow();
+			self.data.check_sort_key(entry.sort_key())?;
 			let hash = entry.partition_key().hash();
 			let mut write_sets = self.data.replication.write_sets(&hash);
 			for set in write_sets.as_mut().iter_mut() {
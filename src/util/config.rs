@@ -2,6 +2,7 @@
 use std::convert::TryFrom;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use serde::{de, Deserialize};
 
@@ -68,6 +69,12 @@ pub struct Config {
 	)]
 	pub block_ram_buffer_max: usize,
 
+	/// Maximum number of bytes per second of data blocks sent by
+	/// PutObject requests that are written to the disks of this node.
+	/// If None, there is no limit
+	#[serde(deserialize_with = "deserialize_option_capacity", default)]
+	pub block_write_rate: Option<u64>,
+
 	/// Skip the permission check of secret files. Useful when
 	/// POSIX ACLs (or more complex chmods) are used.
 	#[serde(default)]
@@ -156,6 +163,73 @@ pub struct S3ApiConfig {
 	/// Suffix to remove from domain name to find bucket. If None,
 	/// vhost-style S3 request are disabled
 	pub root_domain: Option<String>,
+	/// Maximum size of a single object. If None, objects of any size are accepted
+	#[serde(deserialize_with = "deserialize_option_capacity", default)]
+	pub max_object_size: Option<u64>,
+	/// Maximum duration of a single PutObject request, after which the upload
+	/// is aborted. If None, uploads can take an unlimited amount of time
+	#[serde(deserialize_with = "deserialize_option_duration", default)]
+	pub put_max_duration: Option<Duration>,
+	/// Whether the block references of an aborted upload are marked as
+	/// deleted right when it is aborted, instead of when the deletion of
+	/// its version is propagated in the background
+	#[serde(default)]
+	pub eager_abort_gc: bool,
+	/// Age after which uploads that are still in progress, including
+	/// multipart uploads, are aborted by a background worker. If None,
+	/// they are never aborted automatically
+	#[serde(deserialize_with = "deserialize_option_duration", default)]
+	pub stale_upload_timeout: Option<Duration>,
+	/// Maximum number of uploads of objects that are not stored inline that
+	/// can be in progress at the same time in a bucket. If None, there is no limit
+	#[serde(default)]
+	pub max_concurrent_uploads: Option<usize>,
+	/// Number of times the write of a data block to storage nodes is retried
+	/// after a transient failure, before the upload is aborted
+	#[serde(default = "default_put_block_retries")]
+	pub put_block_retries: usize,
+	/// Number of data blocks of an upload that can be encrypted and hashed
+	/// at the same time, on different CPU cores
+	#[serde(default = "default_put_hash_parallelism")]
+	pub put_hash_parallelism: usize,
+	/// Number of data blocks of an upload that can be buffered between
+	/// each stage of the upload pipeline (reading, hashing, encryption, writing)
+	#[serde(default = "default_put_pipeline_depth")]
+	pub put_pipeline_depth: usize,
+	/// Maximum number of bytes of data blocks that all uploads together can
+	/// keep in memory in the upload pipeline. If None, there is no limit
+	#[serde(deserialize_with = "deserialize_option_capacity", default)]
+	pub put_memory_budget: Option<u64>,
+	/// Duration during which the idempotency key given by a client for a
+	/// PutObject request is remembered, so that retries are deduplicated
+	#[serde(
+		deserialize_with = "deserialize_duration",
+		default = "default_idempotency_key_ttl"
+	)]
+	pub idempotency_key_ttl: Duration,
+	/// Maximum amount of time by which the timestamp given by a client for
+	/// a new object version can be ahead of the clock of the node
+	#[serde(
+		deserialize_with = "deserialize_duration",
+		default = "default_max_object_timestamp_skew"
+	)]
+	pub max_object_timestamp_skew: Duration,
+	/// Minimum size of the parts of a multipart upload, except the last one
+	#[serde(
+		deserialize_with = "deserialize_capacity",
+		default = "default_multipart_min_part_size"
+	)]
+	pub multipart_min_part_size: usize,
+	/// Maximum length of object keys, in bytes of their UTF-8 encoding
+	#[serde(default = "default_max_key_length")]
+	pub max_key_length: usize,
+	/// Maximum total size of the user metadata (x-amz-meta-* headers) of
+	/// an object, counting the bytes of both their names and values
+	#[serde(
+		deserialize_with = "deserialize_capacity",
+		default = "default_max_metadata_size"
+	)]
+	pub max_metadata_size: usize,
 }
 
 /// Configuration for K2V api
@@ -259,6 +333,38 @@ fn default_block_ram_buffer_max() -> usize {
 	256 * 1024 * 1024
 }
 
+fn default_put_block_retries() -> usize {
+	2
+}
+
+fn default_put_hash_parallelism() -> usize {
+	1
+}
+
+fn default_put_pipeline_depth() -> usize {
+	1
+}
+
+fn default_idempotency_key_ttl() -> Duration {
+	Duration::from_secs(24 * 3600)
+}
+
+fn default_max_object_timestamp_skew() -> Duration {
+	Duration::from_secs(300)
+}
+
+fn default_multipart_min_part_size() -> usize {
+	5 * 1024 * 1024
+}
+
+fn default_max_key_length() -> usize {
+	1024
+}
+
+fn default_max_metadata_size() -> usize {
+	2048
+}
+
 fn default_consistency_mode() -> String {
 	"consistent".into()
 }
@@ -359,6 +465,29 @@ where
 	deserializer.deserialize_any(CapacityVisitor)
 }
 
+fn deserialize_option_capacity<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+	D: de::Deserializer<'de>,
+{
+	deserialize_capacity(deserializer).map(|x| Some(x as u64))
+}
+
+fn deserialize_option_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+	D: de::Deserializer<'de>,
+{
+	deserialize_duration(deserializer).map(Some)
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+	D: de::Deserializer<'de>,
+{
+	let value = String::deserialize(deserializer)?;
+	parse_duration::parse(&value)
+		.map_err(|e| de::Error::custom(format!("invalid duration value: {}", e)))
+}
+
 #[cfg(test)]
 mod tests {
 	use crate::error::Error;
@@ -137,6 +137,35 @@ pub fn blake2sum(data: &[u8]) -> Hash {
 	hash.into()
 }
 
+/// Incremental computation of a blake2 hash, giving the same result
+/// as `blake2sum` on the concatenation of all the data that is fed to it
+pub struct Blake2Hasher(blake2::Blake2b512);
+
+impl Blake2Hasher {
+	pub fn new() -> Self {
+		use blake2::Digest;
+		Self(blake2::Blake2b512::new())
+	}
+
+	pub fn update(&mut self, data: &[u8]) {
+		use blake2::Digest;
+		self.0.update(data);
+	}
+
+	pub fn finalize(self) -> Hash {
+		use blake2::Digest;
+		let mut hash = [0u8; 32];
+		hash.copy_from_slice(&self.0.finalize()[..32]);
+		hash.into()
+	}
+}
+
+impl Default for Blake2Hasher {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
 /// A 64 bit non cryptographic hash
 pub type FastHash = u64;
 
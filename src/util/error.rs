@@ -73,6 +73,14 @@ pub enum Error {
 	#[error(display = "Missing block {:?}: no node returned a valid block", _0)]
 	MissingBlock(Hash),
 
+	#[error(
+		display = "Sort key of {} bytes is too long for table {} (maximum is {} bytes)",
+		_1,
+		_0,
+		_2
+	)]
+	SortKeyTooLong(&'static str, usize, usize),
+
 	#[error(display = "{}", _0)]
 	Message(String),
 }
@@ -81,6 +89,17 @@ impl Error {
 	pub fn unexpected_rpc_message<T: Serialize>(v: T) -> Self {
 		Self::UnexpectedRpcMessage(debug_serialize(&v))
 	}
+
+	/// Is the error caused by a network failure that might not happen again
+	/// if the request is retried? A quorum error is considered transient,
+	/// unless there are not enough nodes to ever reach the quorum.
+	pub fn is_transient(&self) -> bool {
+		match self {
+			Error::Timeout | Error::Net(_) => true,
+			Error::Quorum(quorum, _, _, nodes, _) => nodes >= quorum,
+			_ => false,
+		}
+	}
 }
 
 impl From<garage_db::TxError<Error>> for Error {
@@ -23,6 +23,14 @@ impl<T: Migrate> Persister<T> {
 		}
 	}
 
+	/// Path of the file to which data is written before it replaces the
+	/// persisted file, so that a crash never leaves a partially written file
+	fn tmp_path(&self) -> PathBuf {
+		let mut tmp_path = self.path.clone().into_os_string();
+		tmp_path.push(".tmp");
+		tmp_path.into()
+	}
+
 	fn decode(&self, bytes: &[u8]) -> Result<T, Error> {
 		match T::decode(bytes) {
 			Some(v) => Ok(v),
@@ -55,13 +63,16 @@ impl<T: Migrate> Persister<T> {
 	pub fn save(&self, t: &T) -> Result<(), Error> {
 		let bytes = t.encode()?;
 
-		let mut file = std::fs::OpenOptions::new()
-			.write(true)
-			.create(true)
-			.truncate(true)
-			.open(&self.path)?;
-
+		let tmp_path = self.tmp_path();
+		let mut file = std::fs::File::create(&tmp_path)?;
 		file.write_all(&bytes[..])?;
+		file.sync_all()?;
+		drop(file);
+
+		std::fs::rename(&tmp_path, &self.path)?;
+		if let Some(dir) = self.path.parent() {
+			std::fs::File::open(dir)?.sync_all()?;
+		}
 
 		Ok(())
 	}
@@ -79,8 +90,16 @@ impl<T: Migrate> Persister<T> {
 	pub async fn save_async(&self, t: &T) -> Result<(), Error> {
 		let bytes = t.encode()?;
 
-		let mut file = tokio::fs::File::create(&self.path).await?;
+		let tmp_path = self.tmp_path();
+		let mut file = tokio::fs::File::create(&tmp_path).await?;
 		file.write_all(&bytes[..]).await?;
+		file.sync_all().await?;
+		drop(file);
+
+		tokio::fs::rename(&tmp_path, &self.path).await?;
+		if let Some(dir) = self.path.parent() {
+			tokio::fs::File::open(dir).await?.sync_all().await?;
+		}
 
 		Ok(())
 	}
@@ -1,7 +1,18 @@
 //! Module containing helper functions to manipulate time
-use chrono::{SecondsFormat, TimeZone, Utc};
+use std::path::Path;
+use std::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use chrono::{SecondsFormat, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, OkOrMessage};
+use crate::persister::Persister;
+
+/// Amount of time (in msec) that is reserved in advance each time the
+/// high-water mark of a `MonotonicClock` is written to disk
+const MONOTONIC_CLOCK_RESERVATION: u64 = 10_000;
+
 /// Returns milliseconds since UNIX Epoch
 pub fn now_msec() -> u64 {
 	SystemTime::now()
@@ -28,3 +39,196 @@ pub fn msec_to_rfc3339(msecs: u64) -> String {
 	let timestamp = Utc.timestamp_opt(secs, nanos).unwrap();
 	timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)
 }
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct MonotonicClockPersisted {
+	pub high_water_mark: u64,
+}
+
+impl crate::migrate::InitialFormat for MonotonicClockPersisted {
+	const VERSION_MARKER: &'static [u8] = b"G10mcp";
+}
+
+/// A clock that generates strictly increasing timestamps (in msec since
+/// UNIX Epoch), even if the system clock goes backward or the process
+/// is restarted.
+///
+/// To avoid writing to disk for every timestamp, a high-water mark is
+/// persisted a bit ahead of the timestamps that are actually handed out.
+/// After a restart, timestamps resume from that high-water mark.
+pub struct MonotonicClock {
+	persister: Persister<MonotonicClockPersisted>,
+	state: Mutex<MonotonicClockState>,
+	/// Held while a new high-water mark is written to disk
+	persist_lock: tokio::sync::Mutex<()>,
+}
+
+struct MonotonicClockState {
+	last: u64,
+	high_water_mark: u64,
+}
+
+impl MonotonicClock {
+	/// Load the clock from its persisted high-water mark. A clock that was
+	/// never persisted starts from zero, but a file that cannot be read is
+	/// an error, as the timestamps that were handed out are then unknown.
+	pub fn new(base_dir: &Path, file_name: &str) -> Result<Self, Error> {
+		let persister = Persister::new(base_dir, file_name);
+		let high_water_mark = match persister.load() {
+			Ok(MonotonicClockPersisted { high_water_mark }) => high_water_mark,
+			Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => 0,
+			Err(e) => {
+				return Err(Error::Message(format!(
+					"Unable to load monotonic clock state: {}",
+					e
+				)))
+			}
+		};
+		Ok(Self {
+			persister,
+			state: Mutex::new(MonotonicClockState {
+				last: high_water_mark,
+				high_water_mark,
+			}),
+			persist_lock: tokio::sync::Mutex::new(()),
+		})
+	}
+
+	/// Returns a timestamp that is strictly greater than `prev`, and than all
+	/// timestamps previously generated from the local clock. A timestamp that
+	/// is increased above `prev`, which may be far in the future, does not move
+	/// the clock forward: the following timestamps only depend on `prev` if
+	/// they are given the same one.
+	pub async fn next(&self, prev: Option<u64>) -> Result<u64, Error> {
+		self.next_at(prev, now_msec()).await
+	}
+
+	async fn next_at(&self, prev: Option<u64>, now: u64) -> Result<u64, Error> {
+		let (ts, local) = {
+			let mut state = self.state.lock().unwrap();
+			let local = state
+				.last
+				.checked_add(1)
+				.ok_or_message("No timestamp can be generated after the maximum timestamp")?;
+			let local = std::cmp::max(local, now);
+			let ts = match prev {
+				Some(prev) => prev
+					.checked_add(1)
+					.ok_or_message("No timestamp can be generated after the maximum timestamp")?,
+				None => 0,
+			};
+			let ts = std::cmp::max(ts, local);
+			state.last = local;
+			if local < state.high_water_mark {
+				return Ok(ts);
+			}
+			(ts, local)
+		};
+
+		// The timestamp is only handed out once a high-water mark above the
+		// local clock has been persisted, so that it is never generated again
+		// after a restart. Concurrent callers wait for the same write to disk.
+		let _persist_lock = self.persist_lock.lock().await;
+		if local < self.state.lock().unwrap().high_water_mark {
+			return Ok(ts);
+		}
+		let high_water_mark = local.saturating_add(MONOTONIC_CLOCK_RESERVATION);
+		self.persister
+			.save_async(&MonotonicClockPersisted { high_water_mark })
+			.await
+			.map_err(|e| {
+				Error::Message(format!("Unable to persist monotonic clock state: {}", e))
+			})?;
+		let mut state = self.state.lock().unwrap();
+		state.high_water_mark = std::cmp::max(state.high_water_mark, high_water_mark);
+
+		Ok(ts)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn test_monotonic_clock() -> Result<(), crate::error::Error> {
+		let dir = mktemp::Temp::new_dir()?;
+
+		let clock = MonotonicClock::new(&dir, "clock")?;
+		let t1 = clock.next_at(None, 1_000_000).await?;
+		assert_eq!(t1, 1_000_000);
+		assert_eq!(clock.next_at(Some(999_000), 1_000_001).await?, 1_000_001);
+
+		// System clock jumps backward
+		let t2 = clock.next_at(None, 500_000).await?;
+		assert!(t2 > 1_000_001);
+		let t3 = clock.next_at(None, 500_001).await?;
+		assert!(t3 > t2);
+
+		// Process restarts, clock is still behind
+		drop(clock);
+		let clock = MonotonicClock::new(&dir, "clock")?;
+		let t4 = clock.next_at(None, 500_002).await?;
+		assert!(t4 > t3);
+		assert!(clock.next_at(None, 500_003).await? > t4);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn test_monotonic_clock_future_prev() -> Result<(), crate::error::Error> {
+		let dir = mktemp::Temp::new_dir()?;
+
+		// A timestamp following one in the future is after it,
+		// but the timestamps of other objects are not moved forward
+		let clock = MonotonicClock::new(&dir, "clock")?;
+		assert_eq!(clock.next_at(Some(5_000_000), 1_000_000).await?, 5_000_001);
+		assert_eq!(clock.next_at(None, 1_000_001).await?, 1_000_001);
+		assert_eq!(clock.next_at(Some(1_000_000), 1_000_001).await?, 1_000_002);
+
+		// Nor are they after a restart
+		drop(clock);
+		let clock = MonotonicClock::new(&dir, "clock")?;
+		let t = clock.next_at(None, 1_000_003).await?;
+		assert!(t > 1_000_002 && t < 5_000_000);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn test_monotonic_clock_overflow() -> Result<(), crate::error::Error> {
+		let dir = mktemp::Temp::new_dir()?;
+
+		// No timestamp is greater than the maximum one
+		let clock = MonotonicClock::new(&dir, "clock")?;
+		assert!(clock.next_at(Some(u64::MAX), 1_000_000).await.is_err());
+		assert_eq!(
+			clock.next_at(Some(u64::MAX - 1), 1_000_000).await?,
+			u64::MAX
+		);
+		assert!(clock.next_at(None, u64::MAX).await.is_ok());
+		assert!(clock.next_at(None, u64::MAX).await.is_err());
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn test_monotonic_clock_corrupt_state() -> Result<(), crate::error::Error> {
+		let dir = mktemp::Temp::new_dir()?;
+
+		// A clock that cannot be loaded does not silently start from zero
+		std::fs::write(dir.join("clock"), b"not a clock state")?;
+		assert!(MonotonicClock::new(&dir, "clock").is_err());
+
+		// The state is replaced as a whole when it is persisted
+		std::fs::remove_file(dir.join("clock"))?;
+		let clock = MonotonicClock::new(&dir, "clock")?;
+		let t1 = clock.next_at(None, 1_000_000).await?;
+		assert!(!dir.join("clock.tmp").exists());
+		drop(clock);
+		let clock = MonotonicClock::new(&dir, "clock")?;
+		assert!(clock.next_at(None, 1_000_000).await? > t1);
+
+		Ok(())
+	}
+}
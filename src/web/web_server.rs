@@ -236,6 +236,10 @@ impl WebServer {
 		let index = &website_config.index_document;
 		let (key, may_redirect) = path_to_keys(&path, index)?;
 
+		// Keys are normalized in the same way as in the S3 API
+		let key_normalization = bucket_params.options.get().key_normalization;
+		let key = key_normalization.normalize(&key);
+
 		debug!(
 			"Selected bucket: \"{}\" {:?}, target key: \"{}\", may redirect to: {:?}",
 			bucket_name, bucket_id, key, may_redirect
@@ -246,15 +250,26 @@ impl WebServer {
 				.map_err(ApiError::from)
 				.map(|res| res.map(|_empty_body: EmptyBody| empty_body())),
 			Method::HEAD => {
-				handle_head_without_ctx(self.garage.clone(), req, bucket_id, &key, None).await
+				handle_head_without_ctx(
+					self.garage.clone(),
+					req,
+					bucket_id,
+					&bucket_params,
+					&key,
+					None,
+					None,
+				)
+				.await
 			}
 			Method::GET => {
 				handle_get_without_ctx(
 					self.garage.clone(),
 					req,
 					bucket_id,
+					&bucket_params,
 					&key,
 					None,
+					None,
 					Default::default(),
 				)
 				.await
@@ -264,8 +279,12 @@ impl WebServer {
 
 		// Try implicit redirect on error
 		let ret_doc_with_redir = match (&ret_doc, may_redirect) {
-			(Err(ApiError::NoSuchKey), ImplicitRedirect::To { key, url })
-				if self.check_key_exists(bucket_id, key.as_str()).await? =>
+			(
+				Err(ApiError::NoSuchKey | ApiError::DeleteMarker(_)),
+				ImplicitRedirect::To { key, url },
+			) if self
+				.check_key_exists(bucket_id, &key_normalization.normalize(&key))
+				.await? =>
 			{
 				Ok(Response::builder()
 					.status(StatusCode::FOUND)
@@ -291,7 +310,7 @@ impl WebServer {
 
 				// If no error document is set: just return the error directly
 				let error_document = match &website_config.error_document {
-					Some(ed) => ed.trim_start_matches('/').to_owned(),
+					Some(ed) => key_normalization.normalize(ed.trim_start_matches('/')),
 					None => return Err(error),
 				};
 
@@ -306,8 +325,10 @@ impl WebServer {
 					self.garage.clone(),
 					&req2,
 					bucket_id,
+					&bucket_params,
 					&error_document,
 					None,
+					None,
 					Default::default(),
 				)
 				.await